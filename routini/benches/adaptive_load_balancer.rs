@@ -71,6 +71,8 @@ fn benchmark_adaptive_lb_strategies(c: &mut Criterion) {
         ("Consistent", Adaptive::Consistent),
         ("FewestConnections", Adaptive::FewestConnections),
         ("FastestServer", Adaptive::FastestServer),
+        ("P2C", Adaptive::P2C),
+        ("PeakEwma", Adaptive::PeakEwma),
     ];
 
     for (name, strategy) in strategies {
@@ -1,10 +1,10 @@
 use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
 use routini::load_balancing::Backend;
 use routini::load_balancing::strategy::{
-    Adaptive, BackendIter, BackendSelection, Consistent, FNVHash, FewestConnections, Random,
-    RoundRobin, Strategy,
+    Adaptive, BackendIter, BackendSelection, Consistent, FNVHash, FewestConnections, Maglev,
+    Random, Rendezvous, RoundRobin, Strategy,
 };
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
 use std::hint::black_box;
 use std::sync::Arc;
 
@@ -72,6 +72,12 @@ fn benchmark_all_strategies(c: &mut Criterion) {
     // Benchmark Consistent
     benchmark_strategy(c, "Consistent", Consistent, &backend_counts);
 
+    // Benchmark Maglev
+    benchmark_strategy(c, "Maglev", Maglev, &backend_counts);
+
+    // Benchmark Rendezvous
+    benchmark_strategy(c, "Rendezvous", Rendezvous, &backend_counts);
+
     // Benchmark FewestConnections
     benchmark_strategy(c, "FewestConnections", FewestConnections, &backend_counts);
 
@@ -179,11 +185,93 @@ fn benchmark_key_distribution(c: &mut Criterion) {
     group.finish();
 }
 
+/// The first choice of each of `keys` keys.
+fn first_choices<S>(strategy: &S, backends: &BTreeSet<Backend>, keys: usize) -> Vec<Backend>
+where
+    S: Strategy,
+    <S::BackendSelector as BackendSelection>::Iter: BackendIter,
+{
+    let selector = Arc::new(strategy.build_backend_selector(backends));
+    (0..keys)
+        .map(|key| {
+            let key = format!("key_{}", key);
+            let mut iter = selector.iter(key.as_bytes());
+            iter.next().expect("a backend").clone()
+        })
+        .collect()
+}
+
+/// Busiest backend's share of the keys over the mean share; 1.0 is a perfect balance.
+fn max_over_mean(choices: &[Backend], backend_count: usize) -> f64 {
+    let mut load = HashMap::new();
+    for backend in choices {
+        *load.entry(&backend.addr).or_insert(0usize) += 1;
+    }
+    let max = load.values().copied().max().unwrap_or(0);
+    max as f64 * backend_count as f64 / choices.len() as f64
+}
+
+/// Print the keys moved and the balance after a backend is removed from or added to a pool.
+fn report_rebalancing<S>(name: &str, strategy: &S, backend_count: usize, keys: usize)
+where
+    S: Strategy,
+    <S::BackendSelector as BackendSelection>::Iter: BackendIter,
+{
+    let backends = create_backends(strategy, backend_count);
+    let mut removed = backends.clone();
+    removed.pop_last();
+    let added = create_backends(strategy, backend_count + 1);
+
+    let before = first_choices(strategy, &backends, keys);
+    println!(
+        "{name}, {backend_count} backends: max/mean load {:.3}",
+        max_over_mean(&before, backends.len())
+    );
+    for (change, after) in [("removed", &removed), ("added", &added)] {
+        let choices = first_choices(strategy, after, keys);
+        let moved = before.iter().zip(&choices).filter(|(a, b)| a != b).count();
+        // only the keys of the backend that came or went need to move
+        let ideal = 100.0 / backends.len().max(after.len()) as f64;
+        println!(
+            "{name}, backend {change}: {:.2}% of keys moved (ideal {:.2}%), max/mean load {:.3}",
+            moved as f64 * 100.0 / keys as f64,
+            ideal,
+            max_over_mean(&choices, after.len())
+        );
+    }
+}
+
+fn benchmark_hash_rebalancing(c: &mut Criterion) {
+    let keys = 100_000;
+    for &count in &[10, 100] {
+        report_rebalancing("Consistent", &Consistent, count, keys);
+        report_rebalancing("Maglev", &Maglev, count, keys);
+        report_rebalancing("Rendezvous", &Rendezvous, count, keys);
+    }
+
+    // Time the selector rebuild on a backend change, Maglev filling its whole table
+    let mut group = c.benchmark_group("HashRebuild");
+    for &count in &[10, 100, 500] {
+        let backends = create_backends(&Consistent, count);
+        group.bench_with_input(BenchmarkId::new("Consistent", count), &count, |b, _| {
+            b.iter(|| black_box(Consistent.build_backend_selector(black_box(&backends))));
+        });
+        group.bench_with_input(BenchmarkId::new("Maglev", count), &count, |b, _| {
+            b.iter(|| black_box(Maglev.build_backend_selector(black_box(&backends))));
+        });
+        group.bench_with_input(BenchmarkId::new("Rendezvous", count), &count, |b, _| {
+            b.iter(|| black_box(Rendezvous.build_backend_selector(black_box(&backends))));
+        });
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     benchmark_all_strategies,
     benchmark_full_iteration,
     benchmark_concurrent_access,
-    benchmark_key_distribution
+    benchmark_key_distribution,
+    benchmark_hash_rebalancing
 );
 criterion_main!(benches);
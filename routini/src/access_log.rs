@@ -0,0 +1,631 @@
+//! Access log (nginx `access_log` / `log_format`): one line per completed request, in a named or
+//! custom format, written to the main log or to a dedicated, rotated file.
+//!
+//! The `default` format is the structured `routini::access` event of the main log. The others
+//! render a line: `combined` (nginx's default), `json`, or a template of nginx-style variables:
+//!
+//! | Variable | Value |
+//! |----------|-------|
+//! | `$remote_addr` | Client IP |
+//! | `$remote_user` | Always `-` |
+//! | `$time_local`, `$time_iso8601` | Completion time (UTC) |
+//! | `$request` | `GET /path?query HTTP/1.1` |
+//! | `$request_method`, `$request_uri`, `$server_protocol` | Parts of the request line |
+//! | `$uri`, `$args` | Path and query string of `$request_uri` |
+//! | `$host` | Request host |
+//! | `$status`, `$body_bytes_sent` | Response status and body size |
+//! | `$request_time`, `$upstream_response_time` | Seconds with millisecond resolution |
+//! | `$upstream_addr` | Backend that served the request |
+//! | `$request_id`, `$trace_id` | Request id and W3C trace id, when enabled |
+//! | `$route` | The route's metrics label (see [`crate::metrics`]) |
+//! | `$http_<name>` | Request header, lowercase with `_` for `-` (`$http_user_agent`) |
+//!
+//! Unset values are written as `-`. As in nginx, `"`, `\`, control characters and non-ASCII bytes
+//! in values are written as `\xHH`, so a client cannot forge fields or lines.
+use std::{
+    io::Write as _,
+    net::IpAddr,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use pingora::{Error, http::RequestHeader};
+use serde::Deserialize;
+use tracing_appender::{
+    non_blocking::{NonBlocking, WorkerGuard},
+    rolling::{RollingFileAppender, Rotation},
+};
+
+/// nginx's `combined` format.
+const COMBINED: &str = "$remote_addr - $remote_user [$time_local] \"$request\" $status \
+                        $body_bytes_sent \"$http_referer\" \"$http_user_agent\"";
+
+/// How access-log lines are rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessLogFormat {
+    /// The structured `routini::access` event; only meaningful in the main log.
+    Default,
+    Json,
+    /// `combined` or a custom template.
+    Template(Vec<Segment>),
+}
+
+/// A piece of an access-log template.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Literal(String),
+    Variable(Variable),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Variable {
+    RemoteAddr,
+    RemoteUser,
+    TimeLocal,
+    TimeIso8601,
+    Request,
+    RequestMethod,
+    RequestUri,
+    Uri,
+    Args,
+    ServerProtocol,
+    Host,
+    Status,
+    BodyBytesSent,
+    RequestTime,
+    UpstreamAddr,
+    UpstreamResponseTime,
+    RequestId,
+    TraceId,
+    Route,
+    /// A request header, by its lowercase name.
+    Header(String),
+}
+
+impl AccessLogFormat {
+    /// Parse a format name (`default`, `combined`, `json`) or a template.
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format {
+            "default" => Ok(Self::Default),
+            "json" => Ok(Self::Json),
+            "combined" => Self::template(COMBINED),
+            template => Self::template(template),
+        }
+    }
+
+    fn template(template: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('$') {
+            literal.push_str(&rest[..start]);
+            rest = &rest[start + 1..];
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let name = &rest[..end];
+            if name.is_empty() {
+                literal.push('$');
+                continue;
+            }
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(Segment::Variable(Variable::parse(name)?));
+            rest = &rest[end..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self::Template(segments))
+    }
+}
+
+impl Variable {
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "remote_addr" => Self::RemoteAddr,
+            "remote_user" => Self::RemoteUser,
+            "time_local" => Self::TimeLocal,
+            "time_iso8601" => Self::TimeIso8601,
+            "request" => Self::Request,
+            "request_method" => Self::RequestMethod,
+            "request_uri" => Self::RequestUri,
+            "uri" => Self::Uri,
+            "args" => Self::Args,
+            "server_protocol" => Self::ServerProtocol,
+            "host" => Self::Host,
+            "status" => Self::Status,
+            "body_bytes_sent" => Self::BodyBytesSent,
+            "request_time" => Self::RequestTime,
+            "upstream_addr" => Self::UpstreamAddr,
+            "upstream_response_time" => Self::UpstreamResponseTime,
+            "request_id" => Self::RequestId,
+            "trace_id" => Self::TraceId,
+            "route" => Self::Route,
+            name => match name.strip_prefix("http_") {
+                Some(header) if !header.is_empty() => Self::Header(header.replace('_', "-")),
+                _ => return Err(format!("Unknown access log variable '${name}'")),
+            },
+        })
+    }
+}
+
+/// Which completed requests are logged. With no condition set every request is; otherwise those
+/// meeting any condition.
+#[derive(Debug, Clone, Default)]
+pub struct AccessLogFilter {
+    /// Log requests answered with at least this status, or that failed outright.
+    pub min_status: Option<u16>,
+    /// Log requests that took at least this long.
+    pub slower_than: Option<Duration>,
+}
+
+impl AccessLogFilter {
+    fn matches(&self, entry: &AccessLogEntry) -> bool {
+        if self.min_status.is_none() && self.slower_than.is_none() {
+            return true;
+        }
+        let failed = self
+            .min_status
+            .is_some_and(|min| entry.error.is_some() || entry.status == 0 || entry.status >= min);
+        failed
+            || self
+                .slower_than
+                .is_some_and(|min| entry.request_time >= min)
+    }
+}
+
+/// Rotation of an access-log file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+/// Where access-log lines go.
+enum Sink {
+    /// The `routini::access` target of the main log.
+    MainLog,
+    /// A dedicated file; the guard flushes buffered lines when the log is dropped.
+    File {
+        writer: NonBlocking,
+        _guard: WorkerGuard,
+    },
+}
+
+/// The configured access log: format, filter and sink.
+pub struct AccessLog {
+    format: AccessLogFormat,
+    filter: AccessLogFilter,
+    sink: Sink,
+}
+
+impl Default for AccessLog {
+    /// The structured event in the main log, for every request.
+    fn default() -> Self {
+        Self {
+            format: AccessLogFormat::Default,
+            filter: AccessLogFilter::default(),
+            sink: Sink::MainLog,
+        }
+    }
+}
+
+impl AccessLog {
+    pub fn new(format: AccessLogFormat, filter: AccessLogFilter) -> Self {
+        Self {
+            format,
+            filter,
+            sink: Sink::MainLog,
+        }
+    }
+
+    /// Write to `path` instead of the main log, rotating it as `rotation` says (rotated files get
+    /// a date suffix) and keeping at most `max_files` of them. The `default` format is written
+    /// as `combined`.
+    pub fn with_file(
+        mut self,
+        path: &Path,
+        rotation: LogRotation,
+        max_files: Option<usize>,
+    ) -> Result<Self, String> {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+        let dir = dir.unwrap_or(Path::new("."));
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            return Err(format!("Invalid access log path '{}'", path.display()));
+        };
+        let rotation = match rotation {
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        };
+        let mut builder = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(file_name);
+        if let Some(max_files) = max_files {
+            builder = builder.max_log_files(max_files);
+        }
+        let appender = builder
+            .build(dir)
+            .map_err(|err| format!("Cannot open access log '{}': {err}", path.display()))?;
+        let (writer, guard) = tracing_appender::non_blocking(appender);
+
+        if self.format == AccessLogFormat::Default {
+            self.format = AccessLogFormat::template(COMBINED)?;
+        }
+        self.sink = Sink::File {
+            writer,
+            _guard: guard,
+        };
+        Ok(self)
+    }
+
+    /// Log `entry` if it passes the filter.
+    pub fn log(&self, entry: &AccessLogEntry) {
+        if !self.filter.matches(entry) {
+            return;
+        }
+        let line = match &self.format {
+            AccessLogFormat::Default => {
+                entry.emit_event();
+                return;
+            }
+            AccessLogFormat::Json => entry.json(),
+            AccessLogFormat::Template(segments) => entry.render(segments),
+        };
+        match &self.sink {
+            Sink::MainLog => tracing::info!(target: "routini::access", "{line}"),
+            Sink::File { writer, .. } => {
+                let mut writer = writer.clone();
+                let _ = writer.write_all(format!("{line}\n").as_bytes());
+            }
+        }
+    }
+}
+
+/// A completed request, as seen by the access log.
+pub struct AccessLogEntry<'a> {
+    pub req: &'a RequestHeader,
+    pub client: Option<IpAddr>,
+    /// The request's path and query as received, before any rewrite.
+    pub uri: &'a str,
+    /// 0 when no response was sent.
+    pub status: u16,
+    pub bytes_sent: usize,
+    pub request_time: Duration,
+    pub upstream: Option<String>,
+    pub upstream_response_time: Option<Duration>,
+    pub request_id: Option<&'a str>,
+    pub trace_id: Option<String>,
+    pub route: Option<&'a str>,
+    pub error: Option<&'a Error>,
+}
+
+impl AccessLogEntry<'_> {
+    fn path(&self) -> &str {
+        self.uri.split_once('?').map_or(self.uri, |(path, _)| path)
+    }
+
+    fn host(&self) -> Option<&str> {
+        self.req.uri.host().or_else(|| {
+            self.req
+                .headers
+                .get(http::header::HOST)
+                .and_then(|h| h.to_str().ok())
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.req.headers.get(name).and_then(|h| h.to_str().ok())
+    }
+
+    fn protocol(&self) -> String {
+        format!("{:?}", self.req.version)
+    }
+
+    /// The structured event of the `default` format.
+    fn emit_event(&self) {
+        let client = self.client.map(|ip| ip.to_string()).unwrap_or_default();
+        let method = self.req.method.as_str();
+        let path = self.path();
+        let status = self.status;
+        let latency_ms = self.request_time.as_millis();
+        let bytes_sent = self.bytes_sent;
+        let upstream = self.upstream.as_deref().unwrap_or_default();
+        let request_id = self.request_id.unwrap_or_default();
+        let trace_id = self.trace_id.as_deref().unwrap_or_default();
+        if let Some(err) = self.error {
+            tracing::warn!(
+                target: "routini::access",
+                client, method, path, status, latency_ms, bytes_sent, upstream, request_id,
+                trace_id,
+                error = %err,
+                "request failed"
+            );
+        } else {
+            tracing::info!(
+                target: "routini::access",
+                client, method, path, status, latency_ms, bytes_sent, upstream, request_id,
+                trace_id,
+                "request"
+            );
+        }
+    }
+
+    fn json(&self) -> String {
+        let mut line = serde_json::json!({
+            "time": iso8601(SystemTime::now()),
+            "client": self.client.map(|ip| ip.to_string()),
+            "method": self.req.method.as_str(),
+            "uri": self.uri,
+            "protocol": self.protocol(),
+            "host": self.host(),
+            "status": self.status,
+            "bytes_sent": self.bytes_sent,
+            "request_time": self.request_time.as_secs_f64(),
+            "upstream": self.upstream,
+            "upstream_response_time": self.upstream_response_time.map(|t| t.as_secs_f64()),
+            "request_id": self.request_id,
+            "trace_id": self.trace_id,
+            "route": self.route,
+            "referer": self.header("referer"),
+            "user_agent": self.header("user-agent"),
+        });
+        if let Some(err) = self.error {
+            line["error"] = err.to_string().into();
+        }
+        line.to_string()
+    }
+
+    fn render(&self, segments: &[Segment]) -> String {
+        let mut line = String::new();
+        for segment in segments {
+            let variable = match segment {
+                Segment::Literal(literal) => {
+                    line.push_str(literal);
+                    continue;
+                }
+                Segment::Variable(variable) => variable,
+            };
+            let value = match variable {
+                Variable::RemoteAddr => self.client.map(|ip| ip.to_string()),
+                Variable::RemoteUser => None,
+                Variable::TimeLocal => Some(time_local(SystemTime::now())),
+                Variable::TimeIso8601 => Some(iso8601(SystemTime::now())),
+                Variable::Request => Some(format!(
+                    "{} {} {}",
+                    self.req.method,
+                    self.uri,
+                    self.protocol()
+                )),
+                Variable::RequestMethod => Some(self.req.method.to_string()),
+                Variable::RequestUri => Some(self.uri.to_string()),
+                Variable::Uri => Some(self.path().to_string()),
+                Variable::Args => self.uri.split_once('?').map(|(_, args)| args.to_string()),
+                Variable::ServerProtocol => Some(self.protocol()),
+                Variable::Host => self.host().map(str::to_string),
+                Variable::Status => Some(self.status.to_string()),
+                Variable::BodyBytesSent => Some(self.bytes_sent.to_string()),
+                Variable::RequestTime => Some(seconds(self.request_time)),
+                Variable::UpstreamAddr => self.upstream.clone(),
+                Variable::UpstreamResponseTime => self.upstream_response_time.map(seconds),
+                Variable::RequestId => self.request_id.map(str::to_string),
+                Variable::TraceId => self.trace_id.clone(),
+                Variable::Route => self.route.map(str::to_string),
+                Variable::Header(name) => self.header(name).map(str::to_string),
+            };
+            match value {
+                Some(value) if !value.is_empty() => push_escaped(&mut line, &value),
+                _ => line.push('-'),
+            }
+        }
+        line
+    }
+}
+
+/// Append `value` to `line` escaped as nginx does: `"`, `\`, control characters and bytes past
+/// ASCII become `\xHH`.
+fn push_escaped(line: &mut String, value: &str) {
+    for &byte in value.as_bytes() {
+        if byte == b'"' || byte == b'\\' || !(0x20..0x7f).contains(&byte) {
+            line.push_str(&format!("\\x{byte:02X}"));
+        } else {
+            line.push(byte as char);
+        }
+    }
+}
+
+/// Seconds with millisecond resolution, as nginx writes `$request_time`.
+fn seconds(duration: Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64())
+}
+
+/// `10/Oct/2000:13:55:36 +0000`
+fn time_local(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (year, month, day, secs) = civil(time);
+    format!(
+        "{day:02}/{}/{year}:{:02}:{:02}:{:02} +0000",
+        MONTHS[month as usize - 1],
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// `2000-10-10T13:55:36+00:00`
+fn iso8601(time: SystemTime) -> String {
+    let (year, month, day, secs) = civil(time);
+    format!(
+        "{year}-{month:02}-{day:02}T{:02}:{:02}:{:02}+00:00",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// UTC `(year, month, day, seconds into the day)` of `time` (Howard Hinnant's `civil_from_days`).
+fn civil(time: SystemTime) -> (i64, u32, u32, u64) {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day, secs % 86_400)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(req: &RequestHeader) -> AccessLogEntry<'_> {
+        AccessLogEntry {
+            req,
+            client: Some("10.0.0.1".parse().unwrap()),
+            uri: "/api/users?page=2",
+            status: 200,
+            bytes_sent: 512,
+            request_time: Duration::from_millis(1234),
+            upstream: Some("127.0.0.1:8080".to_string()),
+            upstream_response_time: Some(Duration::from_millis(1200)),
+            request_id: Some("abc123"),
+            trace_id: None,
+            route: Some("/api/*"),
+            error: None,
+        }
+    }
+
+    fn request() -> RequestHeader {
+        let mut req = RequestHeader::build("GET", b"/users?page=2", None).unwrap();
+        req.insert_header("user-agent", "curl/8.0").unwrap();
+        req.insert_header("host", "example.com").unwrap();
+        req
+    }
+
+    fn render(format: &str, entry: &AccessLogEntry) -> String {
+        match AccessLogFormat::parse(format).unwrap() {
+            AccessLogFormat::Template(segments) => entry.render(&segments),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_template_variables() {
+        let req = request();
+        let line = render(
+            "$remote_addr \"$request\" $status $upstream_response_time $request_id \
+             $http_user_agent $http_referer $trace_id $$ $args",
+            &entry(&req),
+        );
+        assert_eq!(
+            line,
+            "10.0.0.1 \"GET /api/users?page=2 HTTP/1.1\" 200 1.200 abc123 curl/8.0 - - $$ page=2"
+        );
+    }
+
+    #[test]
+    fn test_combined_format() {
+        let req = request();
+        let line = render("combined", &entry(&req));
+        assert!(line.starts_with("10.0.0.1 - - ["));
+        assert!(line.ends_with("] \"GET /api/users?page=2 HTTP/1.1\" 200 512 \"-\" \"curl/8.0\""));
+    }
+
+    #[test]
+    fn test_values_are_escaped() {
+        let mut req = request();
+        req.insert_header("user-agent", "x\" 200 \"evil\\").unwrap();
+        let mut entry = entry(&req);
+        entry.uri = "/a\n10.0.0.9 - - \"GET /é";
+        let line = render("combined", &entry);
+        assert!(!line.contains('\n'));
+        assert!(line.contains("\"GET /a\\x0A10.0.0.9 - - \\x22GET /\\xC3\\xA9 HTTP/1.1\""));
+        assert!(line.ends_with("\"x\\x22 200 \\x22evil\\x5C\""));
+    }
+
+    #[test]
+    fn test_unknown_variable() {
+        let err = AccessLogFormat::parse("$status $nope").unwrap_err();
+        assert!(err.contains("$nope"));
+        assert!(AccessLogFormat::parse("$http_").is_err());
+    }
+
+    #[test]
+    fn test_json_format() {
+        let req = request();
+        let line: serde_json::Value = serde_json::from_str(&entry(&req).json()).unwrap();
+        assert_eq!(line["status"], 200);
+        assert_eq!(line["host"], "example.com");
+        assert_eq!(line["upstream"], "127.0.0.1:8080");
+        assert_eq!(line["trace_id"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_filter() {
+        let req = request();
+        let mut entry = entry(&req);
+        assert!(AccessLogFilter::default().matches(&entry));
+
+        let errors = AccessLogFilter {
+            min_status: Some(500),
+            slower_than: None,
+        };
+        assert!(!errors.matches(&entry));
+        entry.status = 502;
+        assert!(errors.matches(&entry));
+
+        let slow = AccessLogFilter {
+            min_status: None,
+            slower_than: Some(Duration::from_secs(1)),
+        };
+        assert!(slow.matches(&entry));
+        entry.request_time = Duration::from_millis(10);
+        assert!(!slow.matches(&entry));
+    }
+
+    #[test]
+    fn test_filter_counts_errors_only_with_min_status() {
+        let req = request();
+        let error = Error::new(pingora::ErrorType::ConnectRefused);
+        let mut entry = entry(&req);
+        entry.request_time = Duration::from_millis(10);
+        entry.error = Some(&error);
+
+        let slow = AccessLogFilter {
+            min_status: None,
+            slower_than: Some(Duration::from_secs(1)),
+        };
+        assert!(!slow.matches(&entry));
+
+        let errors = AccessLogFilter {
+            min_status: Some(500),
+            slower_than: None,
+        };
+        assert!(errors.matches(&entry));
+    }
+
+    #[test]
+    fn test_time_formats() {
+        let time = UNIX_EPOCH + Duration::from_secs(971_186_136);
+        assert_eq!(time_local(time), "10/Oct/2000:13:55:36 +0000");
+        assert_eq!(iso8601(time), "2000-10-10T13:55:36+00:00");
+        let leap = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(iso8601(leap), "2000-02-29T00:00:00+00:00");
+    }
+}
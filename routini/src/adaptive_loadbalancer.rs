@@ -1,19 +1,31 @@
 pub mod background_service;
+pub mod circuit_breaker;
 pub mod decision_engine;
+pub mod failure_rate;
+pub mod health_check;
 pub mod options;
+pub mod outlier_detection;
+pub mod slow_start;
 
-use std::{collections::BTreeSet, sync::Arc};
+use std::{collections::BTreeSet, sync::Arc, time::Instant};
+
+use arc_swap::ArcSwap;
+use pingora::protocols::l4::socket::SocketAddr;
 
 use crate::{
     adaptive_loadbalancer::{
+        circuit_breaker::CircuitState,
         decision_engine::DecisionEngine,
+        health_check::{HealthCheckKind, HealthCheckOpt},
         options::{AdaptiveLbConfig, AdaptiveLbOpt},
+        outlier_detection::OutlierDetector,
+        slow_start::{SlowStartConfig, WarmupOnRecovery},
     },
     load_balancing::{
-        Backend, Backends, LoadBalancer,
-        health_check::TcpHealthCheck,
+        Backend, Backends, Discovered, LoadBalancer,
         strategy::{Adaptive, adaptive::AdaptiveStrategyMetrics},
     },
+    metrics::{CIRCUIT_TRANSITIONS, OUTLIER_EJECTIONS, PoolLabels},
 };
 
 /// The metrics type the adaptive load balancer pins its backends to.
@@ -25,6 +37,16 @@ pub struct AdaptiveLoadBalancer<D> {
     lb: LoadBalancer<Adaptive, AdaptiveStrategyMetrics>,
     decision_engine: D,
     pub config: AdaptiveLbConfig,
+    /// Backends being drained (nginx `drain`): skipped by selection, but still serving requests
+    /// pinned to them by a sticky cookie.
+    draining: ArcSwap<Vec<SocketAddr>>,
+    /// Weights set through [`AdaptiveLoadBalancer::set_weight`], by address, which override the
+    /// configured ones when the upstreams are reloaded.
+    weights: ArcSwap<Vec<(SocketAddr, usize)>>,
+    /// Backends ejected as outliers, skipped by selection.
+    outliers: OutlierDetector,
+    /// The route and pool this load balancer serves, as labelled in its metrics.
+    pub labels: PoolLabels,
 }
 
 impl<D: DecisionEngine> AdaptiveLoadBalancer<D> {
@@ -38,8 +60,18 @@ impl<D: DecisionEngine> AdaptiveLoadBalancer<D> {
             LoadBalancer::from_backends_with_strategy(backends, options.starting_strategy.clone());
 
         if options.health_check_interval.is_some() {
-            let hc = TcpHealthCheck::new();
-            lb.set_health_check(hc);
+            // The config rejects invalid checks up front; options built by hand fall back to a
+            // TCP connect rather than going unchecked.
+            let check = options.health_check.build().unwrap_or_else(|err| {
+                tracing::error!("Invalid health check, probing with a TCP connect instead: {err}");
+                HealthCheckOpt {
+                    kind: HealthCheckKind::Tcp,
+                    ..options.health_check.clone()
+                }
+                .build()
+                .expect("a TCP check always builds")
+            });
+            lb.set_health_check(Box::new(WarmupOnRecovery(check)));
             lb.health_check_frequency = options.health_check_interval.clone()
         }
 
@@ -47,34 +79,397 @@ impl<D: DecisionEngine> AdaptiveLoadBalancer<D> {
             lb,
             decision_engine,
             config: AdaptiveLbConfig::from(options),
+            draining: ArcSwap::default(),
+            weights: ArcSwap::default(),
+            outliers: OutlierDetector::default(),
+            labels: PoolLabels::default(),
         }
     }
 
+    pub fn with_labels(mut self, labels: PoolLabels) -> Self {
+        self.labels = labels;
+        self
+    }
+
     pub fn backends(&self) -> Arc<BTreeSet<AdaptiveBackend>> {
         self.lb.backends().get_backend()
     }
 
     pub fn select(&self, key: &[u8]) -> Option<AdaptiveBackend> {
-        self.lb.select(key, self.config.max_iterations)
+        self.select_excluding(key, &[], None)
     }
 
     /// Select a healthy backend whose address is not in `exclude`. Used for per-request failover
-    /// so a retry lands on a different backend than the one that just failed.
+    /// so a retry lands on a different backend than the one that just failed. `key` is the
+    /// request's hash key, only consulted by the hashing strategies. Backends warming up under
+    /// `slow_start` only take their current share of requests, unless no other backend can.
     pub fn select_excluding(
         &self,
-        exclude: &[pingora::protocols::l4::socket::SocketAddr],
+        key: &[u8],
+        exclude: &[SocketAddr],
+        slow_start: Option<&SlowStartConfig>,
+    ) -> Option<AdaptiveBackend> {
+        let Some(slow_start) = slow_start else {
+            return self.select_where(key, exclude, |_| true);
+        };
+        let now = Instant::now();
+        self.select_where(key, exclude, |backend| slow_start.admits(backend, now))
+            .or_else(|| self.select_where(key, exclude, |_| true))
+    }
+
+    fn select_where(
+        &self,
+        key: &[u8],
+        exclude: &[SocketAddr],
+        admit: impl Fn(&AdaptiveBackend) -> bool,
+    ) -> Option<AdaptiveBackend> {
+        let draining = self.draining.load();
+        self.lb
+            .select_with(key, self.config.max_iterations, |backend, healthy| {
+                healthy
+                    && !exclude.contains(&backend.addr)
+                    && !draining.contains(&backend.addr)
+                    && !self.outliers.is_ejected(&backend.addr)
+                    && admit(backend)
+                    && self.circuit_allows(backend)
+            })
+    }
+
+    /// The backend at `addr`, if it is not in `exclude`, is currently ready (healthy, enabled and
+    /// not an ejected outlier) and its circuit lets the request through. Used to honour
+    /// sticky-session cookies without going through the selection strategy.
+    pub fn ready_backend(
+        &self,
+        addr: &SocketAddr,
+        exclude: &[SocketAddr],
     ) -> Option<AdaptiveBackend> {
-        self.lb.select_with(&[], self.config.max_iterations, |backend, healthy| {
-            healthy && !exclude.iter().any(|addr| addr == &backend.addr)
-        })
+        if exclude.contains(addr) {
+            return None;
+        }
+        let backends = self.lb.backends();
+        backends
+            .get_backend()
+            .iter()
+            .find(|backend| backend.addr == *addr)
+            .filter(|backend| {
+                backends.ready(backend)
+                    && !self.outliers.is_ejected(&backend.addr)
+                    && self.circuit_allows(backend)
+            })
+            .cloned()
+    }
+
+    /// Whether `backend`'s circuit breaker, if any, lets a request through, taking one of its
+    /// probe slots when half-open.
+    fn circuit_allows(&self, backend: &AdaptiveBackend) -> bool {
+        let Some(opt) = &self.config.circuit_breaker else {
+            return true;
+        };
+        let (allowed, changed) = backend.metrics.circuit().try_acquire(opt, Instant::now());
+        if let Some(state) = changed {
+            self.circuit_changed(backend, state);
+        }
+        allowed
+    }
+
+    /// Feed a response from `backend` to its circuit breaker and outlier detection, each counting
+    /// it as a failure when its status is one of their failure statuses.
+    pub fn record_response(&self, backend: &AdaptiveBackend, status: u16) {
+        let failed = self
+            .config
+            .circuit_breaker
+            .as_ref()
+            .is_some_and(|opt| opt.failure_statuses.contains(&status));
+        self.record_circuit(backend, !failed);
+        if let Some(opt) = &self.config.outlier_detection {
+            let failed = opt.failure_statuses.contains(&status);
+            backend.metrics.outcomes().record(!failed);
+        }
+    }
+
+    /// Feed a failed connection or upstream error to `backend`'s circuit breaker and outlier
+    /// detection.
+    pub fn record_failure(&self, backend: &AdaptiveBackend) {
+        self.record_circuit(backend, false);
+        if self.config.outlier_detection.is_some() {
+            backend.metrics.outcomes().record(false);
+        }
+    }
+
+    /// Put back the backends whose outlier ejection ended and eject the pool's current outliers.
+    /// Run by the background service every outlier detection interval.
+    pub fn detect_outliers(&self) {
+        let Some(opt) = &self.config.outlier_detection else {
+            return;
+        };
+        let report = self.outliers.analyze(opt, &self.backends(), Instant::now());
+        let (route, pool) = (self.labels.route.as_str(), self.labels.pool.as_str());
+        for addr in report.returned {
+            log::info!("Outlier backend {addr} returned to rotation");
+        }
+        for ejection in report.ejected {
+            let addr = ejection.addr.to_string();
+            let reason = ejection.reason.as_str();
+            log::warn!(
+                "Ejected outlier backend {addr} ({reason}) for {}s",
+                ejection.duration.as_secs()
+            );
+            OUTLIER_EJECTIONS
+                .with_label_values(&[route, pool, &addr, reason])
+                .inc();
+        }
+    }
+
+    /// Whether `backend` is currently ejected as an outlier.
+    pub fn is_outlier(&self, backend: &AdaptiveBackend) -> bool {
+        self.outliers.is_ejected(&backend.addr)
+    }
+
+    fn record_circuit(&self, backend: &AdaptiveBackend, success: bool) {
+        let Some(opt) = &self.config.circuit_breaker else {
+            return;
+        };
+        let circuit = backend.metrics.circuit();
+        if let Some(state) = circuit.record(opt, success, Instant::now()) {
+            self.circuit_changed(backend, state);
+        }
+    }
+
+    fn circuit_changed(&self, backend: &AdaptiveBackend, state: CircuitState) {
+        let addr = backend.addr.to_string();
+        match state {
+            CircuitState::Open => log::warn!("Circuit breaker of backend {addr} opened"),
+            _ => log::info!("Circuit breaker of backend {addr} is {state}"),
+        }
+        let (route, pool) = (self.labels.route.as_str(), self.labels.pool.as_str());
+        CIRCUIT_TRANSITIONS
+            .with_label_values(&[route, pool, &addr, state.as_str()])
+            .inc();
+    }
+
+    /// The backend listening on `addr` (as in `127.0.0.1:8080`), ready or not.
+    pub fn find_backend(&self, addr: &str) -> Option<AdaptiveBackend> {
+        self.backends()
+            .iter()
+            .find(|backend| backend.addr.to_string() == addr)
+            .cloned()
+    }
+
+    /// Whether `backend` passes its health checks.
+    pub fn is_healthy(&self, backend: &AdaptiveBackend) -> bool {
+        self.lb.backends().healthy(backend)
+    }
+
+    /// Whether `backend` is enabled, i.e. neither disabled nor passively ejected.
+    pub fn is_enabled(&self, backend: &AdaptiveBackend) -> bool {
+        self.lb.backends().enabled(backend)
     }
 
-    /// Manually enable/disable a backend (used by passive health checks to eject/restore).
+    pub fn is_draining(&self, backend: &AdaptiveBackend) -> bool {
+        self.draining.load().contains(&backend.addr)
+    }
+
+    /// Manually enable/disable a backend (used by passive health checks to eject/restore). A
+    /// backend enabled again warms up as under slow start.
     pub fn set_backend_enabled(&self, backend: &AdaptiveBackend, enabled: bool) {
+        if enabled && !self.is_enabled(backend) {
+            backend.metrics.warmup().begin(Instant::now());
+        }
         self.lb.backends().set_enable(backend, enabled);
     }
 
+    /// Start or stop draining `backend`: while draining it only serves sticky-pinned requests.
+    pub fn set_draining(&self, backend: &AdaptiveBackend, draining: bool) {
+        self.draining.rcu(|current| {
+            let mut next: Vec<SocketAddr> = current
+                .iter()
+                .filter(|addr| **addr != backend.addr)
+                .cloned()
+                .collect();
+            if draining {
+                next.push(backend.addr.clone());
+            }
+            next
+        });
+    }
+
+    /// Change the weight of `backend`, keeping the other backends as they are. It keeps its
+    /// metrics, health and enablement, and, like draining, the weight outlives reloads.
+    pub async fn set_weight(&self, backend: &AdaptiveBackend, weight: usize) {
+        let weight = weight.max(1);
+        self.weights.rcu(|current| {
+            let mut next: Vec<(SocketAddr, usize)> = current
+                .iter()
+                .filter(|(addr, _)| *addr != backend.addr)
+                .cloned()
+                .collect();
+            next.push((backend.addr.clone(), weight));
+            next
+        });
+        let mut backends = BTreeSet::clone(&self.backends());
+        if backends.remove(backend) {
+            let mut reweighted = backend.clone();
+            reweighted.weight = weight;
+            backends.insert(reweighted);
+            self.lb.set_backends(backends).await;
+        }
+    }
+
+    pub async fn current_strategy(&self) -> Adaptive {
+        self.lb.current_strategy().await
+    }
+
+    /// Swap in `discovered` backends (e.g. reloaded upstreams, see [`Backends::discover`]),
+    /// keeping the metrics and health of backends that remain and the weights set on them.
+    pub async fn update_backends(&self, discovered: Discovered<AdaptiveStrategyMetrics>) {
+        let (backends, mut enablement) = discovered;
+        let weights = self.weights.load();
+        let backends = backends
+            .into_iter()
+            .map(|mut backend| {
+                let Some((_, weight)) = weights.iter().find(|(addr, _)| *addr == backend.addr)
+                else {
+                    return backend;
+                };
+                // enablement is keyed by the backend's hash, which covers its weight
+                let enabled = enablement.remove(&backend.hash_key());
+                backend.weight = *weight;
+                if let Some(enabled) = enabled {
+                    enablement.insert(backend.hash_key(), enabled);
+                }
+                backend
+            })
+            .collect();
+        let previous = self.backends();
+        self.lb.replace_backends((backends, enablement)).await;
+        self.warm_up_new_backends(&previous);
+    }
+
+    /// Start the warm-up of the backends whose address was not among `previous`: one whose
+    /// weight changed is already warm. As in nginx, the backends a load balancer starts with (on
+    /// startup, or when a reload rebuilds it) do not warm up, so there is a pool to ramp up into.
+    fn warm_up_new_backends(&self, previous: &BTreeSet<AdaptiveBackend>) {
+        if previous.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        for backend in self.backends().iter() {
+            if !previous.iter().any(|known| known.addr == backend.addr) {
+                backend.metrics.warmup().begin(now);
+            }
+        }
+    }
+
     pub async fn update_strategy(&self, new_strategy: Adaptive) -> bool {
         self.lb.update_strategy(new_strategy).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        adaptive_loadbalancer::{
+            decision_engine::AdaptiveDecisionEngine, outlier_detection::OutlierDetectionOpt,
+        },
+        load_balancing::{Metrics, discovery::Static},
+    };
+
+    use super::*;
+
+    fn upstreams(addrs: &[&str]) -> AdaptiveBackends {
+        let backends = addrs
+            .iter()
+            .map(|addr| Backend::build(addr, 1).unwrap())
+            .collect();
+        Backends::new(Static::new(backends))
+    }
+
+    fn weighted_upstreams(addrs: &[(&str, usize)]) -> AdaptiveBackends {
+        let backends = addrs
+            .iter()
+            .map(|(addr, weight)| Backend::build(addr, *weight).unwrap())
+            .collect();
+        Backends::new(Static::new(backends))
+    }
+
+    async fn reload(lb: &AdaptiveLoadBalancer<AdaptiveDecisionEngine>, addrs: &[(&str, usize)]) {
+        lb.update_backends(weighted_upstreams(addrs).discover().await.unwrap())
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_reweighted_backend_keeps_its_state() {
+        let options = AdaptiveLbOpt::default();
+        let engine = AdaptiveDecisionEngine::new(&options);
+        let addrs = ["127.0.0.1:8080", "127.0.0.1:8081"];
+        let lb = AdaptiveLoadBalancer::from_backends(upstreams(&addrs), Some(options), engine);
+        lb.update_backends(upstreams(&addrs).discover().await.unwrap())
+            .await;
+
+        let backend = lb.find_backend("127.0.0.1:8080").unwrap();
+        lb.set_backend_enabled(&backend, false);
+        backend.metrics.increment_active_connections();
+        lb.set_weight(&backend, 5).await;
+
+        let reweighted = lb.find_backend("127.0.0.1:8080").unwrap();
+        assert_eq!(reweighted.weight, 5);
+        assert!(!lb.is_enabled(&reweighted));
+        assert_eq!(reweighted.metrics.active_connections(), Some(1));
+        for _ in 0..20 {
+            assert_eq!(lb.select(&[]).unwrap().addr.to_string(), "127.0.0.1:8081");
+        }
+
+        // reloading the same upstreams keeps the weight, and the backend disabled
+        lb.update_backends(upstreams(&addrs).discover().await.unwrap())
+            .await;
+        let reloaded = lb.find_backend("127.0.0.1:8080").unwrap();
+        assert_eq!(reloaded.weight, 5);
+        assert!(!lb.is_enabled(&reloaded));
+    }
+
+    #[tokio::test]
+    async fn test_outlier_detection_counts_its_failure_statuses() {
+        let addrs = ["127.0.0.1:8080"];
+        let options = AdaptiveLbOpt {
+            outlier_detection: Some(OutlierDetectionOpt {
+                failure_statuses: vec![503],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let engine = AdaptiveDecisionEngine::new(&options);
+        let lb = AdaptiveLoadBalancer::from_backends(upstreams(&addrs), Some(options), engine);
+        reload(&lb, &addrs).await;
+
+        let backend = lb.find_backend("127.0.0.1:8080").unwrap();
+        lb.record_response(&backend, 200);
+        lb.record_response(&backend, 500);
+        lb.record_response(&backend, 503);
+        lb.record_failure(&backend);
+        assert_eq!(backend.metrics.outcomes().take(), (4, 2));
+    }
+
+    #[tokio::test]
+    async fn test_only_new_addresses_warm_up() {
+        let options = AdaptiveLbOpt::default();
+        let engine = AdaptiveDecisionEngine::new(&options);
+        let addrs = [("127.0.0.1:8080", 1)];
+        let lb =
+            AdaptiveLoadBalancer::from_backends(weighted_upstreams(&addrs), Some(options), engine);
+        reload(&lb, &addrs).await;
+        let warming = |addr| {
+            let backend = lb.find_backend(addr).unwrap();
+            backend.metrics.warmup().elapsed(Instant::now()).is_some()
+        };
+        // the backends the pool starts with take their full share at once
+        assert!(!warming("127.0.0.1:8080"));
+
+        // a weight changed by a reload or the admin API doesn't start a warm-up either
+        reload(&lb, &[("127.0.0.1:8080", 3), ("127.0.0.1:8081", 1)]).await;
+        let backend = lb.find_backend("127.0.0.1:8080").unwrap();
+        lb.set_weight(&backend, 2).await;
+
+        assert!(!warming("127.0.0.1:8080"));
+        assert!(warming("127.0.0.1:8081"));
+    }
+}
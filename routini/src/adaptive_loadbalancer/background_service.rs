@@ -5,9 +5,12 @@ use log::error;
 use pingora::{server::ShutdownWatch, services::background::BackgroundService};
 use tokio::time::Instant;
 
-use crate::adaptive_loadbalancer::{
-    AdaptiveLoadBalancer,
-    decision_engine::{AdaptiveDecisionEngine, DecisionEngine},
+use crate::{
+    adaptive_loadbalancer::{
+        AdaptiveLoadBalancer,
+        decision_engine::{AdaptiveDecisionEngine, DecisionEngine},
+    },
+    metrics::STRATEGY_SWITCHES,
 };
 
 #[async_trait]
@@ -20,6 +23,9 @@ impl BackgroundService for AdaptiveLoadBalancer<AdaptiveDecisionEngine> {
         let mut next_update = now;
         let mut next_health_check = now;
         let mut next_strategy_eval = now;
+        // outliers stand out only once the backends have served an interval of requests
+        let outlier_interval = self.config.outlier_detection.as_ref().map(|o| o.interval);
+        let mut next_outlier_detection = now + outlier_interval.unwrap_or(NEVER);
         let mut selector_rebuild = now + self.lb.rebuild_frequency().await.unwrap_or(NEVER);
 
         loop {
@@ -49,13 +55,27 @@ impl BackgroundService for AdaptiveLoadBalancer<AdaptiveDecisionEngine> {
                     .decision_engine
                     .evaluate_strategy(&current_strategy, &backends);
 
+                let to = strategy.to_string();
                 let was_updated = self.lb.update_strategy(strategy).await;
                 next_strategy_eval = now + self.decision_engine.evaluate_strategy_frequency;
                 if was_updated {
+                    STRATEGY_SWITCHES
+                        .with_label_values(&[
+                            self.labels.route.as_str(),
+                            &self.labels.pool,
+                            &current_strategy.to_string(),
+                            &to,
+                        ])
+                        .inc();
                     selector_rebuild = now + self.lb.rebuild_frequency().await.unwrap_or(NEVER);
                 }
             }
 
+            if next_outlier_detection <= now {
+                self.detect_outliers();
+                next_outlier_detection = now + outlier_interval.unwrap_or(NEVER);
+            }
+
             if selector_rebuild <= now {
                 self.lb.rebuild_selector().await;
                 selector_rebuild = now + self.lb.rebuild_frequency().await.unwrap_or(NEVER);
@@ -65,6 +85,7 @@ impl BackgroundService for AdaptiveLoadBalancer<AdaptiveDecisionEngine> {
                 next_update,
                 next_health_check,
                 next_strategy_eval,
+                next_outlier_detection,
                 selector_rebuild,
             ]
             .iter()
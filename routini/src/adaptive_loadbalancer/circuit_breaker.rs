@@ -0,0 +1,313 @@
+//! A circuit breaker per backend. Closed, it lets every request through and counts failures; past
+//! a threshold it opens and turns the backend's requests away for a cooldown, then goes half-open
+//! and lets a few probe requests through, closing once they all succeed and reopening on a failure.
+use std::{
+    fmt::Display,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    adaptive_loadbalancer::failure_rate::FailureRateWindow,
+    utils::constants::{
+        DEFAULT_CIRCUIT_BREAKER_COOLDOWN, DEFAULT_CIRCUIT_BREAKER_FAILURES,
+        DEFAULT_CIRCUIT_BREAKER_MIN_REQUESTS, DEFAULT_CIRCUIT_BREAKER_WINDOW,
+    },
+};
+
+/// When a backend's circuit opens and how it recovers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CircuitBreakerOpt {
+    /// Consecutive failures opening the circuit.
+    pub consecutive_failures: u32,
+    /// Also open once this fraction of the requests within the sliding `window` failed.
+    pub failure_rate: Option<f64>,
+    pub window: Duration,
+    /// Requests within the window before the failure rate counts.
+    pub min_requests: u32,
+    /// How long an open circuit turns requests away before letting probes through.
+    pub cooldown: Duration,
+    /// Probe requests let through while half-open; all of them must succeed to close.
+    pub half_open_probes: u32,
+    /// Upstream response statuses counted as failures, besides connection and read errors.
+    pub failure_statuses: Vec<u16>,
+}
+
+impl Default for CircuitBreakerOpt {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: DEFAULT_CIRCUIT_BREAKER_FAILURES,
+            failure_rate: None,
+            window: DEFAULT_CIRCUIT_BREAKER_WINDOW,
+            min_requests: DEFAULT_CIRCUIT_BREAKER_MIN_REQUESTS,
+            cooldown: DEFAULT_CIRCUIT_BREAKER_COOLDOWN,
+            half_open_probes: 1,
+            failure_statuses: vec![502, 503, 504],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+
+    /// The state as reported by the `routini_backend_circuit_state` gauge.
+    pub fn value(&self) -> i64 {
+        match self {
+            CircuitState::Closed => 0,
+            CircuitState::Open => 1,
+            CircuitState::HalfOpen => 2,
+        }
+    }
+}
+
+impl Display for CircuitState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A backend's circuit. Clones share the same state, as with the other backend metrics.
+#[derive(Clone, Debug, Default)]
+pub struct CircuitBreaker(Arc<Mutex<Breaker>>);
+
+#[derive(Debug)]
+struct Breaker {
+    state: CircuitState,
+    /// When the current state was entered.
+    since: Instant,
+    consecutive_failures: u32,
+    /// Outcomes within the failure-rate window, while closed.
+    outcomes: FailureRateWindow,
+    probes_sent: u32,
+    probes_passed: u32,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Self::new(CircuitState::Closed, Instant::now())
+    }
+}
+
+impl Breaker {
+    fn new(state: CircuitState, now: Instant) -> Self {
+        Self {
+            state,
+            since: now,
+            consecutive_failures: 0,
+            outcomes: FailureRateWindow::new(now),
+            probes_sent: 0,
+            probes_passed: 0,
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn state(&self) -> CircuitState {
+        self.0.lock().unwrap().state
+    }
+
+    /// Whether a request may be sent to the backend at `now`, taking a probe slot when half-open,
+    /// and the state the circuit moved to, if any.
+    pub fn try_acquire(
+        &self,
+        opt: &CircuitBreakerOpt,
+        now: Instant,
+    ) -> (bool, Option<CircuitState>) {
+        let mut breaker = self.0.lock().unwrap();
+        let mut changed = None;
+        match breaker.state {
+            CircuitState::Closed => return (true, None),
+            CircuitState::Open => {
+                if now.duration_since(breaker.since) < opt.cooldown {
+                    return (false, None);
+                }
+                *breaker = Breaker::new(CircuitState::HalfOpen, now);
+                changed = Some(CircuitState::HalfOpen);
+            }
+            CircuitState::HalfOpen => {
+                // Probes whose outcome never came (e.g. the client went away) give their slots
+                // back after another cooldown, so the circuit cannot stay half-open for good.
+                if now.duration_since(breaker.since) >= opt.cooldown {
+                    breaker.since = now;
+                    breaker.probes_sent = breaker.probes_passed;
+                }
+            }
+        }
+        let allowed = breaker.probes_sent < opt.half_open_probes;
+        if allowed {
+            breaker.probes_sent += 1;
+        }
+        (allowed, changed)
+    }
+
+    /// Record the outcome of a request sent at or before `now`, returning the state the circuit
+    /// moved to, if any.
+    pub fn record(
+        &self,
+        opt: &CircuitBreakerOpt,
+        success: bool,
+        now: Instant,
+    ) -> Option<CircuitState> {
+        let mut breaker = self.0.lock().unwrap();
+        let next = match breaker.state {
+            // A request sent before the circuit opened.
+            CircuitState::Open => return None,
+            CircuitState::HalfOpen if success => {
+                breaker.probes_passed += 1;
+                if breaker.probes_passed < opt.half_open_probes {
+                    return None;
+                }
+                CircuitState::Closed
+            }
+            CircuitState::HalfOpen => CircuitState::Open,
+            CircuitState::Closed => {
+                let counts = breaker.outcomes.record(opt.window, now, !success);
+                if success {
+                    breaker.consecutive_failures = 0;
+                    return None;
+                }
+                breaker.consecutive_failures += 1;
+                let rate_exceeded = opt
+                    .failure_rate
+                    .is_some_and(|rate| counts.reaches(rate, opt.min_requests));
+                if breaker.consecutive_failures < opt.consecutive_failures && !rate_exceeded {
+                    return None;
+                }
+                CircuitState::Open
+            }
+        };
+        *breaker = Breaker::new(next, now);
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opens_on_consecutive_failures_and_recovers() {
+        let opt = CircuitBreakerOpt {
+            consecutive_failures: 3,
+            cooldown: Duration::from_secs(10),
+            half_open_probes: 2,
+            ..Default::default()
+        };
+        let circuit = CircuitBreaker::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(circuit.record(&opt, false, at(0)), None);
+        assert_eq!(circuit.record(&opt, false, at(0)), None);
+        // a success resets the count
+        assert_eq!(circuit.record(&opt, true, at(0)), None);
+        assert_eq!(circuit.record(&opt, false, at(1)), None);
+        assert_eq!(circuit.record(&opt, false, at(1)), None);
+        assert_eq!(circuit.record(&opt, false, at(1)), Some(CircuitState::Open));
+        assert_eq!(circuit.try_acquire(&opt, at(5)), (false, None));
+
+        // after the cooldown, two probes go through and a third waits for their outcome
+        assert_eq!(
+            circuit.try_acquire(&opt, at(11)),
+            (true, Some(CircuitState::HalfOpen))
+        );
+        assert_eq!(circuit.try_acquire(&opt, at(11)), (true, None));
+        assert_eq!(circuit.try_acquire(&opt, at(11)), (false, None));
+        assert_eq!(circuit.record(&opt, true, at(12)), None);
+        assert_eq!(
+            circuit.record(&opt, true, at(12)),
+            Some(CircuitState::Closed)
+        );
+        assert_eq!(circuit.try_acquire(&opt, at(12)), (true, None));
+    }
+
+    #[test]
+    fn test_failed_probe_reopens() {
+        let opt = CircuitBreakerOpt {
+            consecutive_failures: 1,
+            cooldown: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let circuit = CircuitBreaker::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        circuit.record(&opt, false, at(0));
+        assert!(circuit.try_acquire(&opt, at(10)).0);
+        assert_eq!(
+            circuit.record(&opt, false, at(10)),
+            Some(CircuitState::Open)
+        );
+        assert_eq!(circuit.state(), CircuitState::Open);
+        // the cooldown starts over
+        assert!(!circuit.try_acquire(&opt, at(15)).0);
+
+        // a probe that never reports frees its slot after another cooldown
+        assert!(circuit.try_acquire(&opt, at(20)).0);
+        assert!(!circuit.try_acquire(&opt, at(25)).0);
+        assert!(circuit.try_acquire(&opt, at(30)).0);
+    }
+
+    #[test]
+    fn test_opens_on_failure_rate() {
+        let opt = CircuitBreakerOpt {
+            consecutive_failures: 100,
+            failure_rate: Some(0.5),
+            window: Duration::from_secs(10),
+            min_requests: 4,
+            ..Default::default()
+        };
+        let circuit = CircuitBreaker::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // too few requests for the rate to count
+        circuit.record(&opt, false, at(0));
+        circuit.record(&opt, true, at(0));
+        circuit.record(&opt, false, at(0));
+        // the window slides past them
+        assert_eq!(circuit.record(&opt, true, at(10)), None);
+        assert_eq!(circuit.record(&opt, true, at(10)), None);
+        assert_eq!(circuit.record(&opt, false, at(11)), None);
+        assert_eq!(
+            circuit.record(&opt, false, at(11)),
+            Some(CircuitState::Open)
+        );
+    }
+
+    #[test]
+    fn test_failure_rate_counts_bursts_across_the_window() {
+        let opt = CircuitBreakerOpt {
+            consecutive_failures: 100,
+            failure_rate: Some(0.5),
+            window: Duration::from_secs(10),
+            min_requests: 4,
+            ..Default::default()
+        };
+        let circuit = CircuitBreaker::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // a burst straddling the 10s mark is still within one window
+        assert_eq!(circuit.record(&opt, false, at(9_500)), None);
+        assert_eq!(circuit.record(&opt, false, at(9_500)), None);
+        assert_eq!(circuit.record(&opt, false, at(10_500)), None);
+        assert_eq!(
+            circuit.record(&opt, false, at(10_500)),
+            Some(CircuitState::Open)
+        );
+    }
+}
@@ -28,6 +28,10 @@ pub struct AdaptiveDecisionEngine {
     /// Fraction of the enter thresholds used as exit thresholds. Provides hysteresis so the
     /// engine does not oscillate between strategies on every evaluation cycle.
     pub hysteresis_exit_factor: f32,
+    /// Strategy switched to on connection imbalance.
+    pub connections_strategy: Adaptive,
+    /// Strategy switched to on latency divergence.
+    pub latency_strategy: Adaptive,
 }
 
 impl AdaptiveDecisionEngine {
@@ -38,6 +42,8 @@ impl AdaptiveDecisionEngine {
             latency_divergence_ratio: opt.latency_divergence_ratio,
             min_nr_of_connections: opt.min_nr_of_connections,
             hysteresis_exit_factor: opt.hysteresis_exit_factor,
+            connections_strategy: opt.connections_strategy.clone(),
+            latency_strategy: opt.latency_strategy.clone(),
         }
     }
 
@@ -109,8 +115,10 @@ impl DecisionEngine for AdaptiveDecisionEngine {
     ///
     /// Priority order:
     /// 1. **Connection imbalance** (overload protection): if the busiest backend has crossed
-    ///    `min_nr_of_connections` and connections are skewed, switch to `FewestConnections`.
-    /// 2. **Latency divergence**: if some backends are markedly slower, switch to `FastestServer`.
+    ///    `min_nr_of_connections` and connections are skewed, switch to `connections_strategy`
+    ///    (`FewestConnections` by default, or `P2C`).
+    /// 2. **Latency divergence**: if some backends are markedly slower, switch to
+    ///    `latency_strategy` (`FastestServer` by default, or `PeakEwma`).
     /// 3. Otherwise fall back to `RoundRobin`.
     ///
     /// Each signal uses a higher *enter* threshold than *exit* threshold (hysteresis): once a
@@ -130,26 +138,26 @@ impl DecisionEngine for AdaptiveDecisionEngine {
         // 1. Connection imbalance takes priority as a guard against overloading a single backend.
         if let Some((ratio, max)) = self.connection_divergence(backends) {
             if max >= self.min_nr_of_connections {
-                let threshold = if *current_strategy == Adaptive::FewestConnections {
+                let threshold = if *current_strategy == self.connections_strategy {
                     self.connections_divergence_ratio * exit
                 } else {
                     self.connections_divergence_ratio
                 };
                 if ratio > threshold {
-                    return Adaptive::FewestConnections;
+                    return self.connections_strategy.clone();
                 }
             }
         }
 
         // 2. Latency divergence: route more traffic to the faster backends.
         if let Some(ratio) = self.latency_divergence(backends) {
-            let threshold = if *current_strategy == Adaptive::FastestServer {
+            let threshold = if *current_strategy == self.latency_strategy {
                 self.latency_divergence_ratio * exit
             } else {
                 self.latency_divergence_ratio
             };
             if ratio > threshold {
-                return Adaptive::FastestServer;
+                return self.latency_strategy.clone();
             }
         }
 
@@ -272,4 +280,30 @@ mod tests {
             Adaptive::RoundRobin
         );
     }
+
+    #[test]
+    fn switches_to_the_configured_strategies() {
+        let engine = AdaptiveDecisionEngine::new(&AdaptiveLbOpt {
+            min_nr_of_connections: 1,
+            connections_strategy: Adaptive::P2C,
+            latency_strategy: Adaptive::PeakEwma,
+            ..Default::default()
+        });
+        let imbalanced = set([
+            backend_with_connections("127.0.0.1:8080", 50),
+            backend_with_connections("127.0.0.1:8081", 0),
+        ]);
+        assert_eq!(
+            engine.evaluate_strategy(&Adaptive::RoundRobin, &imbalanced),
+            Adaptive::P2C
+        );
+        let diverging = set([
+            backend_with_latency("127.0.0.1:8080", 100.0),
+            backend_with_latency("127.0.0.1:8081", 10.0),
+        ]);
+        assert_eq!(
+            engine.evaluate_strategy(&Adaptive::RoundRobin, &diverging),
+            Adaptive::PeakEwma
+        );
+    }
 }
@@ -0,0 +1,100 @@
+//! A sliding window of request outcomes, for the failure-rate thresholds of passive health checks
+//! and circuit breakers.
+//!
+//! The window is split into [`BUCKETS`] time buckets and slides one bucket at a time, so a burst
+//! of failures is counted together wherever it falls, rather than being split in two by a window
+//! that restarts.
+use std::time::{Duration, Instant};
+
+/// Buckets a window is split into.
+const BUCKETS: usize = 10;
+
+/// Requests and failures within a window.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateCounts {
+    pub requests: u32,
+    pub failures: u32,
+}
+
+impl RateCounts {
+    /// Whether at least `threshold` of the requests failed, out of at least `min_requests`.
+    pub fn reaches(&self, threshold: f64, min_requests: u32) -> bool {
+        self.requests >= min_requests.max(1)
+            && f64::from(self.failures) >= threshold * f64::from(self.requests)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Bucket {
+    /// Which bucket-length interval since the window's origin these outcomes are from.
+    slot: u64,
+    counts: RateCounts,
+}
+
+/// Request outcomes over a sliding window.
+#[derive(Clone, Debug)]
+pub struct FailureRateWindow {
+    /// Origin of the buckets' slots.
+    origin: Instant,
+    buckets: [Bucket; BUCKETS],
+}
+
+impl FailureRateWindow {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            origin: now,
+            buckets: [Bucket::default(); BUCKETS],
+        }
+    }
+
+    /// Count a request at `now`, returning the outcomes within the `window` ending at `now`.
+    pub fn record(&mut self, window: Duration, now: Instant, failed: bool) -> RateCounts {
+        let bucket_len = (window / BUCKETS as u32).max(Duration::from_millis(1));
+        let slot = (now.duration_since(self.origin).as_nanos() / bucket_len.as_nanos()) as u64;
+        let bucket = &mut self.buckets[slot as usize % BUCKETS];
+        if bucket.slot != slot {
+            *bucket = Bucket {
+                slot,
+                ..Default::default()
+            };
+        }
+        bucket.counts.requests += 1;
+        bucket.counts.failures += u32::from(failed);
+
+        self.buckets
+            .iter()
+            .filter(|bucket| slot.saturating_sub(bucket.slot) < BUCKETS as u64)
+            .fold(RateCounts::default(), |total, bucket| RateCounts {
+                requests: total.requests + bucket.counts.requests,
+                failures: total.failures + bucket.counts.failures,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_slides_one_bucket_at_a_time() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let window = Duration::from_secs(10);
+        let mut outcomes = FailureRateWindow::new(start);
+
+        outcomes.record(window, at(0), true);
+        outcomes.record(window, at(9_500), false);
+        // the first second's bucket has just slid out, the last one's has not
+        let counts = outcomes.record(window, at(10_500), true);
+        assert_eq!(
+            counts,
+            RateCounts {
+                requests: 2,
+                failures: 1
+            }
+        );
+        assert!(counts.reaches(0.5, 2));
+        assert!(!counts.reaches(0.5, 3));
+        assert!(!counts.reaches(0.6, 2));
+    }
+}
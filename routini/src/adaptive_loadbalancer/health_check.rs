@@ -0,0 +1,258 @@
+//! Active health checks of a load balancer's backends: a TCP connect (the default), a TLS
+//! handshake, an HTTP(S) request with an expected response (nginx Plus `health_check`) or a gRPC
+//! health check call.
+pub mod grpc;
+
+use std::{ops::RangeInclusive, time::Duration};
+
+use http::Method;
+use pingora::{
+    Error,
+    ErrorType::{Custom, CustomCode},
+    Result,
+    http::{RequestHeader, ResponseHeader},
+};
+use regex::Regex;
+
+use crate::{
+    adaptive_loadbalancer::health_check::grpc::GrpcHealthCheck,
+    load_balancing::{
+        health_check::{HealthCheck, HttpHealthCheck, TcpHealthCheck},
+        strategy::adaptive::AdaptiveStrategyMetrics,
+    },
+    utils::constants::{DEFAULT_HEALTH_CHECK_HOST, DEFAULT_HEALTH_CHECK_TIMEOUT},
+};
+
+/// A health check probing the adaptive load balancer's backends.
+pub type AdaptiveHealthCheck = Box<dyn HealthCheck<AdaptiveStrategyMetrics> + Send + Sync>;
+
+/// How backends are probed and how many results flip their health.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthCheckOpt {
+    pub kind: HealthCheckKind,
+    pub connect_timeout: Duration,
+    /// How long an HTTP check waits for the response.
+    pub read_timeout: Duration,
+    /// Consecutive passed checks marking an unhealthy backend healthy.
+    pub consecutive_success: usize,
+    /// Consecutive failed checks marking a healthy backend unhealthy.
+    pub consecutive_failure: usize,
+    /// Probe this port of each backend instead of the one it serves on.
+    pub port: Option<u16>,
+    /// Verify the certificate and hostname of backends probed over TLS.
+    pub verify: bool,
+}
+
+impl Default for HealthCheckOpt {
+    fn default() -> Self {
+        Self {
+            kind: HealthCheckKind::Tcp,
+            connect_timeout: DEFAULT_HEALTH_CHECK_TIMEOUT,
+            read_timeout: DEFAULT_HEALTH_CHECK_TIMEOUT,
+            consecutive_success: 1,
+            consecutive_failure: 1,
+            port: None,
+            verify: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HealthCheckKind {
+    /// A TCP connection can be established.
+    Tcp,
+    /// A TLS handshake presenting `sni` succeeds.
+    Tls {
+        sni: String,
+    },
+    Http(HttpCheck),
+    Grpc(GrpcCheck),
+}
+
+/// An HTTP(S) request and the response that passes it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpCheck {
+    pub tls: bool,
+    pub method: Method,
+    /// Path and query of the request; must be a valid request target.
+    pub path: String,
+    /// `Host` header, also the SNI of HTTPS checks; must be a valid header value.
+    pub host: String,
+    /// Statuses that pass the check.
+    pub expected_status: Vec<RangeInclusive<u16>>,
+    pub body: Option<BodyMatch>,
+    /// Keep the connection open for the next check instead of testing the handshakes each time.
+    pub reuse_connection: bool,
+}
+
+impl Default for HttpCheck {
+    fn default() -> Self {
+        Self {
+            tls: false,
+            method: Method::GET,
+            path: "/".to_string(),
+            host: DEFAULT_HEALTH_CHECK_HOST.to_string(),
+            expected_status: vec![200..=200],
+            body: None,
+            reuse_connection: false,
+        }
+    }
+}
+
+/// A `grpc.health.v1.Health/Check` call over HTTP/2 (nginx `health_check type=grpc`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct GrpcCheck {
+    /// TLS instead of cleartext HTTP/2 (h2c).
+    pub tls: bool,
+    /// `:authority` of the call, also the SNI over TLS.
+    pub authority: String,
+    /// The service to ask about; empty asks about the server as a whole.
+    pub service: String,
+    pub reuse_connection: bool,
+}
+
+/// What the body of a passing HTTP check response contains.
+#[derive(Clone, Debug)]
+pub enum BodyMatch {
+    Contains(String),
+    Regex(Regex),
+}
+
+impl PartialEq for BodyMatch {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (BodyMatch::Contains(a), BodyMatch::Contains(b)) => a == b,
+            (BodyMatch::Regex(a), BodyMatch::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl BodyMatch {
+    pub fn matches(&self, body: &[u8]) -> bool {
+        let body = String::from_utf8_lossy(body);
+        match self {
+            BodyMatch::Contains(needle) => body.contains(needle.as_str()),
+            BodyMatch::Regex(regex) => regex.is_match(&body),
+        }
+    }
+}
+
+impl HealthCheckOpt {
+    /// The health check these options describe; fails if an HTTP check's path is not a valid
+    /// request target or its host not a valid header value.
+    pub fn build(&self) -> Result<AdaptiveHealthCheck> {
+        match &self.kind {
+            HealthCheckKind::Tcp => Ok(self.tcp_check("")),
+            HealthCheckKind::Tls { sni } => Ok(self.tcp_check(sni)),
+            HealthCheckKind::Http(http) => self.http_check(http),
+            HealthCheckKind::Grpc(grpc) => Ok(self.grpc_check(grpc)),
+        }
+    }
+
+    fn grpc_check(&self, grpc: &GrpcCheck) -> AdaptiveHealthCheck {
+        let mut check = GrpcHealthCheck::new(&grpc.authority, grpc.tls, &grpc.service);
+        check.peer_template.options.verify_cert = self.verify;
+        check.peer_template.options.verify_hostname = self.verify;
+        check.peer_template.options.connection_timeout = Some(self.connect_timeout);
+        check.peer_template.options.read_timeout = Some(self.read_timeout);
+        check.consecutive_success = self.consecutive_success;
+        check.consecutive_failure = self.consecutive_failure;
+        check.port_override = self.port;
+        check.reuse_connection = grpc.reuse_connection;
+        Box::new(check)
+    }
+
+    fn tcp_check(&self, sni: &str) -> AdaptiveHealthCheck {
+        let mut check = TcpHealthCheck::default();
+        check.peer_template.sni = sni.to_string();
+        check.peer_template.options.verify_cert = self.verify;
+        check.peer_template.options.verify_hostname = self.verify;
+        check.peer_template.options.connection_timeout = Some(self.connect_timeout);
+        check.consecutive_success = self.consecutive_success;
+        check.consecutive_failure = self.consecutive_failure;
+        check.port_override = self.port;
+        Box::new(check)
+    }
+
+    fn http_check(&self, http: &HttpCheck) -> Result<AdaptiveHealthCheck> {
+        let mut check = HttpHealthCheck::new(&http.host, http.tls);
+        let mut req = RequestHeader::build(http.method.as_str(), http.path.as_bytes(), None)?;
+        req.append_header("Host", &http.host)?;
+        check.req = req;
+        check.peer_template.options.verify_cert = self.verify;
+        check.peer_template.options.verify_hostname = self.verify;
+        check.peer_template.options.connection_timeout = Some(self.connect_timeout);
+        check.peer_template.options.read_timeout = Some(self.read_timeout);
+        check.consecutive_success = self.consecutive_success;
+        check.consecutive_failure = self.consecutive_failure;
+        check.port_override = self.port;
+        check.reuse_connection = http.reuse_connection;
+
+        let expected = http.expected_status.clone();
+        check.validator = Some(Box::new(move |resp: &ResponseHeader| {
+            let status = resp.status.as_u16();
+            if expected.iter().any(|range| range.contains(&status)) {
+                Ok(())
+            } else {
+                Error::e_explain(
+                    CustomCode("unexpected status", status),
+                    "during http healthcheck",
+                )
+            }
+        }));
+        if let Some(body) = http.body.clone() {
+            check.body_validator = Some(Box::new(move |bytes: &[u8]| {
+                if body.matches(bytes) {
+                    Ok(())
+                } else {
+                    Error::e_explain(Custom("unexpected body"), "during http healthcheck")
+                }
+            }));
+        }
+        Ok(Box::new(check))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_body_match() {
+        assert!(BodyMatch::Contains("ok".to_string()).matches(b"{\"status\": \"ok\"}"));
+        assert!(!BodyMatch::Contains("ok".to_string()).matches(b"degraded"));
+        let regex = BodyMatch::Regex(Regex::new(r#""ready":\s*true"#).unwrap());
+        assert!(regex.matches(b"{\"ready\": true}"));
+        assert!(!regex.matches(b"{\"ready\": false}"));
+    }
+
+    #[test]
+    fn test_changed_check_is_not_equal() {
+        let http = |path: &str| HealthCheckOpt {
+            kind: HealthCheckKind::Http(HttpCheck {
+                path: path.to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(http("/healthz"), http("/healthz"));
+        assert_ne!(http("/healthz"), http("/ready"));
+        assert_ne!(http("/healthz"), HealthCheckOpt::default());
+    }
+
+    #[test]
+    fn test_invalid_http_check_fails_to_build() {
+        let http = |path: &str, host: &str| HealthCheckOpt {
+            kind: HealthCheckKind::Http(HttpCheck {
+                path: path.to_string(),
+                host: host.to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(http("/healthz", "localhost").build().is_ok());
+        assert!(http("/health z", "localhost").build().is_err());
+        assert!(http("/healthz", "local\nhost").build().is_err());
+    }
+}
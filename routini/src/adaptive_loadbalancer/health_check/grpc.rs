@@ -0,0 +1,374 @@
+//! The gRPC health checking protocol: a `grpc.health.v1.Health/Check` call over HTTP/2, either
+//! cleartext (h2c) or TLS, passing when the backend answers `SERVING`.
+//!
+//! The request and response messages each hold a single field, so they are encoded by hand rather
+//! than pulling in a protobuf toolchain.
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use pingora::{
+    Error,
+    ErrorType::{Custom, CustomCode},
+    Result,
+    connectors::http::Connector as HttpConnector,
+    http::RequestHeader,
+    prelude::HttpPeer,
+    protocols::http::client::HttpSession,
+    upstreams::peer::Peer,
+};
+
+use crate::load_balancing::{Backend, Metrics, health_check::HealthCheck};
+
+/// Path of the `Check` RPC.
+const CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+/// `HealthCheckResponse.ServingStatus.SERVING`.
+const SERVING: u64 = 1;
+/// A health response is a few bytes; anything past this is not one.
+const MAX_RESPONSE_BODY: usize = 64 * 1024;
+
+/// gRPC health check
+///
+/// Calls `grpc.health.v1.Health/Check` on the backend; the check passes when the response status
+/// is `SERVING` and the call ends with `grpc-status: 0`.
+pub struct GrpcHealthCheck {
+    /// Number of successful checks to flip from unhealthy to healthy.
+    pub consecutive_success: usize,
+    /// Number of failed checks to flip from healthy to unhealthy.
+    pub consecutive_failure: usize,
+    /// How to connect to the backend; its address is replaced by the backend's for each check.
+    pub peer_template: HttpPeer,
+    /// The service to ask about; empty asks about the server as a whole.
+    pub service: String,
+    /// Whether the HTTP/2 connection is kept for the next check.
+    pub reuse_connection: bool,
+    /// Check this port of the backend IP instead of the backend's own port.
+    pub port_override: Option<u16>,
+    /// The `:authority` of the call, also the SNI over TLS.
+    authority: String,
+    connector: HttpConnector,
+}
+
+impl GrpcHealthCheck {
+    /// Create a new [GrpcHealthCheck] over TLS or h2c, with 1 second connect and read timeouts
+    /// and thresholds of 1.
+    pub fn new(authority: &str, tls: bool, service: &str) -> Self {
+        let sni = if tls { authority.into() } else { String::new() };
+        let mut peer_template = HttpPeer::new("0.0.0.0:1", tls, sni);
+        // HTTP/2 only: over TLS via ALPN, in cleartext with prior knowledge (h2c).
+        peer_template.options.set_http_version(2, 2);
+        peer_template.options.connection_timeout = Some(Duration::from_secs(1));
+        peer_template.options.read_timeout = Some(Duration::from_secs(1));
+        GrpcHealthCheck {
+            consecutive_success: 1,
+            consecutive_failure: 1,
+            peer_template,
+            service: service.to_string(),
+            reuse_connection: false,
+            port_override: None,
+            authority: authority.to_string(),
+            connector: HttpConnector::new(None),
+        }
+    }
+
+    fn request(&self) -> Result<RequestHeader> {
+        let mut req = RequestHeader::build("POST", CHECK_PATH.as_bytes(), None)?;
+        req.insert_header("Host", &self.authority)?;
+        req.insert_header("Content-Type", "application/grpc")?;
+        req.insert_header("TE", "trailers")?;
+        Ok(req)
+    }
+}
+
+#[async_trait]
+impl<M: Metrics> HealthCheck<M> for GrpcHealthCheck {
+    fn health_threshold(&self, success: bool) -> usize {
+        if success {
+            self.consecutive_success
+        } else {
+            self.consecutive_failure
+        }
+    }
+
+    async fn check(&self, target: &Backend<M>) -> Result<()> {
+        let mut peer = self.peer_template.clone();
+        peer._address = target.addr.clone();
+        if let Some(port) = self.port_override {
+            peer._address.set_port(port);
+        }
+        let (mut session, _reused) = self.connector.get_http_session(&peer).await?;
+
+        session
+            .write_request_header(Box::new(self.request()?))
+            .await?;
+        session
+            .write_request_body(encode_request(&self.service), true)
+            .await?;
+        session.finish_request_body().await?;
+        if let Some(read_timeout) = peer.options.read_timeout {
+            session.set_read_timeout(Some(read_timeout));
+        }
+
+        session.read_response_header().await?;
+        let resp = session.response_header().expect("just read");
+        if resp.status != 200 {
+            return Error::e_explain(
+                CustomCode("non 200 code", resp.status.as_u16()),
+                "during grpc healthcheck",
+            );
+        }
+        // A call failing outright is answered with its status in the headers and no body.
+        let grpc_status = resp
+            .headers
+            .get("grpc-status")
+            .map(|status| status.as_bytes());
+        if let Some(status) = grpc_status.filter(|status| *status != b"0") {
+            let status = String::from_utf8_lossy(status);
+            return Error::e_explain(Custom("grpc call failed"), format!("grpc-status {status}"));
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = session.read_response_body().await? {
+            if body.len() + chunk.len() > MAX_RESPONSE_BODY {
+                return Error::e_explain(
+                    Custom("unexpected body"),
+                    "grpc health response too large",
+                );
+            }
+            body.extend_from_slice(&chunk);
+        }
+        // Otherwise the call's status follows the body, in the trailers.
+        let trailers = match &mut session {
+            HttpSession::H2(h2) => h2.read_trailers().await?,
+            _ => None,
+        };
+        let grpc_status = trailers.as_ref().and_then(|t| t.get("grpc-status"));
+        match grpc_status {
+            Some(status) if status.as_bytes() == b"0" => {}
+            Some(status) => {
+                let status = String::from_utf8_lossy(status.as_bytes());
+                return Error::e_explain(
+                    Custom("grpc call failed"),
+                    format!("grpc-status {status}"),
+                );
+            }
+            None => {
+                return Error::e_explain(Custom("grpc call failed"), "missing grpc-status");
+            }
+        }
+        let status =
+            decode_response(&body).map_err(|why| Error::explain(Custom("unexpected body"), why))?;
+        if status != SERVING {
+            return Error::e_explain(
+                CustomCode("not serving", status as u16),
+                "during grpc healthcheck",
+            );
+        }
+
+        if self.reuse_connection {
+            self.connector
+                .release_http_session(session, &peer, peer.idle_timeout())
+                .await;
+        }
+        Ok(())
+    }
+}
+
+/// A `HealthCheckRequest { service }` in a gRPC message frame.
+fn encode_request(service: &str) -> Bytes {
+    let mut message = Vec::with_capacity(service.len() + 6);
+    if !service.is_empty() {
+        // field 1, length-delimited
+        message.push(0x0a);
+        put_varint(&mut message, service.len() as u64);
+        message.extend_from_slice(service.as_bytes());
+    }
+    let mut frame = Vec::with_capacity(message.len() + 5);
+    // uncompressed
+    frame.push(0);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+    Bytes::from(frame)
+}
+
+/// The `status` of the `HealthCheckResponse` in a gRPC message frame.
+fn decode_response(body: &[u8]) -> std::result::Result<u64, &'static str> {
+    let (prefix, rest) = body.split_at_checked(5).ok_or("missing grpc message")?;
+    if prefix[0] != 0 {
+        return Err("compressed grpc message");
+    }
+    let len = u32::from_be_bytes([prefix[1], prefix[2], prefix[3], prefix[4]]) as usize;
+    let mut message = rest.get(..len).ok_or("truncated grpc message")?;
+
+    // An unset status is UNKNOWN (0).
+    let mut status = 0;
+    while !message.is_empty() {
+        let key = take_varint(&mut message)?;
+        match (key >> 3, key & 0x7) {
+            (1, 0) => status = take_varint(&mut message)?,
+            // skip fields this version of the message does not know about
+            (_, 0) => {
+                take_varint(&mut message)?;
+            }
+            (_, 1) => message = message.get(8..).ok_or("truncated field")?,
+            (_, 2) => {
+                let len = take_varint(&mut message)? as usize;
+                message = message.get(len..).ok_or("truncated field")?;
+            }
+            (_, 5) => message = message.get(4..).ok_or("truncated field")?,
+            _ => return Err("invalid protobuf wire type"),
+        }
+    }
+    Ok(status)
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn take_varint(buf: &mut &[u8]) -> std::result::Result<u64, &'static str> {
+    let mut value = 0;
+    for (i, byte) in buf.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *buf = &buf[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err("invalid varint")
+}
+
+#[cfg(test)]
+mod tests {
+    use h2::{RecvStream, server::SendResponse};
+    use http::{HeaderMap, HeaderValue, Request, Response};
+
+    use super::*;
+
+    fn frame(message: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(message);
+        frame
+    }
+
+    /// An in-process h2c gRPC server. With `health`, it serves `grpc.health.v1.Health` with
+    /// `users` SERVING and `billing` NOT_SERVING, and answers `payments` SERVING but then fails
+    /// the call with INTERNAL in the trailers; without, every call is UNIMPLEMENTED.
+    async fn grpc_server(health: bool) -> Backend {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut connection = h2::server::handshake(stream).await.unwrap();
+                    while let Some(Ok((request, respond))) = connection.accept().await {
+                        tokio::spawn(answer(request, respond, health));
+                    }
+                });
+            }
+        });
+        Backend::new(&addr.to_string()).unwrap()
+    }
+
+    async fn answer(request: Request<RecvStream>, mut respond: SendResponse<Bytes>, health: bool) {
+        let mut body = request.into_body();
+        let mut message = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.unwrap();
+            let _ = body.flow_control().release_capacity(chunk.len());
+            message.extend_from_slice(&chunk);
+        }
+        let headers = |grpc_status: Option<&'static str>| {
+            let mut response = Response::builder().header("content-type", "application/grpc");
+            if let Some(status) = grpc_status {
+                response = response.header("grpc-status", status);
+            }
+            response.body(()).unwrap()
+        };
+        // past the frame prefix, the field 1 tag and the length of a short service name
+        let service = message.get(7..).unwrap_or_default();
+        let status = match service {
+            _ if !health => None,
+            b"users" => Some((1, "0")),
+            b"billing" => Some((2, "0")),
+            b"payments" => Some((1, "13")),
+            _ => None,
+        };
+        let Some((status, grpc_status)) = status else {
+            // a trailers-only answer: UNIMPLEMENTED, or NOT_FOUND for an unknown service
+            let grpc_status = if health { "5" } else { "12" };
+            respond
+                .send_response(headers(Some(grpc_status)), true)
+                .unwrap();
+            return;
+        };
+        let mut stream = respond.send_response(headers(None), false).unwrap();
+        stream
+            .send_data(Bytes::from(frame(&[0x08, status])), false)
+            .unwrap();
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static(grpc_status));
+        stream.send_trailers(trailers).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_check_over_h2c() {
+        let backend = grpc_server(true).await;
+        let checker = |service| GrpcHealthCheck::new("localhost", false, service);
+
+        // SERVING, also on a connection kept from the previous check
+        let mut serving = checker("users");
+        serving.reuse_connection = true;
+        for _ in 0..2 {
+            serving.check(&backend).await.unwrap();
+        }
+
+        let err = checker("billing").check(&backend).await.unwrap_err();
+        assert_eq!(err.etype(), &CustomCode("not serving", 2));
+        let err = checker("orders").check(&backend).await.unwrap_err();
+        assert_eq!(err.etype(), &Custom("grpc call failed"));
+        // a SERVING body does not pass a call that failed in its trailers
+        let err = checker("payments").check(&backend).await.unwrap_err();
+        assert_eq!(err.etype(), &Custom("grpc call failed"));
+        assert!(err.to_string().contains("grpc-status 13"));
+
+        // a server without the health service
+        let backend = grpc_server(false).await;
+        let err = checker("users").check(&backend).await.unwrap_err();
+        assert_eq!(err.etype(), &Custom("grpc call failed"));
+        assert!(err.to_string().contains("grpc-status 12"));
+    }
+
+    #[test]
+    fn test_encode_request() {
+        assert_eq!(&encode_request("")[..], [0, 0, 0, 0, 0]);
+        assert_eq!(
+            &encode_request("api.v1.Users")[..],
+            frame(b"\x0a\x0capi.v1.Users")
+        );
+    }
+
+    #[test]
+    fn test_decode_response() {
+        assert_eq!(decode_response(&frame(&[0x08, 0x01])), Ok(SERVING));
+        assert_eq!(decode_response(&frame(&[0x08, 0x02])), Ok(2));
+        // an empty message leaves the status UNKNOWN
+        assert_eq!(decode_response(&frame(&[])), Ok(0));
+        // unknown fields are skipped
+        let message = [0x12, 0x02, b'h', b'i', 0x18, 0xac, 0x02, 0x08, 0x01];
+        assert_eq!(decode_response(&frame(&message)), Ok(SERVING));
+
+        assert!(decode_response(&[]).is_err());
+        assert!(decode_response(&frame(&[0x08])).is_err());
+        assert!(decode_response(&frame(&[0x08, 0x01])[..6]).is_err());
+        let mut compressed = frame(&[0x08, 0x01]);
+        compressed[0] = 1;
+        assert!(decode_response(&compressed).is_err());
+    }
+}
@@ -1,6 +1,10 @@
 use std::time::Duration;
 
 use crate::{
+    adaptive_loadbalancer::{
+        circuit_breaker::CircuitBreakerOpt, health_check::HealthCheckOpt,
+        outlier_detection::OutlierDetectionOpt,
+    },
     load_balancing::strategy::Adaptive,
     utils::constants::{
         DEFAULT_CONNECTIONS_DIV_RATIO, DEFAULT_EVALUATE_STRATEGY_FREQUENCY,
@@ -10,6 +14,7 @@ use crate::{
 };
 
 /// The Options to pass inn during construction of the load balancer.
+#[derive(Clone, PartialEq)]
 pub struct AdaptiveLbOpt {
     pub latency_smoothing_factor: f32,
     pub connections_divergence_ratio: f32,
@@ -18,9 +23,21 @@ pub struct AdaptiveLbOpt {
     pub evaluate_strategy_frequency: Duration,
     pub max_iterations: usize,
     pub health_check_interval: Option<Duration>,
+    /// How backends are probed every `health_check_interval`.
+    pub health_check: HealthCheckOpt,
     pub min_nr_of_connections: usize,
     /// Fraction of the enter thresholds used as exit thresholds (hysteresis).
     pub hysteresis_exit_factor: f32,
+    /// Strategy the decision engine switches to when connections are imbalanced, e.g.
+    /// `FewestConnections` or `P2C`.
+    pub connections_strategy: Adaptive,
+    /// Strategy the decision engine switches to when latencies diverge, e.g. `FastestServer` or
+    /// `PeakEwma`.
+    pub latency_strategy: Adaptive,
+    /// A circuit breaker per backend. `None` = no breaker.
+    pub circuit_breaker: Option<CircuitBreakerOpt>,
+    /// Eject backends much slower or more failing than the rest of the pool. `None` = off.
+    pub outlier_detection: Option<OutlierDetectionOpt>,
 }
 
 impl Default for AdaptiveLbOpt {
@@ -33,12 +50,27 @@ impl Default for AdaptiveLbOpt {
             evaluate_strategy_frequency: DEFAULT_EVALUATE_STRATEGY_FREQUENCY,
             max_iterations: DEFAULT_MAX_ALGORITHM_ITERATIONS,
             health_check_interval: Some(DEFAULT_HEALTH_CHECK_FREQUENCY),
+            health_check: HealthCheckOpt::default(),
             min_nr_of_connections: DEFAULT_MIN_NR_OF_CONNECTIONS,
             hysteresis_exit_factor: DEFAULT_HYSTERESIS_EXIT_FACTOR,
+            connections_strategy: Adaptive::FewestConnections,
+            latency_strategy: Adaptive::FastestServer,
+            circuit_breaker: None,
+            outlier_detection: None,
         }
     }
 }
 
+impl AdaptiveLbOpt {
+    /// Whether `other` differs from these options at most in `starting_strategy`, the only option
+    /// a running load balancer can take on without being rebuilt.
+    pub fn same_tunables(&self, other: &Self) -> bool {
+        let mut other = other.clone();
+        other.starting_strategy = self.starting_strategy.clone();
+        *self == other
+    }
+}
+
 /// The stored configuration to be used at runtime.
 pub struct AdaptiveLbConfig {
     pub latency_smoothing_factor: f32,
@@ -49,6 +81,8 @@ pub struct AdaptiveLbConfig {
     pub health_check_interval: Option<Duration>,
     pub min_nr_of_connections: usize,
     pub hysteresis_exit_factor: f32,
+    pub circuit_breaker: Option<CircuitBreakerOpt>,
+    pub outlier_detection: Option<OutlierDetectionOpt>,
 }
 
 impl From<AdaptiveLbOpt> for AdaptiveLbConfig {
@@ -62,6 +96,8 @@ impl From<AdaptiveLbOpt> for AdaptiveLbConfig {
             health_check_interval: value.health_check_interval,
             min_nr_of_connections: value.min_nr_of_connections,
             hysteresis_exit_factor: value.hysteresis_exit_factor,
+            circuit_breaker: value.circuit_breaker,
+            outlier_detection: value.outlier_detection,
         }
     }
 }
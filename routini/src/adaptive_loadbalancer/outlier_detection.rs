@@ -0,0 +1,392 @@
+//! Outlier detection in the style of Envoy's: every interval, a backend whose latency EWMA or
+//! error rate is more than `stdev_factor` standard deviations worse than the mean of its pool is
+//! ejected, for a period growing with each repeated ejection. At most `max_ejection_percent` of the
+//! pool is ejected at once, and never all of it.
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+use pingora::protocols::l4::socket::SocketAddr;
+
+use crate::{
+    adaptive_loadbalancer::AdaptiveBackend,
+    load_balancing::Metrics,
+    utils::constants::{
+        DEFAULT_OUTLIER_BASE_EJECTION_TIME, DEFAULT_OUTLIER_INTERVAL,
+        DEFAULT_OUTLIER_MAX_EJECTION_PERCENT, DEFAULT_OUTLIER_MAX_EJECTION_TIME,
+        DEFAULT_OUTLIER_MIN_HOSTS, DEFAULT_OUTLIER_MIN_REQUESTS, DEFAULT_OUTLIER_STDEV_FACTOR,
+    },
+};
+
+/// When a backend is an outlier and for how long it is ejected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutlierDetectionOpt {
+    /// How often the pool is analysed.
+    pub interval: Duration,
+    /// Ejection period of a first ejection, multiplied by the number of recent ejections.
+    pub base_ejection_time: Duration,
+    pub max_ejection_time: Duration,
+    /// Most of the pool, in percent, ejected at once. One backend can always be ejected as long
+    /// as another one remains.
+    pub max_ejection_percent: u8,
+    /// How many standard deviations worse than the pool's mean makes an outlier.
+    pub stdev_factor: f64,
+    /// Backends with enough requests in an interval needed to analyse the pool.
+    pub min_hosts: usize,
+    /// Requests a backend must serve in an interval for its latency and error rate to count.
+    pub min_requests: u64,
+    /// Eject latency outliers.
+    pub latency: bool,
+    /// Eject error rate outliers.
+    pub error_rate: bool,
+    /// Upstream response statuses counted as errors, besides connection and read errors.
+    pub failure_statuses: Vec<u16>,
+}
+
+impl Default for OutlierDetectionOpt {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_OUTLIER_INTERVAL,
+            base_ejection_time: DEFAULT_OUTLIER_BASE_EJECTION_TIME,
+            max_ejection_time: DEFAULT_OUTLIER_MAX_EJECTION_TIME,
+            max_ejection_percent: DEFAULT_OUTLIER_MAX_EJECTION_PERCENT,
+            stdev_factor: DEFAULT_OUTLIER_STDEV_FACTOR,
+            min_hosts: DEFAULT_OUTLIER_MIN_HOSTS,
+            min_requests: DEFAULT_OUTLIER_MIN_REQUESTS,
+            latency: true,
+            error_rate: true,
+            failure_statuses: (500..=599).collect(),
+        }
+    }
+}
+
+impl OutlierDetectionOpt {
+    /// How many of `backends` may be ejected at once.
+    fn max_ejected(&self, backends: usize) -> usize {
+        let percent = backends * usize::from(self.max_ejection_percent) / 100;
+        percent.max(1).min(backends.saturating_sub(1))
+    }
+
+    /// The ejection period of a backend's `nth` recent ejection.
+    fn ejection_time(&self, nth: u32) -> Duration {
+        self.base_ejection_time
+            .saturating_mul(nth)
+            .min(self.max_ejection_time)
+    }
+}
+
+/// Requests served by a backend and how many of them failed, since the last analysis. Clones
+/// share the same counters, as with the other backend metrics.
+#[derive(Clone, Debug, Default)]
+pub struct RequestOutcomes(Arc<(AtomicU64, AtomicU64)>);
+
+impl RequestOutcomes {
+    pub fn record(&self, success: bool) {
+        self.0.0.fetch_add(1, Ordering::Relaxed);
+        if !success {
+            self.0.1.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The requests and failures counted so far, starting the count over.
+    pub fn take(&self) -> (u64, u64) {
+        let requests = self.0.0.swap(0, Ordering::Relaxed);
+        let failures = self.0.1.swap(0, Ordering::Relaxed);
+        (requests, failures.min(requests))
+    }
+}
+
+/// Why a backend was ejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutlierReason {
+    Latency,
+    ErrorRate,
+}
+
+impl OutlierReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutlierReason::Latency => "latency",
+            OutlierReason::ErrorRate => "error_rate",
+        }
+    }
+}
+
+impl Display for OutlierReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A backend ejected by an analysis.
+#[derive(Debug, PartialEq)]
+pub struct Ejection {
+    pub addr: SocketAddr,
+    pub reason: OutlierReason,
+    pub duration: Duration,
+}
+
+/// The outcome of an analysis: the backends it ejected and those whose ejection ended.
+#[derive(Debug, Default)]
+pub struct OutlierReport {
+    pub ejected: Vec<Ejection>,
+    pub returned: Vec<SocketAddr>,
+}
+
+/// The outlier detection state of a pool.
+#[derive(Default)]
+pub struct OutlierDetector {
+    /// Backends currently ejected, skipped by selection.
+    ejected: ArcSwap<Vec<SocketAddr>>,
+    /// Recent ejections of each backend, decreasing every interval it is not ejected.
+    history: Mutex<HashMap<SocketAddr, Record>>,
+}
+
+#[derive(Debug)]
+struct Record {
+    ejections: u32,
+    until: Instant,
+}
+
+impl OutlierDetector {
+    pub fn is_ejected(&self, addr: &SocketAddr) -> bool {
+        self.ejected.load().contains(addr)
+    }
+
+    /// Return the backends whose ejection ended by `now` and eject the pool's outliers, reading
+    /// (and resetting) each backend's request outcomes since the previous analysis.
+    pub fn analyze(
+        &self,
+        opt: &OutlierDetectionOpt,
+        backends: &BTreeSet<AdaptiveBackend>,
+        now: Instant,
+    ) -> OutlierReport {
+        let mut history = self.history.lock().unwrap();
+        let mut report = OutlierReport::default();
+        let mut ejected = Vec::new();
+        for addr in self.ejected.load().iter() {
+            let active = history.get(addr).is_some_and(|record| record.until > now);
+            let present = backends.iter().any(|backend| backend.addr == *addr);
+            if active && present {
+                ejected.push(addr.clone());
+            } else if present {
+                report.returned.push(addr.clone());
+            }
+        }
+
+        // Ejected backends serve no requests, so only the others are compared.
+        let samples: Vec<_> = backends
+            .iter()
+            .filter_map(|backend| {
+                let (requests, failures) = backend.metrics.outcomes().take();
+                let eligible =
+                    requests >= opt.min_requests.max(1) && !ejected.contains(&backend.addr);
+                eligible.then(|| Sample {
+                    addr: &backend.addr,
+                    error_rate: failures as f64 / requests as f64,
+                    latency: f64::from(backend.metrics.average_latency().unwrap_or(0.0)),
+                })
+            })
+            .collect();
+
+        let mut candidates = Vec::new();
+        if samples.len() >= opt.min_hosts {
+            if opt.error_rate {
+                let rates: Vec<_> = samples.iter().map(|sample| sample.error_rate).collect();
+                for (i, score) in outliers(&rates, opt.stdev_factor) {
+                    candidates.push((i, OutlierReason::ErrorRate, score));
+                }
+            }
+            if opt.latency {
+                let latencies: Vec<_> = samples.iter().map(|sample| sample.latency).collect();
+                for (i, score) in outliers(&latencies, opt.stdev_factor) {
+                    candidates.push((i, OutlierReason::Latency, score));
+                }
+            }
+        }
+        // The worst outliers first, in case not all of them can be ejected.
+        candidates.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+        let max_ejected = opt.max_ejected(backends.len());
+        for (i, reason, _) in candidates {
+            let addr = samples[i].addr;
+            if ejected.len() >= max_ejected {
+                break;
+            }
+            if ejected.contains(addr) {
+                continue;
+            }
+            let record = history.entry(addr.clone()).or_insert(Record {
+                ejections: 0,
+                until: now,
+            });
+            record.ejections += 1;
+            let duration = opt.ejection_time(record.ejections);
+            record.until = now + duration;
+            ejected.push(addr.clone());
+            report.ejected.push(Ejection {
+                addr: addr.clone(),
+                reason,
+                duration,
+            });
+        }
+
+        // A backend that stays in rotation is forgiven one ejection per interval.
+        history.retain(|addr, record| {
+            if !ejected.contains(addr) {
+                record.ejections = record.ejections.saturating_sub(1);
+            }
+            record.ejections > 0
+        });
+        self.ejected.store(Arc::new(ejected));
+        report
+    }
+}
+
+struct Sample<'a> {
+    addr: &'a SocketAddr,
+    error_rate: f64,
+    latency: f64,
+}
+
+/// The indices of `values` more than `factor` standard deviations above their mean, with how many
+/// standard deviations above it they are.
+fn outliers(values: &[f64], factor: f64) -> Vec<(usize, f64)> {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    let stdev = variance.sqrt();
+    if stdev == 0.0 {
+        return Vec::new();
+    }
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (i, (v - mean) / stdev))
+        .filter(|(_, score)| *score > factor)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(latencies_ms: &[u64]) -> BTreeSet<AdaptiveBackend> {
+        latencies_ms
+            .iter()
+            .enumerate()
+            .map(|(i, ms)| {
+                let backend =
+                    AdaptiveBackend::build(&format!("127.0.0.1:{}", 8000 + i), 1).unwrap();
+                backend
+                    .metrics
+                    .record_latency(Duration::from_millis(*ms), 1.0);
+                backend
+            })
+            .collect()
+    }
+
+    fn serve(backends: &BTreeSet<AdaptiveBackend>, failures: &[u64]) {
+        for (backend, failures) in backends.iter().zip(failures) {
+            for i in 0..10 {
+                backend.metrics.outcomes().record(i >= *failures);
+            }
+        }
+    }
+
+    fn opt() -> OutlierDetectionOpt {
+        OutlierDetectionOpt {
+            base_ejection_time: Duration::from_secs(10),
+            max_ejection_time: Duration::from_secs(25),
+            max_ejection_percent: 50,
+            min_requests: 10,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_outliers() {
+        assert!(outliers(&[1.0, 1.0, 1.0], 1.0).is_empty());
+        let found = outliers(&[10.0, 10.0, 10.0, 10.0, 100.0], 1.9);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, 4);
+        assert!(outliers(&[10.0, 10.0, 10.0, 10.0, 100.0], 2.1).is_empty());
+    }
+
+    #[test]
+    fn test_ejects_latency_outlier_for_growing_periods() {
+        let backends = pool(&[10, 11, 10, 12, 10, 200]);
+        let slow = backends.iter().last().unwrap().addr.clone();
+        let detector = OutlierDetector::default();
+        let opt = opt();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        serve(&backends, &[0; 6]);
+        let report = detector.analyze(&opt, &backends, at(0));
+        let ejection = Ejection {
+            addr: slow.clone(),
+            reason: OutlierReason::Latency,
+            duration: Duration::from_secs(10),
+        };
+        assert_eq!(report.ejected, [ejection]);
+        assert!(detector.is_ejected(&slow));
+
+        // still ejected, and not compared with the others
+        serve(&backends, &[0; 6]);
+        let report = detector.analyze(&opt, &backends, at(5));
+        assert!(report.ejected.is_empty() && report.returned.is_empty());
+
+        serve(&backends, &[0; 6]);
+        let report = detector.analyze(&opt, &backends, at(10));
+        assert_eq!(report.returned, [slow.clone()]);
+        // ejected again right away, for twice as long
+        assert_eq!(report.ejected[0].duration, Duration::from_secs(20));
+        serve(&backends, &[0; 6]);
+        let report = detector.analyze(&opt, &backends, at(30));
+        // capped at the maximum
+        assert_eq!(report.ejected[0].duration, Duration::from_secs(25));
+    }
+
+    #[test]
+    fn test_ejects_error_rate_outliers_up_to_the_limit() {
+        let backends = pool(&[10; 6]);
+        let detector = OutlierDetector::default();
+        let opt = OutlierDetectionOpt {
+            max_ejection_percent: 10,
+            stdev_factor: 1.0,
+            ..opt()
+        };
+
+        serve(&backends, &[0, 0, 0, 0, 8, 9]);
+        let report = detector.analyze(&opt, &backends, Instant::now());
+        // one backend can always be ejected; the worst goes first
+        let worst = backends.iter().last().unwrap().addr.clone();
+        assert_eq!(report.ejected.len(), 1);
+        assert_eq!(report.ejected[0].addr, worst);
+        assert_eq!(report.ejected[0].reason, OutlierReason::ErrorRate);
+
+        // too little traffic to judge
+        let report = detector.analyze(&opt, &backends, Instant::now());
+        assert!(report.ejected.is_empty());
+    }
+
+    #[test]
+    fn test_max_ejected() {
+        let opt = OutlierDetectionOpt {
+            max_ejection_percent: 100,
+            ..Default::default()
+        };
+        assert_eq!(opt.max_ejected(1), 0);
+        assert_eq!(opt.max_ejected(4), 3);
+        assert_eq!(OutlierDetectionOpt::default().max_ejected(5), 1);
+        assert_eq!(OutlierDetectionOpt::default().max_ejected(30), 3);
+    }
+}
@@ -0,0 +1,160 @@
+//! Slow start (nginx Plus `slow_start`): a backend that joins a pool, recovers its health or is
+//! restored after a passive-health ejection warms up, its share of the traffic ramping from a
+//! fraction of its weight up to the full weight over the route's `slow_start` window.
+//!
+//! The ramp works in every selector: a warming backend the selector picks is only accepted at the
+//! odds of its current share of its weight, otherwise selection moves on to the next backend.
+use std::{
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use pingora::Result;
+
+use crate::{
+    adaptive_loadbalancer::{AdaptiveBackend, health_check::AdaptiveHealthCheck},
+    load_balancing::{health_check::HealthCheck, strategy::adaptive::AdaptiveStrategyMetrics},
+    utils::constants::DEFAULT_SLOW_START_MIN_WEIGHT,
+};
+
+/// How a warming backend's share of its weight grows over the window.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SlowStartCurve {
+    #[default]
+    Linear,
+    /// Doubling at a constant pace: slow at first, most of the traffic arriving near the end.
+    Exponential,
+}
+
+/// A route's slow start.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlowStartConfig {
+    pub window: Duration,
+    pub curve: SlowStartCurve,
+    /// Share of its weight a backend starts warming up with, in (0, 1].
+    pub min_weight: f64,
+}
+
+impl SlowStartConfig {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            curve: SlowStartCurve::default(),
+            min_weight: DEFAULT_SLOW_START_MIN_WEIGHT,
+        }
+    }
+
+    /// The share of its weight a backend gets `elapsed` into its warm-up.
+    pub fn weight_factor(&self, elapsed: Duration) -> f64 {
+        if elapsed >= self.window {
+            return 1.0;
+        }
+        let progress = elapsed.as_secs_f64() / self.window.as_secs_f64();
+        match self.curve {
+            SlowStartCurve::Linear => self.min_weight + (1.0 - self.min_weight) * progress,
+            SlowStartCurve::Exponential => self.min_weight.powf(1.0 - progress),
+        }
+    }
+
+    /// Whether a request may go to `backend`, at the odds of its current share of its weight
+    /// while it warms up.
+    pub fn admits(&self, backend: &AdaptiveBackend, now: Instant) -> bool {
+        let Some(elapsed) = backend.metrics.warmup().elapsed(now) else {
+            return true;
+        };
+        let factor = self.weight_factor(elapsed);
+        factor >= 1.0 || rand::random::<f64>() < factor
+    }
+}
+
+/// Reference point of the warm-up start times, which are stored as an offset from it.
+static EPOCH: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// When a backend last started warming up. Clones share the same state, as with the other backend
+/// metrics.
+#[derive(Clone, Debug, Default)]
+pub struct Warmup(Arc<AtomicU64>);
+
+impl Warmup {
+    /// Start warming up at `now`.
+    pub fn begin(&self, now: Instant) {
+        let millis = now.saturating_duration_since(*EPOCH).as_millis() as u64;
+        // 0 is never warmed up
+        self.0.store(millis + 1, Ordering::Relaxed);
+    }
+
+    /// How long ago the last warm-up began, if one did.
+    pub fn elapsed(&self, now: Instant) -> Option<Duration> {
+        let millis = self.0.load(Ordering::Relaxed).checked_sub(1)?;
+        let began = *EPOCH + Duration::from_millis(millis);
+        Some(now.saturating_duration_since(began))
+    }
+}
+
+/// Wraps a pool's health check to start the warm-up of the backends it finds healthy again.
+pub struct WarmupOnRecovery(pub AdaptiveHealthCheck);
+
+#[async_trait]
+impl HealthCheck<AdaptiveStrategyMetrics> for WarmupOnRecovery {
+    async fn check(&self, target: &AdaptiveBackend) -> Result<()> {
+        self.0.check(target).await
+    }
+
+    async fn health_status_change(&self, target: &AdaptiveBackend, healthy: bool) {
+        if healthy {
+            target.metrics.warmup().begin(Instant::now());
+        }
+        self.0.health_status_change(target, healthy).await;
+    }
+
+    fn backend_summary(&self, target: &AdaptiveBackend) -> String {
+        self.0.backend_summary(target)
+    }
+
+    fn health_threshold(&self, success: bool) -> usize {
+        self.0.health_threshold(success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weight_factor() {
+        let mut slow_start = SlowStartConfig::new(Duration::from_secs(100));
+        let at = |secs| Duration::from_secs(secs);
+        assert_eq!(slow_start.weight_factor(at(0)), 0.1);
+        assert!((slow_start.weight_factor(at(50)) - 0.55).abs() < 1e-9);
+        assert_eq!(slow_start.weight_factor(at(100)), 1.0);
+
+        slow_start.curve = SlowStartCurve::Exponential;
+        assert!((slow_start.weight_factor(at(0)) - 0.1).abs() < 1e-9);
+        assert!((slow_start.weight_factor(at(50)) - 0.1f64.sqrt()).abs() < 1e-9);
+        assert!(slow_start.weight_factor(at(99)) < 1.0);
+        assert_eq!(slow_start.weight_factor(at(150)), 1.0);
+    }
+
+    #[test]
+    fn test_warming_backend_gets_a_share_of_requests() {
+        let slow_start = SlowStartConfig::new(Duration::from_secs(100));
+        let backend = AdaptiveBackend::build("127.0.0.1:8080", 1).unwrap();
+        let now = Instant::now();
+        assert!(backend.metrics.warmup().elapsed(now).is_none());
+        assert!((0..100).all(|_| slow_start.admits(&backend, now)));
+
+        backend.metrics.warmup().begin(now);
+        let admitted = (0..10_000)
+            .filter(|_| slow_start.admits(&backend, now))
+            .count();
+        // 10% of the requests, give or take
+        assert!((700..1300).contains(&admitted), "admitted {admitted}");
+
+        let warm = now + Duration::from_secs(100);
+        assert!((0..100).all(|_| slow_start.admits(&backend, warm)));
+    }
+}
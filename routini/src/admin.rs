@@ -0,0 +1,516 @@
+//! Admin API: JSON endpoints to inspect routes and backends, steer load balancing and reload the
+//! config at runtime. Served over HTTP/1.1 and h2c, on localhost unless configured otherwise.
+//!
+//! | Method | Path                | Action                                                       |
+//! |--------|---------------------|--------------------------------------------------------------|
+//! | `GET`  | `/routes`           | List every route: default server, virtual hosts, regex       |
+//! | `GET`  | `/backends`         | A pool's backends: health, state, connections, EWMA          |
+//! | `GET`  | `/strategy`         | A pool's current strategy                                    |
+//! | `PUT`  | `/strategy`         | Switch strategy: `{"strategy": "FewestConnections"}`         |
+//! | `POST` | `/backends/enable`  | Put a disabled or draining `backend` back into rotation      |
+//! | `POST` | `/backends/disable` | Take `backend` out of rotation                               |
+//! | `POST` | `/backends/drain`   | Only send `backend` requests pinned to it by a sticky cookie |
+//! | `PUT`  | `/backends/weight`  | Set `backend`'s weight: `{"weight": 3}`                      |
+//! | `POST` | `/reload`           | Re-read the config file                                      |
+//!
+//! Route-scoped endpoints pick the route with query parameters: `path` as configured (e.g.
+//! `/api/*`) and optionally `host`, `regex=true`, `ordinal` (position among routes sharing the
+//! host and path) and `pool` (`primary` by default, a split pool or `mirror`). `backend` is an
+//! upstream address such as `127.0.0.1:8080`. With a token configured every request must carry
+//! `Authorization: Bearer <token>`.
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use http::{Method, Response, StatusCode, header};
+use pingora::{
+    apps::{
+        HttpServerOptions,
+        http_app::{HttpServer, ServeHttp},
+    },
+    http::RequestHeader,
+    protocols::http::ServerSession,
+    services::listening::Service,
+};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    adaptive_loadbalancer::AdaptiveBackend,
+    load_balancing::{Metrics, strategy::Adaptive},
+    reload::{ReloadTrigger, Reloader},
+    route::{MIRROR_POOL, PRIMARY_POOL, RouteRuntime, SharedLb, query_param},
+    utils::constants::{ADMIN_ENDPOINT_NAME, DEFAULT_WILDCARD_IDENTIFIER},
+};
+
+/// Largest request body the admin API accepts.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+type ApiError = (StatusCode, String);
+type ApiResult<T> = Result<T, ApiError>;
+
+pub struct AdminApi {
+    reloader: Arc<Reloader>,
+    /// Bearer token required on every request; `None` leaves the API open.
+    token: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RouteInfo {
+    host: Option<String>,
+    /// The path as configured (`/api/*`), or the regex of a regex location.
+    path: String,
+    regex: bool,
+    ordinal: usize,
+    pools: Vec<String>,
+    mirror: bool,
+}
+
+#[derive(Serialize)]
+struct BackendInfo {
+    address: String,
+    weight: usize,
+    healthy: bool,
+    enabled: bool,
+    draining: bool,
+    active_connections: Option<usize>,
+    latency_ewma_ms: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StrategyBody {
+    strategy: Adaptive,
+}
+
+#[derive(Deserialize)]
+struct WeightBody {
+    weight: usize,
+}
+
+impl AdminApi {
+    pub fn new(reloader: Arc<Reloader>, token: Option<String>) -> Self {
+        Self { reloader, token }
+    }
+
+    pub fn service(
+        reloader: Arc<Reloader>,
+        token: Option<String>,
+        address: &str,
+    ) -> Service<HttpServer<Self>> {
+        let mut app = HttpServer::new_app(Self::new(reloader, token));
+        let mut options = HttpServerOptions::default();
+        options.h2c = true;
+        app.server_options = Some(options);
+        let mut service = Service::new(ADMIN_ENDPOINT_NAME.to_string(), app);
+        service.add_tcp(address);
+        service
+    }
+
+    fn authorized(&self, req: &RequestHeader) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        let Some(given) = req
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        else {
+            return false;
+        };
+        given.as_bytes().ct_eq(token.as_bytes()).into()
+    }
+
+    async fn handle(&self, req: &RequestHeader, body: &[u8]) -> ApiResult<Response<Vec<u8>>> {
+        match (&req.method, req.uri.path()) {
+            (&Method::GET, "/routes") => json(&self.routes()),
+            (&Method::GET, "/backends") => {
+                let lb = self.pool(req)?;
+                let backends: Vec<_> = lb.backends().iter().map(|b| backend_info(&lb, b)).collect();
+                json(&backends)
+            }
+            (&Method::GET, "/strategy") => {
+                let strategy = self.pool(req)?.current_strategy().await;
+                json(&StrategyBody { strategy })
+            }
+            (&Method::PUT, "/strategy") => {
+                let lb = self.pool(req)?;
+                let StrategyBody { strategy } = parse(body)?;
+                if lb.update_strategy(strategy.clone()).await {
+                    tracing::info!("Admin: strategy updated to {strategy}");
+                }
+                json(&StrategyBody { strategy })
+            }
+            (&Method::POST, "/backends/enable") => {
+                let (lb, backend) = self.backend(req)?;
+                lb.set_draining(&backend, false);
+                lb.set_backend_enabled(&backend, true);
+                tracing::info!("Admin: enabled backend {}", backend.addr);
+                json(&backend_info(&lb, &backend))
+            }
+            (&Method::POST, "/backends/disable") => {
+                let (lb, backend) = self.backend(req)?;
+                lb.set_backend_enabled(&backend, false);
+                tracing::info!("Admin: disabled backend {}", backend.addr);
+                json(&backend_info(&lb, &backend))
+            }
+            (&Method::POST, "/backends/drain") => {
+                let (lb, backend) = self.backend(req)?;
+                lb.set_draining(&backend, true);
+                tracing::info!("Admin: draining backend {}", backend.addr);
+                json(&backend_info(&lb, &backend))
+            }
+            (&Method::PUT, "/backends/weight") => {
+                let (lb, backend) = self.backend(req)?;
+                let WeightBody { weight } = parse(body)?;
+                if weight == 0 {
+                    return Err(bad_request("weight must be at least 1"));
+                }
+                lb.set_weight(&backend, weight).await;
+                tracing::info!("Admin: set weight of backend {} to {weight}", backend.addr);
+                let backend = lb
+                    .find_backend(&backend.addr.to_string())
+                    .unwrap_or(backend);
+                json(&backend_info(&lb, &backend))
+            }
+            (&Method::POST, "/reload") => {
+                let summary = self
+                    .reloader
+                    .reload(ReloadTrigger::Admin)
+                    .await
+                    .map_err(|err| {
+                        let message = err.chain().map(ToString::to_string).collect::<Vec<_>>();
+                        bad_request(message.join(": "))
+                    })?;
+                tracing::info!("Admin: config reloaded ({summary:?})");
+                json(&summary)
+            }
+            (
+                _,
+                "/routes" | "/backends" | "/strategy" | "/backends/enable" | "/backends/disable"
+                | "/backends/drain" | "/backends/weight" | "/reload",
+            ) => Err((
+                StatusCode::METHOD_NOT_ALLOWED,
+                format!("{} not allowed on {}", req.method, req.uri.path()),
+            )),
+            _ => Err((StatusCode::NOT_FOUND, "unknown endpoint".to_string())),
+        }
+    }
+
+    fn routes(&self) -> Vec<RouteInfo> {
+        self.reloader
+            .routes()
+            .into_iter()
+            .map(|((host, regex, path, ordinal), runtime)| {
+                let path = if regex {
+                    path
+                } else {
+                    path.replacen(DEFAULT_WILDCARD_IDENTIFIER, "*", 1)
+                };
+                let mut pools: Vec<String> = runtime.pools.keys().cloned().collect();
+                pools.sort();
+                pools.insert(0, PRIMARY_POOL.to_string());
+                RouteInfo {
+                    host: (!host.is_empty()).then_some(host),
+                    path,
+                    regex,
+                    ordinal,
+                    pools,
+                    mirror: runtime.mirror.is_some(),
+                }
+            })
+            .collect()
+    }
+
+    /// The route picked by the `path`, `host`, `regex` and `ordinal` query parameters.
+    fn route(&self, req: &RequestHeader) -> ApiResult<Arc<RouteRuntime>> {
+        let Some(path) = param(req, "path") else {
+            return Err(bad_request("missing `path` query parameter"));
+        };
+        let regex = param(req, "regex").is_some_and(|value| value == "true");
+        let ordinal = match param(req, "ordinal") {
+            Some(value) => value
+                .parse()
+                .map_err(|_| bad_request("invalid `ordinal` query parameter"))?,
+            None => 0,
+        };
+        let path = if regex || path.contains(DEFAULT_WILDCARD_IDENTIFIER) {
+            path
+        } else {
+            path.replacen('*', DEFAULT_WILDCARD_IDENTIFIER, 1)
+        };
+        let host = param(req, "host").unwrap_or_default().to_ascii_lowercase();
+        self.reloader
+            .route(&(host, regex, path, ordinal))
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "no such route".to_string()))
+    }
+
+    /// The load balancer of the selected route's `pool` (default [`PRIMARY_POOL`]).
+    fn pool(&self, req: &RequestHeader) -> ApiResult<SharedLb> {
+        let runtime = self.route(req)?;
+        let name = param(req, "pool").unwrap_or_else(|| PRIMARY_POOL.to_string());
+        let lb = if name == MIRROR_POOL {
+            runtime.mirror.as_ref()
+        } else {
+            runtime.pool(&name)
+        };
+        lb.cloned().ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                format!("no pool '{name}' on this route"),
+            )
+        })
+    }
+
+    /// The selected pool and its backend named by the `backend` query parameter.
+    fn backend(&self, req: &RequestHeader) -> ApiResult<(SharedLb, AdaptiveBackend)> {
+        let lb = self.pool(req)?;
+        let Some(addr) = param(req, "backend") else {
+            return Err(bad_request("missing `backend` query parameter"));
+        };
+        let Some(backend) = lb.find_backend(&addr) else {
+            return Err((
+                StatusCode::NOT_FOUND,
+                format!("no backend {addr} in this pool"),
+            ));
+        };
+        Ok((lb, backend))
+    }
+}
+
+#[async_trait]
+impl ServeHttp for AdminApi {
+    async fn response(&self, session: &mut ServerSession) -> Response<Vec<u8>> {
+        if !self.authorized(session.req_header()) {
+            let mut response =
+                error_response(StatusCode::UNAUTHORIZED, "missing or invalid bearer token");
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+            return response;
+        }
+
+        let result = match read_body(session).await {
+            Ok(body) => self.handle(session.req_header(), &body).await,
+            Err(err) => Err(err),
+        };
+        result.unwrap_or_else(|(status, message)| error_response(status, &message))
+    }
+}
+
+fn backend_info(lb: &SharedLb, backend: &AdaptiveBackend) -> BackendInfo {
+    BackendInfo {
+        address: backend.addr.to_string(),
+        weight: backend.weight,
+        healthy: lb.is_healthy(backend),
+        enabled: lb.is_enabled(backend),
+        draining: lb.is_draining(backend),
+        active_connections: backend.metrics.active_connections(),
+        latency_ewma_ms: backend.metrics.average_latency(),
+    }
+}
+
+async fn read_body(session: &mut ServerSession) -> ApiResult<Vec<u8>> {
+    let mut body = Vec::new();
+    while let Some(chunk) = session
+        .read_request_body()
+        .await
+        .map_err(|err| bad_request(err.to_string()))?
+    {
+        if body.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "request body too large".to_string(),
+            ));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+fn parse<'a, T: Deserialize<'a>>(body: &'a [u8]) -> ApiResult<T> {
+    serde_json::from_slice(body).map_err(|err| bad_request(format!("invalid JSON body: {err}")))
+}
+
+/// A percent-decoded query parameter.
+fn param(req: &RequestHeader, name: &str) -> Option<String> {
+    query_param(req, name).map(percent_decode)
+}
+
+/// Decode `%XX` escapes; malformed escapes are kept as they are.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn bad_request(message: impl Into<String>) -> ApiError {
+    (StatusCode::BAD_REQUEST, message.into())
+}
+
+fn json<T: Serialize>(value: &T) -> ApiResult<Response<Vec<u8>>> {
+    let body = serde_json::to_vec(value)
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    Ok(response(StatusCode::OK, body))
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Vec<u8>> {
+    let body = serde_json::json!({ "error": message })
+        .to_string()
+        .into_bytes();
+    response(status, body)
+}
+
+fn response(status: StatusCode, body: Vec<u8>) -> Response<Vec<u8>> {
+    let mut response = Response::new(Vec::new());
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
+    headers.insert(header::CONTENT_LENGTH, body.len().into());
+    *response.body_mut() = body;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lb_tasks::LbTasks,
+        proxy::{Proxy, VHostRoutes},
+        reload::{RouteRegistry, UpstreamRegistry},
+        server_builder::Route,
+    };
+
+    async fn admin(token: Option<&str>) -> AdminApi {
+        let proxy = Proxy::with_vhost_routes(VHostRoutes::default(), Vec::new(), 16).unwrap();
+        let tasks = Arc::new(LbTasks::default());
+        let reloader = Reloader::new(
+            None,
+            proxy,
+            tasks,
+            RouteRegistry::new(),
+            UpstreamRegistry::new(),
+        );
+        let routes = vec![
+            Route::new("/api/*", ["127.0.0.1:8080"], Adaptive::default()).unwrap(),
+            Route::new("/", ["127.0.0.1:8081"], Adaptive::default())
+                .unwrap()
+                .host("Example.com"),
+        ];
+        reloader.apply(routes).await.unwrap();
+        AdminApi::new(Arc::new(reloader), token.map(str::to_string))
+    }
+
+    fn request(method: &str, uri: &str) -> RequestHeader {
+        RequestHeader::build(method, uri.as_bytes(), None).unwrap()
+    }
+
+    fn body_json(response: Response<Vec<u8>>) -> serde_json::Value {
+        serde_json::from_slice(response.body()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_bearer_token() {
+        let api = admin(Some("secret")).await;
+        let mut req = request("GET", "/routes");
+        assert!(!api.authorized(&req));
+        req.insert_header("authorization", "Bearer wrong!").unwrap();
+        assert!(!api.authorized(&req));
+        req.insert_header("authorization", "Bearer secret").unwrap();
+        assert!(api.authorized(&req));
+        assert!(admin(None).await.authorized(&request("GET", "/routes")));
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("%2Fapi%2F*"), "/api/*");
+        assert_eq!(percent_decode("a%zz%2"), "a%zz%2");
+    }
+
+    #[tokio::test]
+    async fn test_list_routes() {
+        let api = admin(None).await;
+        let routes = body_json(api.handle(&request("GET", "/routes"), &[]).await.unwrap());
+        let routes = routes.as_array().unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0]["path"], "/api/*");
+        assert_eq!(routes[0]["host"], serde_json::Value::Null);
+        assert_eq!(routes[1]["host"], "example.com");
+        assert_eq!(routes[1]["pools"][0], "primary");
+    }
+
+    #[tokio::test]
+    async fn test_strategy_round_trip() {
+        let api = admin(None).await;
+        let uri = "/strategy?path=/api/*";
+        let body = br#"{"strategy": "FewestConnections"}"#;
+        api.handle(&request("PUT", uri), body).await.unwrap();
+        let strategy = body_json(api.handle(&request("GET", uri), &[]).await.unwrap());
+        assert_eq!(strategy["strategy"], "FewestConnections");
+    }
+
+    #[tokio::test]
+    async fn test_route_selection_errors() {
+        let api = admin(None).await;
+        let missing = api.handle(&request("GET", "/backends"), &[]).await;
+        assert_eq!(missing.unwrap_err().0, StatusCode::BAD_REQUEST);
+        let unknown = api
+            .handle(&request("GET", "/backends?path=/nope"), &[])
+            .await;
+        assert_eq!(unknown.unwrap_err().0, StatusCode::NOT_FOUND);
+        let vhost = api
+            .handle(&request("GET", "/backends?path=/&host=example.com"), &[])
+            .await;
+        assert!(vhost.is_ok());
+        let method = api.handle(&request("DELETE", "/routes"), &[]).await;
+        assert_eq!(method.unwrap_err().0, StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn test_mirror_pool() {
+        let api = admin(None).await;
+        let route = Route::new("/shadowed/*", ["127.0.0.1:8080"], Adaptive::default())
+            .unwrap()
+            .pool(
+                "canary",
+                [("127.0.0.1:8081".to_string(), 1)],
+                Default::default(),
+            )
+            .unwrap()
+            .mirror([("127.0.0.1:9090".to_string(), 1)], Default::default())
+            .unwrap();
+        api.reloader.apply(vec![route]).await.unwrap();
+
+        let backends =
+            |pool: &str| request("GET", &format!("/backends?path=/shadowed/*&pool={pool}"));
+        let mirror = body_json(api.handle(&backends("mirror"), &[]).await.unwrap());
+        assert_eq!(mirror[0]["address"], "127.0.0.1:9090");
+        let canary = body_json(api.handle(&backends("canary"), &[]).await.unwrap());
+        assert_eq!(canary[0]["address"], "127.0.0.1:8081");
+        let unknown = api.handle(&backends("nope"), &[]).await;
+        assert_eq!(unknown.unwrap_err().0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_reload_without_config_path() {
+        let api = admin(None).await;
+        let result = api.handle(&request("POST", "/reload"), &[]).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::BAD_REQUEST);
+    }
+}
@@ -3,22 +3,45 @@
 //! The shapes here mirror `config.json` and convert into the builder types in
 //! [`crate::server_builder`], so `main` can construct the whole server from a file instead of
 //! hard-coded values.
-use std::{collections::HashMap, net::IpAddr, time::Duration};
+pub mod check;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::IpAddr,
+    ops::RangeInclusive,
+    path::Path,
+    time::Duration,
+};
 
 use base64::prelude::{BASE64_STANDARD, Engine};
-use color_eyre::eyre::{Result, WrapErr};
-use http::{HeaderName, HeaderValue};
+use color_eyre::eyre::{Result, WrapErr, eyre};
+use http::{HeaderName, HeaderValue, Method, uri::PathAndQuery};
 use ipnet::IpNet;
+use regex::Regex;
 use serde::Deserialize;
 
 use crate::{
-    adaptive_loadbalancer::options::AdaptiveLbOpt,
+    access_log::{AccessLog, AccessLogFilter, AccessLogFormat, LogRotation},
+    adaptive_loadbalancer::{
+        circuit_breaker::CircuitBreakerOpt,
+        health_check::{BodyMatch, GrpcCheck, HealthCheckKind, HealthCheckOpt, HttpCheck},
+        options::AdaptiveLbOpt,
+        outlier_detection::OutlierDetectionOpt,
+        slow_start::{SlowStartConfig, SlowStartCurve},
+    },
     load_balancing::strategy::Adaptive,
+    proxy::HostPattern,
     route::{
-        AccessControl, CacheConfig, HeaderRules, HostRewrite, PassiveHealthConfig, RetryConfig,
-        RouteAction, RouteConfig, TimeoutConfig, UpstreamTls,
+        AccessControl, Backoff, CacheConfig, FailureRateConfig, HashKey, HeaderRules, HostRewrite,
+        MirrorConfig, PRIMARY_POOL, PassiveHealthConfig, RequestPredicates, RetryBudgetConfig,
+        RetryConfig, RewriteFlag, RewriteRule, RouteAccessLog, RouteAction, RouteConfig,
+        StickyConfig, TimeoutConfig, TrafficSplit, UpstreamTls, ValueMatch,
     },
     server_builder::{Route, TlsConfig as BuilderTlsConfig},
+    utils::constants::{
+        DEFAULT_FAILURE_RATE_MIN_REQUESTS, DEFAULT_FAILURE_RATE_WINDOW, DEFAULT_HEALTH_CHECK_HOST,
+        DEFAULT_RETRY_BACKOFF_MAX, DEFAULT_RETRY_BUDGET_BURST, MAX_RETRY_BODY_SIZE,
+    },
 };
 
 /// Parse a CIDR network, or a bare IP address as a host network.
@@ -83,7 +106,7 @@ impl Config {
         self.proxy
             .router
             .iter()
-            .map(RouteEntry::to_route)
+            .map(|entry| entry.to_route(&self.proxy.upstreams))
             .collect()
     }
 
@@ -96,18 +119,32 @@ impl Config {
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct ServerConfig {
     pub prometheus_address: Option<String>,
-    pub set_strategy_endpoint: Option<String>,
-    /// Per-request access log (nginx `access_log on/off`). Defaults to on when omitted.
-    pub access_log: Option<bool>,
+    /// Admin API listen address. Defaults to `127.0.0.1:5000`.
+    #[serde(alias = "set_strategy_endpoint")]
+    pub admin_address: Option<String>,
+    /// Bearer token required by the admin API. Omitted = no authentication.
+    pub admin_token: Option<String>,
+    /// Per-request access log (nginx `access_log`): `true`/`false`, or its format, file and
+    /// filters. Defaults to on when omitted.
+    pub access_log: Option<AccessLogInput>,
     /// Redirect plain-HTTP requests to `https://`. Defaults to off.
     pub https_redirect: Option<bool>,
     /// Downstream response compression level (nginx `gzip`); 0/omitted disables.
     pub compression_level: Option<u32>,
     /// Generate/propagate an `X-Request-Id` header for tracing. Defaults to off.
     pub request_id: Option<bool>,
+    /// Propagate W3C `traceparent`/`tracestate` and record request spans. Defaults to off.
+    pub trace_context: Option<bool>,
+    /// OTLP/HTTP collector the request spans are exported to, e.g. `http://otel-collector:4318`.
+    /// Omitted = `OTEL_EXPORTER_OTLP_ENDPOINT`, or no export. Read at startup only.
+    pub otlp_endpoint: Option<String>,
     /// Custom error-page bodies keyed by status code (nginx `error_page`).
     #[serde(default)]
     pub error_pages: HashMap<u16, String>,
+    /// Reload the config when its file changes, in addition to on `SIGHUP`. Defaults to off.
+    pub watch_config: Option<bool>,
+    /// How long the file must stay unchanged before a change is reloaded. Defaults to 1000.
+    pub watch_config_debounce_ms: Option<u64>,
     /// Tokio worker threads (nginx `worker_processes`). Omitted = Pingora default.
     pub worker_threads: Option<usize>,
     /// Upstream keepalive connection pool size. Omitted = 200000.
@@ -133,6 +170,11 @@ pub struct ProxyConfig {
     pub listener: u16,
     #[serde(default)]
     pub tls: Option<TlsConfig>,
+    /// Named upstream pools that routes reference by name (nginx `upstream`). Routes naming the
+    /// same upstream share its load balancer, health check and metrics; each sets its own
+    /// `hash_key`.
+    #[serde(default)]
+    pub upstreams: HashMap<String, LoadBalancerConfig>,
     pub router: Vec<RouteEntry>,
 }
 
@@ -159,17 +201,27 @@ impl TlsConfig {
 #[derive(Debug, Clone, Deserialize)]
 pub struct RouteEntry {
     pub path: String,
-    /// Optional virtual host (nginx `server_name`); routes with no host form the default server.
+    /// Optional virtual host (nginx `server_name`): an exact name, `*.example.com`,
+    /// `www.example.*` or `~regex`. Routes with no host form the default server.
     pub host: Option<String>,
-    /// Treat `path` as a regex location (default-server only) instead of a matchit prefix.
+    /// Treat `path` as a regex location instead of a matchit prefix.
     #[serde(default)]
     pub regex: bool,
+    /// Request conditions (method, headers, query, cookies) that select between routes sharing
+    /// a path; tried in declared order.
+    #[serde(default, rename = "match")]
+    pub predicates: MatchInput,
     /// Short-circuit response (redirect/return) instead of proxying.
     pub action: Option<ActionInput>,
+    /// Path rewrite rules (nginx `rewrite`), tried in order.
+    #[serde(default)]
+    pub rewrite: Vec<RewriteInput>,
     /// Response caching (nginx `proxy_cache`).
     pub cache: Option<CacheInput>,
     /// IP allow/deny + Basic auth (nginx `allow`/`deny`/`auth_basic`).
     pub access: Option<AccessInput>,
+    /// Cookie-based session affinity (nginx `sticky cookie`).
+    pub sticky: Option<StickyInput>,
     #[serde(default = "default_true")]
     pub strip_prefix: bool,
     #[serde(default)]
@@ -190,13 +242,33 @@ pub struct RouteEntry {
     pub rate_limit_rps: Option<f64>,
     /// Max concurrent requests per client IP (nginx `limit_conn`).
     pub max_connections: Option<usize>,
+    /// Name of the entry in `upstreams` serving as the primary pool, instead of `load_balancer`'s
+    /// own upstreams (nginx `proxy_pass http://<upstream>`).
+    pub upstream: Option<String>,
+    /// The primary upstream pool. With `upstream` set, only its `hash_key` applies.
+    #[serde(default)]
     pub load_balancer: LoadBalancerConfig,
+    /// Additional named upstream pools (e.g. a canary), each with its own load balancer, health
+    /// check and adaptive engine. Their `hash_key` is ignored in favour of the primary's.
+    /// `primary` and `mirror` name the route's own pools and cannot be used.
+    #[serde(default)]
+    pub pools: HashMap<String, LoadBalancerConfig>,
+    /// Weighted split between `"primary"` and the named `pools`; reloadable.
+    pub split: Option<SplitInput>,
+    /// Copy a sample of requests to a shadow pool, discarding its responses (nginx `mirror`).
+    pub mirror: Option<MirrorInput>,
+    /// Access logging for this route: off, or a sample of its requests.
+    #[serde(default)]
+    pub access_log: RouteAccessLogInput,
+    /// Ramp up backends joining or rejoining a pool (nginx Plus `slow_start`).
+    pub slow_start: Option<SlowStartInput>,
 }
 
 impl RouteEntry {
-    fn to_route(&self) -> Result<Route> {
-        let mut upstreams = self
-            .load_balancer
+    /// Build the route, resolving `upstream` among the config's named `upstreams`.
+    fn to_route(&self, upstreams: &HashMap<String, LoadBalancerConfig>) -> Result<Route> {
+        let load_balancer = self.primary_pool(upstreams)?;
+        let mut upstreams = load_balancer
             .upstreams
             .iter()
             .map(|u| (u.address.clone(), u.weight))
@@ -210,27 +282,68 @@ impl RouteEntry {
             upstreams.push(("127.0.0.1:1".to_string(), 1));
         }
 
-        let lb_opt = self.load_balancer.to_lb_opt();
+        let lb_opt = load_balancer.to_lb_opt()?;
         let built = if self.regex {
             Route::regex(&self.path, upstreams, lb_opt)?
         } else {
             Route::with_weighted_backends(&self.path, upstreams, lb_opt)?
         };
         let mut route = built.route_config(config);
+        for (name, pool) in &self.pools {
+            let upstreams = pool.upstreams.iter().map(|u| (u.address.clone(), u.weight));
+            route = pool
+                .to_lb_opt()
+                .and_then(|lb_opt| route.pool(name.clone(), upstreams, lb_opt))
+                .wrap_err_with(|| format!("Invalid upstream pool '{name}'"))?;
+        }
+        if let Some(mirror) = &self.mirror {
+            let upstreams = mirror
+                .upstreams
+                .iter()
+                .map(|u| (u.address.clone(), u.weight));
+            route = route
+                .mirror(upstreams, AdaptiveLbOpt::default())
+                .wrap_err("Invalid mirror upstreams")?;
+        }
         if let Some(host) = &self.host {
+            HostPattern::parse(host).wrap_err_with(|| format!("Invalid host '{host}'"))?;
             route = route.host(host.clone());
         }
+        if let Some(name) = &self.upstream {
+            route = route.upstream(name.clone());
+        }
         Ok(route)
     }
 
+    /// The named upstream the route references, or its own `load_balancer`.
+    fn primary_pool<'a>(
+        &'a self,
+        upstreams: &'a HashMap<String, LoadBalancerConfig>,
+    ) -> Result<&'a LoadBalancerConfig> {
+        let Some(name) = &self.upstream else {
+            return Ok(&self.load_balancer);
+        };
+        if !self.load_balancer.upstreams.is_empty() {
+            return Err(eyre!(
+                "Route references upstream '{name}' but also lists its own upstreams"
+            ));
+        }
+        upstreams
+            .get(name)
+            .ok_or_else(|| eyre!("Unknown upstream '{name}'"))
+    }
+
     /// Build just the per-route runtime config (no backends/LB). Used for hot reload.
     pub fn route_config(&self) -> Result<RouteConfig> {
         Ok(RouteConfig {
             strip_path_prefix: self.strip_prefix,
             headers: self.headers.to_rules()?,
             timeouts: self.timeouts.to_timeouts(),
-            retry: self.retry.to_retry(),
-            passive_health: self.passive_health.to_config(),
+            retry: self.retry.to_retry().wrap_err("Invalid retry")?,
+            passive_health: self
+                .passive_health
+                .to_config()
+                .wrap_err("Invalid passive_health")?,
             max_body_size: self.max_body_size,
             upstream_tls: self.upstream_tls.to_upstream_tls(),
             hsts: self.hsts.clone(),
@@ -239,6 +352,36 @@ impl RouteEntry {
             action: self.action.as_ref().map(ActionInput::to_action),
             cache: self.cache.as_ref().map(CacheInput::to_cache),
             access: self.access.as_ref().map(AccessInput::to_access).transpose()?,
+            hash_key: self
+                .load_balancer
+                .hash_key
+                .as_ref()
+                .map(HashKeyInput::to_hash_key)
+                .transpose()?,
+            sticky: self.sticky.as_ref().map(StickyInput::to_sticky),
+            predicates: self.predicates.to_predicates()?,
+            rewrites: self
+                .rewrite
+                .iter()
+                .map(RewriteInput::to_rule)
+                .collect::<Result<_>>()?,
+            split: self
+                .split
+                .as_ref()
+                .map(|split| split.to_split(&self.pools))
+                .transpose()?,
+            mirror: self
+                .mirror
+                .as_ref()
+                .map(MirrorInput::to_mirror)
+                .transpose()?,
+            access_log: self.access_log.to_access_log()?,
+            slow_start: self
+                .slow_start
+                .as_ref()
+                .map(SlowStartInput::to_slow_start)
+                .transpose()
+                .wrap_err("Invalid slow_start")?,
         })
     }
 
@@ -260,6 +403,117 @@ impl RouteEntry {
     }
 }
 
+/// Server access log: `true`/`false`, or e.g.
+/// `{"format": "combined", "path": "/var/log/routini/access.log", "min_status": 500}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum AccessLogInput {
+    Enabled(#[serde(deserialize_with = "flag")] bool),
+    Config(AccessLogConfigInput),
+}
+
+/// A boolean, also given as the string `"true"` or `"false"` by an environment override where an
+/// untagged enum can't tell it should be parsed.
+fn flag<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Flag {
+        Bool(bool),
+        String(String),
+    }
+    match Flag::deserialize(deserializer)? {
+        Flag::Bool(enabled) => Ok(enabled),
+        Flag::String(enabled) => enabled.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccessLogConfigInput {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// `default`, `combined`, `json` or a template of `$variables` (nginx `log_format`).
+    #[serde(default = "default_access_log_format")]
+    pub format: String,
+    /// Dedicated access-log file; omitted = the main log.
+    pub path: Option<String>,
+    /// `hourly`, `daily` (default) or `never`.
+    #[serde(default)]
+    pub rotation: LogRotation,
+    /// Rotated files to keep; omitted = all.
+    pub max_files: Option<usize>,
+    /// Only log requests answered with at least this status, or that failed. With
+    /// `slower_than_ms` also set, a request meeting either condition is logged.
+    pub min_status: Option<u16>,
+    /// Only log requests that took at least this many milliseconds.
+    pub slower_than_ms: Option<u64>,
+}
+
+fn default_access_log_format() -> String {
+    "default".to_string()
+}
+
+impl AccessLogInput {
+    /// The configured access log, `None` when disabled.
+    pub fn to_access_log(&self) -> Result<Option<AccessLog>> {
+        let config = match self {
+            AccessLogInput::Enabled(false) => return Ok(None),
+            AccessLogInput::Enabled(true) => return Ok(Some(AccessLog::default())),
+            AccessLogInput::Config(config) if !config.enabled => return Ok(None),
+            AccessLogInput::Config(config) => config,
+        };
+        let format = AccessLogFormat::parse(&config.format).map_err(|err| eyre!(err))?;
+        let filter = AccessLogFilter {
+            min_status: config.min_status,
+            slower_than: config.slower_than_ms.map(Duration::from_millis),
+        };
+        let mut log = AccessLog::new(format, filter);
+        if let Some(path) = &config.path {
+            log = log
+                .with_file(Path::new(path), config.rotation, config.max_files)
+                .map_err(|err| eyre!(err))?;
+        }
+        Ok(Some(log))
+    }
+}
+
+/// Per-route access log, e.g. `{"sample_percent": 10}` or `{"enabled": false}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteAccessLogInput {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Percentage of the route's requests to log (default 100).
+    #[serde(default = "default_access_log_sample_percent")]
+    pub sample_percent: f64,
+}
+
+impl Default for RouteAccessLogInput {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sample_percent: default_access_log_sample_percent(),
+        }
+    }
+}
+
+fn default_access_log_sample_percent() -> f64 {
+    100.0
+}
+
+impl RouteAccessLogInput {
+    fn to_access_log(&self) -> Result<RouteAccessLog> {
+        if !(0.0..=100.0).contains(&self.sample_percent) {
+            return Err(eyre!(
+                "access_log sample_percent must be between 0 and 100, got {}",
+                self.sample_percent
+            ));
+        }
+        Ok(RouteAccessLog {
+            enabled: self.enabled,
+            sample_percent: self.sample_percent,
+        })
+    }
+}
+
 /// Access control config (nginx `allow`/`deny`/`auth_basic`).
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct AccessInput {
@@ -288,6 +542,76 @@ impl AccessInput {
     }
 }
 
+/// Route match conditions, e.g.
+/// `{"methods": ["GET"], "headers": {"accept": {"equals": "application/vnd.api.v2+json"}}}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MatchInput {
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, ValueMatchInput>,
+    #[serde(default)]
+    pub query: HashMap<String, ValueMatchInput>,
+    #[serde(default)]
+    pub cookies: HashMap<String, ValueMatchInput>,
+}
+
+/// `"present"`, `"absent"`, `{"equals": "..."}` or `{"regex": "..."}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueMatchInput {
+    Present,
+    Absent,
+    Equals(String),
+    Regex(String),
+}
+
+impl ValueMatchInput {
+    fn to_value_match(&self) -> Result<ValueMatch> {
+        Ok(match self {
+            ValueMatchInput::Present => ValueMatch::Present,
+            ValueMatchInput::Absent => ValueMatch::Absent,
+            ValueMatchInput::Equals(value) => ValueMatch::Equals(value.clone()),
+            ValueMatchInput::Regex(re) => ValueMatch::Regex(
+                Regex::new(re).wrap_err_with(|| format!("Invalid match regex '{re}'"))?,
+            ),
+        })
+    }
+}
+
+impl MatchInput {
+    fn to_predicates(&self) -> Result<RequestPredicates> {
+        let methods = self
+            .methods
+            .iter()
+            .map(|m| {
+                Method::from_bytes(m.to_ascii_uppercase().as_bytes())
+                    .wrap_err_with(|| format!("Invalid match method '{m}'"))
+            })
+            .collect::<Result<_>>()?;
+        let headers = self
+            .headers
+            .iter()
+            .map(|(name, m)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .wrap_err_with(|| format!("Invalid match header name '{name}'"))?;
+                Ok((name, m.to_value_match()?))
+            })
+            .collect::<Result<_>>()?;
+        let named = |map: &HashMap<String, ValueMatchInput>| {
+            map.iter()
+                .map(|(name, m)| Ok((name.clone(), m.to_value_match()?)))
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(RequestPredicates {
+            methods,
+            headers,
+            query: named(&self.query)?,
+            cookies: named(&self.cookies)?,
+        })
+    }
+}
+
 /// Response caching config (nginx `proxy_cache`).
 #[derive(Debug, Clone, Deserialize)]
 pub struct CacheInput {
@@ -310,6 +634,118 @@ impl CacheInput {
     }
 }
 
+/// Traffic split between a route's upstream pools, e.g.
+/// `{"weights": {"primary": 95, "canary": 5}, "override_header": "x-pool"}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SplitInput {
+    pub weights: BTreeMap<String, u32>,
+    /// Header whose value names a pool to force.
+    pub override_header: Option<String>,
+    /// Cookie whose value names a pool to force.
+    pub override_cookie: Option<String>,
+}
+
+impl SplitInput {
+    fn to_split(&self, pools: &HashMap<String, LoadBalancerConfig>) -> Result<TrafficSplit> {
+        if let Some(name) = self
+            .weights
+            .keys()
+            .find(|name| *name != PRIMARY_POOL && !pools.contains_key(*name))
+        {
+            return Err(eyre!("Traffic split references unknown pool '{name}'"));
+        }
+        let override_header = self
+            .override_header
+            .as_ref()
+            .map(|name| {
+                HeaderName::from_bytes(name.as_bytes())
+                    .wrap_err_with(|| format!("Invalid split override header '{name}'"))
+            })
+            .transpose()?;
+        Ok(TrafficSplit {
+            weights: self
+                .weights
+                .iter()
+                .map(|(name, weight)| (name.clone(), *weight))
+                .collect(),
+            override_header,
+            override_cookie: self.override_cookie.clone(),
+        })
+    }
+}
+
+/// Shadow traffic config (nginx `mirror`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct MirrorInput {
+    pub upstreams: Vec<UpstreamConfig>,
+    /// Percentage of requests to copy (default 100).
+    #[serde(default = "default_mirror_sample_percent")]
+    pub sample_percent: f64,
+    /// Forward the request body with the copy (default false).
+    #[serde(default)]
+    pub include_body: bool,
+    /// Requests with larger bodies are not mirrored (default 1 MiB).
+    #[serde(default = "default_mirror_max_body_size")]
+    pub max_body_size: usize,
+}
+
+fn default_mirror_sample_percent() -> f64 {
+    100.0
+}
+
+fn default_mirror_max_body_size() -> usize {
+    1024 * 1024
+}
+
+impl MirrorInput {
+    fn to_mirror(&self) -> Result<MirrorConfig> {
+        if !(0.0..=100.0).contains(&self.sample_percent) {
+            return Err(eyre!(
+                "mirror sample_percent must be between 0 and 100, got {}",
+                self.sample_percent
+            ));
+        }
+        Ok(MirrorConfig {
+            sample_percent: self.sample_percent,
+            include_body: self.include_body,
+            max_body_size: self.max_body_size,
+        })
+    }
+}
+
+/// Sticky-session config (nginx `sticky cookie`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct StickyInput {
+    #[serde(default = "default_sticky_cookie")]
+    pub cookie: String,
+    /// Cookie lifetime in seconds; omit for a session cookie.
+    pub ttl_secs: Option<u64>,
+    #[serde(default = "default_sticky_path")]
+    pub path: String,
+    /// HMAC key for signing the cookie. Without one a random per-process key is used, so
+    /// affinity does not survive restarts or span multiple instances.
+    pub secret: Option<String>,
+}
+
+fn default_sticky_cookie() -> String {
+    "routini_sticky".to_string()
+}
+
+fn default_sticky_path() -> String {
+    "/".to_string()
+}
+
+impl StickyInput {
+    fn to_sticky(&self) -> StickyConfig {
+        StickyConfig::new(
+            self.cookie.clone(),
+            self.ttl_secs.map(Duration::from_secs),
+            self.path.clone(),
+            self.secret.as_deref().map(str::as_bytes),
+        )
+    }
+}
+
 /// Short-circuit response config (nginx `return` / `rewrite ... redirect`).
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -341,6 +777,41 @@ impl ActionInput {
     }
 }
 
+/// A rewrite rule (nginx `rewrite <regex> <replacement> <flag>`), e.g.
+/// `{"regex": "^/api/(\\w+)/(.*)$", "replacement": "/v2/$2?lang=$1", "flag": "last"}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RewriteInput {
+    pub regex: String,
+    pub replacement: String,
+    /// `"last"` (default, forward internally), `"redirect"` (302) or `"permanent"` (301).
+    #[serde(default)]
+    pub flag: RewriteFlagInput,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RewriteFlagInput {
+    #[default]
+    Last,
+    Redirect,
+    Permanent,
+}
+
+impl RewriteInput {
+    fn to_rule(&self) -> Result<RewriteRule> {
+        Ok(RewriteRule {
+            regex: Regex::new(&self.regex)
+                .wrap_err_with(|| format!("Invalid rewrite regex '{}'", self.regex))?,
+            replacement: self.replacement.clone(),
+            flag: match self.flag {
+                RewriteFlagInput::Last => RewriteFlag::Last,
+                RewriteFlagInput::Redirect => RewriteFlag::Redirect,
+                RewriteFlagInput::Permanent => RewriteFlag::Permanent,
+            },
+        })
+    }
+}
+
 /// Header manipulation config for a route. Mirrors nginx `proxy_set_header` / `add_header`.
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct HeadersConfig {
@@ -380,15 +851,37 @@ impl TimeoutsConfig {
     }
 }
 
-/// Per-request failover config (nginx `proxy_next_upstream` for connect errors).
+/// Per-request failover config (nginx `proxy_next_upstream`), e.g.
+/// `{"max_retries": 2, "retry_on_statuses": [503], "backoff_base_ms": 25, "budget_percent": 20}`.
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct RetryConfigInput {
     pub max_retries: Option<usize>,
     pub retry_on_connect_error: Option<bool>,
+    /// Upstream response statuses retried on another backend.
+    #[serde(default)]
+    pub retry_on_statuses: Vec<u16>,
+    /// Retry upstream read timeouts before the response header.
+    #[serde(default)]
+    pub retry_on_timeout: bool,
+    /// Retry connections reset or closed before the response header.
+    #[serde(default)]
+    pub retry_on_reset: bool,
+    /// Also retry non-idempotent methods such as POST once they reached an upstream.
+    #[serde(default)]
+    pub retry_non_idempotent: bool,
+    /// Largest request body replayed on a retry, at most 64 KiB (the default).
+    pub max_body_size: Option<usize>,
+    /// Jittered exponential backoff between attempts, starting from this delay.
+    pub backoff_base_ms: Option<u64>,
+    pub backoff_max_ms: Option<u64>,
+    /// Retry at most this percentage of the route's requests.
+    pub budget_percent: Option<f64>,
+    /// Retries allowed in a burst before the budget's percentage applies.
+    pub budget_burst: Option<u32>,
 }
 
 impl RetryConfigInput {
-    fn to_retry(&self) -> RetryConfig {
+    fn to_retry(&self) -> Result<RetryConfig> {
         let mut retry = RetryConfig::default();
         if let Some(max) = self.max_retries {
             retry.max_retries = max;
@@ -396,7 +889,50 @@ impl RetryConfigInput {
         if let Some(on_connect) = self.retry_on_connect_error {
             retry.retry_on_connect_error = on_connect;
         }
-        retry
+        let statuses = &self.retry_on_statuses;
+        if let Some(status) = statuses.iter().find(|s| !(100..=599).contains(*s)) {
+            return Err(eyre!("Invalid retry status {status}"));
+        }
+        retry.retry_on_statuses = statuses.clone();
+        retry.retry_on_timeout = self.retry_on_timeout;
+        retry.retry_on_reset = self.retry_on_reset;
+        retry.retry_non_idempotent = self.retry_non_idempotent;
+        if let Some(max) = self.max_body_size {
+            if max > MAX_RETRY_BODY_SIZE {
+                return Err(eyre!(
+                    "'max_body_size' {max} exceeds the {MAX_RETRY_BODY_SIZE} byte retry buffer"
+                ));
+            }
+            retry.max_body_size = max;
+        }
+        if let Some(base) = self.backoff_base_ms {
+            let base = positive_millis("backoff_base_ms", base)?;
+            let max = self
+                .backoff_max_ms
+                .map_or(DEFAULT_RETRY_BACKOFF_MAX, Duration::from_millis);
+            if max < base {
+                return Err(eyre!("'backoff_max_ms' must be at least 'backoff_base_ms'"));
+            }
+            retry.backoff = Some(Backoff { base, max });
+        } else if self.backoff_max_ms.is_some() {
+            return Err(eyre!("'backoff_max_ms' requires 'backoff_base_ms'"));
+        }
+        if let Some(percent) = self.budget_percent {
+            if !(percent > 0.0 && percent <= 100.0) {
+                return Err(eyre!("Invalid budget_percent {percent}: not in (0, 100]"));
+            }
+            let burst = self.budget_burst.unwrap_or(DEFAULT_RETRY_BUDGET_BURST);
+            if burst == 0 {
+                return Err(eyre!("'budget_burst' must be at least 1"));
+            }
+            retry.budget = Some(RetryBudgetConfig {
+                ratio: percent / 100.0,
+                burst,
+            });
+        } else if self.budget_burst.is_some() {
+            return Err(eyre!("'budget_burst' requires 'budget_percent'"));
+        }
+        Ok(retry)
     }
 }
 
@@ -423,16 +959,28 @@ impl UpstreamTlsInput {
     }
 }
 
-/// Passive health checking config (nginx `max_fails` / `fail_timeout`).
+/// Passive health checking config (nginx `max_fails` / `fail_timeout`), e.g.
+/// `{"enabled": true, "failure_statuses": [502, 503, 504], "failure_rate": 0.5}`.
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct PassiveHealthInput {
     pub enabled: Option<bool>,
     pub max_fails: Option<u32>,
     pub fail_timeout_secs: Option<u64>,
+    /// Upstream response statuses counted as failures besides connection failures.
+    #[serde(default)]
+    pub failure_statuses: Vec<u16>,
+    /// Count upstream read timeouts as failures.
+    #[serde(default)]
+    pub timeout_is_failure: bool,
+    /// Also eject a backend once this fraction of its requests within the window failed.
+    pub failure_rate: Option<f64>,
+    pub failure_rate_window_secs: Option<u64>,
+    /// Requests a backend must have served within the window before its failure rate counts.
+    pub failure_rate_min_requests: Option<u32>,
 }
 
 impl PassiveHealthInput {
-    fn to_config(&self) -> PassiveHealthConfig {
+    fn to_config(&self) -> Result<PassiveHealthConfig> {
         let mut config = PassiveHealthConfig::default();
         if let Some(enabled) = self.enabled {
             config.enabled = enabled;
@@ -443,7 +991,31 @@ impl PassiveHealthInput {
         if let Some(secs) = self.fail_timeout_secs {
             config.fail_timeout = Duration::from_secs(secs);
         }
-        config
+        let statuses = &self.failure_statuses;
+        if let Some(status) = statuses.iter().find(|s| !(100..=599).contains(*s)) {
+            return Err(eyre!("Invalid failure status {status}"));
+        }
+        config.failure_statuses = statuses.clone();
+        config.timeout_is_failure = self.timeout_is_failure;
+        if let Some(threshold) = self.failure_rate {
+            if !(threshold > 0.0 && threshold <= 1.0) {
+                return Err(eyre!("Invalid failure_rate {threshold}: must be in (0, 1]"));
+            }
+            let window = self
+                .failure_rate_window_secs
+                .map_or(DEFAULT_FAILURE_RATE_WINDOW, Duration::from_secs);
+            if window.is_zero() {
+                return Err(eyre!("'failure_rate_window_secs' must be greater than 0"));
+            }
+            config.failure_rate = Some(FailureRateConfig {
+                threshold,
+                window,
+                min_requests: self
+                    .failure_rate_min_requests
+                    .unwrap_or(DEFAULT_FAILURE_RATE_MIN_REQUESTS),
+            });
+        }
+        Ok(config)
     }
 }
 
@@ -471,7 +1043,7 @@ impl HeadersConfig {
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 pub struct LoadBalancerConfig {
     /// Starting strategy for the adaptive load balancer.
     #[serde(default)]
@@ -481,12 +1053,22 @@ pub struct LoadBalancerConfig {
     pub max_iterations: Option<usize>,
     /// Health check interval in seconds; `0` disables the health check background service.
     pub health_check_interval_secs: Option<u64>,
+    /// How the health check probes the backends; a TCP connect by default.
+    pub health_check: Option<HealthCheckInput>,
+    /// Stop sending requests to failing backends for a while; off by default.
+    pub circuit_breaker: Option<CircuitBreakerInput>,
+    /// Eject backends much slower or more failing than the rest of the pool; off by default.
+    pub outlier_detection: Option<OutlierDetectionInput>,
+    /// Request attribute hashed by the `FNVHash`/`Consistent`/`Maglev`/`Rendezvous` strategies
+    /// (nginx `hash`).
+    pub hash_key: Option<HashKeyInput>,
+    #[serde(default)]
     pub upstreams: Vec<UpstreamConfig>,
 }
 
 impl LoadBalancerConfig {
     /// Merge the config over [`AdaptiveLbOpt::default`], leaving unset fields at their defaults.
-    fn to_lb_opt(&self) -> AdaptiveLbOpt {
+    fn to_lb_opt(&self) -> Result<AdaptiveLbOpt> {
         let mut opt = AdaptiveLbOpt {
             starting_strategy: self.strategy.clone(),
             ..Default::default()
@@ -498,6 +1080,23 @@ impl LoadBalancerConfig {
         if let Some(secs) = self.health_check_interval_secs {
             opt.health_check_interval = (secs > 0).then(|| Duration::from_secs(secs));
         }
+        if let Some(health_check) = &self.health_check {
+            opt.health_check = health_check
+                .to_health_check()
+                .wrap_err("Invalid health_check")?;
+        }
+        if let Some(circuit_breaker) = &self.circuit_breaker {
+            let circuit_breaker = circuit_breaker
+                .to_circuit_breaker()
+                .wrap_err("Invalid circuit_breaker")?;
+            opt.circuit_breaker = Some(circuit_breaker);
+        }
+        if let Some(outlier_detection) = &self.outlier_detection {
+            let outlier_detection = outlier_detection
+                .to_outlier_detection()
+                .wrap_err("Invalid outlier_detection")?;
+            opt.outlier_detection = Some(outlier_detection);
+        }
 
         let a = &self.adaptive_lb_opt;
         if let Some(v) = a.latency_smoothing_factor {
@@ -518,8 +1117,42 @@ impl LoadBalancerConfig {
         if let Some(v) = a.hysteresis_exit_factor {
             opt.hysteresis_exit_factor = v;
         }
+        a.check_strategies()?;
+        if let Some(v) = &a.connections_strategy {
+            opt.connections_strategy = v.clone();
+        }
+        if let Some(v) = &a.latency_strategy {
+            opt.latency_strategy = v.clone();
+        }
 
-        opt
+        Ok(opt)
+    }
+}
+
+/// Hash key source, e.g. `"client_ip"`, `"path"`, `{"header": "x-user-id"}`,
+/// `{"cookie": "session"}` or `{"query": "user"}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashKeyInput {
+    ClientIp,
+    Header(String),
+    Cookie(String),
+    Path,
+    Query(String),
+}
+
+impl HashKeyInput {
+    fn to_hash_key(&self) -> Result<HashKey> {
+        Ok(match self {
+            HashKeyInput::ClientIp => HashKey::ClientIp,
+            HashKeyInput::Header(name) => HashKey::Header(
+                HeaderName::from_bytes(name.as_bytes())
+                    .wrap_err_with(|| format!("Invalid hash_key header name '{name}'"))?,
+            ),
+            HashKeyInput::Cookie(name) => HashKey::Cookie(name.clone()),
+            HashKeyInput::Path => HashKey::Path,
+            HashKeyInput::Query(name) => HashKey::Query(name.clone()),
+        })
     }
 }
 
@@ -531,6 +1164,460 @@ pub struct AdaptiveLbOptConfig {
     pub evaluate_strategy_frequency_secs: Option<u64>,
     pub min_nr_of_connections: Option<usize>,
     pub hysteresis_exit_factor: Option<f32>,
+    /// Strategy switched to on connection imbalance: `FewestConnections` (default) or `P2C`.
+    pub connections_strategy: Option<Adaptive>,
+    /// Strategy switched to on latency divergence: `FastestServer` (default) or `PeakEwma`.
+    pub latency_strategy: Option<Adaptive>,
+}
+
+impl AdaptiveLbOptConfig {
+    /// Fail if a strategy switched to does not act on the imbalance that triggers the switch.
+    pub(crate) fn check_strategies(&self) -> Result<()> {
+        let valid = [
+            (
+                "connections_strategy",
+                &self.connections_strategy,
+                [Adaptive::FewestConnections, Adaptive::P2C],
+            ),
+            (
+                "latency_strategy",
+                &self.latency_strategy,
+                [Adaptive::FastestServer, Adaptive::PeakEwma],
+            ),
+        ];
+        for (field, strategy, allowed) in valid {
+            if let Some(strategy) = strategy.as_ref().filter(|s| !allowed.contains(s)) {
+                return Err(eyre!(
+                    "Invalid {field} {strategy:?}: must be {:?} or {:?}",
+                    allowed[0],
+                    allowed[1]
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Active health check of a pool's backends (nginx Plus `health_check`), e.g.
+/// `{"type": "http", "path": "/healthz", "expected_status": ["2xx", 304], "body": "ok"}` or
+/// `{"type": "grpc", "service": "users.v1.Users"}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HealthCheckInput {
+    /// `tcp` (default), `tls`, `http`, `https`, `grpc` (HTTP/2 cleartext) or `grpcs` (over TLS).
+    #[serde(default, rename = "type")]
+    pub kind: HealthCheckType,
+    /// Request method of HTTP checks; default `GET`.
+    pub method: Option<String>,
+    /// Request path (and query) of HTTP checks; default `/`.
+    pub path: Option<String>,
+    /// `Host` header (`:authority` of gRPC calls), and the SNI over TLS; default `localhost`.
+    pub host: Option<String>,
+    /// The service a gRPC check asks about; omitted asks about the server as a whole.
+    pub service: Option<String>,
+    /// Statuses passing an HTTP check: codes, ranges (`"200-399"`) or classes (`"2xx"`);
+    /// default `200`.
+    #[serde(default)]
+    pub expected_status: Vec<StatusInput>,
+    /// Text the response body must contain.
+    pub body: Option<String>,
+    /// Regex the response body must match.
+    pub body_regex: Option<String>,
+    /// Probe this port of each backend instead of the one it serves on.
+    pub port: Option<u16>,
+    pub connect_timeout_ms: Option<u64>,
+    /// How long an HTTP or gRPC check waits for the response.
+    pub read_timeout_ms: Option<u64>,
+    /// Passed checks in a row marking an unhealthy backend healthy (nginx `passes`); default 1.
+    pub consecutive_success: Option<usize>,
+    /// Failed checks in a row marking a healthy backend unhealthy (nginx `fails`); default 1.
+    pub consecutive_failure: Option<usize>,
+    /// Keep the connection open across HTTP and gRPC checks instead of testing the handshakes each
+    /// time.
+    #[serde(default)]
+    pub reuse_connection: bool,
+    /// Verify the backend's certificate and hostname in `tls`, `https` and `grpcs` checks;
+    /// default true. Turn it off with the route's `upstream_tls.verify` for self-signed backends.
+    pub verify: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthCheckType {
+    #[default]
+    Tcp,
+    Tls,
+    Http,
+    Https,
+    Grpc,
+    Grpcs,
+}
+
+impl HealthCheckType {
+    fn name(self) -> &'static str {
+        match self {
+            HealthCheckType::Tcp => "tcp",
+            HealthCheckType::Tls => "tls",
+            HealthCheckType::Http => "http",
+            HealthCheckType::Https => "https",
+            HealthCheckType::Grpc => "grpc",
+            HealthCheckType::Grpcs => "grpcs",
+        }
+    }
+}
+
+/// An expected status: `200`, `"200-399"` or `"2xx"`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum StatusInput {
+    Code(u16),
+    Range(String),
+}
+
+impl StatusInput {
+    fn to_range(&self) -> Result<RangeInclusive<u16>> {
+        let range = match self {
+            StatusInput::Code(code) => *code..=*code,
+            StatusInput::Range(range) => parse_status_range(range)
+                .ok_or_else(|| eyre!("Invalid expected_status '{range}'"))?,
+        };
+        if range.is_empty() || *range.start() < 100 || *range.end() > 599 {
+            return Err(eyre!(
+                "Invalid expected_status {}-{}: statuses range from 100 to 599",
+                range.start(),
+                range.end()
+            ));
+        }
+        Ok(range)
+    }
+}
+
+fn parse_status_range(range: &str) -> Option<RangeInclusive<u16>> {
+    let range = range.trim();
+    if let Some(class) = range
+        .strip_suffix("xx")
+        .or_else(|| range.strip_suffix("XX"))
+    {
+        let class: u16 = class.parse().ok().filter(|class| (1..=9).contains(class))?;
+        return Some(class * 100..=class * 100 + 99);
+    }
+    match range.split_once('-') {
+        Some((start, end)) => Some(start.trim().parse().ok()?..=end.trim().parse().ok()?),
+        None => range.parse().ok().map(|code| code..=code),
+    }
+}
+
+impl HealthCheckInput {
+    fn to_health_check(&self) -> Result<HealthCheckOpt> {
+        let mut opt = HealthCheckOpt::default();
+        if let Some(ms) = self.connect_timeout_ms {
+            opt.connect_timeout = positive_millis("connect_timeout_ms", ms)?;
+        }
+        if let Some(ms) = self.read_timeout_ms {
+            opt.read_timeout = positive_millis("read_timeout_ms", ms)?;
+        }
+        if let Some(n) = self.consecutive_success {
+            opt.consecutive_success = at_least_one("consecutive_success", n)?;
+        }
+        if let Some(n) = self.consecutive_failure {
+            opt.consecutive_failure = at_least_one("consecutive_failure", n)?;
+        }
+        opt.port = self.port;
+        opt.verify = self.verify.unwrap_or(true);
+        self.reject_foreign_fields()?;
+        let host = self.host.as_deref().unwrap_or(DEFAULT_HEALTH_CHECK_HOST);
+        opt.kind = match self.kind {
+            HealthCheckType::Tcp => HealthCheckKind::Tcp,
+            HealthCheckType::Tls => HealthCheckKind::Tls {
+                sni: host.to_string(),
+            },
+            HealthCheckType::Http => HealthCheckKind::Http(self.to_http_check(host, false)?),
+            HealthCheckType::Https => HealthCheckKind::Http(self.to_http_check(host, true)?),
+            HealthCheckType::Grpc => HealthCheckKind::Grpc(self.to_grpc_check(host, false)?),
+            HealthCheckType::Grpcs => HealthCheckKind::Grpc(self.to_grpc_check(host, true)?),
+        };
+        Ok(opt)
+    }
+
+    fn to_grpc_check(&self, host: &str, tls: bool) -> Result<GrpcCheck> {
+        HeaderValue::from_str(host).wrap_err_with(|| format!("Invalid host '{host}'"))?;
+        Ok(GrpcCheck {
+            tls,
+            authority: host.to_string(),
+            service: self.service.clone().unwrap_or_default(),
+            reuse_connection: self.reuse_connection,
+        })
+    }
+
+    fn to_http_check(&self, host: &str, tls: bool) -> Result<HttpCheck> {
+        let mut check = HttpCheck {
+            tls,
+            host: host.to_string(),
+            reuse_connection: self.reuse_connection,
+            ..Default::default()
+        };
+        HeaderValue::from_str(host).wrap_err_with(|| format!("Invalid host '{host}'"))?;
+        if let Some(method) = &self.method {
+            check.method = Method::from_bytes(method.as_bytes())
+                .wrap_err_with(|| format!("Invalid method '{method}'"))?;
+        }
+        if let Some(path) = &self.path {
+            if !path.starts_with('/') {
+                return Err(eyre!("Invalid path '{path}': must start with '/'"));
+            }
+            PathAndQuery::try_from(path.as_str())
+                .wrap_err_with(|| format!("Invalid path '{path}'"))?;
+            check.path = path.clone();
+        }
+        if !self.expected_status.is_empty() {
+            check.expected_status = self
+                .expected_status
+                .iter()
+                .map(StatusInput::to_range)
+                .collect::<Result<_>>()?;
+        }
+        check.body = match (&self.body, &self.body_regex) {
+            (Some(_), Some(_)) => return Err(eyre!("Set either 'body' or 'body_regex', not both")),
+            (Some(body), None) => Some(BodyMatch::Contains(body.clone())),
+            (None, Some(regex)) => Some(BodyMatch::Regex(
+                Regex::new(regex).wrap_err_with(|| format!("Invalid body_regex '{regex}'"))?,
+            )),
+            (None, None) => None,
+        };
+        Ok(check)
+    }
+
+    /// Fail if an option is set that this type of check does not use.
+    fn reject_foreign_fields(&self) -> Result<()> {
+        use HealthCheckType::{Grpc, Grpcs, Http, Https, Tls};
+        let tls: &[HealthCheckType] = &[Tls, Https, Grpcs];
+        let http: &[HealthCheckType] = &[Http, Https];
+        let grpc: &[HealthCheckType] = &[Grpc, Grpcs];
+        let requests: &[HealthCheckType] = &[Http, Https, Grpc, Grpcs];
+        let named: &[HealthCheckType] = &[Tls, Http, Https, Grpc, Grpcs];
+        let fields = [
+            ("host", self.host.is_some(), named),
+            ("service", self.service.is_some(), grpc),
+            ("method", self.method.is_some(), http),
+            ("path", self.path.is_some(), http),
+            ("expected_status", !self.expected_status.is_empty(), http),
+            ("body", self.body.is_some(), http),
+            ("body_regex", self.body_regex.is_some(), http),
+            ("read_timeout_ms", self.read_timeout_ms.is_some(), requests),
+            ("reuse_connection", self.reuse_connection, requests),
+            ("verify", self.verify.is_some(), tls),
+        ];
+        let foreign = fields
+            .iter()
+            .find(|(_, set, applies_to)| *set && !applies_to.contains(&self.kind));
+        match foreign {
+            Some((field, ..)) => Err(eyre!(
+                "'{field}' does not apply to {} checks",
+                self.kind.name()
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A circuit breaker on each of a pool's backends, e.g.
+/// `{"consecutive_failures": 5, "failure_rate": 0.5, "cooldown_secs": 30, "half_open_probes": 3}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CircuitBreakerInput {
+    /// Consecutive failures opening the circuit; default 5.
+    pub consecutive_failures: Option<u32>,
+    /// Also open once this fraction of the requests within the window failed.
+    pub failure_rate: Option<f64>,
+    pub window_secs: Option<u64>,
+    /// Requests within the window before the failure rate counts; default 20.
+    pub min_requests: Option<u32>,
+    /// How long an open circuit turns requests away; default 30 seconds.
+    pub cooldown_secs: Option<u64>,
+    /// Probe requests let through while half-open, all of which must succeed; default 1.
+    pub half_open_probes: Option<u32>,
+    /// Upstream statuses counted as failures; default 502, 503 and 504.
+    pub failure_statuses: Option<Vec<u16>>,
+}
+
+impl CircuitBreakerInput {
+    fn to_circuit_breaker(&self) -> Result<CircuitBreakerOpt> {
+        let mut opt = CircuitBreakerOpt::default();
+        if let Some(n) = self.consecutive_failures {
+            if n == 0 {
+                return Err(eyre!("'consecutive_failures' must be at least 1"));
+            }
+            opt.consecutive_failures = n;
+        }
+        if let Some(rate) = self.failure_rate {
+            if !(rate > 0.0 && rate <= 1.0) {
+                return Err(eyre!("Invalid failure_rate {rate}: must be in (0, 1]"));
+            }
+            opt.failure_rate = Some(rate);
+        }
+        if let Some(secs) = self.window_secs {
+            opt.window = positive_secs("window_secs", secs)?;
+        }
+        if let Some(n) = self.min_requests {
+            opt.min_requests = n;
+        }
+        if let Some(secs) = self.cooldown_secs {
+            opt.cooldown = positive_secs("cooldown_secs", secs)?;
+        }
+        if let Some(n) = self.half_open_probes {
+            if n == 0 {
+                return Err(eyre!("'half_open_probes' must be at least 1"));
+            }
+            opt.half_open_probes = n;
+        }
+        if let Some(statuses) = &self.failure_statuses {
+            opt.failure_statuses = failure_statuses(statuses)?;
+        }
+        Ok(opt)
+    }
+}
+
+/// Validated `failure_statuses` of a circuit breaker or outlier detection.
+fn failure_statuses(statuses: &[u16]) -> Result<Vec<u16>> {
+    if let Some(status) = statuses.iter().find(|s| !(100..=599).contains(*s)) {
+        return Err(eyre!("Invalid failure status {status}"));
+    }
+    Ok(statuses.to_vec())
+}
+
+/// Outlier detection of a pool's backends (Envoy `outlier_detection`), e.g.
+/// `{"interval_secs": 10, "base_ejection_secs": 30, "max_ejection_percent": 20}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct OutlierDetectionInput {
+    /// How often the pool is analysed; default 10 seconds.
+    pub interval_secs: Option<u64>,
+    /// Ejection period of a first ejection, growing with each repeated one; default 30 seconds.
+    pub base_ejection_secs: Option<u64>,
+    /// Longest ejection period; default 300 seconds.
+    pub max_ejection_secs: Option<u64>,
+    /// Most of the pool ejected at once, in percent; default 10.
+    pub max_ejection_percent: Option<u8>,
+    /// Standard deviations worse than the pool's mean making an outlier; default 1.9.
+    pub stdev_factor: Option<f64>,
+    /// Backends with enough requests needed to analyse the pool; default 5.
+    pub min_hosts: Option<usize>,
+    /// Requests a backend must serve in an interval to be analysed; default 100.
+    pub min_requests: Option<u64>,
+    /// Eject latency outliers; default `true`.
+    pub latency: Option<bool>,
+    /// Eject error rate outliers; default `true`.
+    pub error_rate: Option<bool>,
+    /// Upstream response statuses counted as errors; default every 5xx.
+    pub failure_statuses: Option<Vec<u16>>,
+}
+
+impl OutlierDetectionInput {
+    fn to_outlier_detection(&self) -> Result<OutlierDetectionOpt> {
+        let mut opt = OutlierDetectionOpt::default();
+        if let Some(secs) = self.interval_secs {
+            opt.interval = positive_secs("interval_secs", secs)?;
+        }
+        if let Some(secs) = self.base_ejection_secs {
+            opt.base_ejection_time = positive_secs("base_ejection_secs", secs)?;
+        }
+        if let Some(secs) = self.max_ejection_secs {
+            opt.max_ejection_time = positive_secs("max_ejection_secs", secs)?;
+        }
+        if opt.max_ejection_time < opt.base_ejection_time {
+            return Err(eyre!(
+                "'max_ejection_secs' must be at least 'base_ejection_secs'"
+            ));
+        }
+        if let Some(percent) = self.max_ejection_percent {
+            if percent > 100 {
+                return Err(eyre!("Invalid max_ejection_percent {percent}: over 100"));
+            }
+            opt.max_ejection_percent = percent;
+        }
+        if let Some(factor) = self.stdev_factor {
+            if !(factor > 0.0 && factor.is_finite()) {
+                return Err(eyre!("Invalid stdev_factor {factor}: must be positive"));
+            }
+            opt.stdev_factor = factor;
+        }
+        if let Some(hosts) = self.min_hosts {
+            if hosts < 2 {
+                return Err(eyre!("'min_hosts' must be at least 2"));
+            }
+            opt.min_hosts = hosts;
+        }
+        if let Some(requests) = self.min_requests {
+            opt.min_requests = requests;
+        }
+        opt.latency = self.latency.unwrap_or(true);
+        opt.error_rate = self.error_rate.unwrap_or(true);
+        if !opt.latency && !opt.error_rate {
+            return Err(eyre!("Either 'latency' or 'error_rate' must be enabled"));
+        }
+        if let Some(statuses) = &self.failure_statuses {
+            opt.failure_statuses = failure_statuses(statuses)?;
+        }
+        Ok(opt)
+    }
+}
+
+/// A route's slow start, e.g. `{"duration_secs": 60, "curve": "exponential"}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SlowStartInput {
+    /// How long a backend takes to get its full weight.
+    pub duration_secs: u64,
+    /// `"linear"` (the default) or `"exponential"`.
+    #[serde(default)]
+    pub curve: SlowStartCurveInput,
+    /// Share of its weight a backend starts with, in percent; default 10.
+    pub min_weight_percent: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlowStartCurveInput {
+    #[default]
+    Linear,
+    Exponential,
+}
+
+impl SlowStartInput {
+    fn to_slow_start(&self) -> Result<SlowStartConfig> {
+        let window = positive_secs("duration_secs", self.duration_secs)?;
+        let mut slow_start = SlowStartConfig::new(window);
+        slow_start.curve = match self.curve {
+            SlowStartCurveInput::Linear => SlowStartCurve::Linear,
+            SlowStartCurveInput::Exponential => SlowStartCurve::Exponential,
+        };
+        if let Some(percent) = self.min_weight_percent {
+            if !(percent > 0.0 && percent <= 100.0) {
+                return Err(eyre!(
+                    "Invalid min_weight_percent {percent}: must be over 0 and at most 100"
+                ));
+            }
+            slow_start.min_weight = percent / 100.0;
+        }
+        Ok(slow_start)
+    }
+}
+
+fn positive_millis(field: &str, ms: u64) -> Result<Duration> {
+    if ms == 0 {
+        return Err(eyre!("'{field}' must be greater than 0"));
+    }
+    Ok(Duration::from_millis(ms))
+}
+
+fn positive_secs(field: &str, secs: u64) -> Result<Duration> {
+    if secs == 0 {
+        return Err(eyre!("'{field}' must be greater than 0"));
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+fn at_least_one(field: &str, n: usize) -> Result<usize> {
+    if n == 0 {
+        return Err(eyre!("'{field}' must be at least 1"));
+    }
+    Ok(n)
 }
 
 #[derive(Debug, Clone, Deserialize)]
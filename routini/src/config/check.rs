@@ -0,0 +1,794 @@
+//! Config validation (`routini check`): every conversion the server runs on a config, with each
+//! problem reported at the JSON path of the offending entry (`$.proxy.router[2].headers`) instead
+//! of failing on the first one, or panicking while the server is built.
+//!
+//! [`plan_reload`] previews what a `SIGHUP` reload from one config to another would do.
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::SocketAddr,
+};
+
+use color_eyre::eyre::{Report, Result, WrapErr, eyre};
+use matchit::Router;
+use pingora::listeners::tls::TlsSettings;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::{
+    AccessLogInput, Config, LoadBalancerConfig, RouteEntry, ServerConfig, TlsConfig,
+    UpstreamConfig, parse_net,
+};
+use crate::{
+    access_log::AccessLogFormat,
+    metrics::route_label,
+    proxy::HostPattern,
+    reload::{RouteKey, RouteKeys, RouteLbOptions},
+    utils::{config_loader::deserialize_value, otlp::traces_url},
+};
+
+/// A problem in a config and where it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// JSON path of the offending entry, e.g. `$.proxy.router[2].headers`.
+    pub path: String,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(path: impl Into<String>, message: impl fmt::Display) -> Self {
+        Self {
+            path: path.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// One error listing every diagnostic.
+pub fn invalid_config(diagnostics: &[Diagnostic]) -> Report {
+    let lines: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+    eyre!("Invalid configuration:\n  {}", lines.join("\n  "))
+}
+
+/// Deserialize a loaded config (see
+/// [`load_config_value`](crate::utils::config_loader::load_config_value)) and run every
+/// conversion the server does at startup.
+pub fn check(value: &Value) -> Result<Config, Vec<Diagnostic>> {
+    let config = deserialize(value)?;
+    let mut diagnostics = check_server(&config);
+    diagnostics.extend(check_routes(&config));
+    if diagnostics.is_empty() {
+        Ok(config)
+    } else {
+        Err(diagnostics)
+    }
+}
+
+/// Check what a reload applies: the routes, and that they can share the route table.
+pub fn check_routes(config: &Config) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    if config.proxy.router.is_empty() {
+        diagnostics.push(Diagnostic::new(
+            "$.proxy.router",
+            "Configuration must define at least one route",
+        ));
+    }
+    let upstreams = &config.proxy.upstreams;
+    let mut names: Vec<_> = upstreams.keys().collect();
+    names.sort();
+    for name in names {
+        check_upstreams(
+            &format!("$.proxy.upstreams.{name}"),
+            &upstreams[name],
+            &mut diagnostics,
+        );
+    }
+    let mut valid = Vec::new();
+    for (i, entry) in config.proxy.router.iter().enumerate() {
+        let path = format!("$.proxy.router[{i}]");
+        let found = diagnostics.len();
+        check_route(entry, upstreams, &path, &mut diagnostics);
+        if diagnostics.len() > found {
+            continue;
+        }
+        // Building the route covers what the field checks don't: the path, empty upstream lists
+        // and duplicate pools.
+        match entry.to_route(upstreams) {
+            Ok(_) => valid.push((i, entry)),
+            Err(err) => diagnostics.push(Diagnostic::new(path, format!("{err:#}"))),
+        }
+    }
+    diagnostics.extend(route_conflicts(valid));
+    diagnostics
+}
+
+/// Deserialize `value`. Deserialization stops at the first error, so on failure the sections are
+/// deserialized on their own to report every broken one.
+fn deserialize(value: &Value) -> Result<Config, Vec<Diagnostic>> {
+    let err = match typed::<Config>(value, "$") {
+        Ok(config) => return Ok(config),
+        Err(err) => err,
+    };
+    let mut diagnostics = Vec::new();
+    if let Some(server) = value.get("server") {
+        diagnostics.extend(typed::<ServerConfig>(server, "$.server").err());
+    }
+    if let Some(tls) = value.pointer("/proxy/tls") {
+        diagnostics.extend(typed::<Option<TlsConfig>>(tls, "$.proxy.tls").err());
+    }
+    if let Some(routes) = value.pointer("/proxy/router").and_then(Value::as_array) {
+        for (i, route) in routes.iter().enumerate() {
+            let path = format!("$.proxy.router[{i}]");
+            diagnostics.extend(typed::<RouteEntry>(route, &path).err());
+        }
+    }
+    if diagnostics.is_empty() {
+        diagnostics.push(err);
+    }
+    Err(diagnostics)
+}
+
+/// Deserialize the value at `path`, pointing errors at the nested value they are in.
+fn typed<T: DeserializeOwned>(value: &Value, path: &str) -> Result<T, Diagnostic> {
+    deserialize_value(value).map_err(|err| {
+        let inner = err.path().to_string();
+        let path = match inner.as_str() {
+            "" | "." => path.to_string(),
+            inner => format!("{path}.{inner}"),
+        };
+        Diagnostic::new(path, err.into_inner())
+    })
+}
+
+fn check_server(config: &Config) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let access_log_format = match &config.server.access_log {
+        Some(AccessLogInput::Config(log)) => Some(AccessLogFormat::parse(&log.format)),
+        _ => None,
+    };
+    if let Some(Err(err)) = access_log_format {
+        diagnostics.push(Diagnostic::new("$.server.access_log.format", err));
+    }
+    if let Some(Err(err)) = config.server.otlp_endpoint.as_deref().map(traces_url) {
+        diagnostics.push(Diagnostic::new("$.server.otlp_endpoint", err));
+    }
+    let tls = config
+        .proxy
+        .tls
+        .as_ref()
+        .map(|tls| TlsSettings::intermediate(&tls.cert_path, &tls.key_path));
+    if let Some(Err(err)) = tls {
+        let message = format!("Failed to load TLS certificate/key: {err}");
+        diagnostics.push(Diagnostic::new("$.proxy.tls", message));
+    }
+    diagnostics
+}
+
+/// Check a named upstream's addresses and optional sections; it is only built if a route
+/// references it.
+fn check_upstreams(path: &str, upstream: &LoadBalancerConfig, diagnostics: &mut Vec<Diagnostic>) {
+    let mut report = reporter(path, diagnostics);
+    if upstream.upstreams.is_empty() {
+        report("upstreams", Err(eyre!("Must provide at least one backend")));
+    }
+    for (i, address) in upstream.upstreams.iter().enumerate() {
+        report(&format!("upstreams[{i}].address"), check_upstream(address));
+    }
+    for (field, result) in load_balancer_sections(upstream) {
+        report(field, result);
+    }
+}
+
+/// Records a failed conversion of `field` as a diagnostic at `{path}.{field}`.
+fn reporter<'a>(
+    path: &'a str,
+    diagnostics: &'a mut Vec<Diagnostic>,
+) -> impl FnMut(&str, Result<()>) + 'a {
+    move |field, result| {
+        if let Err(err) = result {
+            diagnostics.push(Diagnostic::new(
+                format!("{path}.{field}"),
+                format!("{err:#}"),
+            ));
+        }
+    }
+}
+
+/// The conversion of each optional section of a load balancer, by field.
+fn load_balancer_sections(load_balancer: &LoadBalancerConfig) -> [(&'static str, Result<()>); 4] {
+    fn convert<T, U>(section: &Option<T>, to: impl FnOnce(&T) -> Result<U>) -> Result<()> {
+        match section {
+            Some(section) => to(section).map(drop),
+            None => Ok(()),
+        }
+    }
+    let LoadBalancerConfig {
+        adaptive_lb_opt,
+        health_check,
+        circuit_breaker,
+        outlier_detection,
+        ..
+    } = load_balancer;
+    [
+        ("adaptive_lb_opt", adaptive_lb_opt.check_strategies()),
+        (
+            "health_check",
+            convert(health_check, |c| c.to_health_check()),
+        ),
+        (
+            "circuit_breaker",
+            convert(circuit_breaker, |c| c.to_circuit_breaker()),
+        ),
+        (
+            "outlier_detection",
+            convert(outlier_detection, |c| c.to_outlier_detection()),
+        ),
+    ]
+}
+
+/// Run the conversions of each of the route's fields on their own.
+fn check_route(
+    entry: &RouteEntry,
+    upstreams: &HashMap<String, LoadBalancerConfig>,
+    path: &str,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut report = reporter(path, diagnostics);
+
+    if entry.regex {
+        report(
+            "path",
+            Regex::new(&entry.path)
+                .map(drop)
+                .wrap_err("Invalid regex location"),
+        );
+    }
+    if let Some(host) = &entry.host {
+        report(
+            "host",
+            HostPattern::parse(host)
+                .map(drop)
+                .wrap_err("Invalid host pattern"),
+        );
+    }
+    report("match", entry.predicates.to_predicates().map(drop));
+    for (i, rule) in entry.rewrite.iter().enumerate() {
+        report(&format!("rewrite[{i}]"), rule.to_rule().map(drop));
+    }
+    if let Some(access) = &entry.access {
+        for (list, nets) in [("allow", &access.allow), ("deny", &access.deny)] {
+            for (i, net) in nets.iter().enumerate() {
+                report(&format!("access.{list}[{i}]"), parse_net(net).map(drop));
+            }
+        }
+    }
+    report("headers", entry.headers.to_rules().map(drop));
+    report("retry", entry.retry.to_retry().map(drop));
+    report("passive_health", entry.passive_health.to_config().map(drop));
+    report("access_log", entry.access_log.to_access_log().map(drop));
+    if let Some(slow_start) = &entry.slow_start {
+        report("slow_start", slow_start.to_slow_start().map(drop));
+    }
+    if entry.upstream.is_some() {
+        report("upstream", entry.primary_pool(upstreams).map(drop));
+    }
+
+    if let Some(hash_key) = &entry.load_balancer.hash_key {
+        report("load_balancer.hash_key", hash_key.to_hash_key().map(drop));
+    }
+    let mut pools: Vec<_> = entry.pools.iter().collect();
+    pools.sort_by_key(|(name, _)| *name);
+    let pools_by_field = pools
+        .iter()
+        .map(|(name, pool)| (format!("pools.{name}"), *pool));
+    let load_balancers =
+        std::iter::once(("load_balancer".to_string(), &entry.load_balancer)).chain(pools_by_field);
+    for (prefix, load_balancer) in load_balancers {
+        for (field, result) in load_balancer_sections(load_balancer) {
+            report(&format!("{prefix}.{field}"), result);
+        }
+    }
+    let upstreams = std::iter::once(("load_balancer".to_string(), &entry.load_balancer.upstreams))
+        .chain(
+            pools
+                .into_iter()
+                .map(|(name, pool)| (format!("pools.{name}"), &pool.upstreams)),
+        )
+        .chain(
+            entry
+                .mirror
+                .iter()
+                .map(|mirror| ("mirror".to_string(), &mirror.upstreams)),
+        );
+    for (field, upstreams) in upstreams {
+        for (i, upstream) in upstreams.iter().enumerate() {
+            report(
+                &format!("{field}.upstreams[{i}].address"),
+                check_upstream(upstream),
+            );
+        }
+    }
+    if let Some(split) = &entry.split {
+        report("split", split.to_split(&entry.pools).map(drop));
+    }
+    if let Some(mirror) = &entry.mirror {
+        report("mirror", mirror.to_mirror().map(drop));
+    }
+}
+
+fn check_upstream(upstream: &UpstreamConfig) -> Result<()> {
+    upstream
+        .address
+        .parse::<SocketAddr>()
+        .map(drop)
+        .wrap_err_with(|| format!("Invalid upstream address '{}'", upstream.address))
+}
+
+/// Routes, by their index in the config, that matchit cannot tell apart within a virtual host
+/// (`/users/{id}` next to `/users/{name}`), which would stop the route table from being built.
+fn route_conflicts<'a>(
+    entries: impl IntoIterator<Item = (usize, &'a RouteEntry)>,
+) -> Vec<Diagnostic> {
+    let mut routers: HashMap<String, Router<()>> = HashMap::new();
+    let mut mounted = HashSet::new();
+    let mut diagnostics = Vec::new();
+    for (i, entry) in entries {
+        let (host, is_regex, path) = entry.route_key();
+        // Regex locations are not in the router, and routes sharing a host and path are
+        // alternatives mounted once.
+        if is_regex || !mounted.insert((host.clone(), path.clone())) {
+            continue;
+        }
+        let router = routers.entry(host).or_insert_with(Router::new);
+        if let Err(err) = router.insert(path, ()) {
+            diagnostics.push(Diagnostic::new(format!("$.proxy.router[{i}].path"), err));
+        }
+    }
+    diagnostics
+}
+
+/// What a reload does with a route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteChange {
+    Added,
+    /// Declared exactly as before.
+    Unchanged,
+    /// Updated in place, keeping its load balancers.
+    Updated,
+    /// Given new load balancers because its pools or tunables changed.
+    Rebuilt,
+    Removed,
+}
+
+/// The effect of reloading the proxy from one config to another.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReloadPlan {
+    /// Each route by its metrics label, in the new config's order followed by removed routes.
+    pub routes: Vec<(String, RouteChange)>,
+    /// Changed settings that a reload skips because they only apply at startup.
+    pub skipped: Vec<&'static str>,
+}
+
+/// Settings read only at startup, by JSON path and pointer.
+const STARTUP_SETTINGS: [(&str, &str); 3] = [
+    ("$.server", "/server"),
+    ("$.proxy.listener", "/proxy/listener"),
+    ("$.proxy.tls", "/proxy/tls"),
+];
+
+/// Compare the running config `current` with `next` as the reloader would: routes are matched by
+/// host, path and position among routes sharing them. Both configs must be valid.
+pub fn plan_reload(
+    (current, current_value): (&Config, &Value),
+    (next, next_value): (&Config, &Value),
+) -> Result<ReloadPlan> {
+    let before: HashMap<_, _> = keyed_routes(current, current_value)?
+        .into_iter()
+        .map(|(key, options, raw)| (key, (options, raw)))
+        .collect();
+    let after = keyed_routes(next, next_value)?;
+
+    let mut plan = ReloadPlan::default();
+    for (key, options, raw) in &after {
+        let change = match before.get(key) {
+            None => RouteChange::Added,
+            Some((_, old)) if old == raw => RouteChange::Unchanged,
+            Some((old, _)) if old.compatible(options) => RouteChange::Updated,
+            Some(_) => RouteChange::Rebuilt,
+        };
+        plan.routes.push((route_label(key), change));
+    }
+    let kept: HashSet<_> = after.iter().map(|(key, _, _)| key).collect();
+    let mut removed: Vec<_> = before.keys().filter(|key| !kept.contains(key)).collect();
+    removed.sort();
+    plan.routes.extend(
+        removed
+            .into_iter()
+            .map(|key| (route_label(key), RouteChange::Removed)),
+    );
+    plan.skipped = STARTUP_SETTINGS
+        .iter()
+        .filter(|(_, pointer)| current_value.pointer(pointer) != next_value.pointer(pointer))
+        .map(|(path, _)| *path)
+        .collect();
+    Ok(plan)
+}
+
+/// A route's raw entry and the raw named upstream it references, if any.
+type RawRoute<'a> = (&'a Value, Option<&'a Value>);
+
+/// The config's routes with their reload keys, load-balancer options and raw entries.
+fn keyed_routes<'a>(
+    config: &Config,
+    value: &'a Value,
+) -> Result<Vec<(RouteKey, RouteLbOptions, RawRoute<'a>)>> {
+    let entries = value
+        .pointer("/proxy/router")
+        .and_then(Value::as_array)
+        .ok_or_else(|| eyre!("Config has no routes"))?;
+    let mut keys = RouteKeys::default();
+    config
+        .proxy
+        .router
+        .iter()
+        .zip(entries)
+        .map(|(entry, raw)| {
+            let route = entry.to_route(&config.proxy.upstreams)?;
+            let key = keys.next(route.host.as_deref(), route.is_regex, &route.path);
+            let upstream = entry
+                .upstream
+                .as_ref()
+                .and_then(|name| value.pointer("/proxy/upstreams")?.get(name));
+            Ok((key, RouteLbOptions::of(&route), (raw, upstream)))
+        })
+        .collect()
+}
+
+impl fmt::Display for ReloadPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (route, change) in &self.routes {
+            let (mark, action) = match change {
+                RouteChange::Added => ('+', "add"),
+                RouteChange::Unchanged => ('=', "skip (unchanged)"),
+                RouteChange::Updated => ('~', "update in place"),
+                RouteChange::Rebuilt => ('!', "rebuild with new load balancers"),
+                RouteChange::Removed => ('-', "remove"),
+            };
+            writeln!(f, "{mark} {route}: {action}")?;
+        }
+        for path in &self.skipped {
+            writeln!(f, "= {path}: skip (changed, applied on restart only)")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        adaptive_loadbalancer::health_check::HealthCheckKind,
+        config::{HealthCheckInput, HealthCheckType, StatusInput},
+    };
+
+    fn route(path: &str, address: &str) -> Value {
+        json!({ "path": path, "load_balancer": { "upstreams": [{ "address": address }] } })
+    }
+
+    fn config(routes: Vec<Value>) -> Value {
+        json!({ "proxy": { "listener": 3500, "router": routes } })
+    }
+
+    fn paths(diagnostics: Vec<Diagnostic>) -> Vec<String> {
+        diagnostics.into_iter().map(|d| d.path).collect()
+    }
+
+    #[test]
+    fn test_valid_config() {
+        let value = config(vec![route("/api/*", "127.0.0.1:8080")]);
+        assert_eq!(check(&value).unwrap().proxy.router.len(), 1);
+    }
+
+    #[test]
+    fn test_reports_every_problem() {
+        let mut bad_headers = route("/a/*", "127.0.0.1:8080");
+        bad_headers["headers"] = json!({ "remove_request": ["bad header"] });
+        let mut bad_access = route("/b/*", "127.0.0.1:8080");
+        bad_access["access"] = json!({ "allow": ["10.0.0.0/8", "not-a-net"] });
+        let mut bad_regex = route("^/c/(", "127.0.0.1:8080");
+        bad_regex["regex"] = json!(true);
+        let mut bad_health = route("/e/*", "127.0.0.1:8080");
+        bad_health["passive_health"] = json!({ "failure_statuses": [502, 1000] });
+        let mut bad_retry = route("/f/*", "127.0.0.1:8080");
+        bad_retry["retry"] = json!({ "retry_on_statuses": [503], "budget_percent": 150 });
+        let mut bad_slow_start = route("/g/*", "127.0.0.1:8080");
+        bad_slow_start["slow_start"] = json!({ "duration_secs": 30, "min_weight_percent": 0 });
+        let mut mirror_pool = route("/h/*", "127.0.0.1:8080");
+        mirror_pool["pools"] =
+            json!({ "mirror": { "upstreams": [{ "address": "127.0.0.1:8081" }] } });
+        let mut value = config(vec![
+            bad_headers,
+            bad_access,
+            bad_regex,
+            route("/d/*", "nowhere"),
+            route("no-slash", "127.0.0.1:8080"),
+            bad_health,
+            bad_retry,
+            bad_slow_start,
+            mirror_pool,
+        ]);
+        value["server"] = json!({ "otlp_endpoint": "otel-collector:4318" });
+        assert_eq!(
+            paths(check(&value).unwrap_err()),
+            [
+                "$.server.otlp_endpoint",
+                "$.proxy.router[0].headers",
+                "$.proxy.router[1].access.allow[1]",
+                "$.proxy.router[2].path",
+                "$.proxy.router[3].load_balancer.upstreams[0].address",
+                "$.proxy.router[4]",
+                "$.proxy.router[5].passive_health",
+                "$.proxy.router[6].retry",
+                "$.proxy.router[7].slow_start",
+                "$.proxy.router[8]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_deserialize_errors_name_their_path() {
+        let mut bad_strategy = route("/a/*", "127.0.0.1:8080");
+        bad_strategy["load_balancer"]["strategy"] = json!("Nope");
+        let mut value = config(vec![bad_strategy, json!({ "path": "/b/*" })]);
+        value["server"] = json!({ "access_log": { "format": 1 } });
+        assert_eq!(
+            paths(check(&value).unwrap_err()),
+            [
+                "$.server.access_log",
+                "$.proxy.router[0].load_balancer.strategy",
+                "$.proxy.router[1]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_route_conflicts() {
+        let mut alternative = route("/users/{id}", "127.0.0.1:8081");
+        alternative["match"] = json!({ "methods": ["POST"] });
+        let value = config(vec![
+            route("/users/{id}", "127.0.0.1:8080"),
+            alternative,
+            route("/users/{name}", "127.0.0.1:8080"),
+        ]);
+        assert_eq!(
+            paths(check(&value).unwrap_err()),
+            ["$.proxy.router[2].path"]
+        );
+    }
+
+    #[test]
+    fn test_named_upstreams() {
+        let uses = |upstream: &str| json!({ "path": "/a/*", "upstream": upstream });
+        let mut both = uses("api");
+        both["load_balancer"] = json!({ "upstreams": [{ "address": "127.0.0.1:8080" }] });
+        let mut value = config(vec![uses("api"), uses("missing"), both]);
+        value["proxy"]["upstreams"] = json!({
+            "api": { "upstreams": [{ "address": "127.0.0.1:8080" }] },
+            "broken": { "upstreams": [{ "address": "nowhere" }] },
+        });
+        assert_eq!(
+            paths(check(&value).unwrap_err()),
+            [
+                "$.proxy.upstreams.broken.upstreams[0].address",
+                "$.proxy.router[1].upstream",
+                "$.proxy.router[2].upstream",
+            ]
+        );
+
+        // Changing an upstream updates the routes referencing it.
+        let mut current = config(vec![uses("api")]);
+        current["proxy"]["upstreams"] =
+            json!({ "api": { "upstreams": [{ "address": "127.0.0.1:8080" }] } });
+        let mut next = current.clone();
+        next["proxy"]["upstreams"]["api"]["upstreams"][0]["address"] = json!("127.0.0.1:8081");
+        let current_config = check(&current).unwrap();
+        let next_config = check(&next).unwrap();
+        let plan = plan_reload((&current_config, &current), (&next_config, &next)).unwrap();
+        assert_eq!(plan.routes, [("/a/*".to_string(), RouteChange::Updated)]);
+    }
+
+    #[test]
+    fn test_load_balancer_sections() {
+        // Each section is checked on a route's load balancer, on a route's pool and on a named
+        // upstream.
+        let cases = [
+            (
+                "health_check",
+                json!({ "type": "https", "path": "/healthz?full=1", "method": "HEAD",
+                        "expected_status": ["200-399"], "body_regex": "ok|ready",
+                        "port": 8443, "consecutive_failure": 3, "reuse_connection": true }),
+                true,
+            ),
+            (
+                "health_check",
+                json!({ "type": "http", "expected_status": ["2xx", 304] }),
+                true,
+            ),
+            (
+                "health_check",
+                json!({ "type": "grpcs", "service": "users.v1.Users", "host": "users" }),
+                true,
+            ),
+            (
+                "health_check",
+                json!({ "type": "tls", "host": "api.internal", "verify": false }),
+                true,
+            ),
+            (
+                "health_check",
+                json!({ "type": "http", "expected_status": ["6xx"] }),
+                false,
+            ),
+            (
+                "health_check",
+                json!({ "type": "http", "verify": false }),
+                false,
+            ),
+            (
+                "health_check",
+                json!({ "type": "http", "body": "ok", "body_regex": "ok" }),
+                false,
+            ),
+            ("health_check", json!({ "path": "/healthz" }), false),
+            (
+                "health_check",
+                json!({ "type": "http", "service": "users.v1.Users" }),
+                false,
+            ),
+            (
+                "circuit_breaker",
+                json!({ "consecutive_failures": 3, "failure_rate": 0.5, "window_secs": 10,
+                        "cooldown_secs": 5, "failure_statuses": [500, 503] }),
+                true,
+            ),
+            ("circuit_breaker", json!({ "failure_rate": 1.5 }), false),
+            ("circuit_breaker", json!({ "cooldown_secs": 0 }), false),
+            ("circuit_breaker", json!({ "half_open_probes": 0 }), false),
+            (
+                "outlier_detection",
+                json!({ "interval_secs": 5, "max_ejection_percent": 50, "latency": false }),
+                true,
+            ),
+            (
+                "outlier_detection",
+                json!({ "failure_statuses": [502, 503, 504] }),
+                true,
+            ),
+            (
+                "outlier_detection",
+                json!({ "max_ejection_percent": 120 }),
+                false,
+            ),
+            (
+                "outlier_detection",
+                json!({ "failure_statuses": [600] }),
+                false,
+            ),
+            (
+                "outlier_detection",
+                json!({ "base_ejection_secs": 600 }),
+                false,
+            ),
+            (
+                "outlier_detection",
+                json!({ "latency": false, "error_rate": false }),
+                false,
+            ),
+            (
+                "adaptive_lb_opt",
+                json!({ "connections_strategy": "P2C", "latency_strategy": "PeakEwma" }),
+                true,
+            ),
+            (
+                "adaptive_lb_opt",
+                json!({ "connections_strategy": "RoundRobin" }),
+                false,
+            ),
+            (
+                "adaptive_lb_opt",
+                json!({ "latency_strategy": "Consistent" }),
+                false,
+            ),
+        ];
+
+        let mut routes = Vec::new();
+        let mut upstreams = serde_json::Map::new();
+        let mut expected_upstreams = Vec::new();
+        let mut expected_routes = Vec::new();
+        for (i, (field, section, valid)) in cases.iter().enumerate() {
+            let mut own = route(&format!("/{i}/own/*"), "127.0.0.1:8080");
+            own["load_balancer"][field] = section.clone();
+            let mut pool = route(&format!("/{i}/pool/*"), "127.0.0.1:8080");
+            pool["pools"] = json!({ "canary": {
+                "upstreams": [{ "address": "127.0.0.1:8081" }],
+                *field: section,
+            } });
+            routes.extend([own, pool]);
+            let name = format!("u{i:02}");
+            let mut upstream = json!({ "upstreams": [{ "address": "127.0.0.1:8080" }] });
+            upstream[field] = section.clone();
+            upstreams.insert(name.clone(), upstream);
+            if !valid {
+                expected_upstreams.push(format!("$.proxy.upstreams.{name}.{field}"));
+                expected_routes.extend([
+                    format!("$.proxy.router[{}].load_balancer.{field}", 2 * i),
+                    format!("$.proxy.router[{}].pools.canary.{field}", 2 * i + 1),
+                ]);
+            }
+        }
+        let mut value = config(routes);
+        value["proxy"]["upstreams"] = Value::Object(upstreams);
+        // named upstreams are checked first, in name order
+        expected_upstreams.extend(expected_routes);
+        assert_eq!(paths(check(&value).unwrap_err()), expected_upstreams);
+
+        let check = HealthCheckInput {
+            kind: HealthCheckType::Http,
+            expected_status: vec![StatusInput::Range("2xx".into()), StatusInput::Code(304)],
+            ..Default::default()
+        };
+        let HealthCheckKind::Http(http) = check.to_health_check().unwrap().kind else {
+            panic!("expected an http check");
+        };
+        assert_eq!(http.expected_status, [200..=299, 304..=304]);
+        assert_eq!(http.host, "localhost");
+    }
+
+    #[test]
+    fn test_plan_reload() {
+        let current = config(vec![
+            route("/same/*", "127.0.0.1:8080"),
+            route("/moved/*", "127.0.0.1:8080"),
+            route("/gone/*", "127.0.0.1:8080"),
+        ]);
+        let mut rebuilt = route("/moved/*", "127.0.0.1:8081");
+        rebuilt["load_balancer"]["max_iterations"] = json!(7);
+        let mut next = config(vec![
+            route("/same/*", "127.0.0.1:8080"),
+            route("/new/*", "127.0.0.1:8080"),
+            rebuilt,
+        ]);
+        next["proxy"]["listener"] = json!(8080);
+        let mut updated = next.clone();
+        updated["proxy"]["router"][0]["load_balancer"]["upstreams"][0]["address"] =
+            json!("127.0.0.1:9090");
+
+        let current_config = check(&current).unwrap();
+        let next_config = check(&next).unwrap();
+        let plan = plan_reload((&current_config, &current), (&next_config, &next)).unwrap();
+        assert_eq!(
+            plan.routes,
+            [
+                ("/same/*".to_string(), RouteChange::Unchanged),
+                ("/new/*".to_string(), RouteChange::Added),
+                ("/moved/*".to_string(), RouteChange::Rebuilt),
+                ("/gone/*".to_string(), RouteChange::Removed),
+            ]
+        );
+        assert_eq!(plan.skipped, ["$.proxy.listener"]);
+
+        let updated_config = check(&updated).unwrap();
+        let plan = plan_reload((&next_config, &next), (&updated_config, &updated)).unwrap();
+        assert_eq!(plan.routes[0].1, RouteChange::Updated);
+        assert!(plan.skipped.is_empty());
+    }
+}
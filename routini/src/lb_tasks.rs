@@ -0,0 +1,139 @@
+//! Background tasks of the routes' adaptive load balancers (discovery, health checks, strategy
+//! evaluation).
+//!
+//! Tasks are started as routes are built or added by a reload. A reload hands the load balancers
+//! it dropped to [`LbTasks::retire_all`], and their tasks stop once none of their backends is
+//! serving a request, so a removed route keeps health-checking its backends until the requests it
+//! is still serving have drained.
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use pingora::{server::ShutdownWatch, services::background::BackgroundService};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{load_balancing::Metrics, route::SharedLb};
+
+/// How often retired load balancers are checked for having drained.
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Runs the background task of every load balancer handed to [`LbTasks::spawn`] until it is
+/// retired. Registered on the server as a single background service.
+pub struct LbTasks {
+    sender: mpsc::UnboundedSender<Command>,
+    receiver: Mutex<Option<mpsc::UnboundedReceiver<Command>>>,
+}
+
+/// Load balancers built but whose background tasks are yet to be handed to [`LbTasks::spawn`],
+/// so that building routes can be abandoned, e.g. by a failed reload, without starting any.
+pub type PendingTasks = Vec<(String, SharedLb)>;
+
+/// Sent in order over one channel, so a load balancer is always started before it is retired.
+enum Command {
+    Spawn(String, SharedLb),
+    Retire(SharedLb),
+}
+
+/// A load balancer whose background task is running.
+struct Running {
+    name: String,
+    lb: SharedLb,
+    stop: oneshot::Sender<()>,
+    /// Whether no route uses the load balancer any more, so its task stops once drained.
+    retired: bool,
+}
+
+impl Running {
+    /// Whether none of the load balancer's backends is serving a request.
+    fn drained(&self) -> bool {
+        self.lb
+            .backends()
+            .iter()
+            .all(|backend| backend.metrics.active_connections().unwrap_or(0) == 0)
+    }
+}
+
+impl Default for LbTasks {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Self {
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+}
+
+impl LbTasks {
+    /// Run `lb`'s background task. Load balancers started before the server runs are queued and
+    /// start with it.
+    pub fn spawn(&self, name: String, lb: SharedLb) {
+        // The receiver lives as long as `self`, so sending cannot fail.
+        let _ = self.sender.send(Command::Spawn(name, lb));
+    }
+
+    /// Run the background task of each of `pending`'s load balancers.
+    pub fn spawn_all(&self, pending: PendingTasks) {
+        for (name, lb) in pending {
+            self.spawn(name, lb);
+        }
+    }
+
+    /// Stop the background tasks of `retired`, load balancers no route uses any more, once their
+    /// in-flight requests have drained.
+    pub fn retire_all(&self, retired: Vec<SharedLb>) {
+        for lb in retired {
+            let _ = self.sender.send(Command::Retire(lb));
+        }
+    }
+}
+
+#[async_trait]
+impl BackgroundService for LbTasks {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let Some(mut receiver) = self.receiver.lock().expect("lock poisoned").take() else {
+            return;
+        };
+        let mut running: Vec<Running> = Vec::new();
+        let mut drain_check = tokio::time::interval(DRAIN_CHECK_INTERVAL);
+
+        loop {
+            tokio::select! {
+                Some(command) = receiver.recv() => match command {
+                    Command::Spawn(name, lb) => {
+                        let (stop, stopped) = oneshot::channel();
+                        let task_lb = lb.clone();
+                        let task_shutdown = shutdown.clone();
+                        tokio::spawn(async move {
+                            tokio::select! {
+                                _ = task_lb.start(task_shutdown) => {}
+                                _ = stopped => {}
+                            }
+                        });
+                        running.push(Running { name, lb, stop, retired: false });
+                    }
+                    Command::Retire(lb) => {
+                        let retired = running.iter_mut().filter(|task| Arc::ptr_eq(&task.lb, &lb));
+                        for task in retired {
+                            task.retired = true;
+                        }
+                    }
+                },
+                _ = drain_check.tick() => {
+                    let mut i = 0;
+                    while i < running.len() {
+                        if !running[i].retired || !running[i].drained() {
+                            i += 1;
+                            continue;
+                        }
+                        let drained = running.swap_remove(i);
+                        tracing::info!("Stopping load balancer {}: route removed", drained.name);
+                        let _ = drained.stop.send(());
+                    }
+                }
+                _ = shutdown.changed() => break,
+            }
+        }
+    }
+}
@@ -1,9 +1,14 @@
+pub mod access_log;
 pub mod adaptive_loadbalancer;
+pub mod admin;
 pub mod config;
+pub mod lb_tasks;
 pub mod load_balancing;
+pub mod metrics;
+pub mod mirror;
 pub mod proxy;
 pub mod reload;
 pub mod route;
 pub mod server_builder;
-pub mod set_strategy_endpoint;
+pub mod trace_context;
 pub mod utils;
@@ -85,6 +85,8 @@ pub struct TcpHealthCheck {
     /// set, it will also try to establish a TLS connection on top of the TCP connection.
     pub peer_template: BasicPeer,
     connector: TransportConnector,
+    /// Connect to this port of the backend IP instead of the backend's own port.
+    pub port_override: Option<u16>,
     /// A callback that is invoked when the `healthy` status changes for a [Backend].
     pub health_changed_callback: Option<HealthObserveCallback>,
 }
@@ -98,6 +100,7 @@ impl Default for TcpHealthCheck {
             consecutive_failure: 1,
             peer_template,
             connector: TransportConnector::new(None),
+            port_override: None,
             health_changed_callback: None,
         }
     }
@@ -140,6 +143,9 @@ impl<M: Metrics> HealthCheck<M> for TcpHealthCheck {
     async fn check(&self, target: &Backend<M>) -> Result<()> {
         let mut peer = self.peer_template.clone();
         peer._address = target.addr.clone();
+        if let Some(port) = self.port_override {
+            peer._address.set_port(port);
+        }
         self.connector.get_stream(&peer).await.map(|_| {})
     }
 
@@ -151,6 +157,10 @@ impl<M: Metrics> HealthCheck<M> for TcpHealthCheck {
 }
 
 type Validator = Box<dyn Fn(&ResponseHeader) -> Result<()> + Send + Sync>;
+type BodyValidator = Box<dyn Fn(&[u8]) -> Result<()> + Send + Sync>;
+
+/// How much of the response body a [HttpHealthCheck::body_validator] gets to see.
+const MAX_VALIDATED_BODY: usize = 64 * 1024;
 
 /// HTTP health check
 ///
@@ -182,6 +192,9 @@ pub struct HttpHealthCheck {
     ///
     /// If not set, any response with a `200 OK` is considered a successful check.
     pub validator: Option<Validator>,
+    /// Optional field to validate the response body, up to its first 64 KiB, once the header
+    /// has passed.
+    pub body_validator: Option<BodyValidator>,
     /// Sometimes the health check endpoint lives one a different port than the actual backend.
     /// Setting this option allows the health check to perform on the given port of the backend IP.
     pub port_override: Option<u16>,
@@ -215,6 +228,7 @@ impl HttpHealthCheck {
             reuse_connection: false,
             req,
             validator: None,
+            body_validator: None,
             port_override: None,
             health_changed_callback: None,
             backend_summary_callback: None,
@@ -274,8 +288,16 @@ impl<M: Metrics> HealthCheck<M> for HttpHealthCheck {
             );
         };
 
-        while session.read_response_body().await?.is_some() {
-            // drain the body if any
+        let mut body = Vec::new();
+        while let Some(chunk) = session.read_response_body().await? {
+            // drain the body if any, keeping the start of it to validate
+            if self.body_validator.is_some() && body.len() < MAX_VALIDATED_BODY {
+                let take = chunk.len().min(MAX_VALIDATED_BODY - body.len());
+                body.extend_from_slice(&chunk[..take]);
+            }
+        }
+        if let Some(body_validator) = self.body_validator.as_ref() {
+            body_validator(&body)?;
         }
 
         if self.reuse_connection {
@@ -339,6 +361,14 @@ impl Health {
         h.healthy && h.enabled
     }
 
+    pub fn healthy(&self) -> bool {
+        self.0.load().healthy
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.0.load().enabled
+    }
+
     pub fn enable(&self, enabled: bool) {
         let h = self.0.load();
         if h.enabled != enabled {
@@ -156,6 +156,9 @@ impl<M: Metrics> std::net::ToSocketAddrs for Backend<M> {
     }
 }
 
+/// The backends found by a [ServiceDiscovery], and whether each is enabled, not yet applied.
+pub type Discovered<M = NoMetric> = (BTreeSet<Backend<M>>, HashMap<u64, bool>);
+
 /// [Backends] is a collection of [Backend]s.
 ///
 /// It includes a service discovery method (static or dynamic) to discover all
@@ -189,6 +192,11 @@ impl<M: Metrics> Backends<M> {
         self.health_check = Some(hc.into())
     }
 
+    /// Run the service discovery without applying its result, see [LoadBalancer::replace_backends].
+    pub async fn discover(&self) -> Result<Discovered<M>> {
+        self.discovery.discover().await
+    }
+
     /// Updates backends when the new is different from the current set,
     /// the callback will be invoked when the new set of backend is different
     /// from the current one so that the caller can update the selector accordingly.
@@ -208,10 +216,18 @@ impl<M: Metrics> Backends<M> {
 
             let old_health = self.health.load();
             let mut health = HashMap::with_capacity(new_backends.len());
+            let old_by_addr: HashMap<&SocketAddr, &Backend<M>> = old_backends
+                .iter()
+                .map(|backend| (&backend.addr, backend))
+                .collect();
 
             for mut backend in new_backends.into_iter() {
-                // Uses the old backend if it exists, to preserve extensions and metrics if any
-                if let Some(old_backend) = old_backends.get(&backend) {
+                // Uses the old backend if it exists, to preserve extensions and metrics if any.
+                // A backend whose weight changed is still the same server, found by its address.
+                let old_backend = old_backends
+                    .get(&backend)
+                    .or_else(|| old_by_addr.get(&backend.addr).copied());
+                if let Some(old_backend) = old_backend {
                     backend.ext.extend(old_backend.ext.clone());
                     backend.metrics = old_backend.metrics.clone();
                 } else {
@@ -221,7 +237,10 @@ impl<M: Metrics> Backends<M> {
                 let hash_key = backend.hash_key();
 
                 // use the default health if the backend is new
-                let backend_health = old_health.get(&hash_key).cloned().unwrap_or_default();
+                let backend_health = old_backend
+                    .and_then(|old_backend| old_health.get(&old_backend.hash_key()))
+                    .cloned()
+                    .unwrap_or_default();
 
                 // override enablement
                 if let Some(backend_enabled) = enablement.get(&hash_key) {
@@ -279,6 +298,22 @@ impl<M: Metrics> Backends<M> {
         };
     }
 
+    /// Whether `backend` passes its health checks, regardless of it being enabled.
+    pub fn healthy(&self, backend: &Backend<M>) -> bool {
+        self.health
+            .load()
+            .get(&backend.hash_key())
+            .map_or(self.health_check.is_none(), |h| h.healthy())
+    }
+
+    /// Whether `backend` is enabled to serve traffic, regardless of its health.
+    pub fn enabled(&self, backend: &Backend<M>) -> bool {
+        self.health
+            .load()
+            .get(&backend.hash_key())
+            .is_none_or(|h| h.enabled())
+    }
+
     /// Return the collection of the backends.
     pub fn get_backend(&self) -> Arc<BTreeSet<Backend<M>>> {
         self.backends.load_full()
@@ -451,6 +486,35 @@ where
             .await
     }
 
+    /// Replace the backends with the ones discovered by `other` and update the selection
+    /// algorithm, in place. Backends present in both sets keep their metrics and health.
+    ///
+    /// Used to apply reloaded static upstreams; this load balancer's own discovery is not
+    /// consulted again unless `update_frequency` is set.
+    pub async fn update_from(&self, other: &Backends<M>) -> Result<()> {
+        let discovered = other.discover().await?;
+        self.replace_backends(discovered).await;
+        Ok(())
+    }
+
+    /// Replace the backends with `new_backends` and update the selection algorithm, in place.
+    /// Backends present in both sets keep their metrics and health.
+    pub async fn set_backends(&self, new_backends: BTreeSet<Backend<M>>) {
+        self.replace_backends((new_backends, HashMap::new())).await;
+    }
+
+    /// Replace the backends with ones already discovered (see [Backends::discover]) and update
+    /// the selection algorithm, in place. Backends present in both sets keep their metrics and
+    /// health.
+    pub async fn replace_backends(&self, (new_backends, enablement): Discovered<M>) {
+        let strategy = self.strategy.read().await;
+        self.backends
+            .do_update(&*strategy, new_backends, enablement, |backends| {
+                self.selector
+                    .store(strategy.build_backend_selector(&backends).into());
+            });
+    }
+
     pub async fn rebuild_frequency(&self) -> Option<Duration> {
         self.strategy.read().await.rebuild_frequency()
     }
@@ -1,22 +1,31 @@
 use std::{collections::BTreeSet, fmt::Display, sync::Arc, time::Duration};
 
-use serde::Deserialize;
-
-use crate::load_balancing::{
-    Backend, Metrics, NoMetric,
-    strategy::{
-        BackendIter, BackendSelection, Consistent, FNVHash, FewestConnections, Random, RoundRobin,
-        Strategy,
-        consistent::ConsistentSelector,
-        fastest_server::{FastestServer, FastestServerSelector, LatencyEWMA},
-        fewest_connections::{ActiveConnections, FewestConnectionsSelector},
-        fnv_hash::FNVHashSelector,
-        random::RandomSelector,
-        round_robin::RoundRobinSelector,
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    adaptive_loadbalancer::{
+        circuit_breaker::CircuitBreaker, outlier_detection::RequestOutcomes, slow_start::Warmup,
+    },
+    load_balancing::{
+        Backend, Metrics, NoMetric,
+        strategy::{
+            BackendIter, BackendSelection, Consistent, FNVHash, FewestConnections, Maglev, Random,
+            Rendezvous, RoundRobin, Strategy,
+            consistent::ConsistentSelector,
+            fastest_server::{FastestServer, FastestServerSelector, LatencyEWMA},
+            fewest_connections::{ActiveConnections, FewestConnectionsSelector},
+            fnv_hash::FNVHashSelector,
+            maglev::MaglevSelector,
+            p2c::{P2C, P2CSelector},
+            peak_ewma::{PeakEwma, PeakEwmaSelector},
+            random::RandomSelector,
+            rendezvous::RendezvousSelector,
+            round_robin::RoundRobinSelector,
+        },
     },
 };
 
-#[derive(Debug, Default, PartialEq, Clone, Deserialize)]
+#[derive(Debug, Default, PartialEq, Clone, Deserialize, Serialize)]
 pub enum Adaptive {
     #[default]
     RoundRobin,
@@ -25,6 +34,14 @@ pub enum Adaptive {
     Consistent,
     FewestConnections,
     FastestServer,
+    /// Power of two choices on active connections, read live with no selector rebuild.
+    P2C,
+    /// Power of two choices on latency EWMA × (active connections + 1), read live.
+    PeakEwma,
+    /// Maglev hashing: a fixed-size lookup table, more even than the `Consistent` ring.
+    Maglev,
+    /// Weighted rendezvous (highest random weight) hashing.
+    Rendezvous,
 }
 
 impl Display for Adaptive {
@@ -36,6 +53,10 @@ impl Display for Adaptive {
             Adaptive::FewestConnections => write!(f, "FewestConnection"),
             Adaptive::Consistent => write!(f, "Consistent"),
             Adaptive::FastestServer => write!(f, "FastestServer"),
+            Adaptive::P2C => write!(f, "P2C"),
+            Adaptive::PeakEwma => write!(f, "PeakEwma"),
+            Adaptive::Maglev => write!(f, "Maglev"),
+            Adaptive::Rendezvous => write!(f, "Rendezvous"),
         }
     }
 }
@@ -72,6 +93,16 @@ impl<M: Metrics> Strategy<M> for Adaptive {
             Adaptive::FastestServer => AdaptiveSelector::FastestServer(Arc::new(
                 FastestServer.build_backend_selector(backends),
             )),
+            Adaptive::P2C => AdaptiveSelector::P2C(Arc::new(P2C.build_backend_selector(backends))),
+            Adaptive::PeakEwma => {
+                AdaptiveSelector::PeakEwma(Arc::new(PeakEwma.build_backend_selector(backends)))
+            }
+            Adaptive::Maglev => {
+                AdaptiveSelector::Maglev(Arc::new(Maglev.build_backend_selector(backends)))
+            }
+            Adaptive::Rendezvous => {
+                AdaptiveSelector::Rendezvous(Arc::new(Rendezvous.build_backend_selector(backends)))
+            }
         }
     }
 }
@@ -83,6 +114,10 @@ pub enum AdaptiveSelector<M: Metrics = NoMetric> {
     Consistent(Arc<ConsistentSelector<M>>),
     FewestConnections(Arc<FewestConnectionsSelector<M>>),
     FastestServer(Arc<FastestServerSelector<M>>),
+    P2C(Arc<P2CSelector<M>>),
+    PeakEwma(Arc<PeakEwmaSelector<M>>),
+    Maglev(Arc<MaglevSelector<M>>),
+    Rendezvous(Arc<RendezvousSelector<M>>),
 }
 
 impl<M: Metrics> BackendSelection<M> for AdaptiveSelector<M> {
@@ -100,6 +135,10 @@ impl<M: Metrics> BackendSelection<M> for AdaptiveSelector<M> {
             AdaptiveSelector::FastestServer(selector) => {
                 AdaptiveIter::FastestServer(selector.iter(key))
             }
+            AdaptiveSelector::P2C(selector) => AdaptiveIter::P2C(selector.iter(key)),
+            AdaptiveSelector::PeakEwma(selector) => AdaptiveIter::PeakEwma(selector.iter(key)),
+            AdaptiveSelector::Maglev(selector) => AdaptiveIter::Maglev(selector.iter(key)),
+            AdaptiveSelector::Rendezvous(selector) => AdaptiveIter::Rendezvous(selector.iter(key)),
         }
     }
 }
@@ -111,6 +150,10 @@ pub enum AdaptiveIter<M: Metrics = NoMetric> {
     Consistent(<ConsistentSelector<M> as BackendSelection<M>>::Iter),
     FewestConnections(<FewestConnectionsSelector<M> as BackendSelection<M>>::Iter),
     FastestServer(<FastestServerSelector<M> as BackendSelection<M>>::Iter),
+    P2C(<P2CSelector<M> as BackendSelection<M>>::Iter),
+    PeakEwma(<PeakEwmaSelector<M> as BackendSelection<M>>::Iter),
+    Maglev(<MaglevSelector<M> as BackendSelection<M>>::Iter),
+    Rendezvous(<RendezvousSelector<M> as BackendSelection<M>>::Iter),
 }
 
 impl<M: Metrics> BackendIter<M> for AdaptiveIter<M> {
@@ -122,6 +165,10 @@ impl<M: Metrics> BackendIter<M> for AdaptiveIter<M> {
             AdaptiveIter::Consistent(iter) => iter.next(),
             AdaptiveIter::FewestConnections(iter) => iter.next(),
             AdaptiveIter::FastestServer(iter) => iter.next(),
+            AdaptiveIter::P2C(iter) => iter.next(),
+            AdaptiveIter::PeakEwma(iter) => iter.next(),
+            AdaptiveIter::Maglev(iter) => iter.next(),
+            AdaptiveIter::Rendezvous(iter) => iter.next(),
         }
     }
 }
@@ -133,12 +180,31 @@ impl<M: Metrics> BackendIter<M> for AdaptiveIter<M> {
 pub struct AdaptiveStrategyMetrics {
     active_connections: ActiveConnections,
     latency_ewma: LatencyEWMA,
+    circuit: CircuitBreaker,
+    outcomes: RequestOutcomes,
+    warmup: Warmup,
 }
 
 impl AdaptiveStrategyMetrics {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// The backend's circuit breaker, consulted when its load balancer configures one.
+    pub fn circuit(&self) -> &CircuitBreaker {
+        &self.circuit
+    }
+
+    /// The backend's requests and failures since the last outlier analysis, counted when its
+    /// load balancer detects outliers.
+    pub fn outcomes(&self) -> &RequestOutcomes {
+        &self.outcomes
+    }
+
+    /// When the backend last (re)joined its pool, ramping up under a route's slow start.
+    pub fn warmup(&self) -> &Warmup {
+        &self.warmup
+    }
 }
 
 impl Metrics for AdaptiveStrategyMetrics {
@@ -0,0 +1,191 @@
+//! Maglev hashing (Google's Maglev, as in Envoy's `MAGLEV` load balancer)
+
+use std::{collections::BTreeSet, hash::Hasher, sync::Arc};
+
+use fnv::FnvHasher;
+use serde::Deserialize;
+
+use crate::load_balancing::{
+    Backend, Metrics, NoMetric,
+    strategy::{BackendIter, BackendSelection, Strategy},
+};
+
+/// Entries in the lookup table. A prime, so every backend's permutation covers the whole table;
+/// much larger than the number of backends, so each gets a share close to its weight.
+pub const MAGLEV_TABLE_SIZE: usize = 65537;
+
+const EMPTY: u16 = u16::MAX;
+
+/// Weighted Maglev hashing: a key maps to a backend through a fixed-size lookup table, which
+/// spreads keys more evenly than a ketama ring and moves few of them when a backend comes or
+/// goes. Backends are told apart by address, Unix sockets included.
+#[derive(Default, PartialEq, Deserialize, Clone)]
+pub struct Maglev;
+
+impl<M: Metrics> Strategy<M> for Maglev {
+    type BackendSelector = MaglevSelector<M>;
+
+    fn build_backend_selector(&self, backends: &BTreeSet<Backend<M>>) -> Self::BackendSelector {
+        MaglevSelector::new(backends, MAGLEV_TABLE_SIZE)
+    }
+}
+
+pub struct MaglevSelector<M: Metrics = NoMetric> {
+    backends: Box<[Backend<M>]>,
+    // each item is an index to the `backends`, use u16 to save memory, support up to 2^16 - 1
+    // backends
+    table: Box<[u16]>,
+}
+
+impl<M: Metrics> MaglevSelector<M> {
+    /// Fill a table of `size` (a prime) entries, each backend taking turns to claim the next free
+    /// entry of its permutation, `weight` entries per turn.
+    fn new(backends: &BTreeSet<Backend<M>>, size: usize) -> Self {
+        assert!(
+            backends.len() < u16::MAX as usize,
+            "support up to 2^16 - 1 backends"
+        );
+        let backends = Vec::from_iter(backends.iter().cloned()).into_boxed_slice();
+        let mut table = vec![EMPTY; size];
+        if backends.is_empty() {
+            return Self {
+                backends,
+                table: table.into_boxed_slice(),
+            };
+        }
+
+        let size = size as u64;
+        let permutations = backends
+            .iter()
+            .map(|backend| {
+                let name = backend.addr.to_string();
+                let offset = hash(name.as_bytes(), 0) % size;
+                let skip = hash(name.as_bytes(), 1) % (size - 1) + 1;
+                (offset, skip)
+            })
+            .collect::<Vec<_>>();
+        let mut next = vec![0u64; backends.len()];
+        let mut filled = 0;
+        'fill: loop {
+            for (index, backend) in backends.iter().enumerate() {
+                let (offset, skip) = permutations[index];
+                for _ in 0..backend.weight.max(1) {
+                    let mut entry = (offset + next[index] * skip) % size;
+                    while table[entry as usize] != EMPTY {
+                        next[index] += 1;
+                        entry = (offset + next[index] * skip) % size;
+                    }
+                    table[entry as usize] = index as u16;
+                    next[index] += 1;
+                    filled += 1;
+                    if filled == table.len() {
+                        break 'fill;
+                    }
+                }
+            }
+        }
+
+        Self {
+            backends,
+            table: table.into_boxed_slice(),
+        }
+    }
+}
+
+/// FNV-1a of `bytes`, seeded so that one name yields independent hashes.
+fn hash(bytes: &[u8], seed: u8) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write_u8(seed);
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+impl<M: Metrics> BackendSelection<M> for MaglevSelector<M> {
+    type Iter = MaglevIter<M>;
+
+    fn iter(self: &Arc<Self>, key: &[u8]) -> Self::Iter {
+        MaglevIter {
+            entry: (hash(key, 0) % self.table.len() as u64) as usize,
+            walked: 0,
+            seen: Vec::new(),
+            selector: self.clone(),
+        }
+    }
+}
+
+/// Yields the key's backend, then the distinct backends of the following table entries, so the
+/// fallback for an unhealthy backend is as consistent as the first choice.
+pub struct MaglevIter<M: Metrics = NoMetric> {
+    entry: usize,
+    walked: usize,
+    // which backends were already yielded, allocated on the first fallback
+    seen: Vec<bool>,
+    selector: Arc<MaglevSelector<M>>,
+}
+
+impl<M: Metrics> BackendIter<M> for MaglevIter<M> {
+    fn next(&mut self) -> Option<&Backend<M>> {
+        let selector = &self.selector;
+        if selector.backends.is_empty() {
+            return None;
+        }
+        let first = selector.table[self.entry] as usize;
+        if self.walked == 0 {
+            self.walked = 1;
+            return Some(&selector.backends[first]);
+        }
+        if self.seen.is_empty() {
+            self.seen = vec![false; selector.backends.len()];
+            self.seen[first] = true;
+        }
+        while self.walked < selector.table.len() {
+            let index = selector.table[(self.entry + self.walked) % selector.table.len()] as usize;
+            self.walked += 1;
+            if !self.seen[index] {
+                self.seen[index] = true;
+                return Some(&selector.backends[index]);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::load_balancing::strategy::utils::tests::{
+        backends, check_hashing_strategy, keys_moved_by_removal,
+    };
+
+    #[test]
+    fn test_table_shares_follow_weights() {
+        let mut backends = backends(3);
+        let mut heavy = backends.pop_last().unwrap();
+        heavy.weight = 2;
+        backends.insert(heavy.clone());
+        let selector = MaglevSelector::new(&backends, MAGLEV_TABLE_SIZE);
+
+        let mut count = HashMap::new();
+        for &index in selector.table.iter() {
+            *count.entry(index).or_insert(0) += 1;
+        }
+        let heavy = selector.backends.iter().position(|b| *b == heavy).unwrap() as u16;
+        // 1:1:2 of 65537 entries
+        assert!((32000..33500).contains(&count[&heavy]));
+        assert!(count.values().all(|&c| c > 15500));
+    }
+
+    #[test]
+    fn test_removing_a_backend_only_moves_its_keys() {
+        // Maglev may move a few other keys, far fewer than the removed backend's share
+        let moved = keys_moved_by_removal(&Maglev);
+        assert!(moved < 500, "moved {moved}");
+    }
+
+    #[test]
+    fn test_hashing_strategy() {
+        check_hashing_strategy(&Maglev);
+    }
+}
@@ -19,7 +19,11 @@ pub mod consistent;
 pub mod fastest_server;
 pub mod fewest_connections;
 pub mod fnv_hash;
+pub mod maglev;
+pub mod p2c;
+pub mod peak_ewma;
 pub mod random;
+pub mod rendezvous;
 pub mod round_robin;
 pub mod utils;
 
@@ -27,7 +31,8 @@ use crate::load_balancing::{Metrics, NoMetric};
 
 pub use {
     adaptive::Adaptive, consistent::Consistent, fewest_connections::FewestConnections,
-    fnv_hash::FNVHash, random::Random, round_robin::RoundRobin,
+    fnv_hash::FNVHash, maglev::Maglev, random::Random, rendezvous::Rendezvous,
+    round_robin::RoundRobin,
 };
 
 /// Kept around for backwards compatibility until the next breaking change.
@@ -0,0 +1,188 @@
+use std::{cmp::Ordering, collections::BTreeSet, sync::Arc};
+
+use crate::load_balancing::{
+    Backend, Metrics, NoMetric,
+    strategy::{BackendIter, BackendSelection, Strategy},
+};
+
+/// Power of two choices: sample two backends at random, by weight, and pick the one with fewer
+/// active connections. The connections are read at selection time, so the selector is never
+/// rebuilt to keep up with them.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct P2C;
+
+impl<M: Metrics> Strategy<M> for P2C {
+    type BackendSelector = P2CSelector<M>;
+
+    fn build_backend_selector(&self, backends: &BTreeSet<Backend<M>>) -> Self::BackendSelector {
+        P2CSelector::new(backends, fewer_connections)
+    }
+}
+
+/// Backends whose `M` doesn't track connections report 0, leaving the pick to the sampling.
+fn fewer_connections<M: Metrics>(a: &Backend<M>, b: &Backend<M>) -> Ordering {
+    let connections = |backend: &Backend<M>| backend.metrics.active_connections().unwrap_or(0);
+    connections(a).cmp(&connections(b))
+}
+
+/// Picks the better of two backends sampled by weight, `compare` ordering the less loaded first.
+pub struct P2CSelector<M: Metrics = NoMetric> {
+    backends: Box<[Backend<M>]>,
+    // each item is an index to the `backends`, as in `WeightedSelector`
+    weighted: Box<[u16]>,
+    compare: fn(&Backend<M>, &Backend<M>) -> Ordering,
+}
+
+impl<M: Metrics> P2CSelector<M> {
+    pub fn new(
+        backends: &BTreeSet<Backend<M>>,
+        compare: fn(&Backend<M>, &Backend<M>) -> Ordering,
+    ) -> Self {
+        assert!(
+            backends.len() <= u16::MAX as usize,
+            "support up to 2^16 backends"
+        );
+        let backends = Vec::from_iter(backends.iter().cloned()).into_boxed_slice();
+        let mut weighted = Vec::with_capacity(backends.len());
+        for (index, b) in backends.iter().enumerate() {
+            for _ in 0..b.weight {
+                weighted.push(index as u16);
+            }
+        }
+        Self {
+            backends,
+            weighted: weighted.into_boxed_slice(),
+            compare,
+        }
+    }
+
+    /// Two distinct backend indexes, the less loaded first. `backends` must not be empty.
+    fn pick(&self) -> (usize, usize) {
+        let len = self.backends.len();
+        let sample = || self.weighted[rand::random_range(0..self.weighted.len())] as usize;
+        let a = sample();
+        let mut b = sample();
+        if a == b && len > 1 {
+            // any other backend, so a heavy one is still compared against someone
+            b = (a + rand::random_range(1..len)) % len;
+        }
+        match (self.compare)(&self.backends[b], &self.backends[a]) {
+            Ordering::Less => (b, a),
+            _ => (a, b),
+        }
+    }
+}
+
+impl<M: Metrics> BackendSelection<M> for P2CSelector<M> {
+    type Iter = P2CIter<M>;
+
+    fn iter(self: &Arc<Self>, _key: &[u8]) -> Self::Iter {
+        P2CIter {
+            selector: self.clone(),
+            picked: None,
+            offset: 0,
+        }
+    }
+}
+
+/// Yields the better pick, then the other one, then the remaining backends in order so a
+/// caller skipping unhealthy backends still finds one.
+pub struct P2CIter<M: Metrics = NoMetric> {
+    selector: Arc<P2CSelector<M>>,
+    picked: Option<(usize, usize)>,
+    offset: usize,
+}
+
+impl<M: Metrics> BackendIter<M> for P2CIter<M> {
+    fn next(&mut self) -> Option<&Backend<M>> {
+        let backends = &self.selector.backends;
+        let len = backends.len();
+        if len == 0 {
+            return None;
+        }
+        let Some((first, second)) = self.picked else {
+            let picked = self.selector.pick();
+            self.picked = Some(picked);
+            return Some(&backends[picked.0]);
+        };
+        while self.offset < len {
+            self.offset += 1;
+            let index = match self.offset {
+                1 => second,
+                offset => (first + offset - 1) % len,
+            };
+            if index != first && (self.offset == 1 || index != second) {
+                return Some(&backends[index]);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::load_balancing::strategy::fewest_connections::ActiveConnections;
+
+    use super::*;
+
+    fn backend(addr: &str, connections: usize) -> Backend<ActiveConnections> {
+        let mut backend = Backend::build(addr, 1).unwrap();
+        backend.metrics = ActiveConnections::new(connections);
+        backend
+    }
+
+    #[test]
+    fn test_picks_the_less_loaded_of_two() {
+        let busy = backend("127.0.0.1:8080", 10);
+        let idle = backend("127.0.0.1:8081", 0);
+        let backends = BTreeSet::from([busy.clone(), idle.clone()]);
+        let selector = Arc::new(P2C.build_backend_selector(&backends));
+
+        for _ in 0..100 {
+            assert_eq!(selector.iter(&[]).next().unwrap().addr, idle.addr);
+        }
+        // read live: no rebuild needed once the load moves
+        for _ in 0..20 {
+            idle.metrics.increment_active_connections();
+        }
+        assert_eq!(selector.iter(&[]).next().unwrap().addr, busy.addr);
+    }
+
+    #[test]
+    fn test_never_picks_the_most_loaded() {
+        let backends = BTreeSet::from([
+            backend("127.0.0.1:8080", 0),
+            backend("127.0.0.1:8081", 5),
+            backend("127.0.0.1:8082", 50),
+        ]);
+        let selector = Arc::new(P2C.build_backend_selector(&backends));
+
+        let mut count = HashMap::new();
+        for _ in 0..3000 {
+            let addr = selector.iter(&[]).next().unwrap().addr.to_string();
+            *count.entry(addr).or_insert(0) += 1;
+        }
+        assert!(!count.contains_key("127.0.0.1:8082"));
+        // the least loaded wins both pairs it is sampled into: about 2/3 of the picks
+        assert!((1700..2300).contains(&count["127.0.0.1:8080"]));
+    }
+
+    #[test]
+    fn test_iter_yields_every_backend_once() {
+        let backends = BTreeSet::from_iter((0..5).map(|i| backend(&format!("127.0.0.1:80{i}"), i)));
+        let selector = Arc::new(P2C.build_backend_selector(&backends));
+
+        let mut iter = selector.iter(&[]);
+        let mut seen = BTreeSet::new();
+        while let Some(backend) = iter.next() {
+            assert!(seen.insert(backend.addr.to_string()));
+        }
+        assert_eq!(seen.len(), 5);
+
+        let none: BTreeSet<Backend<ActiveConnections>> = BTreeSet::new();
+        let empty = Arc::new(P2C.build_backend_selector(&none));
+        assert!(empty.iter(&[]).next().is_none());
+    }
+}
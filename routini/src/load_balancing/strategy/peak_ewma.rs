@@ -0,0 +1,91 @@
+use std::{cmp::Ordering, collections::BTreeSet};
+
+use crate::load_balancing::{
+    Backend, Metrics, NoMetric,
+    strategy::{Strategy, p2c::P2CSelector},
+};
+
+pub type PeakEwmaSelector<M = NoMetric> = P2CSelector<M>;
+
+/// Peak EWMA (as in Finagle and Linkerd): sample two backends at random, by weight, and pick the
+/// one with the lower latency EWMA × (active connections + 1). Both are read at selection time,
+/// so the selector is never rebuilt to keep up with them.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PeakEwma;
+
+impl<M: Metrics> Strategy<M> for PeakEwma {
+    type BackendSelector = PeakEwmaSelector<M>;
+
+    fn build_backend_selector(&self, backends: &BTreeSet<Backend<M>>) -> Self::BackendSelector {
+        PeakEwmaSelector::new(backends, lower_cost)
+    }
+}
+
+/// A backend without a latency yet (0.0) has no cost to compare, so the pair is then compared on
+/// connections alone rather than sending everything to the unmeasured one.
+fn lower_cost<M: Metrics>(a: &Backend<M>, b: &Backend<M>) -> Ordering {
+    let connections = |backend: &Backend<M>| backend.metrics.active_connections().unwrap_or(0);
+    let latency = |backend: &Backend<M>| backend.metrics.average_latency().unwrap_or(0.0);
+    let (latency_a, latency_b) = (latency(a), latency(b));
+    if latency_a <= 0.0 || latency_b <= 0.0 {
+        return connections(a).cmp(&connections(b));
+    }
+    let cost_a = latency_a * (connections(a) + 1) as f32;
+    let cost_b = latency_b * (connections(b) + 1) as f32;
+    cost_a.total_cmp(&cost_b)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::load_balancing::strategy::{
+        BackendIter, BackendSelection, adaptive::AdaptiveStrategyMetrics,
+    };
+
+    use super::*;
+
+    fn backend(
+        addr: &str,
+        latency_ms: u64,
+        connections: usize,
+    ) -> Backend<AdaptiveStrategyMetrics> {
+        let backend = Backend::build(addr, 1).unwrap();
+        if latency_ms > 0 {
+            // alpha 1.0 with a zero starting average sets the EWMA directly to the latency
+            backend
+                .metrics
+                .record_latency(Duration::from_millis(latency_ms), 1.0);
+        }
+        for _ in 0..connections {
+            backend.metrics.increment_active_connections();
+        }
+        backend
+    }
+
+    fn pick(backends: &[Backend<AdaptiveStrategyMetrics>]) -> String {
+        let backends = BTreeSet::from_iter(backends.iter().cloned());
+        let selector = Arc::new(PeakEwma.build_backend_selector(&backends));
+        selector.iter(&[]).next().unwrap().addr.to_string()
+    }
+
+    #[test]
+    fn test_cost_weighs_latency_by_load() {
+        let fast = backend("127.0.0.1:8080", 10, 0);
+        let slow = backend("127.0.0.1:8081", 50, 0);
+        assert_eq!(pick(&[fast.clone(), slow.clone()]), "127.0.0.1:8080");
+
+        // 10ms × 10 connections costs more than 50ms idle, read without a rebuild
+        for _ in 0..9 {
+            fast.metrics.increment_active_connections();
+        }
+        assert_eq!(pick(&[fast, slow]), "127.0.0.1:8081");
+    }
+
+    #[test]
+    fn test_unmeasured_backend_is_compared_on_connections() {
+        let measured = backend("127.0.0.1:8080", 10, 0);
+        let unmeasured = backend("127.0.0.1:8081", 0, 3);
+        assert_eq!(pick(&[measured, unmeasured]), "127.0.0.1:8080");
+    }
+}
@@ -0,0 +1,146 @@
+//! Rendezvous (highest random weight) hashing
+
+use std::{collections::BTreeSet, hash::Hasher, sync::Arc};
+
+use fnv::FnvHasher;
+use serde::Deserialize;
+
+use crate::load_balancing::{
+    Backend, Metrics, NoMetric,
+    strategy::{BackendIter, BackendSelection, Strategy},
+};
+
+/// Weighted rendezvous hashing: every backend scores the key and the highest score wins, so only
+/// the keys of a backend that comes or goes move. Scores are `-weight / ln(h)`, `h` a hash of the
+/// key and backend in (0, 1), which gives each backend a share of the keys proportional to its
+/// weight. Backends are told apart by address, Unix sockets included.
+#[derive(Default, PartialEq, Deserialize, Clone)]
+pub struct Rendezvous;
+
+impl<M: Metrics> Strategy<M> for Rendezvous {
+    type BackendSelector = RendezvousSelector<M>;
+
+    fn build_backend_selector(&self, backends: &BTreeSet<Backend<M>>) -> Self::BackendSelector {
+        let backends = backends
+            .iter()
+            .map(|backend| {
+                let mut hasher = FnvHasher::default();
+                hasher.write(backend.addr.to_string().as_bytes());
+                (backend.clone(), hasher.finish())
+            })
+            .collect();
+        RendezvousSelector { backends }
+    }
+}
+
+pub struct RendezvousSelector<M: Metrics = NoMetric> {
+    // each backend with the hash of its address
+    backends: Box<[(Backend<M>, u64)]>,
+}
+
+impl<M: Metrics> BackendSelection<M> for RendezvousSelector<M> {
+    type Iter = RendezvousIter<M>;
+
+    fn iter(self: &Arc<Self>, key: &[u8]) -> Self::Iter {
+        let mut hasher = FnvHasher::default();
+        hasher.write(key);
+        RendezvousIter {
+            key: hasher.finish(),
+            first: None,
+            rest: None,
+            selector: self.clone(),
+        }
+    }
+}
+
+/// The score of `backend`, hashed as `hash`, for the key hashed as `key`.
+fn score<M: Metrics>(key: u64, (backend, hash): &(Backend<M>, u64)) -> f64 {
+    // splitmix64 finalizer, so that close key and backend hashes still score independently
+    let mut x = key ^ hash;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^= x >> 31;
+    // the top 53 bits, in (0, 1)
+    let h = ((x >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+    -(backend.weight.max(1) as f64) / h.ln()
+}
+
+/// Yields the backends from the highest score down, so the fallback for an unhealthy backend is
+/// as consistent as the first choice.
+pub struct RendezvousIter<M: Metrics = NoMetric> {
+    key: u64,
+    first: Option<usize>,
+    // the other backends, lowest score first, ranked on the first fallback
+    rest: Option<Vec<(f64, usize)>>,
+    selector: Arc<RendezvousSelector<M>>,
+}
+
+impl<M: Metrics> BackendIter<M> for RendezvousIter<M> {
+    fn next(&mut self) -> Option<&Backend<M>> {
+        let backends = &self.selector.backends;
+        let key = self.key;
+        let scores = || {
+            backends
+                .iter()
+                .enumerate()
+                .map(move |(index, backend)| (score(key, backend), index))
+        };
+        let Some(first) = self.first else {
+            let (_, first) = scores().max_by(|a, b| a.0.total_cmp(&b.0))?;
+            self.first = Some(first);
+            return Some(&backends[first].0);
+        };
+        let rest = self.rest.get_or_insert_with(|| {
+            let mut rest = scores()
+                .filter(|&(_, index)| index != first)
+                .collect::<Vec<_>>();
+            rest.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+            rest
+        });
+        let (_, index) = rest.pop()?;
+        Some(&backends[index].0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::load_balancing::strategy::utils::tests::{
+        backends, check_hashing_strategy, first, keys_moved_by_removal,
+    };
+
+    #[test]
+    fn test_shares_follow_weights() {
+        let mut backends = backends(3);
+        let mut heavy = backends.pop_last().unwrap();
+        heavy.weight = 2;
+        backends.insert(heavy.clone());
+        let selector = Arc::new(Rendezvous.build_backend_selector(&backends));
+
+        let mut count = HashMap::new();
+        for key in 0..10_000 {
+            *count.entry(first(&selector, key)).or_insert(0) += 1;
+        }
+        // 1:1:2
+        assert!((4500..5500).contains(&count[&heavy]), "{}", count[&heavy]);
+        assert!(count.values().all(|&c| c > 2000));
+    }
+
+    #[test]
+    fn test_removing_a_backend_only_moves_its_keys() {
+        assert_eq!(keys_moved_by_removal(&Rendezvous), 0);
+    }
+
+    #[test]
+    fn test_falls_back_from_the_highest_score_down() {
+        let fallbacks = check_hashing_strategy(&Rendezvous);
+
+        // without the first choice, the key goes to its first fallback
+        let mut remaining = backends(4);
+        remaining.remove(&fallbacks[0]);
+        let without = Arc::new(Rendezvous.build_backend_selector(&remaining));
+        assert_eq!(without.iter(b"key").next(), Some(&fallbacks[1]));
+    }
+}
@@ -155,8 +155,81 @@ where
 }
 
 #[cfg(test)]
-mod tests {
+pub(super) mod tests {
+    use pingora::protocols::l4::socket::SocketAddr;
+
     use super::*;
+    use crate::load_balancing::strategy::Strategy;
+
+    /// `count` backends on consecutive loopback addresses.
+    pub fn backends(count: usize) -> BTreeSet<Backend> {
+        (0..count)
+            .map(|i| Backend::new(&format!("127.0.0.{}:80", i + 1)).unwrap())
+            .collect()
+    }
+
+    /// The backend `selector` picks first for `key`.
+    pub fn first<S: BackendSelection>(selector: &Arc<S>, key: usize) -> Backend {
+        let key = key.to_string();
+        selector.iter(key.as_bytes()).next().unwrap().clone()
+    }
+
+    /// How many of 10000 keys move to another backend when one of five backends is removed,
+    /// besides the removed backend's own keys.
+    pub fn keys_moved_by_removal<S: Strategy>(strategy: &S) -> usize {
+        let all = backends(5);
+        let before = Arc::new(strategy.build_backend_selector(&all));
+        let mut remaining = all.clone();
+        let removed = remaining.pop_first().unwrap();
+        let after = Arc::new(strategy.build_backend_selector(&remaining));
+
+        (0..10_000)
+            .filter(|&key| {
+                let was = first(&before, key);
+                was != removed && first(&after, key) != was
+            })
+            .count()
+    }
+
+    /// Check what every hashing strategy guarantees: a key falls back through each backend once,
+    /// the same way every time; no backends select nothing; and Unix socket backends hash like
+    /// the others. Returns the fallback order of `key` over four backends.
+    pub fn check_hashing_strategy<S: Strategy>(strategy: &S) -> Vec<Backend> {
+        let selector = Arc::new(strategy.build_backend_selector(&backends(4)));
+        let order = || {
+            let mut iter = selector.iter(b"key");
+            let mut order = Vec::new();
+            while let Some(backend) = iter.next() {
+                order.push(backend.clone());
+            }
+            order
+        };
+        let fallbacks = order();
+        assert_eq!(BTreeSet::from_iter(fallbacks.iter()).len(), 4);
+        assert_eq!(fallbacks.len(), 4);
+        // the same key falls back the same way
+        assert_eq!(order(), fallbacks);
+
+        let none: BTreeSet<Backend> = BTreeSet::new();
+        let empty = Arc::new(strategy.build_backend_selector(&none));
+        assert!(empty.iter(b"key").next().is_none());
+
+        let mut backends = backends(1);
+        for path in ["/tmp/a.sock", "/tmp/b.sock"] {
+            let mut backend = Backend::new("127.0.0.1:80").unwrap();
+            let uds = std::os::unix::net::SocketAddr::from_pathname(path).unwrap();
+            backend.addr = SocketAddr::Unix(uds);
+            backends.insert(backend);
+        }
+        let selector = Arc::new(strategy.build_backend_selector(&backends));
+        let unix = (0..1000)
+            .filter(|&key| matches!(first(&selector, key).addr, SocketAddr::Unix(_)))
+            .count();
+        // two of the three backends
+        assert!((550..780).contains(&unix), "unix {unix}");
+
+        fallbacks
+    }
 
     struct TestIter {
         seq: Vec<Backend>,
@@ -1,22 +1,42 @@
 use color_eyre::eyre::{Result, eyre};
 use pingora::server::configuration::ServerConf;
 use routini::{
-    server_builder::proxy_server,
+    config::check::{check, invalid_config, plan_reload},
+    metrics::CONFIG_LAST_RELOAD_SUCCESSFUL,
+    server_builder::{AdminConfig, proxy_server},
     utils::{
-        config_loader::{CONFIG_PATH_ENV, DEFAULT_CONFIG_PATH, load_config_from},
+        config_loader::{
+            CONFIG_PATH_ENV, DEFAULT_CONFIG_PATH, config_from_value, load_config_sources,
+            load_config_value,
+        },
         constants::{
-            DEFAULT_LOG_JSON, DEFAULT_LOG_LEVEL_FILTER, DEFAULT_MAX_LOG_AGE_DAYS,
-            SET_STRATEGY_ENDPOINT_ADDRESS,
+            ADMIN_ENDPOINT_ADDRESS, DEFAULT_CONFIG_WATCH_DEBOUNCE, DEFAULT_LOG_JSON,
+            DEFAULT_LOG_LEVEL_FILTER, DEFAULT_MAX_LOG_AGE_DAYS, DEFAULT_OTEL_SERVICE_NAME,
         },
         tracing::{LogConfig, init_tracing_with_config},
     },
 };
-use std::net::TcpListener;
+use std::{net::TcpListener, time::Duration};
 
 #[global_allocator]
 static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
 fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "check") {
+        color_eyre::install().expect("Failed to install color_eyre");
+        return check_command(&args[1..]);
+    }
+
+    color_eyre::install().expect("Failed to install color_eyre");
+
+    // The config is read before tracing is set up, as it may name the OTLP collector
+    let config_path = default_config_path();
+    let config = check(&load_config_value(&config_path)?)
+        .map_err(|diagnostics| invalid_config(&diagnostics))?;
+    // The running config loaded fine, so a fresh instance does not look like a failed reload
+    CONFIG_LAST_RELOAD_SUCCESSFUL.set(1);
+
     // Configure logging based on environment
     let log_config = LogConfig {
         filter: std::env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_LOG_LEVEL_FILTER.to_string()),
@@ -35,14 +55,18 @@ fn main() -> Result<()> {
             .ok()
             .and_then(|v| v.parse::<u64>().ok())
             .unwrap_or(DEFAULT_MAX_LOG_AGE_DAYS),
+
+        otlp_endpoint: config
+            .server
+            .otlp_endpoint
+            .clone()
+            .or_else(|| std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()),
+
+        service_name: std::env::var("OTEL_SERVICE_NAME")
+            .unwrap_or_else(|_| DEFAULT_OTEL_SERVICE_NAME.to_string()),
     };
 
     init_tracing_with_config(log_config).expect("Failed to set up tracing");
-    color_eyre::install().expect("Failed to install color_eyre");
-
-    let config_path =
-        std::env::var(CONFIG_PATH_ENV).unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string());
-    let config = load_config_from(&config_path)?;
     tracing::info!("Loaded configuration: {} route(s)", config.proxy.router.len());
 
     let listener = TcpListener::bind(config.listen_address())?;
@@ -72,19 +96,25 @@ fn main() -> Result<()> {
         builder = builder.add_route(route);
     }
 
-    let strategy_endpoint = config
+    let admin_address = config
         .server
-        .set_strategy_endpoint
+        .admin_address
         .clone()
-        .unwrap_or_else(|| SET_STRATEGY_ENDPOINT_ADDRESS.to_string());
-    builder = builder.set_strategy_endpoint(strategy_endpoint);
+        .unwrap_or_else(|| ADMIN_ENDPOINT_ADDRESS.to_string());
+    builder = builder.admin(AdminConfig {
+        address: admin_address,
+        token: config.server.admin_token.clone(),
+    });
 
     if let Some(prometheus_address) = config.server.prometheus_address.clone() {
         builder = builder.prometheus_address(prometheus_address);
     }
 
-    if let Some(access_log) = config.server.access_log {
-        builder = builder.access_log(access_log);
+    if let Some(access_log) = &config.server.access_log {
+        builder = match access_log.to_access_log()? {
+            Some(log) => builder.access_log_config(log),
+            None => builder.access_log(false),
+        };
     }
 
     if let Some(https_redirect) = config.server.https_redirect {
@@ -99,6 +129,10 @@ fn main() -> Result<()> {
         builder = builder.request_id(request_id);
     }
 
+    if let Some(trace_context) = config.server.trace_context {
+        builder = builder.trace_context(trace_context);
+    }
+
     if !config.server.error_pages.is_empty() {
         builder = builder.error_pages(config.server.error_pages.clone());
     }
@@ -107,7 +141,74 @@ fn main() -> Result<()> {
         builder = builder.tls(tls.to_builder_tls());
     }
 
+    if config.server.watch_config == Some(true) {
+        let debounce = config
+            .server
+            .watch_config_debounce_ms
+            .map_or(DEFAULT_CONFIG_WATCH_DEBOUNCE, Duration::from_millis);
+        builder = builder.watch_config(debounce);
+    }
+
     builder = builder.reload_on_sighup(config_path);
 
     builder.build().run_forever();
 }
+
+fn default_config_path() -> String {
+    std::env::var(CONFIG_PATH_ENV).unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string())
+}
+
+/// `routini check [<config>] [--diff <running config>]`: validate `<config>` (default: the
+/// config the proxy would start with), listing every problem and exiting non-zero if there are
+/// any. With `--diff`, also show what a `SIGHUP` reload from `<running config>` would do.
+fn check_command(args: &[String]) -> Result<()> {
+    let mut config_path = None;
+    let mut running_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--diff" => {
+                let path = args
+                    .next()
+                    .ok_or_else(|| eyre!("--diff needs a config to compare"))?;
+                running_path = Some(path.clone());
+            }
+            flag if flag.starts_with('-') => return Err(eyre!("Unknown option '{flag}'")),
+            path if config_path.is_none() => config_path = Some(path.to_string()),
+            extra => return Err(eyre!("Unexpected argument '{extra}'")),
+        }
+    }
+    let config_path = config_path.unwrap_or_else(default_config_path);
+
+    let (value, sources) = load_config_sources(&config_path)?;
+    let config = match check(&value) {
+        Ok(config) => config,
+        Err(diagnostics) => {
+            for diagnostic in &diagnostics {
+                match sources.locate(&diagnostic.path) {
+                    Some((file, path)) => {
+                        eprintln!("{}: {path}: {}", file.display(), diagnostic.message)
+                    }
+                    None => eprintln!("{config_path}: {diagnostic}"),
+                }
+            }
+            let outcome = if running_path.is_some() {
+                "; a reload to it would be rejected"
+            } else {
+                ""
+            };
+            eprintln!("{config_path}: {} problem(s){outcome}", diagnostics.len());
+            std::process::exit(1);
+        }
+    };
+    println!("{config_path}: OK, {} route(s)", config.proxy.router.len());
+
+    if let Some(running_path) = running_path {
+        let running_value = load_config_value(&running_path)?;
+        let running = config_from_value(&running_value)?;
+        let plan = plan_reload((&running, &running_value), (&config, &value))?;
+        println!("Reloading from {running_path}:");
+        print!("{plan}");
+    }
+    Ok(())
+}
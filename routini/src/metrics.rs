@@ -0,0 +1,391 @@
+//! Per-route and per-backend Prometheus metrics, served by the server's Prometheus endpoint next
+//! to Pingora's own.
+//!
+//! Counters and the latency histogram are recorded by the proxy as requests complete. Backend
+//! state (connections, EWMA latency, health) is read from the live load balancers on every scrape
+//! by [`BackendCollector`], so removed routes and backends disappear from it after a reload.
+//!
+//! Labels: `route` is the route's host and path as configured (`example.com/api/*`, `~^/v\d+/`
+//! for a regex location, `#n` appended to later routes sharing a host and path), `pool` its
+//! upstream pool (`primary`, a split pool, the mirror pool or a named upstream) and `backend` an
+//! upstream address. A named upstream's backend metrics have an empty `route`, as its load
+//! balancer serves every route referencing it.
+use std::{
+    collections::HashSet,
+    sync::{Arc, LazyLock},
+};
+
+use prometheus::{
+    GaugeVec, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    register_histogram_vec, register_int_counter_vec, register_int_gauge,
+};
+
+use crate::{
+    reload::{Reloader, RouteKey},
+    route::SharedLb,
+    utils::constants::DEFAULT_WILDCARD_IDENTIFIER,
+};
+
+pub static REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "routini_requests_total",
+        "Requests handled, by route, pool, backend and response status class",
+        &["route", "pool", "backend", "status"]
+    )
+    .unwrap()
+});
+
+pub static REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "routini_request_duration_seconds",
+        "Time from receiving a request to completing its response",
+        &["route", "pool"]
+    )
+    .unwrap()
+});
+
+pub static RETRIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "routini_retries_total",
+        "Failed upstream attempts retried on another backend",
+        &["route", "pool"]
+    )
+    .unwrap()
+});
+
+pub static RETRY_BUDGET_EXHAUSTED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "routini_retry_budget_exhausted_total",
+        "Retries skipped because the route's retry budget was spent",
+        &["route", "pool"]
+    )
+    .unwrap()
+});
+
+pub static EJECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "routini_passive_ejections_total",
+        "Backends taken out of rotation by passive health checks",
+        &["route", "pool", "backend"]
+    )
+    .unwrap()
+});
+
+pub static CIRCUIT_TRANSITIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "routini_circuit_breaker_transitions_total",
+        "Backend circuit breaker state changes, by the state entered",
+        &["route", "pool", "backend", "state"]
+    )
+    .unwrap()
+});
+
+pub static OUTLIER_EJECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "routini_outlier_ejections_total",
+        "Backends ejected by outlier detection, by `latency` or `error_rate`",
+        &["route", "pool", "backend", "reason"]
+    )
+    .unwrap()
+});
+
+pub static LIMIT_REJECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "routini_limit_rejections_total",
+        "Requests rejected by a per-client limit (`rate` or `conn`)",
+        &["route", "limit"]
+    )
+    .unwrap()
+});
+
+pub static CACHE_LOOKUPS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "routini_cache_lookups_total",
+        "Response cache lookups, by `hit` or `miss`",
+        &["route", "result"]
+    )
+    .unwrap()
+});
+
+pub static STRATEGY_SWITCHES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "routini_strategy_switches_total",
+        "Load balancing strategy switches made by the adaptive decision engine",
+        &["route", "pool", "from", "to"]
+    )
+    .unwrap()
+});
+
+pub static CONFIG_RELOADS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "routini_config_reloads_total",
+        "Config reloads, by trigger (`signal`, `file` or `admin`) and `success` or `failure`",
+        &["trigger", "result"]
+    )
+    .unwrap()
+});
+
+pub static CONFIG_LAST_RELOAD_SUCCESSFUL: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "routini_config_last_reload_successful",
+        "1 if the last config reload (or the startup load) succeeded, 0 if it failed"
+    )
+    .unwrap()
+});
+
+/// The `route` and `pool` labels of a load balancer's metrics.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PoolLabels {
+    pub route: String,
+    pub pool: String,
+}
+
+impl PoolLabels {
+    pub fn new(route: &str, pool: &str) -> Self {
+        Self {
+            route: route.to_string(),
+            pool: pool.to_string(),
+        }
+    }
+}
+
+/// The `route` label of the route registered under `key`.
+pub fn route_label((host, is_regex, path, ordinal): &RouteKey) -> String {
+    let mut label = host.clone();
+    if *is_regex {
+        label.push('~');
+        label.push_str(path);
+    } else {
+        label.push_str(&path.replacen(DEFAULT_WILDCARD_IDENTIFIER, "*", 1));
+    }
+    if *ordinal > 0 {
+        label.push_str(&format!("#{ordinal}"));
+    }
+    label
+}
+
+/// The `status` label of a response status: `2xx`, `4xx`, ..., or `none` when the request ended
+/// without a response.
+pub fn status_class(status: u16) -> &'static str {
+    match status {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        500..=599 => "5xx",
+        _ => "none",
+    }
+}
+
+/// Reports the state of every live route's backends at scrape time.
+pub struct BackendCollector {
+    reloader: Arc<Reloader>,
+    active_connections: IntGaugeVec,
+    latency_ewma: GaugeVec,
+    healthy: IntGaugeVec,
+    enabled: IntGaugeVec,
+    circuit_state: IntGaugeVec,
+    outlier: IntGaugeVec,
+}
+
+impl BackendCollector {
+    pub fn new(reloader: Arc<Reloader>) -> Self {
+        const LABELS: &[&str] = &["route", "pool", "backend"];
+        Self {
+            reloader,
+            active_connections: IntGaugeVec::new(
+                Opts::new(
+                    "routini_backend_active_connections",
+                    "Open upstream connections, when the strategy tracks them",
+                ),
+                LABELS,
+            )
+            .unwrap(),
+            latency_ewma: GaugeVec::new(
+                Opts::new(
+                    "routini_backend_latency_ewma_seconds",
+                    "Exponentially weighted moving average of the upstream response latency",
+                ),
+                LABELS,
+            )
+            .unwrap(),
+            healthy: IntGaugeVec::new(
+                Opts::new(
+                    "routini_backend_healthy",
+                    "1 if the backend passes its health checks",
+                ),
+                LABELS,
+            )
+            .unwrap(),
+            enabled: IntGaugeVec::new(
+                Opts::new(
+                    "routini_backend_enabled",
+                    "1 if the backend is enabled (not disabled or passively ejected)",
+                ),
+                LABELS,
+            )
+            .unwrap(),
+            circuit_state: IntGaugeVec::new(
+                Opts::new(
+                    "routini_backend_circuit_state",
+                    "Circuit breaker state: 0 closed, 1 open, 2 half-open",
+                ),
+                LABELS,
+            )
+            .unwrap(),
+            outlier: IntGaugeVec::new(
+                Opts::new(
+                    "routini_backend_outlier_ejected",
+                    "1 if the backend is ejected by outlier detection",
+                ),
+                LABELS,
+            )
+            .unwrap(),
+        }
+    }
+
+    fn observe(&self, lb: &SharedLb) {
+        for backend in lb.backends().iter() {
+            let addr = backend.addr.to_string();
+            let labels = [lb.labels.route.as_str(), lb.labels.pool.as_str(), &addr];
+            if let Some(connections) = backend.metrics.active_connections() {
+                self.active_connections
+                    .with_label_values(&labels)
+                    .set(connections as i64);
+            }
+            if let Some(latency_ms) = backend.metrics.average_latency() {
+                self.latency_ewma
+                    .with_label_values(&labels)
+                    .set(f64::from(latency_ms) / 1000.0);
+            }
+            self.healthy
+                .with_label_values(&labels)
+                .set(lb.is_healthy(backend).into());
+            self.enabled
+                .with_label_values(&labels)
+                .set(lb.is_enabled(backend).into());
+            if lb.config.circuit_breaker.is_some() {
+                self.circuit_state
+                    .with_label_values(&labels)
+                    .set(backend.metrics.circuit().state().value());
+            }
+            if lb.config.outlier_detection.is_some() {
+                self.outlier
+                    .with_label_values(&labels)
+                    .set(lb.is_outlier(backend).into());
+            }
+        }
+    }
+}
+
+impl Collector for BackendCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        [
+            self.active_connections.desc(),
+            self.latency_ewma.desc(),
+            self.healthy.desc(),
+            self.enabled.desc(),
+            self.circuit_state.desc(),
+            self.outlier.desc(),
+        ]
+        .concat()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.active_connections.reset();
+        self.latency_ewma.reset();
+        self.healthy.reset();
+        self.enabled.reset();
+        self.circuit_state.reset();
+        self.outlier.reset();
+        // Routes sharing a named upstream share its load balancer: observe it once.
+        let mut observed = HashSet::new();
+        for (_, runtime) in self.reloader.routes() {
+            let pools = runtime.pools.values().chain(&runtime.mirror);
+            for lb in std::iter::once(&runtime.lb).chain(pools) {
+                if observed.insert(Arc::as_ptr(lb)) {
+                    self.observe(lb);
+                }
+            }
+        }
+        [
+            self.active_connections.collect(),
+            self.latency_ewma.collect(),
+            self.healthy.collect(),
+            self.enabled.collect(),
+            self.circuit_state.collect(),
+            self.outlier.collect(),
+        ]
+        .concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        lb_tasks::LbTasks,
+        load_balancing::strategy::Adaptive,
+        proxy::{Proxy, VHostRoutes},
+        reload::{RouteRegistry, UpstreamRegistry},
+        server_builder::Route,
+    };
+
+    #[test]
+    fn test_route_label() {
+        let key = |host: &str, is_regex, path: &str, ordinal| {
+            (host.to_string(), is_regex, path.to_string(), ordinal)
+        };
+        assert_eq!(route_label(&key("", false, "/api/{*rest}", 0)), "/api/*");
+        assert_eq!(
+            route_label(&key("example.com", false, "/", 1)),
+            "example.com/#1"
+        );
+        assert_eq!(route_label(&key("", true, r"^/v\d+/", 0)), r"~^/v\d+/");
+    }
+
+    #[test]
+    fn test_status_class() {
+        assert_eq!(status_class(204), "2xx");
+        assert_eq!(status_class(503), "5xx");
+        assert_eq!(status_class(0), "none");
+    }
+
+    #[tokio::test]
+    async fn test_backend_collector() {
+        let proxy = Proxy::with_vhost_routes(VHostRoutes::default(), Vec::new(), 16).unwrap();
+        let reloader = Reloader::new(
+            None,
+            proxy,
+            Arc::new(LbTasks::default()),
+            RouteRegistry::new(),
+            UpstreamRegistry::new(),
+        );
+        let route = || Route::new("/api/*", ["127.0.0.1:8080"], Adaptive::default()).unwrap();
+        reloader.apply(vec![route()]).await.unwrap();
+        // Backends are discovered by the load balancer's background task, or by a reload.
+        reloader.apply(vec![route()]).await.unwrap();
+
+        let collector = BackendCollector::new(Arc::new(reloader));
+        let families = collector.collect();
+        let healthy = families
+            .iter()
+            .find(|family| family.get_name() == "routini_backend_healthy")
+            .unwrap();
+        let labels: Vec<_> = healthy.get_metric()[0]
+            .get_label()
+            .iter()
+            .map(|label| (label.get_name(), label.get_value()))
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                ("backend", "127.0.0.1:8080"),
+                ("pool", "primary"),
+                ("route", "/api/*")
+            ]
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! Shadow traffic (nginx `mirror`): copies of sampled requests are sent to a route's mirror pool
+//! and the responses discarded.
+//!
+//! Copies are fired from a detached task once the primary request has finished, so they never add
+//! latency or errors to the primary path. Each outcome is logged under the `routini::mirror`
+//! tracing target, alongside the primary's status and latency, so the two pools can be diffed.
+use std::{
+    sync::{
+        LazyLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use pingora::{
+    Result, connectors::http::Connector as HttpConnector, http::RequestHeader, prelude::HttpPeer,
+    upstreams::peer::Peer,
+};
+
+use crate::route::SharedLb;
+
+/// Shared upstream connector (and connection pool) for all mirror requests.
+static CONNECTOR: LazyLock<HttpConnector> = LazyLock::new(|| HttpConnector::new(None));
+
+/// Cap on concurrently in-flight mirror requests. Beyond it copies are dropped rather than queued,
+/// so a slow mirror pool cannot accumulate unbounded tasks.
+const MAX_IN_FLIGHT: usize = 1024;
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// A reserved in-flight slot, released on drop so a panicking or cancelled mirror task cannot leak
+/// it.
+struct InFlightSlot;
+
+impl InFlightSlot {
+    fn acquire() -> Option<Self> {
+        let previous = IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+        // Owns the increment either way; dropping it when the cap is hit gives it straight back.
+        let slot = InFlightSlot;
+        (previous < MAX_IN_FLIGHT).then_some(slot)
+    }
+}
+
+impl Drop for InFlightSlot {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Upper bound on a mirror exchange, so stuck mirror backends do not pin tasks.
+const MIRROR_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A request captured from the primary path, ready to be replayed against the mirror pool.
+pub struct MirrorRequest {
+    /// The upstream request header as sent to the primary pool.
+    pub header: RequestHeader,
+    /// The buffered request body; `None` sends the copy without a body.
+    pub body: Option<Bytes>,
+    pub request_id: Option<String>,
+    /// The primary response's status and latency, logged next to the mirror's for diffing.
+    pub primary_status: u16,
+    pub primary_latency: Duration,
+}
+
+/// Send `request` to a backend of the mirror pool `lb` in the background.
+pub fn spawn(lb: SharedLb, request: MirrorRequest) {
+    let Some(slot) = InFlightSlot::acquire() else {
+        tracing::debug!(target: "routini::mirror", "mirror request dropped: too many in flight");
+        return;
+    };
+
+    tokio::spawn(async move {
+        let _slot = slot;
+        let path = request.header.uri.path().to_string();
+        let request_id = request.request_id.clone().unwrap_or_default();
+        let primary_status = request.primary_status;
+        let primary_latency_ms = request.primary_latency.as_millis();
+
+        let Some(backend) = lb.select(&[]) else {
+            tracing::warn!(
+                target: "routini::mirror",
+                path, request_id, primary_status, primary_latency_ms,
+                "mirror skipped: no healthy backends"
+            );
+            return;
+        };
+        let upstream = backend.addr.to_string();
+
+        let start = Instant::now();
+        let outcome = tokio::time::timeout(MIRROR_TIMEOUT, send(&backend.peer, request)).await;
+        let latency_ms = start.elapsed().as_millis();
+        match outcome {
+            Ok(Ok(status)) => tracing::info!(
+                target: "routini::mirror",
+                path, request_id, upstream, status, latency_ms, primary_status, primary_latency_ms,
+                "mirror"
+            ),
+            Ok(Err(err)) => tracing::warn!(
+                target: "routini::mirror",
+                path, request_id, upstream, latency_ms, primary_status, primary_latency_ms,
+                error = %err,
+                "mirror failed"
+            ),
+            Err(_) => tracing::warn!(
+                target: "routini::mirror",
+                path, request_id, upstream, latency_ms, primary_status, primary_latency_ms,
+                "mirror timed out"
+            ),
+        }
+    });
+}
+
+/// Replay the request against `peer`, drain the response and return its status.
+async fn send(peer: &HttpPeer, request: MirrorRequest) -> Result<u16> {
+    let (mut session, _reused) = CONNECTOR.get_http_session(peer).await?;
+
+    let mut header = request.header;
+    header.remove_header(&http::header::TRANSFER_ENCODING);
+    let body_len = request.body.as_ref().map_or(0, Bytes::len);
+    header.insert_header(http::header::CONTENT_LENGTH, body_len.to_string())?;
+
+    session.write_request_header(Box::new(header)).await?;
+    if let Some(body) = request.body {
+        session.write_request_body(body, true).await?;
+    }
+    session.finish_request_body().await?;
+
+    session.read_response_header().await?;
+    let status = session
+        .response_header()
+        .map_or(0, |resp| resp.status.as_u16());
+    while session.read_response_body().await?.is_some() {
+        // discard the mirror's response body
+    }
+
+    CONNECTOR
+        .release_http_session(session, peer, peer.idle_timeout())
+        .await;
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, sync::Arc};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::mpsc::{UnboundedReceiver, unbounded_channel},
+    };
+
+    use super::*;
+    use crate::{
+        adaptive_loadbalancer::{
+            AdaptiveBackend, AdaptiveBackends, AdaptiveLoadBalancer,
+            decision_engine::AdaptiveDecisionEngine, options::AdaptiveLbOpt,
+        },
+        load_balancing::discovery::Static,
+        route::MirrorConfig,
+    };
+
+    const NO_CONTENT: &str = "HTTP/1.1 204 No Content\r\n\r\n";
+
+    /// A mirror backend answering every request with `response` after `delay`, sending each
+    /// request it reads to the returned channel.
+    async fn mirror_backend(
+        delay: Duration,
+        response: &'static str,
+    ) -> (String, UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (requests, received) = unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let requests = requests.clone();
+                tokio::spawn(async move {
+                    while let Some(request) = read_request(&mut stream).await {
+                        let _ = requests.send(request);
+                        tokio::time::sleep(delay).await;
+                        if stream.write_all(response.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (addr, received)
+    }
+
+    /// The next request on `stream`, its body sized by `Content-Length`; `None` once closed.
+    async fn read_request(stream: &mut TcpStream) -> Option<String> {
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        loop {
+            if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&request[..end]).to_ascii_lowercase();
+                let body_len = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("content-length:"))
+                    .map_or(0, |len| len.trim().parse().unwrap());
+                if request.len() >= end + 4 + body_len {
+                    return Some(String::from_utf8(request).unwrap());
+                }
+            }
+            let read = stream.read(&mut buf).await.ok()?;
+            if read == 0 {
+                return None;
+            }
+            request.extend_from_slice(&buf[..read]);
+        }
+    }
+
+    async fn mirror_pool(addr: &str) -> SharedLb {
+        let backend = AdaptiveBackend::build(addr, 1).unwrap();
+        let backends = AdaptiveBackends::new(Static::new(BTreeSet::from([backend])));
+        let options = AdaptiveLbOpt::default();
+        backends
+            .update(&options.starting_strategy, |_| {})
+            .await
+            .unwrap();
+        let engine = AdaptiveDecisionEngine::new(&options);
+        let lb = AdaptiveLoadBalancer::from_backends(backends, Some(options), engine);
+        Arc::new(lb)
+    }
+
+    fn request(body: Option<&'static str>) -> MirrorRequest {
+        let mut header = RequestHeader::build("POST", b"/api/users", None).unwrap();
+        header.insert_header("Host", "example.com").unwrap();
+        header
+            .insert_header("Transfer-Encoding", "chunked")
+            .unwrap();
+        MirrorRequest {
+            header,
+            body: body.map(Bytes::from_static),
+            request_id: Some("req-1".to_string()),
+            primary_status: 200,
+            primary_latency: Duration::from_millis(5),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spawn_does_not_wait_for_the_mirror() {
+        let (addr, mut received) = mirror_backend(Duration::from_secs(5), NO_CONTENT).await;
+        let lb = mirror_pool(&addr).await;
+
+        let start = Instant::now();
+        spawn(lb, request(Some("payload")));
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        // the copy still goes out, in the background, with its body at a fixed length
+        let copy = tokio::time::timeout(Duration::from_secs(2), received.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(copy.starts_with("POST /api/users HTTP/1.1\r\n"));
+        assert!(copy.to_ascii_lowercase().contains("content-length: 7\r\n"));
+        assert!(!copy.to_ascii_lowercase().contains("transfer-encoding"));
+        assert!(copy.ends_with("\r\n\r\npayload"));
+    }
+
+    #[tokio::test]
+    async fn test_mirror_response_is_discarded() {
+        let response =
+            "HTTP/1.1 500 Internal Server Error\r\ncontent-length: 20\r\n\r\ndiscarded by routini";
+        let (addr, mut received) = mirror_backend(Duration::ZERO, response).await;
+        let peer = AdaptiveBackend::build(&addr, 1).unwrap().peer;
+
+        // Only the status comes back; the body is read off the connection and dropped, so the
+        // connection is left ready for the next copy.
+        for _ in 0..2 {
+            assert_eq!(send(&peer, request(None)).await.unwrap(), 500);
+        }
+        for _ in 0..2 {
+            let copy = received.recv().await.unwrap();
+            assert!(copy.to_ascii_lowercase().contains("content-length: 0\r\n"));
+        }
+    }
+
+    #[test]
+    fn test_sampling() {
+        let mut mirror = MirrorConfig {
+            sample_percent: 25.0,
+            include_body: false,
+            max_body_size: 0,
+        };
+        let sampled = (0..10_000).filter(|_| mirror.sample()).count();
+        assert!((2300..2700).contains(&sampled), "sampled {sampled}");
+
+        mirror.sample_percent = 0.0;
+        assert!((0..1000).all(|_| !mirror.sample()));
+        mirror.sample_percent = 100.0;
+        assert!((0..1000).all(|_| mirror.sample()));
+    }
+}
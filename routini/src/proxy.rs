@@ -1,15 +1,21 @@
-use bytes::Bytes;
+use arc_swap::ArcSwap;
+use bytes::{Bytes, BytesMut};
+use color_eyre::eyre::{WrapErr, eyre};
 use http::StatusCode;
 use matchit::Router;
 use pingora::cache::cache_control::CacheControl;
 use pingora::cache::eviction::simple_lru::Manager as LruManager;
 use pingora::cache::filters::resp_cacheable;
-use pingora::cache::{CacheMeta, CacheMetaDefaults, MemCache, RespCacheable};
+use pingora::cache::{CacheMeta, CacheMetaDefaults, CachePhase, MemCache, RespCacheable};
 use quick_cache::sync::Cache;
-use regex::Regex;
+use regex::{Regex, RegexBuilder};
 use std::sync::LazyLock;
 use std::time::SystemTime;
-use std::{net::IpAddr, sync::Arc, time::Instant};
+use std::{
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 /// Shared in-memory response cache backend and its LRU eviction manager (nginx `proxy_cache_path`).
 static CACHE_BACKEND: LazyLock<MemCache> = LazyLock::new(MemCache::new);
@@ -26,16 +32,45 @@ use pingora::{
     proxy::{FailToProxy, ProxyHttp, Session},
 };
 use std::collections::HashMap;
+use tracing::Span;
 
 use crate::{
+    access_log::{AccessLog, AccessLogEntry},
     adaptive_loadbalancer::AdaptiveBackend,
     load_balancing::Metrics,
-    route::{RouteAction, RouteRuntime, RouteState},
+    metrics::{
+        CACHE_LOOKUPS, EJECTIONS, LIMIT_REJECTIONS, REQUEST_DURATION, REQUESTS, RETRIES,
+        RETRY_BUDGET_EXHAUSTED, status_class,
+    },
+    mirror::{self, MirrorRequest},
+    route::{
+        PassiveHealth, RewriteFlag, RouteAction, RouteRuntime, RouteState, SharedLb, cookie_value,
+        expand_template,
+    },
+    trace_context::TraceContext,
     utils::constants::{DEFAULT_PATH_CACHE_CAPACITY, DEFAULT_PATH_REMAINDER_IDENTIFIER},
 };
 
+#[derive(Clone)]
 pub struct RouteValue {
     pub runtime: Arc<RouteRuntime>,
+    /// Later-declared routes sharing this path, told apart by their request predicates and tried
+    /// in declared order after `runtime`.
+    pub alternatives: Vec<Arc<RouteRuntime>>,
+}
+
+impl RouteValue {
+    pub fn new(runtime: Arc<RouteRuntime>) -> Self {
+        Self {
+            runtime,
+            alternatives: Vec::new(),
+        }
+    }
+
+    /// All routes for this path, in declared order.
+    pub fn runtimes(&self) -> impl Iterator<Item = &Arc<RouteRuntime>> {
+        std::iter::once(&self.runtime).chain(&self.alternatives)
+    }
 }
 
 /// The pre-computed result of routing a concrete request path, stored in the path cache.
@@ -48,6 +83,30 @@ pub struct CachedRoute {
     /// The rewritten upstream path when `strip_path_prefix` is enabled, already encoded as bytes
     /// ready for `set_raw_path`. `None` when the path is forwarded unchanged.
     pub stripped_path: Option<Box<[u8]>>,
+    /// The regex location this route was matched by, whose captures a redirect action may use.
+    pub location: Option<Regex>,
+    /// The next candidate for this path (a route sharing the path, then any matching regex
+    /// location), consulted when this route's request predicates reject the request.
+    pub next: Option<Arc<CachedRoute>>,
+}
+
+impl CachedRoute {
+    /// The first candidate, in declared order, whose request predicates accept `req`, together
+    /// with the state snapshot the predicates were evaluated against.
+    pub fn select(
+        self: &Arc<Self>,
+        req: &RequestHeader,
+    ) -> Option<(Arc<CachedRoute>, Arc<RouteState>)> {
+        let mut candidate = Some(self);
+        while let Some(route) = candidate {
+            let state = route.runtime.state.load_full();
+            if state.config.predicates.matches(req) {
+                return Some((route.clone(), state));
+            }
+            candidate = route.next.as_ref();
+        }
+        None
+    }
 }
 
 /// Extract the client IP from the downstream session, if it is an inet socket.
@@ -66,20 +125,6 @@ fn client_is_tls(session: &Session) -> bool {
         .unwrap_or(false)
 }
 
-/// Expand a redirect `Location` template, substituting `$request_uri` (path + query) and `$uri`
-/// (path). Returns the template unchanged when it contains no `$` placeholder.
-fn redirect_location(template: &str, session: &Session) -> String {
-    if !template.contains('$') {
-        return template.to_string();
-    }
-    let uri = &session.req_header().uri;
-    let path = uri.path();
-    let request_uri = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or(path);
-    template
-        .replace("$request_uri", request_uri)
-        .replace("$uri", path)
-}
-
 /// Parse the request's `Content-Length`, if present and valid.
 fn content_length(session: &Session) -> Option<usize> {
     session
@@ -92,35 +137,189 @@ fn content_length(session: &Session) -> Option<usize> {
         .ok()
 }
 
-/// A name-based virtual host: its own path router and path cache (nginx `server` block).
+/// The routes of one server: matchit paths plus regex locations tried when no path matches.
+pub struct VHostRoutes {
+    pub router: Router<RouteValue>,
+    pub regex: Vec<(Regex, RouteValue)>,
+}
+
+impl Default for VHostRoutes {
+    fn default() -> Self {
+        Self {
+            router: Router::new(),
+            regex: Vec::new(),
+        }
+    }
+}
+
+impl From<Router<RouteValue>> for VHostRoutes {
+    fn from(router: Router<RouteValue>) -> Self {
+        Self {
+            router,
+            regex: Vec::new(),
+        }
+    }
+}
+
+/// A virtual host `server_name` (nginx semantics): an exact name, a leading (`*.example.com`) or
+/// trailing (`www.example.*`) wildcard, or a `~`-prefixed regex. As in nginx, a wildcard can only
+/// stand for whole labels at either end of the name.
+#[derive(Debug, Clone)]
+pub enum HostPattern {
+    Exact(String),
+    /// The required suffix, including the leading dot (`.example.com`).
+    Leading(String),
+    /// The required prefix, including the trailing dot (`www.example.`).
+    Trailing(String),
+    Regex(Regex),
+}
+
+impl HostPattern {
+    pub fn parse(name: &str) -> color_eyre::Result<Self> {
+        if let Some(re) = name.strip_prefix('~') {
+            let re = RegexBuilder::new(re).case_insensitive(true).build()?;
+            return Ok(HostPattern::Regex(re));
+        }
+        let name = name.to_ascii_lowercase();
+        let (leading, trailing) = (name.strip_prefix("*."), name.strip_suffix(".*"));
+        let rest = leading.or(trailing).unwrap_or(&name);
+        if rest.is_empty() || rest.contains('*') {
+            return Err(eyre!(
+                "Invalid server_name '{name}': a wildcard must be a leading '*.' or a trailing '.*'"
+            ));
+        }
+        Ok(match (leading, trailing) {
+            (Some(suffix), _) => HostPattern::Leading(format!(".{suffix}")),
+            (None, Some(prefix)) => HostPattern::Trailing(format!("{prefix}.")),
+            (None, None) => HostPattern::Exact(name),
+        })
+    }
+}
+
+/// A name-based virtual host: its own path router, regex locations and path cache (nginx
+/// `server` block).
 struct VHost {
     router: Router<RouteValue>,
+    regex: Vec<(Regex, RouteValue)>,
     cache: Cache<Box<str>, Arc<CachedRoute>>,
 }
 
-#[derive(Clone)]
-pub struct Proxy {
+/// Virtual hosts by `server_name` kind, searched in nginx precedence order: exact, longest
+/// leading wildcard, longest trailing wildcard, then the first matching regex in declared order.
+#[derive(Default)]
+struct VHosts {
+    exact: HashMap<String, VHost>,
+    leading: Vec<(String, VHost)>,
+    trailing: Vec<(String, VHost)>,
+    regex: Vec<(Regex, VHost)>,
+}
+
+impl VHosts {
+    fn new(vhosts: Vec<(String, VHostRoutes)>, capacity: usize) -> color_eyre::Result<Self> {
+        let mut table = VHosts::default();
+        for (name, routes) in vhosts {
+            let vhost = VHost {
+                router: routes.router,
+                regex: routes.regex,
+                cache: Cache::new(capacity),
+            };
+            match HostPattern::parse(&name).wrap_err_with(|| format!("Invalid host '{name}'"))? {
+                HostPattern::Exact(name) => {
+                    table.exact.insert(name, vhost);
+                }
+                HostPattern::Leading(suffix) => table.leading.push((suffix, vhost)),
+                HostPattern::Trailing(prefix) => table.trailing.push((prefix, vhost)),
+                HostPattern::Regex(re) => table.regex.push((re, vhost)),
+            }
+        }
+        table.leading.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        table.trailing.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+        Ok(table)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.exact.is_empty()
+            && self.leading.is_empty()
+            && self.trailing.is_empty()
+            && self.regex.is_empty()
+    }
+
+    /// Find the vhost for a lowercased, port-less host name.
+    fn find(&self, host: &str) -> Option<&VHost> {
+        if let Some(vhost) = self.exact.get(host) {
+            return Some(vhost);
+        }
+        self.leading
+            .iter()
+            .find(|(suffix, _)| host.ends_with(suffix.as_str()))
+            .or_else(|| {
+                self.trailing
+                    .iter()
+                    .find(|(prefix, _)| host.starts_with(prefix.as_str()))
+            })
+            .map(|(_, vhost)| vhost)
+            .or_else(|| {
+                self.regex
+                    .iter()
+                    .find(|(re, _)| re.is_match(host))
+                    .map(|(_, vhost)| vhost)
+            })
+    }
+}
+
+/// Every route the proxy serves. Swapped as a whole on config reload, so each request routes
+/// against one consistent table.
+pub struct RouteTable {
     /// Router + path cache for requests that match no configured virtual host (the default server).
-    default_router: Arc<Router<RouteValue>>,
+    default_router: Router<RouteValue>,
     /// Bounded concurrent cache mapping a concrete request path to its resolved route. The first
     /// request to a path does the matchit lookup and stripping; subsequent requests reuse the
-    /// cached `Arc<CachedRoute>` with no allocation. Routes never change after build, so entries
+    /// cached `Arc<CachedRoute>` with no allocation. A reload replaces the whole table, so entries
     /// never need invalidation — eviction only bounds memory.
-    default_cache: Arc<Cache<Box<str>, Arc<CachedRoute>>>,
-    /// Name-based virtual hosts, keyed by lowercased host. Empty in the common single-host case,
-    /// in which routing skips host handling entirely and behaves exactly like path-only routing.
-    vhosts: Arc<std::collections::HashMap<String, VHost>>,
+    default_cache: Cache<Box<str>, Arc<CachedRoute>>,
+    /// Name-based virtual hosts (exact, wildcard and regex `server_name`s). Empty in the common
+    /// single-host case, in which routing skips host handling entirely and behaves exactly like
+    /// path-only routing.
+    vhosts: VHosts,
     /// Regex location routes for the default server, tried in order when matchit finds no match
     /// (nginx `location ~ <regex>`). These forward the full path (no prefix stripping).
-    default_regex: Arc<Vec<(Regex, RouteValue)>>,
-    /// Emit a structured access-log line per request (nginx `access_log on/off`).
-    access_log: bool,
+    default_regex: Vec<(Regex, RouteValue)>,
+}
+
+impl RouteTable {
+    /// Build a table from the default server's routes plus virtual hosts keyed by `server_name`
+    /// (exact, `*.example.com`, `www.example.*` or `~regex`), in declared order. Fails on an
+    /// invalid `server_name`.
+    pub fn new(
+        default: VHostRoutes,
+        vhosts: Vec<(String, VHostRoutes)>,
+        capacity: usize,
+    ) -> color_eyre::Result<Self> {
+        Ok(Self {
+            default_router: default.router,
+            default_cache: Cache::new(capacity),
+            vhosts: VHosts::new(vhosts, capacity)?,
+            default_regex: default.regex,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Proxy {
+    /// The live route table, shared by all clones of the proxy and replaced on reload.
+    routes: Arc<ArcSwap<RouteTable>>,
+    /// Path cache capacity of each router, reused for tables built on reload.
+    cache_capacity: usize,
+    /// Log each completed request (nginx `access_log`); `None` = off.
+    access_log: Option<Arc<AccessLog>>,
     /// Redirect plain-HTTP requests to `https://` (nginx `return 301 https://...`).
     https_redirect: bool,
     /// Downstream response compression level (nginx `gzip`); 0 disables it.
     compression_level: u32,
     /// Generate/propagate an `X-Request-Id` header for request tracing.
     request_id: bool,
+    /// Propagate W3C `traceparent`/`tracestate` and record a span per request and upstream attempt.
+    trace_context: bool,
     /// Custom error-page bodies keyed by status code (nginx `error_page`).
     error_pages: Arc<HashMap<u16, String>>,
 }
@@ -151,6 +350,7 @@ impl Proxy {
 
     pub fn with_cache_capacity(routes: Router<RouteValue>, capacity: usize) -> Self {
         Self::with_vhosts(routes, std::collections::HashMap::new(), capacity)
+            .expect("no server_name to parse")
     }
 
     /// Build a proxy with a default router plus name-based virtual host routers.
@@ -158,7 +358,7 @@ impl Proxy {
         default_router: Router<RouteValue>,
         vhosts: std::collections::HashMap<String, Router<RouteValue>>,
         capacity: usize,
-    ) -> Self {
+    ) -> color_eyre::Result<Self> {
         Self::with_regex_routes(default_router, Vec::new(), vhosts, capacity)
     }
 
@@ -168,31 +368,54 @@ impl Proxy {
         default_regex: Vec<(Regex, RouteValue)>,
         vhosts: std::collections::HashMap<String, Router<RouteValue>>,
         capacity: usize,
-    ) -> Self {
-        let vhosts = vhosts
-            .into_iter()
-            .map(|(host, router)| {
-                (
-                    host,
-                    VHost {
-                        router,
-                        cache: Cache::new(capacity),
-                    },
-                )
-            })
-            .collect();
-
-        Proxy {
-            default_router: Arc::new(default_router),
-            default_cache: Arc::new(Cache::new(capacity)),
-            vhosts: Arc::new(vhosts),
-            default_regex: Arc::new(default_regex),
-            access_log: true,
+    ) -> color_eyre::Result<Self> {
+        Self::with_vhost_routes(
+            VHostRoutes {
+                router: default_router,
+                regex: default_regex,
+            },
+            vhosts
+                .into_iter()
+                .map(|(host, router)| (host, router.into()))
+                .collect(),
+            capacity,
+        )
+    }
+
+    /// Build a proxy from the default server's routes plus virtual hosts keyed by `server_name`
+    /// (exact, `*.example.com`, `www.example.*` or `~regex`), in declared order.
+    pub fn with_vhost_routes(
+        default: VHostRoutes,
+        vhosts: Vec<(String, VHostRoutes)>,
+        capacity: usize,
+    ) -> color_eyre::Result<Self> {
+        let table = RouteTable::new(default, vhosts, capacity)?;
+        Ok(Proxy {
+            routes: Arc::new(ArcSwap::from_pointee(table)),
+            cache_capacity: capacity,
+            access_log: Some(Arc::new(AccessLog::default())),
             https_redirect: false,
             compression_level: 0,
             request_id: false,
+            trace_context: false,
             error_pages: Arc::new(HashMap::new()),
-        }
+        })
+    }
+
+    /// Build a route table to replace this proxy's with, its path caches as large as the
+    /// current ones.
+    pub fn route_table(
+        &self,
+        default: VHostRoutes,
+        vhosts: Vec<(String, VHostRoutes)>,
+    ) -> color_eyre::Result<RouteTable> {
+        RouteTable::new(default, vhosts, self.cache_capacity)
+    }
+
+    /// Atomically replace every route with `table`, e.g. on config reload. Requests already
+    /// routed finish on their old route; the new table starts with empty path caches.
+    pub fn replace_routes(&self, table: RouteTable) {
+        self.routes.store(Arc::new(table));
     }
 
     /// Set custom error-page bodies keyed by status code.
@@ -216,9 +439,18 @@ impl Proxy {
         }
     }
 
-    /// Enable or disable the per-request access log.
+    /// Enable or disable the per-request access log. Enabling keeps a configured log.
     pub fn set_access_log(&mut self, enabled: bool) {
-        self.access_log = enabled;
+        if !enabled {
+            self.access_log = None;
+        } else if self.access_log.is_none() {
+            self.access_log = Some(Arc::new(AccessLog::default()));
+        }
+    }
+
+    /// Enable the access log with its format, filter and sink.
+    pub fn set_access_log_config(&mut self, log: AccessLog) {
+        self.access_log = Some(Arc::new(log));
     }
 
     /// Set the downstream response compression level (0 disables).
@@ -231,6 +463,11 @@ impl Proxy {
         self.request_id = enabled;
     }
 
+    /// Enable W3C Trace Context propagation and request spans (see [`crate::trace_context`]).
+    pub fn set_trace_context(&mut self, enabled: bool) {
+        self.trace_context = enabled;
+    }
+
     /// Redirect plain-HTTP requests to the `https://` equivalent.
     pub fn set_https_redirect(&mut self, enabled: bool) {
         self.https_redirect = enabled;
@@ -238,11 +475,12 @@ impl Proxy {
 
     /// Resolve a request's `host` + `path` to its (cached) route.
     ///
-    /// When virtual hosts are configured and the host matches one, that vhost's router/cache are
+    /// When virtual hosts are configured and the host matches one, that vhost's routes/cache are
     /// used; otherwise the default server handles it. In the common (no-vhost) case this is a
     /// single concurrent-map lookup plus an `Arc` clone, identical to path-only routing.
     pub fn resolve(&self, host: Option<&str>, path: &str) -> Result<Arc<CachedRoute>> {
-        if !self.vhosts.is_empty() {
+        let table = self.routes.load();
+        if !table.vhosts.is_empty() {
             if let Some(host) = host {
                 // Match nginx server_name semantics: case-insensitive, ignore the port.
                 let key = host
@@ -250,15 +488,15 @@ impl Proxy {
                     .next()
                     .unwrap_or(host)
                     .to_ascii_lowercase();
-                if let Some(vhost) = self.vhosts.get(&key) {
-                    return Self::resolve_in(&vhost.router, &[], &vhost.cache, path);
+                if let Some(vhost) = table.vhosts.find(&key) {
+                    return Self::resolve_in(&vhost.router, &vhost.regex, &vhost.cache, path);
                 }
             }
         }
         Self::resolve_in(
-            &self.default_router,
-            &self.default_regex,
-            &self.default_cache,
+            &table.default_router,
+            &table.default_regex,
+            &table.default_cache,
             path,
         )
     }
@@ -273,62 +511,195 @@ impl Proxy {
             return Ok(cached);
         }
 
-        let cached = match Self::route_in(router, path) {
-            Ok((route_value, stripped_path)) => Arc::new(CachedRoute {
-                runtime: route_value.runtime.clone(),
-                stripped_path: stripped_path.map(|p| p.into_bytes().into_boxed_slice()),
-            }),
-            // matchit miss: fall back to regex locations (first match wins, full path forwarded).
-            Err(not_found) => match regex.iter().find(|(re, _)| re.is_match(path)) {
-                Some((_, route_value)) => Arc::new(CachedRoute {
-                    runtime: route_value.runtime.clone(),
-                    stripped_path: None,
-                }),
-                None => return Err(not_found),
-            },
+        // Candidates in declared order: the routes sharing the matchit match, then the regex
+        // locations matching the path (full path forwarded). Without predicates the first candidate
+        // always wins, so regex locations are only reached on a matchit miss as before.
+        let mut candidates: Vec<(Arc<RouteRuntime>, Option<Box<[u8]>>, Option<Regex>)> = Vec::new();
+        let not_found = match router.at(path) {
+            Ok(m) => {
+                for runtime in m.value.runtimes() {
+                    let strip = runtime.state.load().config.strip_path_prefix;
+                    let stripped_path = strip
+                        .then(|| Self::stripped_path(&m.params))
+                        .flatten()
+                        .map(|p| p.into_bytes().into_boxed_slice());
+                    candidates.push((runtime.clone(), stripped_path, None));
+                }
+                None
+            }
+            Err(e) => Some(e),
+        };
+        for (re, route_value) in regex.iter().filter(|(re, _)| re.is_match(path)) {
+            candidates.extend(
+                route_value
+                    .runtimes()
+                    .map(|runtime| (runtime.clone(), None, Some(re.clone()))),
+            );
+        }
+
+        let mut chain = None;
+        for (runtime, stripped_path, location) in candidates.into_iter().rev() {
+            chain = Some(Arc::new(CachedRoute {
+                runtime,
+                stripped_path,
+                location,
+                next: chain,
+            }));
+        }
+        let Some(cached) = chain else {
+            return Err(Self::not_found(not_found));
         };
 
         cache.insert(path.into(), cached.clone());
         Ok(cached)
     }
 
+    /// Count a completed request in the route's metrics (see [`crate::metrics`]). Requests that
+    /// matched no route are not counted.
+    fn record_metrics(session: &Session, status: u16, ctx: &ConnectionCTX) {
+        let Some(route) = &ctx.route else {
+            return;
+        };
+        let route_label = route.runtime.label.as_str();
+        let lb = ctx.lb.as_ref().unwrap_or(&route.runtime.lb);
+        let pool = lb.labels.pool.as_str();
+        let backend = ctx
+            .backend
+            .as_ref()
+            .map(|b| b.addr.to_string())
+            .unwrap_or_default();
+        REQUESTS
+            .with_label_values(&[route_label, pool, &backend, status_class(status)])
+            .inc();
+        REQUEST_DURATION
+            .with_label_values(&[route_label, pool])
+            .observe(ctx.request_start.elapsed().as_secs_f64());
+
+        if ctx.state.as_ref().is_none_or(|s| s.config.cache.is_none()) {
+            return;
+        }
+        let result = match session.cache.phase() {
+            CachePhase::Hit
+            | CachePhase::Stale
+            | CachePhase::StaleUpdating
+            | CachePhase::Revalidated => "hit",
+            CachePhase::Miss | CachePhase::Expired | CachePhase::RevalidatedNoCache(_) => "miss",
+            _ => return,
+        };
+        CACHE_LOOKUPS
+            .with_label_values(&[route_label, result])
+            .inc();
+    }
+
+    fn not_found(cause: Option<matchit::MatchError>) -> Box<Error> {
+        Box::new(Error {
+            cause: cause.map(|e| Box::new(e) as _),
+            context: Some(ImmutStr::Static("Failed to route path to backend")),
+            esource: ErrorSource::Internal,
+            etype: ErrorType::HTTPStatus(StatusCode::NOT_FOUND.as_u16()),
+            retry: RetryType::Decided(false),
+        })
+    }
+
+    /// Record a passive-health outcome of the request's backend with `record`, ejecting the
+    /// backend from the route's pools when it returns `true`.
+    fn record_passive_health(
+        ctx: &ConnectionCTX,
+        reason: &str,
+        record: impl FnOnce(&PassiveHealth, &AdaptiveBackend) -> bool,
+    ) {
+        let (Some(route), Some(state), Some(lb), Some(backend)) =
+            (&ctx.route, &ctx.state, &ctx.lb, &ctx.backend)
+        else {
+            return;
+        };
+        if !record(&state.health, backend) {
+            return;
+        }
+        route.runtime.set_backend_enabled(backend, false);
+        log::warn!("Passively ejected backend {} after {reason}", backend.addr);
+        let (route_label, pool) = (route.runtime.label.as_str(), lb.labels.pool.as_str());
+        EJECTIONS
+            .with_label_values(&[route_label, pool, &backend.addr.to_string()])
+            .inc();
+    }
+
+    /// Whether the route retries this upstream error: a read timeout or a dropped connection
+    /// before any of the response reached the client.
+    fn retries_upstream_error(session: &Session, ctx: &ConnectionCTX, e: &Error) -> bool {
+        let Some(state) = &ctx.state else {
+            return false;
+        };
+        if e.esource() != &ErrorSource::Upstream || session.as_ref().response_written().is_some() {
+            return false;
+        }
+        let retry = &state.config.retry;
+        match e.etype() {
+            ErrorType::ReadTimedout => retry.retry_on_timeout,
+            ErrorType::ConnectionClosed | ErrorType::ReadError | ErrorType::WriteError => {
+                retry.retry_on_reset
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the failed attempt may be retried on another backend: attempts remain, a request
+    /// that reached the upstream can be replayed, and the route's retry budget has one to spend.
+    /// Counts the retry when it is allowed.
+    fn may_retry(session: &Session, ctx: &ConnectionCTX, request_sent: bool) -> bool {
+        let (Some(route), Some(state), Some(lb)) = (&ctx.route, &ctx.state, &ctx.lb) else {
+            return false;
+        };
+        let retry = &state.config.retry;
+        if ctx.tried.len() > retry.max_retries {
+            return false;
+        }
+        // Once sent, a request is only replayed when repeating it is safe and its whole body is
+        // still buffered.
+        let replayable = retry.may_resend(&session.req_header().method)
+            && ctx.body_seen <= retry.max_body_size
+            && !session.as_ref().retry_buffer_truncated();
+        if request_sent && !replayable {
+            return false;
+        }
+        let (route_label, pool) = (route.runtime.label.as_str(), lb.labels.pool.as_str());
+        let budget = state.retry_budget.as_ref();
+        if budget.is_some_and(|budget| !budget.try_withdraw()) {
+            RETRY_BUDGET_EXHAUSTED
+                .with_label_values(&[route_label, pool])
+                .inc();
+            return false;
+        }
+        RETRIES.with_label_values(&[route_label, pool]).inc();
+        true
+    }
+
+    /// The path remainder captured by a trailing `{*rest}` wildcard, as an absolute path.
+    fn stripped_path(params: &matchit::Params) -> Option<String> {
+        params.get(DEFAULT_PATH_REMAINDER_IDENTIFIER).map(|p| {
+            if !p.starts_with('/') {
+                format!("/{p}")
+            } else {
+                p.to_string()
+            }
+        })
+    }
+
     /// Look up a path in the default router (used by the strategy endpoint and tests).
-    pub fn route(&self, path: &str) -> Result<(&RouteValue, Option<String>)> {
-        Self::route_in(&self.default_router, path)
+    pub fn route(&self, path: &str) -> Result<(RouteValue, Option<String>)> {
+        let table = self.routes.load();
+        let (route_value, stripped_path) = Self::route_in(&table.default_router, path)?;
+        Ok((route_value.clone(), stripped_path))
     }
 
     fn route_in<'r>(
         router: &'r Router<RouteValue>,
         path: &str,
     ) -> Result<(&'r RouteValue, Option<String>)> {
-        router
-            .at(path)
-            .map_err(|e| {
-                Box::new(Error {
-                    cause: Some(Box::new(e)),
-                    context: Some(ImmutStr::Static("Failed to route path to backend")),
-                    esource: ErrorSource::Internal,
-                    etype: ErrorType::HTTPStatus(StatusCode::NOT_FOUND.as_u16()),
-                    retry: RetryType::Decided(false),
-                })
-            })
-            .map(|m| {
-                let value = m.value;
-                let strip = value.runtime.state.load().config.strip_path_prefix;
-                let stripped_path = strip
-                        .then_some(m)
-                        .and_then(|m| {
-                            m.params.get(DEFAULT_PATH_REMAINDER_IDENTIFIER).map(|p| {
-                                if !p.starts_with('/') {
-                                    format!("/{p}")
-                                } else {
-                                    p.to_string()
-                                }
-                            })
-                        });
-
-                (value, stripped_path)
-            })
+        let m = router.at(path).map_err(|e| Self::not_found(Some(e)))?;
+        let strip = m.value.runtime.state.load().config.strip_path_prefix;
+        let stripped_path = strip.then(|| Self::stripped_path(&m.params)).flatten();
+        Ok((m.value, stripped_path))
     }
 }
 
@@ -346,13 +717,46 @@ pub struct ConnectionCTX {
     body_seen: usize,
     /// When the request was received, for access-log latency.
     request_start: Instant,
-    /// Original request path (captured before prefix stripping) for the access log.
-    orig_path: Option<Box<str>>,
+    /// Original request path and query (captured before prefix stripping) for the access log.
+    orig_uri: Option<Box<str>>,
+    /// Time from sending the request upstream to receiving the response header, for the access
+    /// log's `$upstream_response_time`.
+    upstream_response_time: Option<Duration>,
     /// Held for the request's lifetime to keep the per-IP concurrency count accurate; the count is
     /// decremented when this guard drops at the end of the request.
     conn_guard: Option<pingora_limits::inflight::Guard>,
     /// The request's `X-Request-Id` (incoming or generated) for upstream/response/log propagation.
     request_id: Option<String>,
+    /// Bytes fed to the hashing strategies, extracted once from the original request so retries
+    /// (which see the rewritten path) hash the same way.
+    hash_key: Vec<u8>,
+    /// `Set-Cookie` value pinning the client to the selected backend, written in
+    /// `response_filter` when the request arrived without a usable sticky cookie.
+    sticky_cookie: Option<String>,
+    /// The upstream URI produced by a matching `last` rewrite rule; takes precedence over the
+    /// route's stripped path.
+    rewritten_uri: Option<Box<[u8]>>,
+    /// The upstream pool chosen for this request (by the route's traffic split), fixed for all
+    /// attempts so retries stay within the pool.
+    lb: Option<SharedLb>,
+    /// Set when this request was sampled for mirroring; filled in as the request is proxied and
+    /// sent to the mirror pool from `logging`.
+    mirror: Option<MirrorCapture>,
+    /// The request's own span context when trace context is enabled, and its server span.
+    trace: Option<TraceContext>,
+    server_span: Option<Span>,
+    /// The current upstream attempt's span context (sent upstream as `traceparent`) and span.
+    /// Replacing them on a retry ends the failed attempt's span.
+    upstream_trace: Option<TraceContext>,
+    upstream_span: Option<Span>,
+}
+
+/// A request being captured for the route's mirror pool.
+struct MirrorCapture {
+    /// The upstream request header of the first attempt.
+    header: Option<RequestHeader>,
+    /// The buffered request body, when the route mirrors bodies.
+    body: Option<BytesMut>,
 }
 
 #[async_trait::async_trait]
@@ -368,9 +772,19 @@ impl ProxyHttp for Proxy {
             tried: Vec::new(),
             body_seen: 0,
             request_start: Instant::now(),
-            orig_path: None,
+            orig_uri: None,
+            upstream_response_time: None,
             conn_guard: None,
             request_id: None,
+            hash_key: Vec::new(),
+            sticky_cookie: None,
+            rewritten_uri: None,
+            lb: None,
+            mirror: None,
+            trace: None,
+            server_span: None,
+            upstream_trace: None,
+            upstream_span: None,
         }
     }
 
@@ -428,11 +842,22 @@ impl ProxyHttp for Proxy {
             ctx.request_id = Some(id);
         }
 
+        // Continue the caller's trace (or start one) with this request's server span.
+        if self.trace_context {
+            let req = session.req_header();
+            let trace = TraceContext::from_request(req)
+                .map(|incoming| incoming.child())
+                .unwrap_or_else(TraceContext::root);
+            ctx.server_span = Some(trace.server_span(req.method.as_str()));
+            ctx.trace = Some(trace);
+        }
+
         let resolved = {
             let req = session.req_header();
             let path = req.uri.path();
-            if self.access_log {
-                ctx.orig_path = Some(Box::from(path));
+            if self.access_log.is_some() {
+                let uri = req.uri.path_and_query().map_or(path, |pq| pq.as_str());
+                ctx.orig_uri = Some(Box::from(uri));
             }
             // Prefer the URI authority (HTTP/2 :authority / absolute-form), fall back to Host header.
             let host = req.uri.host().or_else(|| {
@@ -443,16 +868,22 @@ impl ProxyHttp for Proxy {
             self.resolve(host, path)
         };
 
-        let cached = match resolved {
-            Ok(cached) => cached,
-            Err(_) => {
+        // Pick the first route for this path whose request predicates match, snapshotting its
+        // hot-swappable state once; every later filter reuses this via ctx.
+        let selected = resolved
+            .ok()
+            .and_then(|cached| cached.select(session.req_header()));
+        let (cached, state) = match selected {
+            Some(selected) => selected,
+            None => {
                 self.write_status(session, StatusCode::NOT_FOUND.as_u16()).await?;
                 return Ok(true);
             }
         };
-
-        // Snapshot the route's hot-swappable state once; every later filter reuses this via ctx.
-        let state = cached.runtime.state.load_full();
+        ctx.route = Some(cached.clone());
+        if let Some(span) = &ctx.server_span {
+            span.record("route", cached.runtime.label.as_str());
+        }
 
         // Access control: IP allow/deny then HTTP Basic auth.
         if let Some(access) = &state.config.access {
@@ -490,11 +921,41 @@ impl ProxyHttp for Proxy {
             }
         }
 
+        // Rewrite rules: the first match either redirects or replaces the upstream URI.
+        let rewrite = state.config.rewrites.iter().find_map(|rule| {
+            rule.apply(session.req_header())
+                .map(|target| (rule.flag, target))
+        });
+        match rewrite {
+            Some((RewriteFlag::Last, target)) => {
+                ctx.rewritten_uri = Some(target.into_bytes().into_boxed_slice());
+            }
+            Some((flag, target)) => {
+                let status = match flag {
+                    RewriteFlag::Permanent => StatusCode::MOVED_PERMANENTLY,
+                    _ => StatusCode::FOUND,
+                };
+                let mut resp = ResponseHeader::build(status.as_u16(), None)?;
+                resp.insert_header(http::header::LOCATION, target)?;
+                resp.insert_header(http::header::CONTENT_LENGTH, "0")?;
+                session.write_response_header(Box::new(resp), true).await?;
+                return Ok(true);
+            }
+            None => {}
+        }
+
         // Redirect / return actions short-circuit without proxying.
         if let Some(action) = &state.config.action {
             match action {
                 RouteAction::Redirect { status, location } => {
-                    let location = redirect_location(location, session);
+                    let location = {
+                        let req = session.req_header();
+                        let captures = cached
+                            .location
+                            .as_ref()
+                            .and_then(|re| re.captures(req.uri.path()));
+                        expand_template(location, captures.as_ref(), req)
+                    };
                     let mut resp = ResponseHeader::build(*status, None)?;
                     resp.insert_header(http::header::LOCATION, location)?;
                     resp.insert_header(http::header::CONTENT_LENGTH, "0")?;
@@ -523,6 +984,9 @@ impl ProxyHttp for Proxy {
         let client = client_ip(session);
         if let (Some(limiter), Some(ip)) = (&state.rate_limiter, client) {
             if limiter.over_limit(&ip) {
+                LIMIT_REJECTIONS
+                    .with_label_values(&[cached.runtime.label.as_str(), "rate"])
+                    .inc();
                 self.write_status(session, StatusCode::TOO_MANY_REQUESTS.as_u16())
                     .await?;
                 return Ok(true);
@@ -532,6 +996,9 @@ impl ProxyHttp for Proxy {
             match limiter.acquire(&ip) {
                 Ok(guard) => ctx.conn_guard = Some(guard),
                 Err(()) => {
+                    LIMIT_REJECTIONS
+                        .with_label_values(&[cached.runtime.label.as_str(), "conn"])
+                        .inc();
                     self.write_status(session, StatusCode::TOO_MANY_REQUESTS.as_u16())
                         .await?;
                     return Ok(true);
@@ -549,8 +1016,35 @@ impl ProxyHttp for Proxy {
             }
         }
 
+        if let Some(hash_key) = &state.config.hash_key {
+            ctx.hash_key = hash_key.extract(session.req_header(), client);
+        }
+
+        let lb = cached
+            .runtime
+            .select_pool(state.config.split.as_ref(), session.req_header());
+        ctx.lb = Some(lb.clone());
+
+        // Decide up front whether this request is copied to the route's mirror pool.
+        if let Some(mirror) = &state.config.mirror {
+            if cached.runtime.mirror.is_some() && mirror.sample() {
+                ctx.mirror = Some(MirrorCapture {
+                    header: None,
+                    body: mirror.include_body.then(BytesMut::new),
+                });
+            }
+        }
+
+        // Every request earns its share of the route's retry budget, and keeps its body for
+        // replaying when it may be retried after reaching an upstream.
+        if let Some(budget) = &state.retry_budget {
+            budget.deposit();
+        }
+        if state.config.retry.may_resend(&session.req_header().method) {
+            session.as_mut().enable_retry_buffering();
+        }
+
         ctx.state = Some(state);
-        ctx.route = Some(cached);
         Ok(false)
     }
 
@@ -561,18 +1055,29 @@ impl ProxyHttp for Proxy {
         _end_of_stream: bool,
         ctx: &mut Self::CTX,
     ) -> Result<()> {
-        let max = ctx.state.as_ref().and_then(|s| s.config.max_body_size);
-        if let Some(max) = max {
-            if let Some(chunk) = body.as_ref() {
-                ctx.body_seen = ctx.body_seen.saturating_add(chunk.len());
-                if ctx.body_seen > max {
-                    return Err(Error::explain(
-                        ErrorType::HTTPStatus(StatusCode::PAYLOAD_TOO_LARGE.as_u16()),
-                        "request body exceeds max_body_size",
-                    ));
+        // Buffer the body for the mirror copy; a body too large to buffer cancels the mirror.
+        if let (Some(capture), Some(chunk)) = (&mut ctx.mirror, body.as_ref()) {
+            let limit = ctx.state.as_ref().and_then(|s| s.config.mirror.as_ref());
+            if let (Some(buffer), Some(limit)) = (&mut capture.body, limit) {
+                if buffer.len() + chunk.len() > limit.max_body_size {
+                    ctx.mirror = None;
+                } else {
+                    buffer.extend_from_slice(chunk);
                 }
             }
         }
+
+        // Counted for every request: retries only replay bodies that fit the retry buffer.
+        if let Some(chunk) = body.as_ref() {
+            ctx.body_seen = ctx.body_seen.saturating_add(chunk.len());
+        }
+        let max = ctx.state.as_ref().and_then(|s| s.config.max_body_size);
+        if max.is_some_and(|max| ctx.body_seen > max) {
+            return Err(Error::explain(
+                ErrorType::HTTPStatus(StatusCode::PAYLOAD_TOO_LARGE.as_u16()),
+                "request body exceeds max_body_size",
+            ));
+        }
         Ok(())
     }
 
@@ -649,32 +1154,72 @@ impl ProxyHttp for Proxy {
             retry: RetryType::Decided(false),
         })?;
         let state = ctx.state.clone();
+        let lb = ctx.lb.clone().unwrap_or_else(|| route.runtime.lb.clone());
+
+        // Back off before a retry, so retries against a struggling upstream spread out.
+        let backoff = state.as_ref().and_then(|s| s.config.retry.backoff);
+        if let (Some(backoff), false) = (backoff, ctx.tried.is_empty()) {
+            tokio::time::sleep(backoff.delay(ctx.tried.len())).await;
+        }
 
         // Restore any backends whose passive-health ejection window has elapsed.
         if let Some(state) = &state {
             for backend in state.health.take_expired(Instant::now()) {
-                route.runtime.lb.set_backend_enabled(&backend, true);
+                route.runtime.set_backend_enabled(&backend, true);
             }
         }
 
-        // Pick a healthy backend we have not already tried this request. A retry (driven by
-        // `fail_to_connect`) re-enters here with the previous backend recorded, so failover lands
-        // on a different upstream. Once exhausted the error is non-retryable.
-        let backend = route
-            .runtime
-            .lb
-            .select_excluding(&ctx.tried)
-            .ok_or(Error {
-                context: Some(ImmutStr::Static("No healthy backends available")),
-                cause: None,
-                etype: ErrorType::InternalError,
-                esource: ErrorSource::Internal,
-                retry: RetryType::Decided(false),
-            })?;
+        // Honour a valid sticky cookie as long as its backend is ready and has not already failed
+        // this request.
+        let sticky = state.as_ref().and_then(|s| s.config.sticky.as_ref());
+        let pinned = sticky.and_then(|sticky| {
+            let id = cookie_value(session.req_header(), &sticky.cookie)
+                .and_then(|value| sticky.verify(value))?;
+            let addr = sticky.backend_addr(&lb.backends(), id)?;
+            lb.ready_backend(&addr, &ctx.tried)
+        });
+
+        // Otherwise pick a healthy backend we have not already tried this request. A retry (driven
+        // by `fail_to_connect`, `error_while_proxy` or a retried status) re-enters here with the
+        // previous backend recorded, so failover lands on a different upstream. Once exhausted the
+        // error is non-retryable.
+        let backend = match pinned {
+            Some(backend) => backend,
+            None => {
+                let slow_start = state.as_ref().and_then(|s| s.config.slow_start.as_ref());
+                let backend = lb
+                    .select_excluding(&ctx.hash_key, &ctx.tried, slow_start)
+                    .ok_or(Error {
+                        context: Some(ImmutStr::Static("No healthy backends available")),
+                        cause: None,
+                        etype: ErrorType::InternalError,
+                        esource: ErrorSource::Internal,
+                        retry: RetryType::Decided(false),
+                    })?;
+                if let Some(sticky) = sticky {
+                    ctx.sticky_cookie =
+                        Some(sticky.set_cookie(&backend.addr, client_is_tls(session)));
+                }
+                backend
+            }
+        };
         ctx.tried.push(backend.addr.clone());
 
-        if let Some(stripped_path) = &route.stripped_path {
-            session.req_header_mut().set_raw_path(stripped_path)?;
+        if let Some(trace) = &ctx.trace {
+            let attempt = trace.child();
+            let strategy = lb.current_strategy().await.to_string();
+            ctx.upstream_span = Some(attempt.upstream_span(
+                &route.runtime.label,
+                &backend.addr.to_string(),
+                &strategy,
+                ctx.tried.len() - 1,
+            ));
+            ctx.upstream_trace = Some(attempt);
+        }
+
+        let upstream_path = ctx.rewritten_uri.as_deref();
+        if let Some(path) = upstream_path.or(route.stripped_path.as_deref()) {
+            session.req_header_mut().set_raw_path(path)?;
         }
 
         let tls = state.as_ref().map(|s| &s.config.upstream_tls);
@@ -725,6 +1270,14 @@ impl ProxyHttp for Proxy {
         if let Some(id) = &ctx.request_id {
             let _ = upstream_request.insert_header(X_REQUEST_ID, id);
         }
+        if let Some(trace) = &ctx.upstream_trace {
+            trace.inject(upstream_request);
+        }
+        if let Some(capture) = &mut ctx.mirror {
+            if capture.header.is_none() {
+                capture.header = Some(upstream_request.clone());
+            }
+        }
         Ok(())
     }
 
@@ -748,52 +1301,115 @@ impl ProxyHttp for Proxy {
         if let Some(id) = &ctx.request_id {
             let _ = upstream_response.insert_header(X_REQUEST_ID, id);
         }
+        if let Some(cookie) = &ctx.sticky_cookie {
+            let _ = upstream_response.append_header(http::header::SET_COOKIE, cookie);
+        }
         Ok(())
     }
 
+    /// Record the response's passive-health outcome and latency, or fail the attempt so it is
+    /// retried on another backend when its status is one the route retries.
     async fn upstream_response_filter(
         &self,
-        _session: &mut Session,
-        _upstream_response: &mut ResponseHeader,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
         ctx: &mut Self::CTX,
     ) -> Result<()> {
-        if let (Some(route), Some(state), Some(backend)) = (&ctx.route, &ctx.state, &ctx.backend) {
-            // A response means the connection succeeded: clear the passive-health failure window.
-            state.health.record_success(backend);
-
-            if let Some(start) = ctx.upstream_start {
-                let latency = start.elapsed();
-                backend
-                    .metrics
-                    .record_latency(latency, route.runtime.lb.config.latency_smoothing_factor);
+        let status = upstream_response.status.as_u16();
+        if let Some(span) = &ctx.upstream_span {
+            span.record("status", status);
+            if status >= 500 {
+                span.record("error", status_class(status));
             }
         }
+        // A response means the connection succeeded, but its status may still count as a failure.
+        Self::record_passive_health(ctx, "failure statuses", |health, backend| {
+            health.record_response(backend, status)
+        });
+        if let (Some(lb), Some(backend)) = (&ctx.lb, &ctx.backend) {
+            lb.record_response(backend, status);
+        }
+        if let (Some(lb), Some(backend), Some(start)) = (&ctx.lb, &ctx.backend, ctx.upstream_start)
+        {
+            let latency = start.elapsed();
+            backend
+                .metrics
+                .record_latency(latency, lb.config.latency_smoothing_factor);
+            ctx.upstream_response_time = Some(latency);
+        }
+
+        let retry_status = ctx
+            .state
+            .as_ref()
+            .is_some_and(|s| s.config.retry.retry_on_statuses.contains(&status));
+        if retry_status && Self::may_retry(session, ctx, true) {
+            let mut e = Error::explain(ErrorType::HTTPStatus(status), "retrying upstream status");
+            e.esource = ErrorSource::Upstream;
+            e.set_retry(true);
+            return Err(e);
+        }
         Ok(())
     }
 
-    /// On a failed upstream *connection*, record a passive-health failure (ejecting the backend
-    /// once it crosses `max_fails`) and mark the error retryable so `upstream_peer` is re-invoked
-    /// to fail over to another backend, up to `max_retries`.
+    /// On a failed upstream *connection*, record a passive-health and circuit breaker failure
+    /// (ejecting the backend once it crosses the route's thresholds) and mark the error retryable
+    /// so `upstream_peer` is re-invoked to fail over to another backend, up to `max_retries`.
     fn fail_to_connect(
         &self,
-        _session: &mut Session,
+        session: &mut Session,
         _peer: &HttpPeer,
         ctx: &mut Self::CTX,
         mut e: Box<Error>,
     ) -> Box<Error> {
-        if let (Some(route), Some(state)) = (&ctx.route, &ctx.state) {
-            if let Some(backend) = &ctx.backend {
-                if state.health.record_failure(backend) {
-                    route.runtime.lb.set_backend_enabled(backend, false);
-                    log::warn!("Passively ejected backend {}", backend.addr);
-                }
-            }
+        if let Some(span) = &ctx.upstream_span {
+            span.record("error", tracing::field::display(&e));
+        }
+        Self::record_passive_health(ctx, "connection failures", PassiveHealth::record_failure);
+        if let (Some(lb), Some(backend)) = (&ctx.lb, &ctx.backend) {
+            lb.record_failure(backend);
+        }
+        let on_connect_error = ctx
+            .state
+            .as_ref()
+            .is_some_and(|s| s.config.retry.retry_on_connect_error);
+        if on_connect_error && Self::may_retry(session, ctx, false) {
+            e.set_retry(true);
+        }
+        e
+    }
 
-            let retry = state.config.retry;
-            if retry.retry_on_connect_error && ctx.tried.len() <= retry.max_retries {
-                e.set_retry(true);
-            }
+    /// On an error after connecting, count an upstream read timeout as a passive-health failure
+    /// when the route asks for it, and an upstream error before the response as a circuit breaker
+    /// failure. A timeout or reset before the response header is retried on
+    /// another backend when the route allows it; otherwise retrying is decided as pingora's
+    /// default does.
+    fn error_while_proxy(
+        &self,
+        peer: &HttpPeer,
+        session: &mut Session,
+        e: Box<Error>,
+        ctx: &mut Self::CTX,
+        client_reused: bool,
+    ) -> Box<Error> {
+        if e.esource() == &ErrorSource::Upstream && e.etype() == &ErrorType::ReadTimedout {
+            Self::record_passive_health(ctx, "read timeouts", PassiveHealth::record_timeout);
         }
+        // An upstream failing before its response (a retried status was already counted as a
+        // response) trips its circuit breaker.
+        let failed_before_response = e.esource() == &ErrorSource::Upstream
+            && !matches!(e.etype(), ErrorType::HTTPStatus(_))
+            && session.as_ref().response_written().is_none();
+        if let (true, Some(lb), Some(backend)) = (failed_before_response, &ctx.lb, &ctx.backend) {
+            lb.record_failure(backend);
+        }
+        let mut e = e.more_context(format!("Peer: {peer}"));
+        if Self::retries_upstream_error(session, ctx, &e) && Self::may_retry(session, ctx, true) {
+            e.set_retry(true);
+            return e;
+        }
+        // only reused client connections where retry buffer is not truncated
+        e.retry
+            .decide_reuse(client_reused && !session.as_ref().retry_buffer_truncated());
         e
     }
 
@@ -870,45 +1486,68 @@ impl ProxyHttp for Proxy {
 
     /// Emit a structured access-log record once the request completes.
     async fn logging(&self, session: &mut Session, e: Option<&Error>, ctx: &mut Self::CTX) {
-        if !self.access_log {
-            return;
-        }
-
         let status = session
             .response_written()
             .map(|r| r.status.as_u16())
             .unwrap_or(0);
-        let latency_ms = ctx.request_start.elapsed().as_millis();
-        let bytes_sent = session.body_bytes_sent();
-        let method = session.req_header().method.clone();
-        let client = client_ip(session)
-            .map(|ip| ip.to_string())
-            .unwrap_or_default();
-        let upstream = ctx
-            .backend
+
+        // The primary exchange is complete: fire the mirror copy, if any, in the background.
+        let mirror_lb = ctx.route.as_ref().and_then(|r| r.runtime.mirror.clone());
+        if let (Some(capture), Some(lb)) = (ctx.mirror.take(), mirror_lb) {
+            if let Some(header) = capture.header {
+                mirror::spawn(
+                    lb,
+                    MirrorRequest {
+                        header,
+                        body: capture.body.map(BytesMut::freeze),
+                        request_id: ctx.request_id.clone(),
+                        primary_status: status,
+                        primary_latency: ctx.request_start.elapsed(),
+                    },
+                );
+            }
+        }
+
+        Self::record_metrics(session, status, ctx);
+
+        // End the request's spans, the last upstream attempt first.
+        ctx.upstream_span = None;
+        if let Some(span) = ctx.server_span.take() {
+            span.record("status", status);
+            if let Some(err) = e {
+                span.record("error", tracing::field::display(err));
+            } else if status >= 500 {
+                span.record("error", status_class(status));
+            }
+        }
+
+        let Some(log) = &self.access_log else {
+            return;
+        };
+        // Routes may turn their log off or sample it; requests matching no route are always logged.
+        if ctx
+            .state
             .as_ref()
-            .map(|b| b.addr.to_string())
-            .unwrap_or_default();
-        let path = ctx
-            .orig_path
-            .as_deref()
-            .unwrap_or_else(|| session.req_header().uri.path());
-        let request_id = ctx.request_id.as_deref().unwrap_or("");
-
-        if let Some(err) = e {
-            tracing::warn!(
-                target: "routini::access",
-                %client, %method, path, status, latency_ms, bytes_sent, upstream, request_id,
-                error = %err,
-                "request failed"
-            );
-        } else {
-            tracing::info!(
-                target: "routini::access",
-                %client, %method, path, status, latency_ms, bytes_sent, upstream, request_id,
-                "request"
-            );
+            .is_some_and(|state| !state.config.access_log.sample())
+        {
+            return;
         }
+        let route = ctx.route.as_ref().map(|r| r.runtime.label.as_str());
+        let req = session.req_header();
+        log.log(&AccessLogEntry {
+            req,
+            client: client_ip(session),
+            uri: ctx.orig_uri.as_deref().unwrap_or_else(|| req.uri.path()),
+            status,
+            bytes_sent: session.body_bytes_sent(),
+            request_time: ctx.request_start.elapsed(),
+            upstream: ctx.backend.as_ref().map(|b| b.addr.to_string()),
+            upstream_response_time: ctx.upstream_response_time,
+            request_id: ctx.request_id.as_deref(),
+            trace_id: ctx.trace.as_ref().map(TraceContext::trace_id_hex),
+            route,
+            error: e,
+        });
     }
 }
 
@@ -921,25 +1560,25 @@ mod tests {
             AdaptiveLoadBalancer, decision_engine::AdaptiveDecisionEngine, options::AdaptiveLbOpt,
         },
         load_balancing::{Backends, discovery::Static},
-        route::RouteConfig,
+        route::{RequestPredicates, RouteConfig, ValueMatch},
     };
 
     use super::*;
 
-    fn create_test_route_value(strip_path: bool) -> RouteValue {
+    fn create_test_runtime(config: RouteConfig) -> Arc<RouteRuntime> {
         let mut backends = BTreeSet::new();
         backends.insert(AdaptiveBackend::build("127.0.0.1:8080", 1).unwrap());
         let backends = Backends::new(Static::new(backends));
         let decision_engine = AdaptiveDecisionEngine::new(&AdaptiveLbOpt::default());
         let lb = AdaptiveLoadBalancer::from_backends(backends, None, decision_engine);
+        Arc::new(RouteRuntime::new(Arc::new(lb), config))
+    }
 
-        let config = RouteConfig {
+    fn create_test_route_value(strip_path: bool) -> RouteValue {
+        RouteValue::new(create_test_runtime(RouteConfig {
             strip_path_prefix: strip_path,
             ..Default::default()
-        };
-        RouteValue {
-            runtime: Arc::new(RouteRuntime::new(Arc::new(lb), config)),
-        }
+        }))
     }
 
     #[test]
@@ -1112,7 +1751,7 @@ mod tests {
         let mut vhosts = std::collections::HashMap::new();
         vhosts.insert("api.example.com".to_string(), vhost_router);
 
-        let proxy = Proxy::with_vhosts(default_router, vhosts, 64);
+        let proxy = Proxy::with_vhosts(default_router, vhosts, 64).unwrap();
 
         // Matching host uses the vhost router (case-insensitive, port ignored).
         let r = proxy
@@ -1130,6 +1769,88 @@ mod tests {
         assert!(r.stripped_path.is_none());
     }
 
+    #[test]
+    fn test_vhost_name_precedence() {
+        // Each vhost serves a distinct path so the chosen server is observable.
+        let vhost = |path: &str| {
+            let mut router = Router::new();
+            router.insert(path, create_test_route_value(false)).unwrap();
+            VHostRoutes::from(router)
+        };
+        let proxy = Proxy::with_vhost_routes(
+            VHostRoutes::default(),
+            vec![
+                ("~^api\\d+\\.".to_string(), vhost("/regex")),
+                ("api.*".to_string(), vhost("/trailing")),
+                ("*.example.com".to_string(), vhost("/leading")),
+                ("*.api.example.com".to_string(), vhost("/longer-leading")),
+                ("www.example.com".to_string(), vhost("/exact")),
+            ],
+            64,
+        )
+        .unwrap();
+        let served = |host: &str, path: &str| proxy.resolve(Some(host), path).is_ok();
+
+        assert!(served("WWW.example.com", "/exact"));
+        assert!(served("v1.api.example.com", "/longer-leading"));
+        assert!(served("api.example.com", "/leading"));
+        assert!(served("api.example.org", "/trailing"));
+        assert!(served("api2.example.org", "/regex"));
+        // a bare domain does not match its leading wildcard
+        assert!(!served("example.com", "/leading"));
+        assert!(!served("badexample.com", "/leading"));
+    }
+
+    #[test]
+    fn test_host_pattern_wildcards() {
+        let leading = HostPattern::parse("*.Example.com").unwrap();
+        assert!(matches!(leading, HostPattern::Leading(suffix) if suffix == ".example.com"));
+        let trailing = HostPattern::parse("www.example.*").unwrap();
+        assert!(matches!(trailing, HostPattern::Trailing(prefix) if prefix == "www.example."));
+        let invalid = [
+            "*example.com",
+            "www.example*",
+            "w*.example.com",
+            "www.*.com",
+            "*.",
+        ];
+        for name in invalid {
+            assert!(HostPattern::parse(name).is_err(), "{name}");
+        }
+
+        let vhosts = vec![("*example.com".to_string(), VHostRoutes::default())];
+        assert!(Proxy::with_vhost_routes(VHostRoutes::default(), vhosts, 64).is_err());
+    }
+
+    #[test]
+    fn test_vhost_regex_locations() {
+        let mut router = Router::new();
+        router
+            .insert("/api", create_test_route_value(false))
+            .unwrap();
+        let images = create_test_route_value(false);
+        let images_runtime = images.runtime.clone();
+        let proxy = Proxy::with_vhost_routes(
+            VHostRoutes::default(),
+            vec![(
+                "static.example.com".to_string(),
+                VHostRoutes {
+                    router,
+                    regex: vec![(Regex::new(r"\.(jpg|png)$").unwrap(), images)],
+                },
+            )],
+            64,
+        )
+        .unwrap();
+
+        let r = proxy
+            .resolve(Some("static.example.com"), "/img/cat.png")
+            .expect("vhost regex route");
+        assert!(Arc::ptr_eq(&r.runtime, &images_runtime));
+        // not visible on the default server
+        assert!(proxy.resolve(None, "/img/cat.png").is_err());
+    }
+
     #[test]
     fn test_regex_route_fallback() {
         let mut default_router = Router::new();
@@ -1147,7 +1868,8 @@ mod tests {
             regex_routes,
             std::collections::HashMap::new(),
             64,
-        );
+        )
+        .unwrap();
 
         // matchit route still wins.
         assert!(proxy.resolve(None, "/api").is_ok());
@@ -1184,4 +1906,65 @@ mod tests {
         let (_, stripped_path) = result.unwrap();
         assert_eq!(stripped_path, Some("/query?q=test".to_string()));
     }
+
+    #[test]
+    fn test_select_by_request_predicates() {
+        let v2 = create_test_runtime(RouteConfig {
+            predicates: RequestPredicates {
+                headers: vec![(
+                    http::header::ACCEPT,
+                    ValueMatch::Equals("application/vnd.api.v2+json".into()),
+                )],
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let internal = create_test_runtime(RouteConfig {
+            predicates: RequestPredicates {
+                methods: vec![http::Method::POST],
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let fallback = create_test_runtime(RouteConfig::default());
+
+        let mut router = Router::new();
+        router
+            .insert(
+                "/api",
+                RouteValue {
+                    runtime: v2.clone(),
+                    alternatives: vec![internal.clone()],
+                },
+            )
+            .unwrap();
+        let regex_routes = vec![(
+            Regex::new("^/api").unwrap(),
+            RouteValue::new(fallback.clone()),
+        )];
+        let proxy =
+            Proxy::with_regex_routes(router, regex_routes, std::collections::HashMap::new(), 64)
+                .unwrap();
+        let resolved = proxy.resolve(None, "/api").expect("should resolve");
+
+        let mut req = RequestHeader::build("GET", b"/api", None).unwrap();
+        req.insert_header(http::header::ACCEPT, "application/vnd.api.v2+json")
+            .unwrap();
+        let (route, _) = resolved.select(&req).expect("v2 route");
+        assert!(Arc::ptr_eq(&route.runtime, &v2));
+
+        // Declared order decides between routes sharing the path.
+        req.set_method(http::Method::POST);
+        let (route, _) = resolved.select(&req).expect("v2 route");
+        assert!(Arc::ptr_eq(&route.runtime, &v2));
+
+        req.remove_header(&http::header::ACCEPT);
+        let (route, _) = resolved.select(&req).expect("internal route");
+        assert!(Arc::ptr_eq(&route.runtime, &internal));
+
+        // Neither shared route matches: fall through to the regex location.
+        req.set_method(http::Method::GET);
+        let (route, _) = resolved.select(&req).expect("regex route");
+        assert!(Arc::ptr_eq(&route.runtime, &fallback));
+    }
 }
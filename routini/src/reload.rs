@@ -1,31 +1,483 @@
-//! Hot config reload on `SIGHUP` (nginx `nginx -s reload`).
+//! Hot config reload on `SIGHUP` (nginx `nginx -s reload`), and optionally when the config file
+//! changes (see [`spawn_config_watcher`]).
 //!
-//! On `SIGHUP` the config file is re-read and each route's hot-swappable [`RouteState`] is rebuilt
-//! and atomically replaced. This covers per-route tunables (headers, timeouts, retry, limits,
-//! auth, cache, actions, ...). Structural changes — adding/removing routes, changing upstreams or
-//! the load-balancing strategy — cannot be applied in place (their background services are wired at
-//! startup) and require Pingora's zero-downtime graceful restart instead; such entries are counted
-//! as "skipped".
+//! On reload the config file is re-read and diffed against the live routes:
+//! - routes whose load balancers keep their pools and tunables are updated in place: their
+//!   [`RouteState`] is swapped, upstream lists are applied to the existing backend sets and a new
+//!   starting strategy is switched to;
+//! - new routes, and routes whose pools or load-balancer tunables changed, get fresh load
+//!   balancers with their background tasks started;
+//! - the route tables are then swapped atomically, and the load balancers of removed or rebuilt
+//!   routes are retired: they stop once their in-flight requests have drained (see
+//!   [`crate::lb_tasks`]).
+//!
+//! All of it is prepared before any of it is applied, so a reload that fails leaves the running
+//! routes as they were.
 //!
 //! [`RouteState`]: crate::route::RouteState
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{Result, eyre};
+use futures::executor::block_on;
+use serde::Serialize;
 use signal_hook::consts::SIGHUP;
 use signal_hook::iterator::Signals;
 
-use crate::route::RouteRuntime;
-use crate::utils::config_loader::load_config_from;
+use crate::adaptive_loadbalancer::{AdaptiveBackends, options::AdaptiveLbOpt};
+use crate::config::check::{check_routes, invalid_config};
+use crate::lb_tasks::{LbTasks, PendingTasks};
+use crate::load_balancing::Discovered;
+use crate::load_balancing::strategy::{Adaptive, adaptive::AdaptiveStrategyMetrics};
+use crate::metrics::{CONFIG_LAST_RELOAD_SUCCESSFUL, CONFIG_RELOADS};
+use crate::proxy::Proxy;
+use crate::route::{RouteConfig, RouteRuntime, SharedLb};
+use crate::server_builder::{Route, RouteMount, route_tables, start_route};
+use crate::utils::config_loader::{config_files, load_config_from};
+use crate::utils::constants::CONFIG_WATCH_POLL_INTERVAL;
 
-/// `(lowercased host, is_regex, transformed path)` — matches `RouteEntry::route_key`.
-pub type RouteKey = (String, bool, String);
+/// `(lowercased host, is_regex, transformed path, ordinal)` — `RouteEntry::route_key` plus the
+/// entry's position among routes declared with the same host and path.
+pub type RouteKey = (String, bool, String, usize);
 /// Maps each configured route to its live runtime so reloads can target the right one.
-pub type RouteRegistry = HashMap<RouteKey, Arc<RouteRuntime>>;
+pub type RouteRegistry = HashMap<RouteKey, RegisteredRoute>;
+
+/// The load balancers of named upstreams (see [`Route::upstream`]), by name.
+pub type UpstreamRegistry = HashMap<String, RegisteredUpstream>;
+
+/// A named upstream's load balancer, shared by the routes referencing it, and the options it was
+/// built with.
+#[derive(Clone)]
+pub struct RegisteredUpstream {
+    pub lb: SharedLb,
+    pub lb_options: AdaptiveLbOpt,
+}
+
+/// A live route and the options its load balancers were built with.
+#[derive(Clone)]
+pub struct RegisteredRoute {
+    pub runtime: Arc<RouteRuntime>,
+    pub lb_options: RouteLbOptions,
+}
+
+/// The options of each of a route's load balancers, by pool.
+#[derive(Clone)]
+pub struct RouteLbOptions {
+    /// The named upstream serving as the primary pool.
+    upstream: Option<String>,
+    primary: AdaptiveLbOpt,
+    pools: HashMap<String, AdaptiveLbOpt>,
+    mirror: Option<AdaptiveLbOpt>,
+}
+
+impl RouteLbOptions {
+    pub fn of(route: &Route) -> Self {
+        Self {
+            upstream: route.upstream.clone(),
+            primary: route.lb_options.clone(),
+            pools: route
+                .pools
+                .iter()
+                .map(|pool| (pool.name.clone(), pool.lb_options.clone()))
+                .collect(),
+            mirror: route
+                .mirror
+                .as_ref()
+                .map(|mirror| mirror.lb_options.clone()),
+        }
+    }
+
+    /// Whether load balancers built with these options can serve a route declared with `other`:
+    /// the same upstream and pools, differing at most in starting strategies.
+    pub fn compatible(&self, other: &Self) -> bool {
+        let mirror = match (&self.mirror, &other.mirror) {
+            (Some(current), Some(new)) => current.same_tunables(new),
+            (None, None) => true,
+            _ => false,
+        };
+        mirror
+            && self.upstream == other.upstream
+            && self.primary.same_tunables(&other.primary)
+            && self.pools.len() == other.pools.len()
+            && self.pools.iter().all(|(name, current)| {
+                other
+                    .pools
+                    .get(name)
+                    .is_some_and(|new| current.same_tunables(new))
+            })
+    }
+}
+
+/// Hands out [`RouteKey`]s in declaration order, numbering routes that share a host and path.
+#[derive(Default)]
+pub struct RouteKeys(HashMap<(String, bool, String), usize>);
+
+impl RouteKeys {
+    pub fn next(&mut self, host: Option<&str>, is_regex: bool, path: &str) -> RouteKey {
+        let host = host.map(|h| h.to_ascii_lowercase()).unwrap_or_default();
+        let ordinal = self
+            .0
+            .entry((host.clone(), is_regex, path.to_string()))
+            .or_default();
+        let key = (host, is_regex, path.to_string(), *ordinal);
+        *ordinal += 1;
+        key
+    }
+}
+
+/// Route counts of an applied reload.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ReloadSummary {
+    pub added: usize,
+    /// Updated in place, keeping their load balancers.
+    pub updated: usize,
+    /// Given new load balancers because their pools or tunables changed.
+    pub rebuilt: usize,
+    pub removed: usize,
+}
+
+/// What asked for a reload, as logged and labelled in `routini_config_reloads_total`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReloadTrigger {
+    Signal,
+    FileChange,
+    Admin,
+}
+
+impl ReloadTrigger {
+    fn label(self) -> &'static str {
+        match self {
+            ReloadTrigger::Signal => "signal",
+            ReloadTrigger::FileChange => "file",
+            ReloadTrigger::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for ReloadTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReloadTrigger::Signal => "SIGHUP",
+            ReloadTrigger::FileChange => "config file change",
+            ReloadTrigger::Admin => "admin API",
+        })
+    }
+}
+
+/// Owns the registry of live routes and applies the routes of a re-read config to the running
+/// proxy. Shared by the `SIGHUP` and file watchers and the admin API.
+pub struct Reloader {
+    config_path: Option<String>,
+    proxy: Proxy,
+    tasks: Arc<LbTasks>,
+    registry: Mutex<RouteRegistry>,
+    upstreams: Mutex<UpstreamRegistry>,
+    /// Held for the whole of a reload, so that concurrent ones apply one after the other.
+    reloading: tokio::sync::Mutex<()>,
+}
+
+impl Reloader {
+    /// `registry` holds the routes `proxy` was built with, keyed as [`RouteKeys`] assigns them,
+    /// and `upstreams` the named upstreams they share. Without a `config_path` there is nothing
+    /// to reload from.
+    pub fn new(
+        config_path: Option<String>,
+        proxy: Proxy,
+        tasks: Arc<LbTasks>,
+        registry: RouteRegistry,
+        upstreams: UpstreamRegistry,
+    ) -> Self {
+        Self {
+            config_path,
+            proxy,
+            tasks,
+            registry: Mutex::new(registry),
+            upstreams: Mutex::new(upstreams),
+            reloading: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn config_path(&self) -> Option<&str> {
+        self.config_path.as_deref()
+    }
+
+    /// The live routes, ordered by key.
+    pub fn routes(&self) -> Vec<(RouteKey, Arc<RouteRuntime>)> {
+        let registry = self.registry.lock().expect("lock poisoned");
+        let mut routes: Vec<_> = registry
+            .iter()
+            .map(|(key, route)| (key.clone(), route.runtime.clone()))
+            .collect();
+        routes.sort_by(|a, b| a.0.cmp(&b.0));
+        routes
+    }
+
+    /// The live route registered under `key`.
+    pub fn route(&self, key: &RouteKey) -> Option<Arc<RouteRuntime>> {
+        let registry = self.registry.lock().expect("lock poisoned");
+        registry.get(key).map(|route| route.runtime.clone())
+    }
 
-/// Spawn a background thread that reloads `config_path` whenever `SIGHUP` is received.
-pub fn spawn_reload_watcher(config_path: String, registry: Arc<RouteRegistry>) {
+    /// Re-read the config file and apply its routes, recording the outcome in the reload metrics.
+    pub async fn reload(&self, trigger: ReloadTrigger) -> Result<ReloadSummary> {
+        let result = self.reload_config().await;
+        let outcome = if result.is_ok() { "success" } else { "failure" };
+        CONFIG_RELOADS
+            .with_label_values(&[trigger.label(), outcome])
+            .inc();
+        CONFIG_LAST_RELOAD_SUCCESSFUL.set(result.is_ok().into());
+        result
+    }
+
+    async fn reload_config(&self) -> Result<ReloadSummary> {
+        let Some(config_path) = &self.config_path else {
+            return Err(eyre!("No config file to reload from"));
+        };
+        let config = load_config_from(config_path)?;
+        // Reject the whole config up front, rather than failing (or panicking) midway.
+        let diagnostics = check_routes(&config);
+        if !diagnostics.is_empty() {
+            return Err(invalid_config(&diagnostics));
+        }
+        self.apply(config.routes()?).await
+    }
+
+    /// Replace the live routes with `routes`, reusing the load balancers of routes and named
+    /// upstreams that are still declared with compatible options.
+    ///
+    /// The whole reload is prepared first, discovering upstreams and building the new load
+    /// balancers and route tables, and only then applied, so routes that fail (say, two paths
+    /// the router cannot tell apart) leave the running config untouched.
+    pub(crate) async fn apply(&self, routes: Vec<Route>) -> Result<ReloadSummary> {
+        let _reloading = self.reloading.lock().await;
+        let registry = self.registry.lock().expect("lock poisoned").clone();
+        let upstreams = self.upstreams.lock().expect("lock poisoned").clone();
+        let mut plan = ReloadPlan::default();
+        let mut summary = ReloadSummary::default();
+        let mut keys = RouteKeys::default();
+        let mut next = RouteRegistry::new();
+        let mut next_upstreams = UpstreamRegistry::new();
+        let mut mounted = Vec::with_capacity(routes.len());
+
+        for route in routes {
+            let key = keys.next(route.host.as_deref(), route.is_regex, &route.path);
+            let lb_options = RouteLbOptions::of(&route);
+            plan.reuse_upstream(&upstreams, &mut next_upstreams, &route)
+                .await?;
+            let (mount, registered) = match registry.get(&key) {
+                Some(current)
+                    if current.lb_options.compatible(&lb_options)
+                        && keeps_upstream(current, &route, &next_upstreams) =>
+                {
+                    summary.updated += 1;
+                    plan.update_in_place(current, route, lb_options).await?
+                }
+                Some(_) => {
+                    summary.rebuilt += 1;
+                    start_route(&mut plan.started, &key, route, &mut next_upstreams)
+                }
+                None => {
+                    summary.added += 1;
+                    start_route(&mut plan.started, &key, route, &mut next_upstreams)
+                }
+            };
+            mounted.push((mount, registered.runtime.clone()));
+            next.insert(key, registered);
+        }
+        summary.removed = registry
+            .keys()
+            .filter(|key| !next.contains_key(key))
+            .count();
+        plan.retired = retired_load_balancers(&registry, &next);
+        let (default_routes, vhosts) = route_tables(mounted)?;
+        let table = self.proxy.route_table(default_routes, vhosts)?;
+
+        // Nothing can fail from here on.
+        plan.commit(&self.tasks).await;
+        self.proxy.replace_routes(table);
+        *self.registry.lock().expect("lock poisoned") = next;
+        *self.upstreams.lock().expect("lock poisoned") = next_upstreams;
+        Ok(summary)
+    }
+}
+
+/// The changes a reload makes to running load balancers and routes, held back until the whole
+/// reload has been prepared.
+#[derive(Default)]
+struct ReloadPlan {
+    updates: Vec<LbUpdate>,
+    /// The new config of each route updated in place.
+    configs: Vec<(Arc<RouteRuntime>, RouteConfig)>,
+    /// The load balancers of new and rebuilt routes, whose tasks are yet to start.
+    started: PendingTasks,
+    /// The load balancers no route uses after the reload, whose tasks are to stop.
+    retired: Vec<SharedLb>,
+}
+
+/// A reused load balancer's newly discovered backends, and the starting strategy to switch it to
+/// if it changed.
+struct LbUpdate {
+    lb: SharedLb,
+    backends: Discovered<AdaptiveStrategyMetrics>,
+    strategy: Option<Adaptive>,
+}
+
+impl ReloadPlan {
+    /// Carry the running load balancer of `route`'s named upstream over to `next` if it was
+    /// built with the same tunables, updating it with the route's backends and starting
+    /// strategy. Otherwise the route starts a new one.
+    async fn reuse_upstream(
+        &mut self,
+        current: &UpstreamRegistry,
+        next: &mut UpstreamRegistry,
+        route: &Route,
+    ) -> Result<()> {
+        let Some(name) = &route.upstream else {
+            return Ok(());
+        };
+        let Some(upstream) = current.get(name) else {
+            return Ok(());
+        };
+        if next.contains_key(name) || !upstream.lb_options.same_tunables(&route.lb_options) {
+            return Ok(());
+        }
+        self.update_lb(
+            &upstream.lb,
+            &upstream.lb_options,
+            &route.backends,
+            &route.lb_options,
+        )
+        .await?;
+        let reused = RegisteredUpstream {
+            lb: upstream.lb.clone(),
+            lb_options: route.lb_options.clone(),
+        };
+        next.insert(name.clone(), reused);
+        Ok(())
+    }
+
+    /// Update the running `current` route with `route`'s upstreams, starting strategies and
+    /// config.
+    async fn update_in_place(
+        &mut self,
+        current: &RegisteredRoute,
+        route: Route,
+        lb_options: RouteLbOptions,
+    ) -> Result<(RouteMount, RegisteredRoute)> {
+        let runtime = &current.runtime;
+        // A named upstream's load balancer was already updated by `reuse_upstream`.
+        if route.upstream.is_none() {
+            self.update_lb(
+                &runtime.lb,
+                &current.lb_options.primary,
+                &route.backends,
+                &route.lb_options,
+            )
+            .await?;
+        }
+        for pool in &route.pools {
+            let lb = runtime
+                .pool(&pool.name)
+                .ok_or_else(|| eyre!("Missing upstream pool '{}'", pool.name))?;
+            let options = &current.lb_options.pools[&pool.name];
+            self.update_lb(lb, options, &pool.backends, &pool.lb_options)
+                .await?;
+        }
+        if let (Some(lb), Some(options), Some(mirror)) =
+            (&runtime.mirror, &current.lb_options.mirror, &route.mirror)
+        {
+            self.update_lb(lb, options, &mirror.backends, &mirror.lb_options)
+                .await?;
+        }
+        self.configs.push((runtime.clone(), route.route_config));
+
+        let mount = RouteMount {
+            host: route.host,
+            is_regex: route.is_regex,
+            path: route.path,
+        };
+        let registered = RegisteredRoute {
+            runtime: runtime.clone(),
+            lb_options,
+        };
+        Ok((mount, registered))
+    }
+
+    /// Discover `backends` for `lb`, and switch it to the new starting strategy if it changed.
+    async fn update_lb(
+        &mut self,
+        lb: &SharedLb,
+        current: &AdaptiveLbOpt,
+        backends: &AdaptiveBackends,
+        options: &AdaptiveLbOpt,
+    ) -> Result<()> {
+        let backends = backends
+            .discover()
+            .await
+            .map_err(|err| eyre!("Failed to update upstreams: {err}"))?;
+        let strategy = (current.starting_strategy != options.starting_strategy)
+            .then(|| options.starting_strategy.clone());
+        self.updates.push(LbUpdate {
+            lb: lb.clone(),
+            backends,
+            strategy,
+        });
+        Ok(())
+    }
+
+    /// Apply the updates to the running load balancers and routes, start the tasks of the new
+    /// load balancers on `tasks` and retire those of the dropped ones.
+    async fn commit(self, tasks: &LbTasks) {
+        for update in self.updates {
+            update.lb.update_backends(update.backends).await;
+            if let Some(strategy) = update.strategy {
+                update.lb.update_strategy(strategy).await;
+            }
+        }
+        for (runtime, config) in self.configs {
+            runtime.reload(config);
+        }
+        tasks.spawn_all(self.started);
+        tasks.retire_all(self.retired);
+    }
+}
+
+/// The load balancers of the `current` routes that none of the `next` routes uses.
+fn retired_load_balancers(current: &RouteRegistry, next: &RouteRegistry) -> Vec<SharedLb> {
+    let kept = |lb: &SharedLb| {
+        next.values()
+            .flat_map(|route| route.runtime.load_balancers())
+            .any(|other| Arc::ptr_eq(other, lb))
+    };
+    let mut retired: Vec<SharedLb> = Vec::new();
+    for lb in current
+        .values()
+        .flat_map(|route| route.runtime.load_balancers())
+    {
+        if !kept(lb) && !retired.iter().any(|other| Arc::ptr_eq(other, lb)) {
+            retired.push(lb.clone());
+        }
+    }
+    retired
+}
+
+/// Whether the live `current` route already uses the load balancer of `route`'s named upstream.
+fn keeps_upstream(current: &RegisteredRoute, route: &Route, upstreams: &UpstreamRegistry) -> bool {
+    route.upstream.as_ref().is_none_or(|name| {
+        upstreams
+            .get(name)
+            .is_some_and(|upstream| Arc::ptr_eq(&upstream.lb, &current.runtime.lb))
+    })
+}
+
+/// Spawn a background thread that runs `reloader` whenever `SIGHUP` is received.
+pub fn spawn_reload_watcher(reloader: Arc<Reloader>) {
     thread::spawn(move || {
         let mut signals = match Signals::new([SIGHUP]) {
             Ok(signals) => signals,
@@ -34,32 +486,434 @@ pub fn spawn_reload_watcher(config_path: String, registry: Arc<RouteRegistry>) {
                 return;
             }
         };
-        tracing::info!("SIGHUP config reload enabled (config: {config_path})");
+        tracing::info!(
+            "SIGHUP config reload enabled (config: {})",
+            reloader.config_path().unwrap_or_default()
+        );
         for _ in signals.forever() {
-            match reload(&config_path, &registry) {
-                Ok((applied, skipped)) => tracing::info!(
-                    "Config reloaded: {applied} route(s) updated, {skipped} skipped \
-                     (new/removed routes or backend/strategy changes need a restart)"
-                ),
-                Err(err) => tracing::error!("Config reload failed, keeping current config: {err}"),
+            reload_and_log(&reloader, ReloadTrigger::Signal);
+        }
+    });
+}
+
+/// Spawn a background thread that runs `reloader` when its config file changes.
+///
+/// The file is polled rather than watched for events, so edits, replacements and symlink swaps
+/// (such as a Kubernetes ConfigMap update) are all seen: its contents, and those of the files it
+/// includes, are read through their paths and compared with those last applied. A change is
+/// only applied once the contents have stayed the same for `debounce`, so a burst of writes
+/// reloads once and a half-written file is unlikely to be read; a writer that stalls mid-write for
+/// longer than `debounce` can still be caught, so replace files atomically to rule it out.
+/// Contents that failed to load are not retried until the files change again.
+pub fn spawn_config_watcher(reloader: Arc<Reloader>, debounce: Duration) {
+    let Some(path) = reloader.config_path().map(PathBuf::from) else {
+        tracing::warn!("Config file watching needs a config file to reload from");
+        return;
+    };
+    thread::spawn(move || {
+        tracing::info!(
+            "Watching {} for config changes (debounce: {debounce:?})",
+            path.display()
+        );
+        let mut files = WatchedFiles::new(path);
+        let mut changes = ChangeDetector::new(files.fingerprint().ok(), debounce);
+        loop {
+            thread::sleep(CONFIG_WATCH_POLL_INTERVAL.min(debounce));
+            if changes.poll(files.fingerprint().ok(), Instant::now()) {
+                reload_and_log(&reloader, ReloadTrigger::FileChange);
             }
         }
     });
 }
 
-/// Re-read the config and swap in fresh per-route state. Returns `(applied, skipped)`.
-pub fn reload(config_path: &str, registry: &RouteRegistry) -> Result<(usize, usize)> {
-    let config = load_config_from(config_path)?;
-    let mut applied = 0;
-    let mut skipped = 0;
-    for entry in &config.proxy.router {
-        match registry.get(&entry.route_key()) {
-            Some(runtime) => {
-                runtime.reload(entry.route_config()?);
-                applied += 1;
+/// Reload from a watcher thread. It is not one of the runtime's, so it can block on the reload.
+fn reload_and_log(reloader: &Reloader, trigger: ReloadTrigger) {
+    match block_on(reloader.reload(trigger)) {
+        Ok(summary) => tracing::info!(
+            "Config reloaded on {trigger}: {} route(s) added, {} updated, {} rebuilt, {} removed",
+            summary.added,
+            summary.updated,
+            summary.rebuilt,
+            summary.removed
+        ),
+        Err(err) => {
+            tracing::error!("Config reload on {trigger} failed, keeping current config: {err}")
+        }
+    }
+}
+
+/// A watched config file and the files it includes.
+///
+/// Resolving the includes means parsing the config and expanding its globs, so the list is only
+/// refreshed when the config file's own bytes change; a new file matching an include glob is
+/// picked up with the next change to the config file.
+struct WatchedFiles {
+    path: PathBuf,
+    /// The hash of the config file's bytes the includes were resolved from.
+    resolved_from: Option<u64>,
+    includes: Vec<PathBuf>,
+}
+
+impl WatchedFiles {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            resolved_from: None,
+            includes: Vec::new(),
+        }
+    }
+
+    /// A hash of the contents of the config file and of the files it includes, read through any
+    /// symlinks. A missing include is hashed as such, so its loss reloads (and fails).
+    fn fingerprint(&mut self) -> std::io::Result<u64> {
+        let mut hasher = DefaultHasher::new();
+        std::fs::read(&self.path)?.hash(&mut hasher);
+        let main = hasher.finish();
+        if self.resolved_from != Some(main) {
+            self.includes = config_files(&self.path).split_off(1);
+            self.resolved_from = Some(main);
+        }
+        for include in &self.includes {
+            include.hash(&mut hasher);
+            std::fs::read(include).ok().hash(&mut hasher);
+        }
+        Ok(hasher.finish())
+    }
+}
+
+/// Decides from successive [`WatchedFiles::fingerprint`]s when a watched file has changed and settled.
+struct ChangeDetector {
+    /// The contents last reloaded (or loaded at startup).
+    applied: Option<u64>,
+    /// Contents that differ from `applied`, and since when they have been seen.
+    pending: Option<(u64, Instant)>,
+    debounce: Duration,
+}
+
+impl ChangeDetector {
+    fn new(applied: Option<u64>, debounce: Duration) -> Self {
+        Self {
+            applied,
+            pending: None,
+            debounce,
+        }
+    }
+
+    /// Feed the file's current fingerprint, `None` if it cannot be read; true when a change has
+    /// settled and should be reloaded.
+    fn poll(&mut self, current: Option<u64>, now: Instant) -> bool {
+        // A missing file is mid-swap or gone: wait for it to come back.
+        let Some(current) = current else {
+            self.pending = None;
+            return false;
+        };
+        if self.applied == Some(current) {
+            self.pending = None;
+            return false;
+        }
+        match self.pending {
+            Some((pending, since)) if pending == current => {
+                if now.duration_since(since) < self.debounce {
+                    return false;
+                }
+                self.applied = Some(current);
+                self.pending = None;
+                true
+            }
+            _ => {
+                self.pending = Some((current, now));
+                false
             }
-            None => skipped += 1,
         }
     }
-    Ok((applied, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{load_balancing::strategy::Adaptive, proxy::VHostRoutes};
+
+    fn route(path: &str, upstreams: &[&str]) -> Route {
+        Route::new(path, upstreams.iter().copied(), Adaptive::default()).unwrap()
+    }
+
+    async fn reloader(routes: Vec<Route>) -> Reloader {
+        let tasks = Arc::new(LbTasks::default());
+        let proxy = Proxy::with_vhost_routes(VHostRoutes::default(), Vec::new(), 16).unwrap();
+        let reloader = Reloader::new(
+            None,
+            proxy,
+            tasks,
+            RouteRegistry::new(),
+            UpstreamRegistry::new(),
+        );
+        reloader.apply(routes).await.unwrap();
+        reloader
+    }
+
+    #[test]
+    fn test_route_keys_number_shared_paths() {
+        let mut keys = RouteKeys::default();
+        assert_eq!(keys.next(None, false, "/a").3, 0);
+        assert_eq!(keys.next(Some("Example.com"), false, "/a").3, 0);
+        assert_eq!(keys.next(None, false, "/a").3, 1);
+        assert_eq!(keys.next(None, true, "/a").3, 0);
+        assert_eq!(keys.next(Some("example.com"), false, "/a").0, "example.com");
+    }
+
+    #[tokio::test]
+    async fn test_reload_adds_and_removes_routes() {
+        let reloader = reloader(vec![route("/a", &["127.0.0.1:8080"])]).await;
+        assert!(reloader.proxy.route("/b").is_err());
+
+        let summary = reloader
+            .apply(vec![route("/b", &["127.0.0.1:8081"])])
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            ReloadSummary {
+                added: 1,
+                removed: 1,
+                ..Default::default()
+            }
+        );
+        assert!(reloader.proxy.route("/a").is_err());
+        assert!(reloader.proxy.route("/b").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_reload_updates_upstreams_in_place() {
+        let reloader = reloader(vec![route("/a", &["127.0.0.1:8080"])]).await;
+        let (before, _) = reloader.proxy.route("/a").unwrap();
+
+        let summary = reloader
+            .apply(vec![route("/a", &["127.0.0.1:8081", "127.0.0.1:8082"])])
+            .await
+            .unwrap();
+        assert_eq!(summary.updated, 1);
+
+        let (after, _) = reloader.proxy.route("/a").unwrap();
+        assert!(Arc::ptr_eq(&before.runtime, &after.runtime));
+        assert_eq!(after.runtime.lb.backends().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_reload_rebuilds_on_changed_tunables() {
+        let reloader = reloader(vec![route("/a", &["127.0.0.1:8080"])]).await;
+        let (before, _) = reloader.proxy.route("/a").unwrap();
+
+        let summary = reloader
+            .apply(vec![route("/a", &["127.0.0.1:8080"]).max_iterations(3)])
+            .await
+            .unwrap();
+        assert_eq!(summary.rebuilt, 1);
+
+        let (after, _) = reloader.proxy.route("/a").unwrap();
+        assert!(!Arc::ptr_eq(&before.runtime, &after.runtime));
+    }
+
+    #[tokio::test]
+    async fn test_reload_retires_unused_load_balancers() {
+        let reloader = reloader(vec![
+            route("/a", &["127.0.0.1:8080"]),
+            route("/b", &["127.0.0.1:8080"]),
+        ])
+        .await;
+        let lb = |path| reloader.proxy.route(path).unwrap().0.runtime.lb.clone();
+        let (kept, dropped) = (lb("/a"), lb("/b"));
+        let before = reloader.registry.lock().unwrap().clone();
+
+        reloader
+            .apply(vec![route("/a", &["127.0.0.1:8081"])])
+            .await
+            .unwrap();
+        let after = reloader.registry.lock().unwrap().clone();
+
+        // /a is updated in place, so only the load balancer of the removed /b retires
+        let retired = retired_load_balancers(&before, &after);
+        assert_eq!(retired.len(), 1);
+        assert!(Arc::ptr_eq(&retired[0], &dropped));
+        assert!(Arc::ptr_eq(&lb("/a"), &kept));
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_upstreams_still_in_use() {
+        let reloader = reloader(vec![
+            route("/a", &["127.0.0.1:8080"]).upstream("api"),
+            route("/b", &["127.0.0.1:8080"]).upstream("api"),
+            route("/c", &["127.0.0.1:8080"]),
+        ])
+        .await;
+        let lb = |path| reloader.proxy.route(path).unwrap().0.runtime.lb.clone();
+        let (shared, dropped) = (lb("/b"), lb("/c"));
+        let before = reloader.registry.lock().unwrap().clone();
+
+        reloader
+            .apply(vec![route("/b", &["127.0.0.1:8080"]).upstream("api")])
+            .await
+            .unwrap();
+        let after = reloader.registry.lock().unwrap().clone();
+
+        // the upstream removed with /a is still used by /b
+        let retired = retired_load_balancers(&before, &after);
+        assert_eq!(retired.len(), 1);
+        assert!(Arc::ptr_eq(&retired[0], &dropped));
+        assert!(Arc::ptr_eq(&lb("/b"), &shared));
+    }
+
+    #[tokio::test]
+    async fn test_reload_shares_named_upstreams() {
+        let reloader = reloader(vec![
+            route("/a", &["127.0.0.1:8080"]).upstream("api"),
+            route("/b", &["127.0.0.1:8080"]).upstream("api"),
+            route("/c", &["127.0.0.1:8080"]),
+        ])
+        .await;
+        let lb = |path| reloader.proxy.route(path).unwrap().0.runtime.lb.clone();
+        assert!(Arc::ptr_eq(&lb("/a"), &lb("/b")));
+        assert!(!Arc::ptr_eq(&lb("/a"), &lb("/c")));
+        assert_eq!(reloader.proxy.route("/b").unwrap().0.runtime.label, "/b");
+        let shared = lb("/a");
+
+        // New upstreams are applied to the shared load balancer once.
+        let upstreams = ["127.0.0.1:8081", "127.0.0.1:8082"];
+        let summary = reloader
+            .apply(vec![
+                route("/a", &upstreams).upstream("api"),
+                route("/b", &upstreams).upstream("api"),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(summary.updated, 2);
+        assert!(Arc::ptr_eq(&lb("/a"), &shared));
+        assert!(Arc::ptr_eq(&lb("/b"), &shared));
+        assert_eq!(shared.backends().len(), 2);
+
+        // Changed tunables give every route of the upstream one new load balancer.
+        let summary = reloader
+            .apply(vec![
+                route("/a", &upstreams).upstream("api").max_iterations(3),
+                route("/b", &upstreams).upstream("api").max_iterations(3),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(summary.rebuilt, 2);
+        assert!(!Arc::ptr_eq(&lb("/a"), &shared));
+        assert!(Arc::ptr_eq(&lb("/a"), &lb("/b")));
+    }
+
+    #[tokio::test]
+    async fn test_reload_rejects_conflicting_routes() {
+        let path = std::env::temp_dir().join(format!("routini-reload-{}.json", std::process::id()));
+        let upstreams = r#""load_balancer": { "upstreams": [{ "address": "127.0.0.1:8080" }] }"#;
+        let config = format!(
+            r#"{{ "proxy": {{ "listener": 3500, "router": [
+                {{ "path": "/users/{{id}}", {upstreams} }},
+                {{ "path": "/users/{{name}}", {upstreams} }}
+            ] }} }}"#
+        );
+        std::fs::write(&path, config).unwrap();
+
+        let mut reloader = reloader(vec![route("/a", &["127.0.0.1:8080"])]).await;
+        reloader.config_path = Some(path.to_string_lossy().into_owned());
+        let err = reloader.reload(ReloadTrigger::Admin).await.unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.to_string().contains("$.proxy.router[1].path"));
+        assert!(reloader.proxy.route("/a").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_failed_reload_changes_nothing() {
+        let reloader = reloader(vec![route("/a", &["127.0.0.1:8080"])]).await;
+        let (before, _) = reloader.proxy.route("/a").unwrap();
+
+        // `/a` is updated in place before the router rejects the conflicting pair.
+        let err = reloader
+            .apply(vec![
+                route("/a", &["127.0.0.1:8081", "127.0.0.1:8082"]),
+                route("/users/{id}", &["127.0.0.1:8080"]),
+                route("/users/{name}", &["127.0.0.1:8080"]),
+            ])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("/users/{name}"), "{err}");
+
+        let (after, _) = reloader.proxy.route("/a").unwrap();
+        assert!(Arc::ptr_eq(&before.runtime, &after.runtime));
+        assert_eq!(after.runtime.lb.backends().len(), 1);
+        assert_eq!(reloader.routes().len(), 1);
+        assert!(reloader.proxy.route("/users/1").is_err());
+    }
+
+    #[test]
+    fn test_change_detector_debounces() {
+        let debounce = Duration::from_secs(1);
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut changes = ChangeDetector::new(Some(1), debounce);
+
+        assert!(!changes.poll(Some(1), at(0)));
+        // A burst of writes keeps restarting the debounce.
+        assert!(!changes.poll(Some(2), at(100)));
+        assert!(!changes.poll(Some(3), at(600)));
+        assert!(!changes.poll(Some(3), at(1500)));
+        // A file briefly missing during a swap is not a change.
+        assert!(!changes.poll(None, at(1550)));
+        assert!(!changes.poll(Some(3), at(1600)));
+        assert!(changes.poll(Some(3), at(2600)));
+        // Reloaded contents, good or bad, are not reloaded again.
+        assert!(!changes.poll(Some(3), at(5000)));
+        // Reverting to earlier contents is a change.
+        assert!(!changes.poll(Some(1), at(5100)));
+        assert!(changes.poll(Some(1), at(6100)));
+    }
+
+    #[test]
+    fn test_fingerprint_follows_symlink_swaps() {
+        let dir = std::env::temp_dir().join(format!("routini-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (first, second, link) = (dir.join("a.json"), dir.join("b.json"), dir.join("config"));
+        std::fs::write(&first, "{}").unwrap();
+        std::fs::write(&second, "{ }").unwrap();
+        std::os::unix::fs::symlink(&first, &link).unwrap();
+        let mut files = WatchedFiles::new(link.clone());
+        let before = files.fingerprint().unwrap();
+
+        let swap = dir.join("config.tmp");
+        std::os::unix::fs::symlink(&second, &swap).unwrap();
+        std::fs::rename(&swap, &link).unwrap();
+        let after = files.fingerprint().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_ne!(before, after);
+    }
+
+    #[test]
+    fn test_fingerprint_resolves_includes_when_the_config_changes() {
+        let dir = std::env::temp_dir().join(format!("routini-includes-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("conf.d")).unwrap();
+        let config = dir.join("config.json");
+        std::fs::write(&config, r#"{"include": ["conf.d/*.json"]}"#).unwrap();
+        std::fs::write(dir.join("conf.d/a.json"), "{}").unwrap();
+        let mut files = WatchedFiles::new(config.clone());
+        let first = files.fingerprint().unwrap();
+        assert_eq!(files.includes, [dir.join("conf.d/a.json")]);
+
+        // Included contents are re-read on every poll
+        std::fs::write(dir.join("conf.d/a.json"), "{ }").unwrap();
+        let edited = files.fingerprint().unwrap();
+        assert_ne!(first, edited);
+
+        // but a new glob match is only seen once the config itself changes
+        std::fs::write(dir.join("conf.d/b.json"), "{}").unwrap();
+        assert_eq!(files.fingerprint().unwrap(), edited);
+        std::fs::write(&config, r#"{"include": ["conf.d/*.json"] }"#).unwrap();
+        files.fingerprint().unwrap();
+        let includes = files.includes.clone();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            includes,
+            [dir.join("conf.d/a.json"), dir.join("conf.d/b.json")]
+        );
+    }
 }
@@ -4,33 +4,404 @@
 //! bundles a route's config with its load balancer and is what the proxy stores per route and
 //! caches per concrete request path.
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
     net::IpAddr,
-    sync::{Arc, Mutex},
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicI64, Ordering},
+    },
     time::{Duration, Instant},
 };
 
+use base64::prelude::{BASE64_URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
 use ipnet::IpNet;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
 
-use http::{HeaderName, HeaderValue, header};
+use http::{HeaderName, HeaderValue, Method, header};
 use pingora::http::{RequestHeader, ResponseHeader};
 use pingora::prelude::HttpPeer;
 use pingora::protocols::l4::socket::SocketAddr;
 use pingora_limits::inflight::{Guard, Inflight};
 use pingora_limits::rate::Rate;
-
-use crate::adaptive_loadbalancer::{
-    AdaptiveBackend, AdaptiveLoadBalancer, decision_engine::AdaptiveDecisionEngine,
+use regex::{Captures, Regex};
+
+use crate::{
+    adaptive_loadbalancer::{
+        AdaptiveBackend, AdaptiveLoadBalancer, decision_engine::AdaptiveDecisionEngine,
+        failure_rate::FailureRateWindow, slow_start::SlowStartConfig,
+    },
+    utils::constants::MAX_RETRY_BODY_SIZE,
 };
 
 pub type SharedLb = Arc<AdaptiveLoadBalancer<AdaptiveDecisionEngine>>;
 
-/// Per-request failover behaviour (nginx `proxy_next_upstream` for connect errors).
-#[derive(Debug, Clone, Copy)]
+/// Which part of the request feeds the hashing strategies (`FNVHash`, `Consistent`, `Maglev`,
+/// `Rendezvous`), so that a given client, session or resource keeps landing on the same backend.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HashKey {
+    /// The downstream client IP.
+    ClientIp,
+    /// The value of a request header.
+    Header(HeaderName),
+    /// The value of a request cookie.
+    Cookie(String),
+    /// The request path, without the query string.
+    Path,
+    /// The raw (still percent-encoded) value of a query-string parameter.
+    Query(String),
+}
+
+impl HashKey {
+    /// Extract the key bytes from the request. A missing header/cookie/parameter yields an empty
+    /// key, so such requests still hash consistently instead of failing.
+    pub fn extract(&self, req: &RequestHeader, client_ip: Option<IpAddr>) -> Vec<u8> {
+        match self {
+            HashKey::ClientIp => match client_ip {
+                Some(IpAddr::V4(ip)) => ip.octets().to_vec(),
+                Some(IpAddr::V6(ip)) => ip.octets().to_vec(),
+                None => Vec::new(),
+            },
+            HashKey::Header(name) => req
+                .headers
+                .get(name)
+                .map(|v| v.as_bytes().to_vec())
+                .unwrap_or_default(),
+            HashKey::Cookie(name) => cookie_value(req, name)
+                .map(|v| v.as_bytes().to_vec())
+                .unwrap_or_default(),
+            HashKey::Path => req.uri.path().as_bytes().to_vec(),
+            HashKey::Query(name) => query_param(req, name)
+                .map(|v| v.as_bytes().to_vec())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Find a cookie by name across all of the request's `Cookie` headers.
+pub fn cookie_value<'a>(req: &'a RequestHeader, name: &str) -> Option<&'a str> {
+    req.headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|pair| {
+            let (k, v) = pair.trim().split_once('=')?;
+            (k == name).then_some(v)
+        })
+}
+
+/// Find a query-string parameter by name. The value is returned raw (not percent-decoded); a
+/// parameter without `=` yields an empty value.
+pub fn query_param<'a>(req: &'a RequestHeader, name: &str) -> Option<&'a str> {
+    req.uri.query()?.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        (k == name).then_some(v)
+    })
+}
+
+/// How a header, query parameter or cookie value must look for a predicate to hold.
+#[derive(Debug, Clone)]
+pub enum ValueMatch {
+    Present,
+    Absent,
+    Equals(String),
+    Regex(Regex),
+}
+
+impl ValueMatch {
+    pub fn matches(&self, value: Option<&str>) -> bool {
+        match (self, value) {
+            (ValueMatch::Present, value) => value.is_some(),
+            (ValueMatch::Absent, value) => value.is_none(),
+            (ValueMatch::Equals(expected), Some(value)) => value == expected,
+            (ValueMatch::Regex(re), Some(value)) => re.is_match(value),
+            (_, None) => false,
+        }
+    }
+}
+
+/// Extra conditions a request must meet to use a route, so several routes can share a path and be
+/// told apart (e.g. API versioning by `Accept`, internal-only endpoints by header). All conditions
+/// must hold; an empty set matches every request.
+#[derive(Debug, Clone, Default)]
+pub struct RequestPredicates {
+    /// Allowed methods; empty = any.
+    pub methods: Vec<Method>,
+    pub headers: Vec<(HeaderName, ValueMatch)>,
+    pub query: Vec<(String, ValueMatch)>,
+    pub cookies: Vec<(String, ValueMatch)>,
+}
+
+impl RequestPredicates {
+    pub fn is_empty(&self) -> bool {
+        self.methods.is_empty()
+            && self.headers.is_empty()
+            && self.query.is_empty()
+            && self.cookies.is_empty()
+    }
+
+    pub fn matches(&self, req: &RequestHeader) -> bool {
+        if !self.methods.is_empty() && !self.methods.contains(&req.method) {
+            return false;
+        }
+        // A repeated header matches if any of its values does.
+        let headers_ok = self.headers.iter().all(|(name, m)| {
+            let mut values = req.headers.get_all(name).iter().peekable();
+            if values.peek().is_none() {
+                return m.matches(None);
+            }
+            values.any(|v| m.matches(Some(v.to_str().unwrap_or_default())))
+        });
+        headers_ok
+            && self
+                .query
+                .iter()
+                .all(|(name, m)| m.matches(query_param(req, name)))
+            && self
+                .cookies
+                .iter()
+                .all(|(name, m)| m.matches(cookie_value(req, name)))
+    }
+}
+
+/// Name of a route's primary upstream pool (its own `load_balancer`).
+pub const PRIMARY_POOL: &str = "primary";
+/// Name of a route's shadow pool (its `mirror`), reserved so no split pool can take it.
+pub const MIRROR_POOL: &str = "mirror";
+
+/// Weighted split of a route's traffic between its upstream pools (canary releases).
+#[derive(Debug, Clone, Default)]
+pub struct TrafficSplit {
+    /// `(pool, weight)`; a request goes to a pool with probability `weight / total`.
+    pub weights: Vec<(String, u32)>,
+    /// Header whose value names a pool to force, e.g. `x-pool: canary`.
+    pub override_header: Option<HeaderName>,
+    /// Cookie whose value names a pool to force.
+    pub override_cookie: Option<String>,
+}
+
+impl TrafficSplit {
+    /// The pool named by the override header or cookie, if the request carries one.
+    pub fn forced<'a>(&self, req: &'a RequestHeader) -> Option<&'a str> {
+        let header = self
+            .override_header
+            .as_ref()
+            .and_then(|name| req.headers.get(name)?.to_str().ok());
+        header.or_else(|| cookie_value(req, self.override_cookie.as_deref()?))
+    }
+
+    /// Pick a pool by weight, given a uniform `roll` in `[0, 1)`.
+    pub fn pick(&self, roll: f64) -> Option<&str> {
+        let total: u64 = self.weights.iter().map(|(_, w)| u64::from(*w)).sum();
+        let mut point = (roll * total as f64) as u64;
+        for (pool, weight) in &self.weights {
+            let weight = u64::from(*weight);
+            if point < weight {
+                return Some(pool);
+            }
+            point -= weight;
+        }
+        None
+    }
+}
+
+/// Shadow traffic for a route (nginx `mirror`): a sample of requests is copied to the route's
+/// mirror pool and the responses discarded.
+#[derive(Debug, Clone)]
+pub struct MirrorConfig {
+    /// Percentage of requests to copy, `0.0..=100.0`.
+    pub sample_percent: f64,
+    /// Buffer and forward the request body with the copy.
+    pub include_body: bool,
+    /// Requests whose body exceeds this are not mirrored (the body is never buffered past it).
+    pub max_body_size: usize,
+}
+
+impl MirrorConfig {
+    /// Roll whether the current request is mirrored.
+    pub fn sample(&self) -> bool {
+        rand::random::<f64>() * 100.0 < self.sample_percent
+    }
+}
+
+/// Per-route access logging (nginx `access_log` inside a `location`).
+#[derive(Debug, Clone)]
+pub struct RouteAccessLog {
+    pub enabled: bool,
+    /// Percentage of the route's requests to log, `0.0..=100.0`.
+    pub sample_percent: f64,
+}
+
+impl Default for RouteAccessLog {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sample_percent: 100.0,
+        }
+    }
+}
+
+impl RouteAccessLog {
+    /// Roll whether the current request is logged.
+    pub fn sample(&self) -> bool {
+        self.enabled && rand::random::<f64>() * 100.0 < self.sample_percent
+    }
+}
+
+/// Cookie-based session affinity: the backend chosen for a client is named in an HMAC-signed
+/// cookie and reused on later requests for as long as it stays healthy.
+#[derive(Debug, Clone)]
+pub struct StickyConfig {
+    pub cookie: String,
+    /// Cookie `Max-Age`; `None` issues a session cookie.
+    pub ttl: Option<Duration>,
+    pub path: String,
+    secret: Vec<u8>,
+    /// The backend ids of each backend set looked up, computed once per set rather than per
+    /// request. Entries of sets that have since been replaced are dropped.
+    ids: Arc<arc_swap::ArcSwap<Vec<Arc<BackendIds>>>>,
+}
+
+/// The addresses of one backend set by their [`StickyConfig::backend_id`].
+#[derive(Debug)]
+struct BackendIds {
+    backends: Weak<BTreeSet<AdaptiveBackend>>,
+    addrs: HashMap<String, SocketAddr>,
+}
+
+impl StickyConfig {
+    /// `secret: None` signs with a random per-process key, so cookies do not survive a restart
+    /// and are not shared between instances.
+    pub fn new(cookie: String, ttl: Option<Duration>, path: String, secret: Option<&[u8]>) -> Self {
+        let secret = match secret {
+            Some(secret) => secret.to_vec(),
+            None => process_secret().to_vec(),
+        };
+        Self {
+            cookie,
+            ttl,
+            path,
+            secret,
+            ids: Arc::default(),
+        }
+    }
+
+    /// The address of the backend among `backends` whose id is `id`.
+    pub fn backend_addr(
+        &self,
+        backends: &Arc<BTreeSet<AdaptiveBackend>>,
+        id: &str,
+    ) -> Option<SocketAddr> {
+        let is_current = |ids: &Arc<BackendIds>| ids.backends.as_ptr() == Arc::as_ptr(backends);
+        if let Some(ids) = self.ids.load().iter().find(|ids| is_current(ids)) {
+            return ids.addrs.get(id).cloned();
+        }
+        let ids = Arc::new(BackendIds {
+            backends: Arc::downgrade(backends),
+            addrs: backends
+                .iter()
+                .map(|backend| (self.backend_id(&backend.addr), backend.addr.clone()))
+                .collect(),
+        });
+        self.ids.rcu(|cached| {
+            let mut next: Vec<_> = cached
+                .iter()
+                .filter(|ids| ids.backends.strong_count() > 0 && !is_current(ids))
+                .cloned()
+                .collect();
+            next.push(ids.clone());
+            next
+        });
+        ids.addrs.get(id).cloned()
+    }
+
+    /// An opaque name for the backend at `addr`: a keyed hash, so the cookie does not reveal the
+    /// backend's address.
+    pub fn backend_id(&self, addr: &SocketAddr) -> String {
+        let mut mac = self.mac();
+        mac.update(b"backend:");
+        mac.update(addr.to_string().as_bytes());
+        BASE64_URL_SAFE_NO_PAD.encode(&mac.finalize().into_bytes()[..BACKEND_ID_LEN])
+    }
+
+    /// The signed cookie value naming the backend at `addr`: `<id>|<base64url(hmac)>`.
+    pub fn sign(&self, addr: &SocketAddr) -> String {
+        let id = self.backend_id(addr);
+        let mut mac = self.mac();
+        mac.update(id.as_bytes());
+        let mac = BASE64_URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{id}|{mac}")
+    }
+
+    /// The backend id named by a cookie value, if its signature is valid.
+    pub fn verify<'a>(&self, value: &'a str) -> Option<&'a str> {
+        let (id, mac) = value.rsplit_once('|')?;
+        let mac = BASE64_URL_SAFE_NO_PAD.decode(mac).ok()?;
+        let mut expected = self.mac();
+        expected.update(id.as_bytes());
+        // Constant-time comparison so the signature cannot be recovered byte by byte.
+        let expected = expected.finalize().into_bytes();
+        bool::from(expected.as_slice().ct_eq(&mac)).then_some(id)
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::new_from_slice(&self.secret).expect("HMAC accepts keys of any length")
+    }
+
+    /// A `Set-Cookie` value pinning the client to `addr`.
+    pub fn set_cookie(&self, addr: &SocketAddr, secure: bool) -> String {
+        let mut cookie = format!(
+            "{}={}; Path={}; HttpOnly; SameSite=Lax",
+            self.cookie,
+            self.sign(addr),
+            self.path
+        );
+        if let Some(ttl) = self.ttl {
+            cookie.push_str(&format!("; Max-Age={}", ttl.as_secs()));
+        }
+        if secure {
+            cookie.push_str("; Secure");
+        }
+        cookie
+    }
+}
+
+/// Bytes of the keyed hash kept as a backend id: enough that ids of one pool never collide.
+const BACKEND_ID_LEN: usize = 12;
+
+/// Random signing key used by sticky routes that do not configure a secret.
+fn process_secret() -> &'static [u8; 32] {
+    static SECRET: std::sync::OnceLock<[u8; 32]> = std::sync::OnceLock::new();
+    SECRET.get_or_init(rand::random)
+}
+
+/// Per-request failover behaviour (nginx `proxy_next_upstream`).
+///
+/// A failed connection is always safe to retry. The other conditions fail after the request
+/// reached the upstream, so they only retry idempotent methods (unless `retry_non_idempotent`)
+/// whose body fits `max_body_size`.
+#[derive(Debug, Clone)]
 pub struct RetryConfig {
     /// Maximum additional upstream attempts after the first, each on a different backend.
     pub max_retries: usize,
     pub retry_on_connect_error: bool,
+    /// Upstream response statuses retried on another backend (nginx `http_502`, `http_503`, ...).
+    pub retry_on_statuses: Vec<u16>,
+    /// Retry when the upstream times out before sending the response header (nginx `timeout`).
+    pub retry_on_timeout: bool,
+    /// Retry when the upstream connection is reset or closed before the response header arrives
+    /// (nginx `error`).
+    pub retry_on_reset: bool,
+    /// Also retry methods that are not idempotent, e.g. POST (nginx `non_idempotent`).
+    pub retry_non_idempotent: bool,
+    /// Largest request body, in bytes, replayed on a retry; at most [`MAX_RETRY_BODY_SIZE`].
+    pub max_body_size: usize,
+    /// Wait between attempts. `None` retries immediately.
+    pub backoff: Option<Backoff>,
+    /// Cap on retries as a share of the route's requests. `None` = unlimited.
+    pub budget: Option<RetryBudgetConfig>,
 }
 
 impl Default for RetryConfig {
@@ -38,18 +409,112 @@ impl Default for RetryConfig {
         Self {
             max_retries: 1,
             retry_on_connect_error: true,
+            retry_on_statuses: Vec::new(),
+            retry_on_timeout: false,
+            retry_on_reset: false,
+            retry_non_idempotent: false,
+            max_body_size: MAX_RETRY_BODY_SIZE,
+            backoff: None,
+            budget: None,
         }
     }
 }
 
+impl RetryConfig {
+    /// Whether a request with this method may be sent again after it reached an upstream.
+    pub fn may_resend(&self, method: &Method) -> bool {
+        self.retry_non_idempotent || is_idempotent(method)
+    }
+}
+
+/// Methods that can be repeated without changing the outcome (RFC 9110, section 9.2.2).
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+/// Exponential backoff with full jitter: retry `n` waits a random time up to
+/// `min(base * 2^(n-1), max)`, so clients retrying together spread out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl Backoff {
+    /// How long to wait before retry `retry` (counting from 1).
+    pub fn delay(&self, retry: usize) -> Duration {
+        let exponent = retry.saturating_sub(1).min(20) as u32;
+        let ceiling = self.base.saturating_mul(1 << exponent).min(self.max);
+        ceiling.mul_f64(rand::random::<f64>())
+    }
+}
+
+/// A retry budget: every request earns `ratio` of a retry, so at most that share of requests is
+/// retried once the `burst` allowance is spent (e.g. 0.2 caps retries at 20% of requests).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryBudgetConfig {
+    pub ratio: f64,
+    /// Retries that can be banked: allowed up front and after a quiet spell.
+    pub burst: u32,
+}
+
+/// Runtime state of a [`RetryBudgetConfig`]: the retries earned and not yet spent, in thousandths.
+pub struct RetryBudget {
+    balance: AtomicI64,
+    earned_per_request: i64,
+    capacity: i64,
+}
+
+impl RetryBudget {
+    pub fn new(config: RetryBudgetConfig) -> Self {
+        let capacity = i64::from(config.burst.max(1)) * 1000;
+        Self {
+            balance: AtomicI64::new(capacity),
+            earned_per_request: (config.ratio * 1000.0).round() as i64,
+            capacity,
+        }
+    }
+
+    /// Credit a request's share of a retry.
+    pub fn deposit(&self) {
+        let earned = self.earned_per_request;
+        let capacity = self.capacity;
+        let _ = self
+            .balance
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |balance| {
+                (balance < capacity).then(|| (balance + earned).min(capacity))
+            });
+    }
+
+    /// Spend one retry, if the budget has one left.
+    pub fn try_withdraw(&self) -> bool {
+        self.balance
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |balance| {
+                (balance >= 1000).then_some(balance - 1000)
+            })
+            .is_ok()
+    }
+}
+
 /// Passive health checking (nginx `max_fails` / `fail_timeout`): eject a backend after it accrues
-/// `max_fails` connection failures within `fail_timeout`, and restore it once `fail_timeout` elapses.
-#[derive(Debug, Clone, Copy)]
+/// `max_fails` failures within `fail_timeout`, and restore it once `fail_timeout` elapses.
+/// Connection failures always count; failure statuses and read timeouts count when configured.
+#[derive(Debug, Clone)]
 pub struct PassiveHealthConfig {
     /// Off by default — routini already runs active health checks; this is opt-in.
     pub enabled: bool,
     pub max_fails: u32,
     pub fail_timeout: Duration,
+    /// Upstream response statuses counted as failures, e.g. 502/503/504 (nginx
+    /// `proxy_next_upstream http_502`).
+    pub failure_statuses: Vec<u16>,
+    /// Count an upstream read timeout as a failure (nginx `proxy_next_upstream timeout`).
+    pub timeout_is_failure: bool,
+    /// Also eject a backend once too many of its recent requests failed, however they were spread.
+    pub failure_rate: Option<FailureRateConfig>,
 }
 
 impl Default for PassiveHealthConfig {
@@ -58,10 +523,23 @@ impl Default for PassiveHealthConfig {
             enabled: false,
             max_fails: 3,
             fail_timeout: Duration::from_secs(10),
+            failure_statuses: Vec::new(),
+            timeout_is_failure: false,
+            failure_rate: None,
         }
     }
 }
 
+/// Eject a backend once at least `threshold` of its requests within the sliding `window` failed,
+/// provided it served at least `min_requests` of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FailureRateConfig {
+    /// Fraction of failed requests, in `(0, 1]`.
+    pub threshold: f64,
+    pub window: Duration,
+    pub min_requests: u32,
+}
+
 /// IP allow/deny and HTTP Basic auth for a route (nginx `allow`/`deny`, `auth_basic`).
 #[derive(Debug, Clone, Default)]
 pub struct AccessControl {
@@ -113,13 +591,112 @@ pub struct CacheConfig {
 /// An immediate response a route can return without proxying (nginx `return` / `rewrite ... redirect`).
 #[derive(Debug, Clone)]
 pub enum RouteAction {
-    /// Redirect to `location`. The template may contain `$uri` (request path) and `$request_uri`
-    /// (path + query), substituted per request.
+    /// Redirect to `location`, expanded per request by [`expand_template`]. On a regex location
+    /// `$1`/`${name}` refer to the location's capture groups.
     Redirect { status: u16, location: String },
     /// Respond immediately with `status` and an optional body.
     Return { status: u16, body: Option<String> },
 }
 
+/// What happens once a rewrite rule matches (nginx `rewrite` flags).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RewriteFlag {
+    /// Forward the rewritten URI to the upstream; later rules are not evaluated.
+    #[default]
+    Last,
+    /// Answer with a `302` redirect to the rewritten URI.
+    Redirect,
+    /// Answer with a `301` redirect to the rewritten URI.
+    Permanent,
+}
+
+/// An nginx-style `rewrite <regex> <replacement> <flag>` rule, matched against the request path.
+#[derive(Debug, Clone)]
+pub struct RewriteRule {
+    pub regex: Regex,
+    /// Expanded by [`expand_template`] with the rule's capture groups, e.g. `/v2/$1?lang=$2`.
+    pub replacement: String,
+    pub flag: RewriteFlag,
+}
+
+impl RewriteRule {
+    /// The rewritten URI if the rule matches. As in nginx, the original query string is appended
+    /// after any arguments the replacement sets, unless the replacement ends in `?`, which drops
+    /// it (the trailing `?` itself is removed).
+    pub fn apply(&self, req: &RequestHeader) -> Option<String> {
+        let captures = self.regex.captures(req.uri.path())?;
+        if let Some(replacement) = self.replacement.strip_suffix('?') {
+            return Some(expand_template(replacement, Some(&captures), req));
+        }
+        let mut target = expand_template(&self.replacement, Some(&captures), req);
+        if let Some(query) = req.uri.query().filter(|query| !query.is_empty()) {
+            target.push(if target.contains('?') { '&' } else { '?' });
+            target.push_str(query);
+        }
+        Some(target)
+    }
+}
+
+/// Expand a rewrite/redirect template for a request. Supports `$uri` (path), `$request_uri`
+/// (path + query), `$args` (query), the positional captures `$0`-`$9` and `${n}`, and named
+/// captures `${name}`. Unknown `$` sequences are kept literally; a positional capture that did not
+/// participate in the match expands to "".
+pub fn expand_template(template: &str, captures: Option<&Captures>, req: &RequestHeader) -> String {
+    if !template.contains('$') {
+        return template.to_string();
+    }
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+            match braced.split_once('}') {
+                Some((name, after)) => (name, after),
+                None => ("", rest),
+            }
+        } else if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            // Like nginx, a bare positional capture is a single digit: `$10` is `$1` then `0`.
+            rest.split_at(1)
+        } else {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            rest.split_at(len)
+        };
+        match template_var(name, captures, req).filter(|_| !name.is_empty()) {
+            Some(value) => {
+                out.push_str(value);
+                rest = after;
+            }
+            None => out.push('$'),
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The value of a template variable or capture group; `None` if `name` is neither.
+fn template_var<'a>(
+    name: &str,
+    captures: Option<&Captures<'a>>,
+    req: &'a RequestHeader,
+) -> Option<&'a str> {
+    let uri = &req.uri;
+    match name {
+        "uri" => Some(uri.path()),
+        "request_uri" => Some(uri.path_and_query().map_or(uri.path(), |pq| pq.as_str())),
+        "args" => Some(uri.query().unwrap_or_default()),
+        _ => {
+            let captures = captures?;
+            match name.parse::<usize>() {
+                Ok(index) => Some(captures.get(index).map_or("", |m| m.as_str())),
+                Err(_) => captures.name(name).map(|m| m.as_str()),
+            }
+        }
+    }
+}
+
 /// Per-client-IP request-rate limiter (nginx `limit_req`). Backed by a 1-second sliding window.
 pub struct RateLimiter {
     rate: Rate,
@@ -190,6 +767,18 @@ impl Default for UpstreamTls {
 struct FailWindow {
     count: u32,
     window_start: Instant,
+    /// Outcomes within the failure-rate window.
+    outcomes: FailureRateWindow,
+}
+
+impl FailWindow {
+    fn new(now: Instant) -> Self {
+        Self {
+            count: 0,
+            window_start: now,
+            outcomes: FailureRateWindow::new(now),
+        }
+    }
 }
 
 struct Ejection {
@@ -214,23 +803,28 @@ impl PassiveHealth {
         }
     }
 
-    /// Record a connection failure. Returns `true` when the backend has crossed `max_fails`
-    /// within the window and should be ejected by the caller.
+    /// Record a failure. Returns `true` when the backend has crossed `max_fails` within the
+    /// window, or the failure rate threshold, and should be ejected by the caller.
     pub fn record_failure(&self, backend: &AdaptiveBackend) -> bool {
+        self.record_failure_at(backend, Instant::now())
+    }
+
+    fn record_failure_at(&self, backend: &AdaptiveBackend, now: Instant) -> bool {
         if !self.config.enabled {
             return false;
         }
-        let now = Instant::now();
         let mut windows = self.windows.lock().unwrap();
         let window = windows
             .entry(backend.addr.clone())
-            .or_insert(FailWindow { count: 0, window_start: now });
-        if now.duration_since(window.window_start) > self.config.fail_timeout {
+            .or_insert_with(|| FailWindow::new(now));
+        let expired = now.duration_since(window.window_start) > self.config.fail_timeout;
+        if window.count == 0 || expired {
             window.count = 0;
             window.window_start = now;
         }
         window.count += 1;
-        if window.count >= self.config.max_fails {
+        let rate_exceeded = self.record_outcome(window, now, true);
+        if window.count >= self.config.max_fails || rate_exceeded {
             windows.remove(&backend.addr);
             self.ejections.lock().unwrap().push(Ejection {
                 backend: backend.clone(),
@@ -241,12 +835,55 @@ impl PassiveHealth {
         false
     }
 
-    /// Clear the failure window for a backend after a successful response.
+    /// Clear the failure count for a backend after a successful response.
     pub fn record_success(&self, backend: &AdaptiveBackend) {
+        self.record_success_at(backend, Instant::now());
+    }
+
+    fn record_success_at(&self, backend: &AdaptiveBackend, now: Instant) {
         if !self.config.enabled {
             return;
         }
-        self.windows.lock().unwrap().remove(&backend.addr);
+        let mut windows = self.windows.lock().unwrap();
+        if self.config.failure_rate.is_none() {
+            windows.remove(&backend.addr);
+            return;
+        }
+        // Successes count towards the failure rate, so the window outlives the failure count.
+        let window = windows
+            .entry(backend.addr.clone())
+            .or_insert_with(|| FailWindow::new(now));
+        window.count = 0;
+        self.record_outcome(window, now, false);
+    }
+
+    /// Record an upstream response: a failure if its status is one of `failure_statuses`,
+    /// otherwise a success. Returns `true` when the backend should be ejected.
+    pub fn record_response(&self, backend: &AdaptiveBackend, status: u16) -> bool {
+        if self.config.failure_statuses.contains(&status) {
+            self.record_failure(backend)
+        } else {
+            self.record_success(backend);
+            false
+        }
+    }
+
+    /// Record an upstream read timeout, a failure only with `timeout_is_failure`. Returns `true`
+    /// when the backend should be ejected.
+    pub fn record_timeout(&self, backend: &AdaptiveBackend) -> bool {
+        self.config.timeout_is_failure && self.record_failure(backend)
+    }
+
+    /// Count a request in the backend's failure-rate window, returning whether the window's
+    /// failure rate has reached the threshold.
+    fn record_outcome(&self, window: &mut FailWindow, now: Instant, failed: bool) -> bool {
+        let Some(rate) = self.config.failure_rate else {
+            return false;
+        };
+        window
+            .outcomes
+            .record(rate.window, now, failed)
+            .reaches(rate.threshold, rate.min_requests)
     }
 
     /// Return backends whose ejection window has elapsed, so the caller can re-enable them.
@@ -424,6 +1061,25 @@ pub struct RouteConfig {
     pub cache: Option<CacheConfig>,
     /// IP allow/deny + Basic auth (nginx `allow`/`deny`/`auth_basic`). `None` = open.
     pub access: Option<AccessControl>,
+    /// Request attribute hashed by the `FNVHash`/`Consistent`/`Maglev`/`Rendezvous` strategies
+    /// (nginx `hash`).
+    /// `None` hashes an empty key, i.e. every request maps to the same backend.
+    pub hash_key: Option<HashKey>,
+    /// Cookie-based session affinity. `None` = every request goes through normal selection.
+    pub sticky: Option<StickyConfig>,
+    /// Conditions on method/headers/query/cookies the request must meet to use this route.
+    pub predicates: RequestPredicates,
+    /// Path rewrite rules (nginx `rewrite`), tried in order; the first match applies.
+    pub rewrites: Vec<RewriteRule>,
+    /// Weighted split between the route's upstream pools. `None` = everything to the primary.
+    pub split: Option<TrafficSplit>,
+    /// Copy a sample of requests to the route's mirror pool. `None` = no mirroring.
+    pub mirror: Option<MirrorConfig>,
+    /// Whether, and how often, the route's requests are access-logged.
+    pub access_log: RouteAccessLog,
+    /// Ramp up backends joining or rejoining a pool (nginx Plus `slow_start`). `None` = full
+    /// weight right away.
+    pub slow_start: Option<SlowStartConfig>,
 }
 
 impl Default for RouteConfig {
@@ -442,6 +1098,14 @@ impl Default for RouteConfig {
             action: None,
             cache: None,
             access: None,
+            hash_key: None,
+            sticky: None,
+            predicates: RequestPredicates::default(),
+            rewrites: Vec::new(),
+            split: None,
+            mirror: None,
+            access_log: RouteAccessLog::default(),
+            slow_start: None,
         }
     }
 }
@@ -453,18 +1117,21 @@ pub struct RouteState {
     pub health: PassiveHealth,
     pub rate_limiter: Option<RateLimiter>,
     pub conn_limiter: Option<ConnLimiter>,
+    pub retry_budget: Option<RetryBudget>,
 }
 
 impl RouteState {
     pub fn new(config: RouteConfig) -> Self {
-        let health = PassiveHealth::new(config.passive_health);
+        let health = PassiveHealth::new(config.passive_health.clone());
         let rate_limiter = config.rate_limit_rps.map(RateLimiter::new);
         let conn_limiter = config.max_connections.map(ConnLimiter::new);
+        let retry_budget = config.retry.budget.map(RetryBudget::new);
         Self {
             config,
             health,
             rate_limiter,
             conn_limiter,
+            retry_budget,
         }
     }
 }
@@ -473,18 +1140,82 @@ impl RouteState {
 /// is fixed for the process lifetime; `state` is read lock-free per request and can be replaced on
 /// SIGHUP config reload via [`RouteRuntime::reload`].
 pub struct RouteRuntime {
+    /// The primary pool's load balancer.
     pub lb: SharedLb,
+    /// Additional named upstream pools (e.g. a canary), each with its own load balancer, health
+    /// check and adaptive engine.
+    pub pools: HashMap<String, SharedLb>,
+    /// Load balancer of the shadow pool receiving mirrored copies; never serves responses.
+    pub mirror: Option<SharedLb>,
     pub state: arc_swap::ArcSwap<RouteState>,
+    /// The route's `route` metrics label; its load balancers' own label when they are not shared
+    /// with other routes.
+    pub label: String,
 }
 
 impl RouteRuntime {
     pub fn new(lb: SharedLb, config: RouteConfig) -> Self {
+        Self::with_pools(lb, HashMap::new(), config)
+    }
+
+    pub fn with_pools(lb: SharedLb, pools: HashMap<String, SharedLb>, config: RouteConfig) -> Self {
         Self {
+            label: lb.labels.route.clone(),
             lb,
+            pools,
+            mirror: None,
             state: arc_swap::ArcSwap::from_pointee(RouteState::new(config)),
         }
     }
 
+    /// Label the route's metrics `label` (see [`RouteRuntime::label`]).
+    pub fn with_label(mut self, label: String) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// Attach the shadow pool that mirrored requests are copied to.
+    pub fn with_mirror(mut self, mirror: SharedLb) -> Self {
+        self.mirror = Some(mirror);
+        self
+    }
+
+    /// The load balancer of the named pool; [`PRIMARY_POOL`] is the route's own.
+    pub fn pool(&self, name: &str) -> Option<&SharedLb> {
+        if name == PRIMARY_POOL {
+            return Some(&self.lb);
+        }
+        self.pools.get(name)
+    }
+
+    /// The pool to serve a request from: one forced by the split's override header/cookie, else a
+    /// weighted pick, else the primary. Unknown pool names fall back to the primary.
+    pub fn select_pool(&self, split: Option<&TrafficSplit>, req: &RequestHeader) -> &SharedLb {
+        split
+            .and_then(|split| {
+                split
+                    .forced(req)
+                    .and_then(|name| self.pool(name))
+                    .or_else(|| self.pool(split.pick(rand::random())?))
+            })
+            .unwrap_or(&self.lb)
+    }
+
+    /// The route's load balancers: the primary pool's, the other pools' and the mirror's.
+    pub fn load_balancers(&self) -> impl Iterator<Item = &SharedLb> {
+        std::iter::once(&self.lb)
+            .chain(self.pools.values())
+            .chain(&self.mirror)
+    }
+
+    /// Enable/disable a backend in every pool that contains it (passive health ejection).
+    pub fn set_backend_enabled(&self, backend: &AdaptiveBackend, enabled: bool) {
+        self.lb.set_backend_enabled(backend, enabled);
+        for lb in self.pools.values() {
+            lb.set_backend_enabled(backend, enabled);
+        }
+    }
+
     /// Atomically replace this route's config-derived state (used by config reload). Limiter and
     /// passive-health counters reset, matching nginx's "new workers, fresh limit state" reload.
     pub fn reload(&self, config: RouteConfig) {
@@ -581,6 +1312,7 @@ mod tests {
             enabled: true,
             max_fails: 2,
             fail_timeout: Duration::from_millis(50),
+            ..Default::default()
         };
         let ph = PassiveHealth::new(cfg);
         let backend = AdaptiveBackend::build("127.0.0.1:9001", 1).unwrap();
@@ -598,6 +1330,106 @@ mod tests {
         assert_eq!(expired[0].addr, backend.addr);
     }
 
+    #[test]
+    fn passive_health_counts_failure_statuses_and_timeouts() {
+        let ph = PassiveHealth::new(PassiveHealthConfig {
+            enabled: true,
+            max_fails: 2,
+            failure_statuses: vec![502, 503, 504],
+            ..Default::default()
+        });
+        let backend = AdaptiveBackend::build("127.0.0.1:9001", 1).unwrap();
+
+        assert!(!ph.record_response(&backend, 503));
+        // a success in between resets the count
+        assert!(!ph.record_response(&backend, 404));
+        assert!(!ph.record_response(&backend, 502));
+        // timeouts are not failures unless configured
+        assert!(!ph.record_timeout(&backend));
+        let ejected = ph.record_response(&backend, 504);
+        assert!(ejected, "second failure in a row should eject");
+    }
+
+    #[test]
+    fn passive_health_ejects_on_failure_rate() {
+        let ph = PassiveHealth::new(PassiveHealthConfig {
+            enabled: true,
+            max_fails: 100,
+            failure_rate: Some(FailureRateConfig {
+                threshold: 0.5,
+                window: Duration::from_secs(10),
+                min_requests: 10,
+            }),
+            ..Default::default()
+        });
+        let backend = AdaptiveBackend::build("127.0.0.1:9001", 1).unwrap();
+        let start = Instant::now();
+
+        // 40% failures, spread out so they are never consecutive
+        for i in 0..10 {
+            if i % 5 < 2 {
+                assert!(!ph.record_failure_at(&backend, start));
+            } else {
+                ph.record_success_at(&backend, start);
+            }
+        }
+        // the window slides past those, then 5 of 10 new requests fail
+        let later = start + Duration::from_secs(11);
+        for _ in 0..5 {
+            ph.record_success_at(&backend, later);
+        }
+        for _ in 0..4 {
+            assert!(!ph.record_failure_at(&backend, later));
+        }
+        let ejected = ph.record_failure_at(&backend, later);
+        assert!(ejected, "50% failures should eject");
+    }
+
+    #[test]
+    fn retry_budget_caps_retries_to_a_share_of_requests() {
+        let budget = RetryBudget::new(RetryBudgetConfig {
+            ratio: 0.2,
+            burst: 2,
+        });
+        // the burst is available up front, then spent
+        assert!(budget.try_withdraw());
+        assert!(budget.try_withdraw());
+        assert!(!budget.try_withdraw());
+
+        // then one retry per five requests
+        let retries = (0..50)
+            .filter(|_| {
+                budget.deposit();
+                budget.try_withdraw()
+            })
+            .count();
+        assert_eq!(retries, 10);
+
+        // a quiet spell banks no more than the burst
+        for _ in 0..100 {
+            budget.deposit();
+        }
+        assert!(budget.try_withdraw());
+        assert!(budget.try_withdraw());
+        assert!(!budget.try_withdraw());
+    }
+
+    #[test]
+    fn retry_backoff_is_jittered_and_capped() {
+        let backoff = Backoff {
+            base: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+        };
+        for _ in 0..100 {
+            assert!(backoff.delay(1) <= Duration::from_millis(10));
+            assert!(backoff.delay(3) <= Duration::from_millis(40));
+            assert!(backoff.delay(30) <= Duration::from_millis(50));
+        }
+        let retry = RetryConfig::default();
+        assert!(retry.may_resend(&Method::PUT));
+        assert!(!retry.may_resend(&Method::POST));
+    }
+
     #[test]
     fn access_control_ip_and_basic_auth() {
         let ac = AccessControl {
@@ -640,6 +1472,231 @@ mod tests {
         assert!(ph.take_expired(Instant::now()).is_empty());
     }
 
+    #[test]
+    fn hash_key_extracts_request_attributes() {
+        let mut r = RequestHeader::build("GET", b"/cart/items?user=42&lang=en", None).unwrap();
+        r.insert_header("x-user", "alice").unwrap();
+        r.append_header(http::header::COOKIE, "theme=dark; sid=abc123").unwrap();
+        let ip: IpAddr = "10.0.0.7".parse().unwrap();
+
+        assert_eq!(HashKey::ClientIp.extract(&r, Some(ip)), vec![10, 0, 0, 7]);
+        assert_eq!(
+            HashKey::Header(HeaderName::from_static("x-user")).extract(&r, None),
+            b"alice"
+        );
+        assert_eq!(HashKey::Cookie("sid".into()).extract(&r, None), b"abc123");
+        assert_eq!(HashKey::Path.extract(&r, None), b"/cart/items");
+        assert_eq!(HashKey::Query("user".into()).extract(&r, None), b"42");
+
+        // missing attributes hash as an empty key rather than failing
+        assert!(HashKey::Query("missing".into()).extract(&r, None).is_empty());
+        assert!(HashKey::ClientIp.extract(&r, None).is_empty());
+    }
+
+    #[test]
+    fn predicates_match_method_headers_query_and_cookies() {
+        let predicates = RequestPredicates {
+            methods: vec![Method::GET, Method::HEAD],
+            headers: vec![(
+                header::ACCEPT,
+                ValueMatch::Regex(Regex::new(r"application/vnd\.api\.v2").unwrap()),
+            )],
+            query: vec![("debug".into(), ValueMatch::Absent)],
+            cookies: vec![("beta".into(), ValueMatch::Equals("1".into()))],
+        };
+
+        let mut r = RequestHeader::build("GET", b"/items", None).unwrap();
+        r.insert_header(header::ACCEPT, "application/vnd.api.v2+json")
+            .unwrap();
+        r.insert_header(header::COOKIE, "beta=1").unwrap();
+        assert!(predicates.matches(&r));
+
+        r.set_method(Method::POST);
+        assert!(!predicates.matches(&r));
+        r.set_method(Method::GET);
+
+        r.set_uri("/items?debug=1".parse().unwrap());
+        assert!(!predicates.matches(&r));
+        r.set_uri("/items".parse().unwrap());
+
+        r.insert_header(header::ACCEPT, "application/json").unwrap();
+        assert!(!predicates.matches(&r));
+
+        assert!(RequestPredicates::default().matches(&r));
+    }
+
+    #[test]
+    fn sticky_cookie_round_trips_and_rejects_tampering() {
+        let sticky = StickyConfig::new("srv".into(), None, "/".into(), Some(b"secret"));
+        let addr = SocketAddr::Inet("10.0.0.1:8080".parse().unwrap());
+        let value = sticky.sign(&addr);
+        let id = sticky.backend_id(&addr);
+        assert_eq!(sticky.verify(&value), Some(id.as_str()));
+        // the cookie names the backend without revealing its address
+        assert!(!value.contains("10.0.0.1"));
+
+        // another backend's id with the original signature
+        let other_addr = SocketAddr::Inet("10.0.0.2:8080".parse().unwrap());
+        let (_, mac) = value.rsplit_once('|').unwrap();
+        let forged = format!("{}|{mac}", sticky.backend_id(&other_addr));
+        assert_eq!(sticky.verify(&forged), None);
+        // signed with a different secret, which also names backends differently
+        let other = StickyConfig::new("srv".into(), None, "/".into(), Some(b"other"));
+        assert_eq!(other.verify(&value), None);
+        assert_ne!(other.backend_id(&addr), sticky.backend_id(&addr));
+        assert_eq!(sticky.verify("garbage"), None);
+    }
+
+    #[test]
+    fn expand_template_substitutes_variables_and_captures() {
+        let r = RequestHeader::build("GET", b"/docs/en/intro?print=1", None).unwrap();
+        let re = Regex::new(r"^/docs/(?<lang>\w+)/(.*)$").unwrap();
+        let caps = re.captures("/docs/en/intro").unwrap();
+
+        assert_eq!(
+            expand_template("/v2/$2?lang=${lang}&$args", Some(&caps), &r),
+            "/v2/intro?lang=en&print=1"
+        );
+        assert_eq!(
+            expand_template("https://example.com$request_uri", None, &r),
+            "https://example.com/docs/en/intro?print=1"
+        );
+        assert_eq!(expand_template("$uri/", None, &r), "/docs/en/intro/");
+        // `$10` is capture 1 followed by a literal 0; missing captures expand to nothing
+        assert_eq!(expand_template("$10-${9}", Some(&caps), &r), "en0-");
+        // unknown variables and a lone `$` are kept literally
+        assert_eq!(expand_template("$host$", None, &r), "$host$");
+    }
+
+    #[test]
+    fn rewrite_rule_keeps_original_query() {
+        let rule = RewriteRule {
+            regex: Regex::new(r"^/old/(.*)$").unwrap(),
+            replacement: "/new/$1".into(),
+            flag: RewriteFlag::Last,
+        };
+        let r = RequestHeader::build("GET", b"/old/page?id=7", None).unwrap();
+        assert_eq!(rule.apply(&r).as_deref(), Some("/new/page?id=7"));
+
+        let r = RequestHeader::build("GET", b"/old/page", None).unwrap();
+        assert_eq!(rule.apply(&r).as_deref(), Some("/new/page"));
+
+        let r = RequestHeader::build("GET", b"/other", None).unwrap();
+        assert_eq!(rule.apply(&r), None);
+    }
+
+    #[test]
+    fn rewrite_rule_appends_original_query_after_replacement_args() {
+        let rule = RewriteRule {
+            regex: Regex::new(r"^/old/(.*)$").unwrap(),
+            replacement: "/new?page=$1".into(),
+            flag: RewriteFlag::Last,
+        };
+        let r = RequestHeader::build("GET", b"/old/page?id=7", None).unwrap();
+        assert_eq!(rule.apply(&r).as_deref(), Some("/new?page=page&id=7"));
+
+        let r = RequestHeader::build("GET", b"/old/page", None).unwrap();
+        assert_eq!(rule.apply(&r).as_deref(), Some("/new?page=page"));
+    }
+
+    #[test]
+    fn rewrite_rule_trailing_question_mark_drops_original_query() {
+        let rule = RewriteRule {
+            regex: Regex::new(r"^/old/(.*)$").unwrap(),
+            replacement: "/new/$1?".into(),
+            flag: RewriteFlag::Last,
+        };
+        let r = RequestHeader::build("GET", b"/old/page?id=7", None).unwrap();
+        assert_eq!(rule.apply(&r).as_deref(), Some("/new/page"));
+
+        let rule = RewriteRule {
+            replacement: "/new?page=$1?".into(),
+            ..rule
+        };
+        assert_eq!(rule.apply(&r).as_deref(), Some("/new?page=page"));
+    }
+
+    #[test]
+    fn traffic_split_picks_by_weight_and_honours_override() {
+        let split = TrafficSplit {
+            weights: vec![(PRIMARY_POOL.into(), 95), ("canary".into(), 5)],
+            override_header: Some(HeaderName::from_static("x-pool")),
+            override_cookie: Some("pool".into()),
+        };
+        assert_eq!(split.pick(0.0), Some(PRIMARY_POOL));
+        assert_eq!(split.pick(0.949), Some(PRIMARY_POOL));
+        assert_eq!(split.pick(0.95), Some("canary"));
+        assert_eq!(split.pick(0.999), Some("canary"));
+        assert_eq!(TrafficSplit::default().pick(0.5), None);
+
+        let mut r = req();
+        assert_eq!(split.forced(&r), None);
+        r.insert_header(header::COOKIE, "pool=canary").unwrap();
+        assert_eq!(split.forced(&r), Some("canary"));
+        r.insert_header("x-pool", PRIMARY_POOL).unwrap();
+        assert_eq!(split.forced(&r), Some(PRIMARY_POOL));
+    }
+
+    #[test]
+    fn mirror_sampling_bounds() {
+        let mut mirror = MirrorConfig {
+            sample_percent: 0.0,
+            include_body: false,
+            max_body_size: 0,
+        };
+        assert!((0..1000).all(|_| !mirror.sample()));
+        mirror.sample_percent = 100.0;
+        assert!((0..1000).all(|_| mirror.sample()));
+    }
+
+    #[test]
+    fn access_log_sampling() {
+        let mut log = RouteAccessLog::default();
+        assert!((0..1000).all(|_| log.sample()));
+        log.sample_percent = 0.0;
+        assert!((0..1000).all(|_| !log.sample()));
+        log.sample_percent = 100.0;
+        log.enabled = false;
+        assert!(!log.sample());
+    }
+
+    #[test]
+    fn sticky_backend_addr_follows_the_backend_set() {
+        let sticky = StickyConfig::new("srv".into(), None, "/".into(), Some(b"secret"));
+        let backends = |addrs: &[&str]| {
+            let set: BTreeSet<AdaptiveBackend> = addrs
+                .iter()
+                .map(|addr| crate::load_balancing::Backend::build(addr, 1).unwrap())
+                .collect();
+            Arc::new(set)
+        };
+        let addr = SocketAddr::Inet("10.0.0.1:8080".parse().unwrap());
+        let id = sticky.backend_id(&addr);
+
+        let before = backends(&["10.0.0.1:8080", "10.0.0.2:8080"]);
+        assert_eq!(sticky.backend_addr(&before, &id), Some(addr.clone()));
+        assert_eq!(sticky.backend_addr(&before, "unknown"), None);
+        // a replaced set is looked up afresh, and the cached ids of the old one are dropped
+        let after = backends(&["10.0.0.2:8080"]);
+        drop(before);
+        assert_eq!(sticky.backend_addr(&after, &id), None);
+        assert_eq!(sticky.ids.load().len(), 1);
+    }
+
+    #[test]
+    fn sticky_set_cookie_attributes() {
+        let sticky = StickyConfig::new(
+            "srv".into(),
+            Some(Duration::from_secs(3600)),
+            "/app".into(),
+            None,
+        );
+        let addr = SocketAddr::Inet("10.0.0.1:8080".parse().unwrap());
+        let cookie = sticky.set_cookie(&addr, true);
+        assert!(cookie.starts_with(&format!("srv={}|", sticky.backend_id(&addr))));
+        assert!(cookie.contains("; Path=/app; HttpOnly; SameSite=Lax; Max-Age=3600; Secure"));
+    }
+
     #[test]
     fn response_headers_added_and_removed() {
         let rules = HeaderRules {
@@ -7,7 +7,6 @@ use std::{
 };
 
 use color_eyre::eyre::{Result, eyre};
-use matchit::Router;
 use pingora::{
     listeners::tls::TlsSettings,
     prelude::background_service,
@@ -18,15 +17,21 @@ use pingora::{
 use regex::Regex;
 
 use crate::{
+    access_log::AccessLog,
     adaptive_loadbalancer::{
         AdaptiveBackend, AdaptiveBackends, AdaptiveLoadBalancer,
         decision_engine::AdaptiveDecisionEngine, options::AdaptiveLbOpt,
     },
+    admin::AdminApi,
+    lb_tasks::{LbTasks, PendingTasks},
     load_balancing::{Backends, discovery::Static, strategy::Adaptive},
-    proxy::{Proxy, RouteValue},
-    reload::{RouteRegistry, spawn_reload_watcher},
-    route::RouteRuntime,
-    set_strategy_endpoint::SetStrategyEndpoint,
+    metrics::{BackendCollector, PoolLabels, route_label},
+    proxy::{Proxy, RouteValue, VHostRoutes},
+    reload::{
+        RegisteredRoute, RegisteredUpstream, Reloader, RouteKey, RouteKeys, RouteLbOptions,
+        RouteRegistry, UpstreamRegistry, spawn_config_watcher, spawn_reload_watcher,
+    },
+    route::{MIRROR_POOL, PRIMARY_POOL, RouteRuntime, SharedLb},
     utils::constants::{
         DEFAULT_PATH_CACHE_CAPACITY, DEFAULT_WILDCARD_IDENTIFIER, PROMETHEUS_ENDPOINT_ADDRESS,
     },
@@ -36,6 +41,13 @@ use crate::{
 // definition now lives in `crate::route` alongside the rest of the per-route runtime config.
 pub use crate::route::RouteConfig;
 
+/// Admin API listener (see [`crate::admin`]).
+pub struct AdminConfig {
+    pub address: String,
+    /// Bearer token required on every admin request; `None` leaves the API unauthenticated.
+    pub token: Option<String>,
+}
+
 /// TLS termination settings for the proxy's public listener.
 pub struct TlsConfig {
     pub address: String,
@@ -60,15 +72,18 @@ pub fn proxy_server(listener: TcpListener) -> ServerBuilder {
     ServerBuilder {
         address,
         routes: Vec::new(),
-        set_strategy_endpoint: None,
+        admin: None,
         server_config: None,
         tls: None,
         prometheus_address: None,
         access_log: true,
+        access_log_config: None,
         https_redirect: false,
         compression_level: 0,
         reload_config_path: None,
+        watch_config: None,
         request_id: false,
+        trace_context: false,
         error_pages: HashMap::new(),
     }
 }
@@ -82,9 +97,23 @@ pub struct Route {
     pub route_config: RouteConfig,
     /// Optional virtual host this route belongs to (nginx `server_name`). `None` = default server.
     pub host: Option<String>,
-    /// When true, `path` is a regex location matched against the full request path (default server
-    /// only), tried after matchit routes (nginx `location ~ <regex>`).
+    /// When true, `path` is a regex location matched against the full request path, tried after
+    /// matchit routes (nginx `location ~ <regex>`).
     pub is_regex: bool,
+    /// Additional named upstream pools the route's traffic split can send requests to.
+    pub pools: Vec<UpstreamPool>,
+    /// Shadow pool that sampled requests are copied to (see [`RouteConfig::mirror`]).
+    pub mirror: Option<UpstreamPool>,
+    /// Named upstream (nginx `upstream`) whose load balancer the route shares with every other
+    /// route naming it, see [`Route::upstream`].
+    pub upstream: Option<String>,
+}
+
+/// A named upstream pool on a route (e.g. a canary), with its own adaptive load balancer.
+pub struct UpstreamPool {
+    pub name: String,
+    pub backends: AdaptiveBackends,
+    pub lb_options: AdaptiveLbOpt,
 }
 
 impl Route {
@@ -135,6 +164,9 @@ impl Route {
             route_config: RouteConfig::default(),
             host: None,
             is_regex: false,
+            pools: Vec::new(),
+            mirror: None,
+            upstream: None,
         })
     }
 
@@ -155,6 +187,9 @@ impl Route {
             route_config: RouteConfig::default(),
             host: None,
             is_regex: true,
+            pools: Vec::new(),
+            mirror: None,
+            upstream: None,
         })
     }
 
@@ -165,9 +200,10 @@ impl Route {
             .into_iter()
             .map(|(addr, weight)| {
                 // `build` constructs the default plain-HTTP peer and `AdaptiveStrategyMetrics`.
-                AdaptiveBackend::build(&addr, weight.max(1)).expect("Invalid backend address")
+                AdaptiveBackend::build(&addr, weight.max(1))
+                    .map_err(|err| eyre!("Invalid backend address '{addr}': {err}"))
             })
-            .collect::<BTreeSet<_>>();
+            .collect::<Result<BTreeSet<_>>>()?;
 
         if backends.is_empty() {
             return Err(eyre!("Must provide at least one backend"));
@@ -175,12 +211,60 @@ impl Route {
         Ok(backends)
     }
 
-    /// Restrict this route to a named virtual host (nginx `server_name`).
+    /// Add a named upstream pool with its own load balancer, selectable through
+    /// [`RouteConfig::split`]. The route's own backends form the [`PRIMARY_POOL`].
+    pub fn pool(
+        mut self,
+        name: impl Into<String>,
+        backends: impl IntoIterator<Item = (String, usize)>,
+        lb_options: AdaptiveLbOpt,
+    ) -> Result<Self> {
+        let name = name.into();
+        if name == MIRROR_POOL {
+            return Err(eyre!(
+                "Upstream pool name '{name}' is reserved for the mirror pool"
+            ));
+        }
+        if name == PRIMARY_POOL || self.pools.iter().any(|pool| pool.name == name) {
+            return Err(eyre!("Duplicate upstream pool '{name}'"));
+        }
+        self.pools.push(UpstreamPool {
+            name,
+            backends: Backends::new(Static::new(Self::build_backend_set(backends)?)),
+            lb_options,
+        });
+        Ok(self)
+    }
+
+    /// Set the shadow pool that requests sampled by [`RouteConfig::mirror`] are copied to.
+    pub fn mirror(
+        mut self,
+        backends: impl IntoIterator<Item = (String, usize)>,
+        lb_options: AdaptiveLbOpt,
+    ) -> Result<Self> {
+        self.mirror = Some(UpstreamPool {
+            name: MIRROR_POOL.to_string(),
+            backends: Backends::new(Static::new(Self::build_backend_set(backends)?)),
+            lb_options,
+        });
+        Ok(self)
+    }
+
+    /// Restrict this route to a named virtual host (nginx `server_name`): an exact name,
+    /// `*.example.com`, `www.example.*` or a `~`-prefixed regex.
     pub fn host(mut self, host: impl Into<String>) -> Self {
         self.host = Some(host.into());
         self
     }
 
+    /// Serve the primary pool from the load balancer of the named upstream `name`, shared by every
+    /// route naming it (nginx `upstream`). The first route started with a name supplies the
+    /// upstream's backends and options; the others' are ignored.
+    pub fn upstream(mut self, name: impl Into<String>) -> Self {
+        self.upstream = Some(name.into());
+        self
+    }
+
     /// Default: [DEFAULT_MAX_ALGORITHM_ITERATIONS](crate::utils::constants::DEFAULT_MAX_ALGORITHM_ITERATIONS)
     pub fn max_iterations(mut self, max_iterations: usize) -> Self {
         self.lb_options.max_iterations = max_iterations;
@@ -206,18 +290,206 @@ impl Route {
     }
 }
 
+/// Where a route is mounted: its virtual host and its path or regex location.
+pub(crate) struct RouteMount {
+    pub host: Option<String>,
+    pub is_regex: bool,
+    pub path: String,
+}
+
+/// Create a route's adaptive load balancer, queueing its background task on `pending`.
+fn add_load_balancer(
+    pending: &mut PendingTasks,
+    name: String,
+    labels: PoolLabels,
+    backends: AdaptiveBackends,
+    lb_options: AdaptiveLbOpt,
+) -> SharedLb {
+    let decision_engine = AdaptiveDecisionEngine::new(&lb_options);
+    let lb = Arc::new(
+        AdaptiveLoadBalancer::from_backends(backends, Some(lb_options), decision_engine)
+            .with_labels(labels),
+    );
+    pending.push((name, lb.clone()));
+    lb
+}
+
+/// The load balancer of the named upstream `name`, created from `backends` and `lb_options` by
+/// the first route referencing it. Its metrics are labelled with the upstream's name as the
+/// `pool` and no `route`, as it serves several.
+fn shared_load_balancer(
+    pending: &mut PendingTasks,
+    upstreams: &mut UpstreamRegistry,
+    name: &str,
+    backends: AdaptiveBackends,
+    lb_options: AdaptiveLbOpt,
+) -> SharedLb {
+    if let Some(upstream) = upstreams.get(name) {
+        return upstream.lb.clone();
+    }
+    let lb = add_load_balancer(
+        pending,
+        format!("adaptive-lb-upstream-{name}"),
+        PoolLabels::new("", name),
+        backends,
+        lb_options.clone(),
+    );
+    let upstream = RegisteredUpstream {
+        lb: lb.clone(),
+        lb_options,
+    };
+    upstreams.insert(name.to_string(), upstream);
+    lb
+}
+
+/// Create the route's load balancers (its own backends, split pools and mirror pool), queue their
+/// background tasks on `pending` and wrap them in the route's runtime. A route naming an upstream
+/// uses the one in `upstreams`, adding it if missing. `key` is the route's registry key, which
+/// also names it in metrics.
+pub(crate) fn start_route(
+    pending: &mut PendingTasks,
+    key: &RouteKey,
+    route: Route,
+    upstreams: &mut UpstreamRegistry,
+) -> (RouteMount, RegisteredRoute) {
+    let lb_options = RouteLbOptions::of(&route);
+    let label = route_label(key);
+    let lb = match route.upstream.as_deref() {
+        Some(name) => {
+            shared_load_balancer(pending, upstreams, name, route.backends, route.lb_options)
+        }
+        None => add_load_balancer(
+            pending,
+            format!("adaptive-lb-{}", &route.path),
+            PoolLabels::new(&label, PRIMARY_POOL),
+            route.backends,
+            route.lb_options,
+        ),
+    };
+    let pools = route
+        .pools
+        .into_iter()
+        .map(|pool| {
+            let name = format!("adaptive-lb-{}-{}", &route.path, &pool.name);
+            let labels = PoolLabels::new(&label, &pool.name);
+            let lb = add_load_balancer(pending, name, labels, pool.backends, pool.lb_options);
+            (pool.name, lb)
+        })
+        .collect();
+
+    let mut runtime =
+        RouteRuntime::with_pools(lb, pools, route.route_config).with_label(label.clone());
+    if let Some(mirror) = route.mirror {
+        let name = format!("adaptive-lb-{}-{}", &route.path, &mirror.name);
+        runtime = runtime.with_mirror(add_load_balancer(
+            pending,
+            name,
+            PoolLabels::new(&label, &mirror.name),
+            mirror.backends,
+            mirror.lb_options,
+        ));
+    }
+
+    let mount = RouteMount {
+        host: route.host,
+        is_regex: route.is_regex,
+        path: route.path,
+    };
+    let registered = RegisteredRoute {
+        runtime: Arc::new(runtime),
+        lb_options,
+    };
+    (mount, registered)
+}
+
+/// Assemble mounted routes, in declared order, into the default server's and each virtual host's
+/// routes. Routes sharing a host + path are grouped into one `RouteValue` (told apart by their
+/// request predicates), preserving declaration order. Fails on a path the router rejects.
+pub(crate) fn route_tables(
+    routes: Vec<(RouteMount, Arc<RouteRuntime>)>,
+) -> Result<(VHostRoutes, Vec<(String, VHostRoutes)>)> {
+    let mut default_routes = VHostRoutes::default();
+    let mut vhosts: Vec<(String, VHostRoutes)> = Vec::new();
+    let mut grouped: Vec<(Option<String>, String, RouteValue)> = Vec::new();
+    let mut group_index: HashMap<(String, String), usize> = HashMap::new();
+    for (mount, runtime) in routes {
+        if mount.is_regex {
+            tracing::info!("Adding regex route: {}", mount.path);
+            let re = Regex::new(&mount.path)
+                .map_err(|err| eyre!("Invalid regex route '{}': {err}", mount.path))?;
+            server_routes(&mut default_routes, &mut vhosts, mount.host.as_deref())
+                .regex
+                .push((re, RouteValue::new(runtime)));
+            continue;
+        }
+
+        let host_key = mount
+            .host
+            .as_deref()
+            .map(|h| h.to_ascii_lowercase())
+            .unwrap_or_default();
+        match group_index.get(&(host_key.clone(), mount.path.clone())) {
+            Some(&index) => {
+                tracing::info!("Adding alternative route: {}", mount.path);
+                grouped[index].2.alternatives.push(runtime);
+            }
+            None => {
+                group_index.insert((host_key, mount.path.clone()), grouped.len());
+                grouped.push((mount.host, mount.path, RouteValue::new(runtime)));
+            }
+        }
+    }
+
+    for (host, path, route_value) in grouped {
+        match &host {
+            Some(host) => tracing::info!("Adding route: {path} (host: {host})"),
+            None => tracing::info!("Adding route: {path}"),
+        }
+        server_routes(&mut default_routes, &mut vhosts, host.as_deref())
+            .router
+            .insert(path.as_str(), route_value)
+            .map_err(|err| eyre!("Invalid route '{path}': {err}"))?;
+    }
+    Ok((default_routes, vhosts))
+}
+
+/// The routes for `host` (a `server_name`), or the default server's when `None`.
+fn server_routes<'a>(
+    default: &'a mut VHostRoutes,
+    vhosts: &'a mut Vec<(String, VHostRoutes)>,
+    host: Option<&str>,
+) -> &'a mut VHostRoutes {
+    let Some(host) = host else {
+        return default;
+    };
+    let index = match vhosts
+        .iter()
+        .position(|(name, _)| name.eq_ignore_ascii_case(host))
+    {
+        Some(index) => index,
+        None => {
+            vhosts.push((host.to_string(), VHostRoutes::default()));
+            vhosts.len() - 1
+        }
+    };
+    &mut vhosts[index].1
+}
+
 pub struct ServerBuilder {
     address: String,
     routes: Vec<Route>,
-    set_strategy_endpoint: Option<String>,
+    admin: Option<AdminConfig>,
     server_config: Option<ServerConf>,
     tls: Option<TlsConfig>,
     prometheus_address: Option<String>,
     access_log: bool,
+    access_log_config: Option<AccessLog>,
     https_redirect: bool,
     compression_level: u32,
     reload_config_path: Option<String>,
+    watch_config: Option<Duration>,
     request_id: bool,
+    trace_context: bool,
     error_pages: HashMap<u16, String>,
 }
 impl ServerBuilder {
@@ -226,8 +498,9 @@ impl ServerBuilder {
         self
     }
 
-    pub fn set_strategy_endpoint(mut self, addr: String) -> Self {
-        self.set_strategy_endpoint = Some(addr);
+    /// Serve the admin API (routes, backends, strategies, reload). Default: off.
+    pub fn admin(mut self, admin: AdminConfig) -> Self {
+        self.admin = Some(admin);
         self
     }
 
@@ -255,6 +528,13 @@ impl ServerBuilder {
         self
     }
 
+    /// Enable the access log with a custom format, filter or file sink.
+    pub fn access_log_config(mut self, log: AccessLog) -> Self {
+        self.access_log = true;
+        self.access_log_config = Some(log);
+        self
+    }
+
     /// Redirect plain-HTTP requests to their `https://` equivalent. Default: off.
     pub fn https_redirect(mut self, enabled: bool) -> Self {
         self.https_redirect = enabled;
@@ -267,19 +547,33 @@ impl ServerBuilder {
         self
     }
 
-    /// Reload per-route config from `path` on `SIGHUP` (nginx `-s reload`). Structural changes
-    /// still require a restart.
+    /// Reload the routes from `path` on `SIGHUP` (nginx `-s reload`), including added and removed
+    /// routes and changed upstreams.
     pub fn reload_on_sighup(mut self, path: String) -> Self {
         self.reload_config_path = Some(path);
         self
     }
 
+    /// Also reload when the file given to [`Self::reload_on_sighup`] changes, once it has stayed
+    /// unchanged for `debounce`. Default: off.
+    pub fn watch_config(mut self, debounce: Duration) -> Self {
+        self.watch_config = Some(debounce);
+        self
+    }
+
     /// Generate/propagate an `X-Request-Id` header for request tracing. Default: off.
     pub fn request_id(mut self, enabled: bool) -> Self {
         self.request_id = enabled;
         self
     }
 
+    /// Propagate W3C `traceparent`/`tracestate` and record a span per request and upstream
+    /// attempt, exported over OTLP when a collector is configured. Default: off.
+    pub fn trace_context(mut self, enabled: bool) -> Self {
+        self.trace_context = enabled;
+        self
+    }
+
     /// Custom error-page bodies keyed by status code (nginx `error_page`).
     pub fn error_pages(mut self, pages: HashMap<u16, String>) -> Self {
         self.error_pages = pages;
@@ -297,84 +591,63 @@ impl ServerBuilder {
         });
         let mut server = Server::new_with_opt_and_conf(None, server_config);
 
-        let mut default_router = Router::new();
-        let mut default_regex: Vec<(Regex, RouteValue)> = Vec::new();
-        let mut vhost_routers: HashMap<String, Router<RouteValue>> = HashMap::new();
-        let mut registry: RouteRegistry = HashMap::new();
+        // The load balancers' background tasks run in one service, so routes added by a reload
+        // can start theirs (and removed ones stop) while the server runs.
+        let mut lb_tasks = background_service("adaptive-lb-tasks", LbTasks::default());
+        lb_tasks.threads = Some(1);
+        let tasks = lb_tasks.task();
+        server.add_service(lb_tasks);
+
+        // Register each runtime under its key so reloads can diff against it.
+        let mut keys = RouteKeys::default();
+        let mut registry = RouteRegistry::new();
+        let mut upstreams = UpstreamRegistry::new();
+        let mut mounted = Vec::with_capacity(self.routes.len());
+        let mut pending = PendingTasks::new();
         for route in self.routes {
-            let lb_options = route.lb_options;
-
-            let decision_engine = AdaptiveDecisionEngine::new(&lb_options);
-            let lb = AdaptiveLoadBalancer::from_backends(
-                route.backends,
-                Some(lb_options),
-                decision_engine,
-            );
-
-            let service_name = format!("adaptive-lb-{}", &route.path);
-            let mut background_service = background_service(&service_name, lb);
-            background_service.threads = Some(1);
-            let task = background_service.task();
-            server.add_service(background_service);
-
-            let route_value = RouteValue {
-                runtime: Arc::new(RouteRuntime::new(task, route.route_config)),
-            };
-
-            // Register the runtime so SIGHUP reload can target it (key mirrors RouteEntry::route_key).
-            let host_key = route
-                .host
-                .as_deref()
-                .map(|h| h.to_ascii_lowercase())
-                .unwrap_or_default();
-            registry.insert(
-                (host_key, route.is_regex, route.path.clone()),
-                route_value.runtime.clone(),
-            );
-
-            if route.is_regex {
-                tracing::info!("Adding regex route: {}", route.path);
-                let re = Regex::new(&route.path).expect("regex validated at construction");
-                default_regex.push((re, route_value));
-                continue;
-            }
-
-            match route.host {
-                Some(host) => {
-                    tracing::info!("Adding route: {} (host: {host})", route.path);
-                    vhost_routers
-                        .entry(host.to_ascii_lowercase())
-                        .or_insert_with(Router::new)
-                        .insert(route.path, route_value)
-                        .expect("Invalid route");
-                }
-                None => {
-                    tracing::info!("Adding route: {}", route.path);
-                    default_router
-                        .insert(route.path, route_value)
-                        .expect("Invalid route");
-                }
-            }
+            let key = keys.next(route.host.as_deref(), route.is_regex, &route.path);
+            let (mount, registered) = start_route(&mut pending, &key, route, &mut upstreams);
+            mounted.push((mount, registered.runtime.clone()));
+            registry.insert(key, registered);
         }
-        let mut router = Proxy::with_regex_routes(
-            default_router,
-            default_regex,
-            vhost_routers,
-            DEFAULT_PATH_CACHE_CAPACITY,
-        );
+        tasks.spawn_all(pending);
+
+        let (default_routes, vhosts) = route_tables(mounted).expect("Invalid route");
+        let mut router =
+            Proxy::with_vhost_routes(default_routes, vhosts, DEFAULT_PATH_CACHE_CAPACITY)
+                .expect("Invalid host");
         router.set_access_log(self.access_log);
+        if let (true, Some(log)) = (self.access_log, self.access_log_config) {
+            router.set_access_log_config(log);
+        }
         router.set_https_redirect(self.https_redirect);
         router.set_compression_level(self.compression_level);
         router.set_request_id(self.request_id);
+        router.set_trace_context(self.trace_context);
         router.set_error_pages(self.error_pages);
 
-        if let Some(path) = self.reload_config_path {
-            spawn_reload_watcher(path, Arc::new(registry));
+        let reload_on_sighup = self.reload_config_path.is_some();
+        let reloader = Arc::new(Reloader::new(
+            self.reload_config_path,
+            router.clone(),
+            tasks,
+            registry,
+            upstreams,
+        ));
+        if reload_on_sighup {
+            spawn_reload_watcher(reloader.clone());
+        }
+        if let Some(debounce) = self.watch_config {
+            spawn_config_watcher(reloader.clone(), debounce);
+        }
+        if let Err(err) = prometheus::register(Box::new(BackendCollector::new(reloader.clone()))) {
+            tracing::warn!("Backend metrics not exported: {err}");
         }
 
-        if let Some(endpoint_address) = self.set_strategy_endpoint {
-            let endpoint = SetStrategyEndpoint::service(router.clone(), &endpoint_address);
-            server.add_service(endpoint);
+        if let Some(admin) = self.admin {
+            tracing::info!("Admin API listening on {}", admin.address);
+            let service = AdminApi::service(reloader, admin.token, &admin.address);
+            server.add_service(service);
         }
 
         let mut router_service = http_proxy_service(&server.configuration, router);
@@ -523,6 +796,21 @@ mod tests {
         assert_eq!(route.route_config.strip_path_prefix, false);
     }
 
+    #[test]
+    fn test_pool_names() {
+        let route = || Route::new("/api/*", vec!["127.0.0.1:8080"], Adaptive::default()).unwrap();
+        let pool = |route: Route, name: &str| {
+            let backends = [("127.0.0.1:8081".to_string(), 1)];
+            route.pool(name, backends, AdaptiveLbOpt::default())
+        };
+        let canary = pool(route(), "canary").unwrap();
+        assert!(pool(canary, "canary").is_err());
+        // the mirror pool is addressed by name too, so no split pool may take it
+        for name in [PRIMARY_POOL, MIRROR_POOL] {
+            assert!(pool(route(), name).is_err());
+        }
+    }
+
     #[test]
     fn test_route_default_values() {
         let route = Route::new("/api", vec!["127.0.0.1:8080"], Adaptive::default()).unwrap();
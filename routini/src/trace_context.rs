@@ -0,0 +1,242 @@
+//! W3C Trace Context (`traceparent` / `tracestate`) propagation and the request's spans.
+//!
+//! Each proxied request gets a server span, a child of the incoming `traceparent` (or the root of
+//! a new trace), and every upstream attempt a client span under it whose id is sent upstream.
+//! Spans are `tracing` spans on the [`TRACE_TARGET`] target, exported by
+//! [`crate::utils::otlp::OtlpLayer`] when an OTLP collector is configured.
+use std::fmt::Write;
+
+use pingora::http::RequestHeader;
+use tracing::{Span, field::Empty};
+
+/// `tracing` target of the request spans.
+pub const TRACE_TARGET: &str = "routini::trace";
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACESTATE: &str = "tracestate";
+
+/// The `sampled` bit of the `traceparent` trace flags.
+const FLAG_SAMPLED: u8 = 0x01;
+
+/// A span's position in a trace: its trace and span ids, its parent and the propagated flags and
+/// vendor state.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub parent_id: Option<[u8; 8]>,
+    pub flags: u8,
+    /// The incoming `tracestate`, forwarded untouched.
+    pub state: Option<String>,
+}
+
+impl TraceContext {
+    /// The context of a new, sampled trace.
+    pub fn root() -> Self {
+        Self {
+            trace_id: random_id(),
+            span_id: random_id(),
+            parent_id: None,
+            flags: FLAG_SAMPLED,
+            state: None,
+        }
+    }
+
+    /// Parse a version `00` `traceparent` (`00-<trace id>-<parent id>-<flags>`). Later versions
+    /// are read by their first four fields, as the spec asks; all-zero ids are invalid.
+    pub fn parse(traceparent: &str, tracestate: Option<&str>) -> Option<Self> {
+        let mut fields = traceparent.trim().split('-');
+        let version = fields.next().filter(|v| v.len() == 2 && *v != "ff")?;
+        let trace_id = hex_id::<16>(fields.next()?)?;
+        let span_id = hex_id::<8>(fields.next()?)?;
+        let flags = fields.next().filter(|f| f.len() == 2)?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        if (version == "00" && fields.next().is_some()) || trace_id == [0; 16] || span_id == [0; 8]
+        {
+            return None;
+        }
+        Some(Self {
+            trace_id,
+            span_id,
+            parent_id: None,
+            flags,
+            state: tracestate
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string),
+        })
+    }
+
+    /// The context carried by `req`'s `traceparent` and `tracestate` headers, if valid.
+    pub fn from_request(req: &RequestHeader) -> Option<Self> {
+        let header = |name| req.headers.get(name).and_then(|v| v.to_str().ok());
+        Self::parse(header(TRACEPARENT)?, header(TRACESTATE))
+    }
+
+    /// A new span in the same trace, parented by this one.
+    pub fn child(&self) -> Self {
+        Self {
+            trace_id: self.trace_id,
+            span_id: random_id(),
+            parent_id: Some(self.span_id),
+            flags: self.flags,
+            state: self.state.clone(),
+        }
+    }
+
+    pub fn sampled(&self) -> bool {
+        self.flags & FLAG_SAMPLED != 0
+    }
+
+    pub fn trace_id_hex(&self) -> String {
+        to_hex(&self.trace_id)
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            to_hex(&self.trace_id),
+            to_hex(&self.span_id),
+            self.flags
+        )
+    }
+
+    /// Set `traceparent` (naming this span as the parent) and `tracestate` on an upstream request.
+    pub fn inject(&self, req: &mut RequestHeader) {
+        let _ = req.insert_header(TRACEPARENT, self.traceparent());
+        match &self.state {
+            Some(state) => {
+                let _ = req.insert_header(TRACESTATE, state);
+            }
+            None => {
+                req.remove_header(TRACESTATE);
+            }
+        }
+    }
+
+    /// The server span of a request received in this context. `route` and `status` are recorded
+    /// once known.
+    pub fn server_span(&self, method: &str) -> Span {
+        if !self.sampled() {
+            return Span::none();
+        }
+        tracing::info_span!(
+            target: TRACE_TARGET,
+            "proxy request",
+            otel.kind = "server",
+            trace_id = %to_hex(&self.trace_id),
+            span_id = %to_hex(&self.span_id),
+            parent_span_id = self.parent_id.map(|id| to_hex(&id)),
+            method,
+            route = Empty,
+            status = Empty,
+            error = Empty,
+        )
+    }
+
+    /// The client span of an upstream attempt made in this context; `retry` counts the attempts
+    /// before it.
+    pub fn upstream_span(&self, route: &str, backend: &str, strategy: &str, retry: usize) -> Span {
+        if !self.sampled() {
+            return Span::none();
+        }
+        tracing::info_span!(
+            target: TRACE_TARGET,
+            "upstream attempt",
+            otel.kind = "client",
+            trace_id = %to_hex(&self.trace_id),
+            span_id = %to_hex(&self.span_id),
+            parent_span_id = self.parent_id.map(|id| to_hex(&id)),
+            route,
+            backend,
+            strategy,
+            retry,
+            status = Empty,
+            error = Empty,
+        )
+    }
+}
+
+fn random_id<const N: usize>() -> [u8; N] {
+    loop {
+        let id: [u8; N] = rand::random();
+        if id != [0; N] {
+            return id;
+        }
+    }
+}
+
+/// Parse exactly `2 * N` lowercase hex digits.
+fn hex_id<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != 2 * N || !hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    let mut id = [0; N];
+    for (i, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(id)
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{b:02x}");
+        hex
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT_SAMPLE: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parse_round_trip() {
+        let ctx = TraceContext::parse(TRACEPARENT_SAMPLE, Some("congo=t61rcWkgMzE")).unwrap();
+        assert_eq!(ctx.trace_id_hex(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert!(ctx.sampled());
+        assert_eq!(ctx.traceparent(), TRACEPARENT_SAMPLE);
+        assert_eq!(ctx.state.as_deref(), Some("congo=t61rcWkgMzE"));
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid() {
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        ] {
+            assert!(TraceContext::parse(invalid, None).is_none(), "{invalid}");
+        }
+        // A future version may append fields.
+        let future = "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra";
+        assert!(!TraceContext::parse(future, None).unwrap().sampled());
+    }
+
+    #[test]
+    fn test_child_keeps_trace() {
+        let parent = TraceContext::parse(TRACEPARENT_SAMPLE, None).unwrap();
+        let child = parent.child();
+        assert_eq!(child.trace_id, parent.trace_id);
+        assert_eq!(child.parent_id, Some(parent.span_id));
+        assert_ne!(child.span_id, parent.span_id);
+        assert_eq!(child.flags, parent.flags);
+    }
+
+    #[test]
+    fn test_inject() {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header(TRACESTATE, "stale=1").unwrap();
+        let ctx = TraceContext::root();
+        ctx.inject(&mut req);
+        assert_eq!(
+            req.headers.get(TRACEPARENT).unwrap().to_str().unwrap(),
+            ctx.traceparent()
+        );
+        assert!(req.headers.get(TRACESTATE).is_none());
+    }
+}
@@ -1,7 +1,30 @@
-//! Loads the proxy [`Config`](crate::config::Config) from a JSON file.
-use std::path::Path;
+//! Loads the proxy [`Config`](crate::config::Config) from a JSON, YAML or TOML file.
+//!
+//! After parsing, `${VAR}` and `${VAR:-default}` in the file's string values are replaced with
+//! environment variables (write `$${` for a literal `${`), so secrets need not be committed. A
+//! value is taken as is whatever characters it holds, and is read as a number or boolean where the
+//! config expects one (`"listener": "${PORT}"`). Then `ROUTINI__<SECTION>__<KEY>` variables
+//! override single values, e.g.
+//! `ROUTINI__PROXY__LISTENER=8080`. An override is read as a number or boolean only where the
+//! config expects one, so a numeric secret stays a string.
+//!
+//! A top-level `include` lists further files (nginx `include`), relative to the config's directory
+//! and with glob patterns allowed, e.g. `"include": ["routes.d/*.yaml", "teams/*/routes.json"]`.
+//! Each may only define `upstreams` and `router` entries, which are added to the config's own, so
+//! each team can own its routes in its own file. [`IncludeSources`] remembers which file each
+//! entry came from.
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
 
-use color_eyre::eyre::{Context, Result};
+use ::config::{Config as Layers, Environment, File, FileFormat};
+use color_eyre::eyre::{Context, Result, eyre};
+use serde::de::{
+    DeserializeOwned, Deserializer, IntoDeserializer, Visitor,
+    value::{MapDeserializer, SeqDeserializer},
+};
+use serde_json::{Map, Value};
 
 use crate::config::Config;
 
@@ -9,15 +32,96 @@ use crate::config::Config;
 pub const CONFIG_PATH_ENV: &str = "ROUTINI_CONFIG";
 /// Default config path used when [`CONFIG_PATH_ENV`] is unset.
 pub const DEFAULT_CONFIG_PATH: &str = "config.json";
+/// Prefix of the environment variables overriding config values; nested keys are separated by
+/// [`ENV_OVERRIDE_SEPARATOR`].
+pub const ENV_OVERRIDE_PREFIX: &str = "ROUTINI";
+pub const ENV_OVERRIDE_SEPARATOR: &str = "__";
 
-/// Load and parse the config from `path`.
+/// Load and parse the config from `path`, in the format given by its extension (`.yaml`/`.yml`,
+/// `.toml`, otherwise JSON).
 pub fn load_config_from<P: AsRef<Path>>(path: P) -> Result<Config> {
     let path = path.as_ref();
-    let contents = std::fs::read_to_string(path)
-        .wrap_err_with(|| format!("Failed to read config file {}", path.display()))?;
-    let config = serde_json::from_str(&contents)
-        .wrap_err_with(|| format!("Failed to parse config file {}", path.display()))?;
-    Ok(config)
+    let value = load_config_value(path)?;
+    config_from_value(&value)
+        .wrap_err_with(|| format!("Failed to parse config file {}", path.display()))
+}
+
+/// Load the config at `path` with variables interpolated, overrides applied and included files
+/// merged in, but not yet deserialized into a [`Config`].
+pub fn load_config_value<P: AsRef<Path>>(path: P) -> Result<Value> {
+    Ok(load_config_sources(path)?.0)
+}
+
+/// [`load_config_value`], along with the files the included entries came from.
+pub fn load_config_sources<P: AsRef<Path>>(path: P) -> Result<(Value, IncludeSources)> {
+    let path = path.as_ref();
+    let mut value = read_config_file(path, Some(env_overrides()))?;
+    let mut sources = IncludeSources::default();
+    for include in take_includes(&mut value)? {
+        for file in expand_include(path, &include)? {
+            let included = read_config_file(&file, None)?;
+            merge_include(&mut value, included, &file, &mut sources)
+                .wrap_err_with(|| format!("Invalid included config file {}", file.display()))?;
+        }
+    }
+    Ok((value, sources))
+}
+
+/// Where the entries merged in from included files were declared, so a problem with one can be
+/// reported in the file that declares it rather than at its place in the merged config.
+#[derive(Debug, Clone, Default)]
+pub struct IncludeSources {
+    /// Included `proxy.router` entries by their index in the merged config, with their file and
+    /// their index in it.
+    routes: BTreeMap<usize, (PathBuf, usize)>,
+    /// Included upstreams by name, with their file.
+    upstreams: HashMap<String, PathBuf>,
+}
+
+impl IncludeSources {
+    /// The file declaring the value at `path` in the merged config (`$.proxy.router[3].path`) and
+    /// its path in that file (`$.router[0].path`). `None` for the including config's own values.
+    pub fn locate(&self, path: &str) -> Option<(&Path, String)> {
+        if let Some(entry) = path.strip_prefix("$.proxy.router[") {
+            let (index, rest) = entry.split_once(']')?;
+            let (file, index_in_file) = self.routes.get(&index.parse().ok()?)?;
+            return Some((file, format!("$.router[{index_in_file}]{rest}")));
+        }
+        let entry = path.strip_prefix("$.proxy.upstreams.")?;
+        self.upstreams.iter().find_map(|(name, file)| {
+            let rest = entry.strip_prefix(name.as_str())?;
+            (rest.is_empty() || rest.starts_with(['.', '[']))
+                .then(|| (file.as_path(), format!("$.upstreams.{name}{rest}")))
+        })
+    }
+}
+
+/// The config file at `path` and the files it includes, for watching. Includes are resolved on a
+/// best-effort basis: a config that cannot be read yet includes nothing.
+pub fn config_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = vec![path.to_path_buf()];
+    let includes = read_config_file(path, None)
+        .and_then(|mut value| take_includes(&mut value))
+        .unwrap_or_default();
+    for include in includes {
+        files.extend(expand_include(path, &include).unwrap_or_default());
+    }
+    files
+}
+
+/// Deserialize a loaded config; errors name the path of the offending value
+/// (`proxy.router[2].load_balancer.strategy: unknown variant ...`).
+pub fn config_from_value(value: &Value) -> Result<Config> {
+    Ok(deserialize_value(value)?)
+}
+
+/// Deserialize a loaded config value into `T`, reading strings as the numbers and booleans `T`
+/// expects, since environment overrides are strings whatever they set. Errors name the path of
+/// the offending value.
+pub fn deserialize_value<T: DeserializeOwned>(
+    value: &Value,
+) -> Result<T, serde_path_to_error::Error<serde_json::Error>> {
+    serde_path_to_error::deserialize(Lenient(value.clone()))
 }
 
 /// Load the config from [`CONFIG_PATH_ENV`] if set, otherwise [`DEFAULT_CONFIG_PATH`].
@@ -25,3 +129,607 @@ pub fn load_config() -> Result<Config> {
     let path = std::env::var(CONFIG_PATH_ENV).unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string());
     load_config_from(path)
 }
+
+/// Read, parse and interpolate one file, layering `overrides` over it.
+fn read_config_file(path: &Path, overrides: Option<Environment>) -> Result<Value> {
+    let contents = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read config file {}", path.display()))?;
+    let mut value = parse_config(&contents, file_format(path))
+        .wrap_err_with(|| format!("Failed to parse config file {}", path.display()))?;
+    interpolate_values(&mut value, &|name| std::env::var(name).ok())
+        .wrap_err_with(|| format!("Failed to expand config file {}", path.display()))?;
+    layer_overrides(value, overrides)
+        .wrap_err_with(|| format!("Failed to parse config file {}", path.display()))
+}
+
+/// Remove the config's `include` list, a single path or an array of them.
+fn take_includes(value: &mut Value) -> Result<Vec<String>> {
+    let include = value
+        .as_object_mut()
+        .and_then(|config| config.remove("include"));
+    match include {
+        None => Ok(Vec::new()),
+        Some(Value::String(path)) => Ok(vec![path]),
+        Some(Value::Array(paths)) => paths
+            .into_iter()
+            .map(|path| match path {
+                Value::String(path) => Ok(path),
+                other => Err(eyre!("Invalid include '{other}': expected a file path")),
+            })
+            .collect(),
+        Some(other) => Err(eyre!(
+            "Invalid include '{other}': expected a path or a list"
+        )),
+    }
+}
+
+/// The files `pattern` names, relative to the directory of the config at `config_path`. A glob
+/// pattern's matches are sorted so routes load in a stable order, and a pattern that matches
+/// nothing includes nothing.
+fn expand_include(config_path: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let base = config_path.parent().unwrap_or(Path::new(""));
+    if !pattern.contains(['*', '?', '[']) {
+        return Ok(vec![base.join(pattern)]);
+    }
+    // The config's own directory is taken literally, whatever characters it contains.
+    let base = glob::Pattern::escape(&base.to_string_lossy());
+    let full = Path::new(&base).join(pattern);
+    let matches = glob::glob(&full.to_string_lossy())
+        .wrap_err_with(|| format!("Invalid include '{pattern}'"))?;
+    let mut files = Vec::new();
+    for path in matches {
+        let path = path.wrap_err_with(|| format!("Failed to read include '{pattern}'"))?;
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Add the `upstreams` and `router` entries of the included `file` to `config`, recording where
+/// they came from in `sources`.
+fn merge_include(
+    config: &mut Value,
+    included: Value,
+    file: &Path,
+    sources: &mut IncludeSources,
+) -> Result<()> {
+    let Value::Object(included) = included else {
+        return Err(eyre!("Expected an object with 'upstreams' and/or 'router'"));
+    };
+    let Some(proxy) = config.get_mut("proxy").and_then(Value::as_object_mut) else {
+        return Err(eyre!("The including config has no 'proxy' section"));
+    };
+    for (key, value) in included {
+        match (key.as_str(), value) {
+            ("upstreams", Value::Object(upstreams)) => {
+                let defined = proxy
+                    .entry("upstreams")
+                    .or_insert_with(|| Value::Object(Map::new()));
+                let Some(defined) = defined.as_object_mut() else {
+                    return Err(eyre!("'proxy.upstreams' must be an object"));
+                };
+                for (name, upstream) in upstreams {
+                    if defined.contains_key(&name) {
+                        return Err(eyre!("Upstream '{name}' is already defined"));
+                    }
+                    sources.upstreams.insert(name.clone(), file.to_path_buf());
+                    defined.insert(name, upstream);
+                }
+            }
+            ("router", Value::Array(routes)) => {
+                let router = proxy
+                    .entry("router")
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                let Some(router) = router.as_array_mut() else {
+                    return Err(eyre!("'proxy.router' must be a list"));
+                };
+                for (i, route) in routes.into_iter().enumerate() {
+                    sources.routes.insert(router.len(), (file.to_path_buf(), i));
+                    router.push(route);
+                }
+            }
+            ("upstreams", _) => return Err(eyre!("'upstreams' must be an object")),
+            ("router", _) => return Err(eyre!("'router' must be a list")),
+            (key, _) => {
+                return Err(eyre!(
+                    "Unexpected '{key}': included files may only define 'upstreams' and 'router'"
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn file_format(path: &Path) -> FileFormat {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml" | "yml") => FileFormat::Yaml,
+        Some("toml") => FileFormat::Toml,
+        _ => FileFormat::Json,
+    }
+}
+
+fn env_overrides() -> Environment {
+    Environment::with_prefix(ENV_OVERRIDE_PREFIX)
+        .prefix_separator(ENV_OVERRIDE_SEPARATOR)
+        .separator(ENV_OVERRIDE_SEPARATOR)
+}
+
+/// A [`Value`] deserializer that parses a string when asked for a number or boolean.
+struct Lenient(Value);
+
+impl Lenient {
+    /// The value, with a string holding a number or boolean parsed.
+    fn scalar(self) -> Value {
+        match self.0 {
+            Value::String(s) => match serde_json::from_str(s.trim()) {
+                Ok(value @ (Value::Number(_) | Value::Bool(_))) => value,
+                _ => Value::String(s),
+            },
+            value => value,
+        }
+    }
+}
+
+macro_rules! deserialize_scalar {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+            self.scalar().$method(visitor)
+        }
+    )*};
+}
+
+impl<'de> Deserializer<'de> for Lenient {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Array(items) => {
+                let mut seq = SeqDeserializer::new(items.into_iter().map(Lenient));
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Value::Object(entries) => {
+                // keys too, for maps keyed by numbers such as `error_pages`
+                let entries = entries
+                    .into_iter()
+                    .map(|(key, value)| (Lenient(Value::String(key)), Lenient(value)));
+                let mut map = MapDeserializer::new(entries);
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_enum(name, variants, visitor)
+    }
+
+    deserialize_scalar! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64
+    }
+
+    serde::forward_to_deserialize_any! {
+        char str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, serde_json::Error> for Lenient {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// Parse the file's values. The result is a JSON value so that numeric map keys (`error_pages`) and
+/// untagged enums deserialize the same as they always have from `config.json`.
+fn parse_config(contents: &str, format: FileFormat) -> Result<Value> {
+    let layers = Layers::builder().add_source(File::from_str(contents, format));
+    Ok(layers.build()?.try_deserialize()?)
+}
+
+/// Layer `overrides` over the parsed `value`.
+fn layer_overrides(value: Value, overrides: Option<Environment>) -> Result<Value> {
+    let Some(overrides) = overrides else {
+        return Ok(value);
+    };
+    let layers = Layers::builder()
+        .add_source(File::from_str(&value.to_string(), FileFormat::Json))
+        .add_source(overrides);
+    Ok(layers.build()?.try_deserialize()?)
+}
+
+/// Expand the variables in every string of `value`, leaving keys as they are. Substituting into
+/// parsed strings rather than the file's text means a value cannot break the file's syntax or add
+/// keys to it, whatever quotes, newlines or comment characters it holds.
+fn interpolate_values(value: &mut Value, lookup: &impl Fn(&str) -> Option<String>) -> Result<()> {
+    match value {
+        Value::String(s) if s.contains("${") => *s = interpolate_env(s, lookup)?,
+        Value::Array(items) => {
+            for item in items {
+                interpolate_values(item, lookup)?;
+            }
+        }
+        Value::Object(entries) => {
+            for (key, entry) in entries {
+                interpolate_values(entry, lookup)
+                    .wrap_err_with(|| format!("Failed to expand '{key}'"))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replace `${VAR}` and `${VAR:-default}` in `contents` with `lookup(VAR)`. As in the shell, the
+/// default is also used when the variable is empty. A variable that is unset and has no default
+/// is an error.
+fn interpolate_env(contents: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut expanded = String::with_capacity(contents.len());
+    let mut rest = contents;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find('}') else {
+            return Err(eyre!("Unterminated '${{' in config"));
+        };
+        let (name, default) = match after[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&after[..end], None),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(eyre!(
+                "Invalid environment variable name '{name}' in config"
+            ));
+        }
+        let value = match (lookup(name), default) {
+            (Some(value), Some(_)) if !value.is_empty() => value,
+            (Some(value), None) => value,
+            (_, Some(default)) => default.to_string(),
+            (None, None) => {
+                return Err(eyre!(
+                    "Environment variable '{name}' used in config is not set"
+                ));
+            }
+        };
+        expanded.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AccessLogInput;
+
+    /// A secret that would break or extend the config if pasted into the file's text.
+    const TRICKY: &str = "p\"a'ss\n  listener: 1 # x\\ = }";
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "AUTH" => Some("admin:secret".to_string()),
+            "EMPTY" => Some(String::new()),
+            "TRICKY" => Some(TRICKY.to_string()),
+            _ => None,
+        }
+    }
+
+    fn load(contents: &str, format: FileFormat, overrides: Environment) -> Result<Config> {
+        let mut value = parse_config(contents, format)?;
+        interpolate_values(&mut value, &lookup)?;
+        config_from_value(&layer_overrides(value, Some(overrides))?)
+    }
+
+    /// Overrides read from `vars` instead of the process environment.
+    fn overrides(vars: &[(&str, &str)]) -> Environment {
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        env_overrides().source(Some(vars))
+    }
+
+    #[test]
+    fn test_interpolate_env() {
+        let expanded = interpolate_env(
+            "${AUTH} ${MISSING:-8080} ${EMPTY:-x}${EMPTY} $${AUTH} $path",
+            lookup,
+        )
+        .unwrap();
+        assert_eq!(expanded, "admin:secret 8080 x ${AUTH} $path");
+        assert!(interpolate_env("${MISSING}", lookup).is_err());
+        assert!(interpolate_env("${AUTH", lookup).is_err());
+        assert!(interpolate_env("${A-B}", lookup).is_err());
+    }
+
+    #[test]
+    fn test_interpolated_values_stay_strings() {
+        let json = r#"{
+            "server": { "admin_token": "${TRICKY}" },
+            "proxy": { "listener": "${PORT:-3500}", "router": [] }
+        }"#;
+        let yaml = "
+server:
+  admin_token: ${TRICKY}
+proxy:
+  listener: ${PORT:-3500}
+  router: []
+";
+        let toml = r#"
+[server]
+admin_token = "${TRICKY}"
+
+[proxy]
+listener = "${PORT:-3500}"
+router = []
+"#;
+        for (contents, format) in [
+            (json, FileFormat::Json),
+            (yaml, FileFormat::Yaml),
+            (toml, FileFormat::Toml),
+        ] {
+            let config = load(contents, format, overrides(&[])).unwrap();
+            assert_eq!(config.server.admin_token.as_deref(), Some(TRICKY));
+            assert_eq!(config.proxy.listener, 3500);
+        }
+    }
+
+    #[test]
+    fn test_formats_agree() {
+        let json = r#"{
+            "server": { "error_pages": { "404": "gone" } },
+            "proxy": {
+                "listener": 3500,
+                "router": [
+                    { "path": "/api/*", "load_balancer": { "upstreams": [
+                        { "address": "127.0.0.1:8080" }
+                    ] } }
+                ]
+            }
+        }"#;
+        let yaml = "
+server:
+  error_pages:
+    404: gone
+proxy:
+  listener: 3500
+  router:
+    - path: /api/*
+      load_balancer:
+        upstreams:
+          - address: 127.0.0.1:8080
+";
+        let toml = r#"
+[server.error_pages]
+404 = "gone"
+
+[proxy]
+listener = 3500
+
+[[proxy.router]]
+path = "/api/*"
+
+[[proxy.router.load_balancer.upstreams]]
+address = "127.0.0.1:8080"
+"#;
+        for (contents, format) in [
+            (json, FileFormat::Json),
+            (yaml, FileFormat::Yaml),
+            (toml, FileFormat::Toml),
+        ] {
+            let config = load(contents, format, overrides(&[])).unwrap();
+            assert_eq!(config.proxy.listener, 3500);
+            assert_eq!(config.server.error_pages[&404], "gone");
+            assert_eq!(config.proxy.router[0].path, "/api/*");
+            assert_eq!(
+                config.proxy.router[0].load_balancer.upstreams[0].address,
+                "127.0.0.1:8080"
+            );
+        }
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let contents = r#"{ "proxy": { "listener": 3500, "router": [] } }"#;
+        let config = load(
+            contents,
+            FileFormat::Json,
+            overrides(&[
+                ("ROUTINI__PROXY__LISTENER", "8080"),
+                ("ROUTINI__SERVER__REQUEST_ID", "true"),
+                ("ROUTINI_CONFIG", "other.json"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.proxy.listener, 8080);
+        assert_eq!(config.server.request_id, Some(true));
+    }
+
+    #[test]
+    fn test_env_overrides_keep_numeric_strings() {
+        let contents = r#"{ "proxy": { "listener": 3500, "router": [] } }"#;
+        let config = load(
+            contents,
+            FileFormat::Json,
+            overrides(&[
+                ("ROUTINI__SERVER__ADMIN_TOKEN", "012345"),
+                ("ROUTINI__SERVER__ACCESS_LOG", "false"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.server.admin_token.as_deref(), Some("012345"));
+        assert!(matches!(
+            config.server.access_log,
+            Some(AccessLogInput::Enabled(false))
+        ));
+    }
+
+    #[test]
+    fn test_file_format() {
+        let format = |path| file_format(Path::new(path));
+        assert!(matches!(format("config.yml"), FileFormat::Yaml));
+        assert!(matches!(
+            format("/etc/routini/config.toml"),
+            FileFormat::Toml
+        ));
+        assert!(matches!(format("config.json"), FileFormat::Json));
+        assert!(matches!(format("config"), FileFormat::Json));
+    }
+
+    #[test]
+    fn test_include_patterns() {
+        let dir = std::env::temp_dir().join(format!("routini-glob-{}", std::process::id()));
+        for team in ["a", "b", "c"] {
+            std::fs::create_dir_all(dir.join("teams").join(team)).unwrap();
+            std::fs::write(dir.join("teams").join(team).join("routes.json"), "{}").unwrap();
+        }
+        std::fs::write(dir.join("teams/a/notes.txt"), "").unwrap();
+        let config = dir.join("config.json");
+        let names = |pattern: &str| {
+            let files = expand_include(&config, pattern).unwrap();
+            let relative = |file: &PathBuf| file.strip_prefix(&dir).unwrap().display().to_string();
+            files.iter().map(relative).collect::<Vec<_>>()
+        };
+
+        let every_team = names("teams/*/routes.json");
+        let some_teams = names("teams/[ab]/routes.json");
+        let missing = names("teams/d/*.json");
+        let literal = names("teams/d/routes.json");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            every_team,
+            [
+                "teams/a/routes.json",
+                "teams/b/routes.json",
+                "teams/c/routes.json"
+            ]
+        );
+        assert_eq!(some_teams, ["teams/a/routes.json", "teams/b/routes.json"]);
+        assert!(missing.is_empty());
+        // a path without wildcards is read as is, so a missing file is an error
+        assert_eq!(literal, ["teams/d/routes.json"]);
+        assert!(expand_include(&config, "teams/[a/*.json").is_err());
+    }
+
+    #[test]
+    fn test_includes() {
+        let dir = std::env::temp_dir().join(format!("routini-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("routes.d")).unwrap();
+        let route = |path: &str| format!(r#"{{ "path": "{path}", "upstream": "api" }}"#);
+        let main = format!(
+            r#"{{
+                "include": ["routes.d/*.json", "upstreams.yaml"],
+                "proxy": {{ "listener": 3500, "router": [{}] }}
+            }}"#,
+            route("/a/*")
+        );
+        std::fs::write(dir.join("config.json"), main).unwrap();
+        std::fs::write(
+            dir.join("routes.d/b.json"),
+            format!(r#"{{ "router": [{}] }}"#, route("/b/*")),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("routes.d/c.json"),
+            format!(r#"{{ "router": [{}] }}"#, route("/c/*")),
+        )
+        .unwrap();
+        std::fs::write(dir.join("routes.d/ignored.yaml"), "router: []").unwrap();
+        std::fs::write(
+            dir.join("upstreams.yaml"),
+            "upstreams:\n  api:\n    upstreams:\n      - address: 127.0.0.1:8080\n",
+        )
+        .unwrap();
+
+        let config = load_config_from(dir.join("config.json"));
+        let (_, sources) = load_config_sources(dir.join("config.json")).unwrap();
+        let files = config_files(&dir.join("config.json"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let config = config.unwrap();
+        let paths: Vec<_> = config
+            .proxy
+            .router
+            .iter()
+            .map(|r| r.path.as_str())
+            .collect();
+        assert_eq!(paths, ["/a/*", "/b/*", "/c/*"]);
+        assert_eq!(
+            config.proxy.upstreams["api"].upstreams[0].address,
+            "127.0.0.1:8080"
+        );
+        assert_eq!(files.len(), 4);
+
+        // included entries are located in the file that declares them
+        assert_eq!(sources.locate("$.proxy.router[0].path"), None);
+        assert_eq!(
+            sources.locate("$.proxy.router[2].path"),
+            Some((
+                dir.join("routes.d/c.json").as_path(),
+                "$.router[0].path".into()
+            ))
+        );
+        assert_eq!(
+            sources.locate("$.proxy.upstreams.api.upstreams[0].address"),
+            Some((
+                dir.join("upstreams.yaml").as_path(),
+                "$.upstreams.api.upstreams[0].address".into()
+            ))
+        );
+        assert_eq!(sources.locate("$.proxy.upstreams.apiv2"), None);
+    }
+
+    #[test]
+    fn test_merge_include_errors() {
+        let mut sources = IncludeSources::default();
+        let mut merge = |config: &mut Value, included| {
+            merge_include(config, included, Path::new("inc.json"), &mut sources)
+        };
+        let mut config = serde_json::json!({
+            "proxy": { "listener": 3500, "router": [], "upstreams": { "api": {} } }
+        });
+        let duplicate = serde_json::json!({ "upstreams": { "api": {} } });
+        assert!(merge(&mut config, duplicate).is_err());
+        let listener = serde_json::json!({ "listener": 8080 });
+        assert!(merge(&mut config, listener).is_err());
+        let router = serde_json::json!({ "router": { "path": "/" } });
+        assert!(merge(&mut config, router).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use std::time::Duration;
 
-pub const SET_STRATEGY_ENDPOINT_NAME: &str = "strategy_updater";
-pub const SET_STRATEGY_ENDPOINT_ADDRESS: &str = "0.0.0.0:5000";
+pub const ADMIN_ENDPOINT_NAME: &str = "admin";
+pub const ADMIN_ENDPOINT_ADDRESS: &str = "127.0.0.1:5000";
 pub const PROMETHEUS_ENDPOINT_NAME: &str = "prometheus";
 pub const PROMETHEUS_ENDPOINT_ADDRESS: &str = "0.0.0.0:9090";
 
@@ -21,6 +21,10 @@ pub const DEFAULT_CONNECTIONS_DIV_RATIO: f32 = 1.2;
 pub const DEFAULT_LATENCY_DIV_RATIO: f32 = 2.0;
 pub const DEFAULT_EVALUATE_STRATEGY_FREQUENCY: Duration = Duration::from_secs(5);
 pub const DEFAULT_HEALTH_CHECK_FREQUENCY: Duration = Duration::from_secs(1);
+/// Connect and read timeout of a health check.
+pub const DEFAULT_HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+/// `Host` header of an HTTP health check that does not set one.
+pub const DEFAULT_HEALTH_CHECK_HOST: &str = "localhost";
 pub const DEFAULT_MIN_NR_OF_CONNECTIONS: usize = 1000;
 /// Fraction of an enter threshold used as the exit threshold, giving the adaptive
 /// engine hysteresis so it does not flap between strategies on every evaluation.
@@ -28,7 +32,58 @@ pub const DEFAULT_MIN_NR_OF_CONNECTIONS: usize = 1000;
 /// FastestServer once the ratio drops below 1.5.
 pub const DEFAULT_HYSTERESIS_EXIT_FACTOR: f32 = 0.75;
 
+// Retries
+/// Pingora keeps at most this much of a request body for replaying it on a retry.
+pub const MAX_RETRY_BODY_SIZE: usize = 64 * 1024;
+/// Longest backoff between retries, when only the base is configured.
+pub const DEFAULT_RETRY_BACKOFF_MAX: Duration = Duration::from_secs(1);
+/// Retries a retry budget allows in a burst, when not configured.
+pub const DEFAULT_RETRY_BUDGET_BURST: u32 = 10;
+
+// Passive health
+/// Window over which a backend's failure rate is measured, when not configured.
+pub const DEFAULT_FAILURE_RATE_WINDOW: Duration = Duration::from_secs(30);
+/// Requests a backend must serve within the window before its failure rate can eject it.
+pub const DEFAULT_FAILURE_RATE_MIN_REQUESTS: u32 = 10;
+
+// Circuit breaker
+/// Consecutive failures opening a backend's circuit, when not configured.
+pub const DEFAULT_CIRCUIT_BREAKER_FAILURES: u32 = 5;
+/// Window over which a circuit breaker's failure rate is measured, when not configured.
+pub const DEFAULT_CIRCUIT_BREAKER_WINDOW: Duration = Duration::from_secs(10);
+/// Requests within the window before a circuit breaker's failure rate counts.
+pub const DEFAULT_CIRCUIT_BREAKER_MIN_REQUESTS: u32 = 20;
+/// How long an open circuit turns requests away before probing the backend.
+pub const DEFAULT_CIRCUIT_BREAKER_COOLDOWN: Duration = Duration::from_secs(30);
+
+// Slow start
+/// Share of its weight a backend starts warming up with, when not configured.
+pub const DEFAULT_SLOW_START_MIN_WEIGHT: f64 = 0.1;
+
+// Outlier detection
+/// How often a pool is analysed for outliers, when not configured.
+pub const DEFAULT_OUTLIER_INTERVAL: Duration = Duration::from_secs(10);
+/// Ejection period of an outlier's first ejection, when not configured.
+pub const DEFAULT_OUTLIER_BASE_EJECTION_TIME: Duration = Duration::from_secs(30);
+/// Longest ejection period of an outlier ejected again and again, when not configured.
+pub const DEFAULT_OUTLIER_MAX_EJECTION_TIME: Duration = Duration::from_secs(300);
+/// Most of a pool ejected as outliers at once, in percent (Envoy's default).
+pub const DEFAULT_OUTLIER_MAX_EJECTION_PERCENT: u8 = 10;
+/// Standard deviations worse than the pool's mean making a backend an outlier (Envoy's default).
+pub const DEFAULT_OUTLIER_STDEV_FACTOR: f64 = 1.9;
+/// Backends with enough requests needed to analyse a pool (Envoy's default).
+pub const DEFAULT_OUTLIER_MIN_HOSTS: usize = 5;
+/// Requests a backend must serve in an interval to be analysed (Envoy's default).
+pub const DEFAULT_OUTLIER_MIN_REQUESTS: u64 = 100;
+
+// Config reload
+/// How long a watched config file must stay unchanged before it is reloaded.
+pub const DEFAULT_CONFIG_WATCH_DEBOUNCE: Duration = Duration::from_secs(1);
+/// How often a watched config file is polled for changes.
+pub const CONFIG_WATCH_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 // Logging
 pub const DEFAULT_LOG_LEVEL_FILTER: &str = "info,routini=debug,pingora=info";
 pub const DEFAULT_LOG_JSON: bool = false;
 pub const DEFAULT_MAX_LOG_AGE_DAYS: u64 = 7;
+pub const DEFAULT_OTEL_SERVICE_NAME: &str = "routini";
@@ -1,3 +1,4 @@
 pub mod config_loader;
 pub mod constants;
+pub mod otlp;
 pub mod tracing;
@@ -0,0 +1,364 @@
+//! OTLP/HTTP span export: a `tracing` layer that turns the request spans of
+//! [`crate::trace_context`] into OpenTelemetry spans and posts them, JSON-encoded and in batches,
+//! to a collector's `/v1/traces` endpoint.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::{Client, Url};
+use serde_json::{Value, json};
+use tokio::{
+    sync::mpsc::{Receiver, Sender, channel},
+    time::{Instant, timeout_at},
+};
+use tracing::{
+    Id, Subscriber,
+    field::{Field, Visit},
+    span::{Attributes, Record},
+};
+use tracing_subscriber::{Layer, layer::Context, registry::LookupSpan};
+
+use crate::trace_context::TRACE_TARGET;
+
+/// Spans waiting for export; further spans are dropped while the queue is full.
+const QUEUE_SIZE: usize = 4096;
+const MAX_BATCH_SIZE: usize = 512;
+/// How long a finished span may wait for its batch to fill up.
+const BATCH_TIMEOUT: Duration = Duration::from_secs(1);
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_TRACES_PATH: &str = "/v1/traces";
+
+/// OTLP span kinds.
+const SPAN_KIND_SERVER: u8 = 2;
+const SPAN_KIND_CLIENT: u8 = 3;
+/// OTLP status code of a failed span.
+const STATUS_CODE_ERROR: u8 = 2;
+
+/// Exports closed [`TRACE_TARGET`] spans to an OTLP/HTTP collector.
+pub struct OtlpLayer {
+    sender: Sender<SpanData>,
+}
+
+/// A span being recorded: its identity from the `trace_id`, `span_id`, `parent_span_id` and
+/// `otel.kind` fields, and every other field as an attribute.
+#[derive(Default)]
+struct SpanData {
+    name: &'static str,
+    trace_id: String,
+    span_id: String,
+    parent_span_id: Option<String>,
+    kind: u8,
+    error: bool,
+    start: u128,
+    end: u128,
+    attributes: Vec<(&'static str, Value)>,
+}
+
+impl Visit for SpanData {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "trace_id" => self.trace_id = value.to_string(),
+            "span_id" => self.span_id = value.to_string(),
+            "parent_span_id" => self.parent_span_id = Some(value.to_string()),
+            "otel.kind" => {
+                self.kind = match value {
+                    "server" => SPAN_KIND_SERVER,
+                    "client" => SPAN_KIND_CLIENT,
+                    _ => 0,
+                }
+            }
+            name => self.attribute(name, json!({ "stringValue": value })),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{value:?}"));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        // OTLP/JSON encodes 64-bit integers as strings.
+        self.attribute(field.name(), json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.attribute(field.name(), json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.attribute(field.name(), json!({ "boolValue": value }));
+    }
+}
+
+impl SpanData {
+    fn attribute(&mut self, name: &'static str, value: Value) {
+        if name == "error" {
+            self.error = true;
+        }
+        self.attributes.retain(|(key, _)| *key != name);
+        self.attributes.push((name, value));
+    }
+
+    fn to_json(&self) -> Value {
+        let attributes: Vec<Value> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| json!({ "key": key, "value": value }))
+            .collect();
+        let mut span = json!({
+            "traceId": self.trace_id,
+            "spanId": self.span_id,
+            "name": self.name,
+            "kind": self.kind,
+            "startTimeUnixNano": self.start.to_string(),
+            "endTimeUnixNano": self.end.to_string(),
+            "attributes": attributes,
+        });
+        if let Some(parent) = &self.parent_span_id {
+            span["parentSpanId"] = json!(parent);
+        }
+        if self.error {
+            span["status"] = json!({ "code": STATUS_CODE_ERROR });
+        }
+        span
+    }
+}
+
+impl OtlpLayer {
+    /// Export to the collector at `endpoint` (`http://host:4318`, or a full traces URL), naming
+    /// this process `service_name`. The export runs on its own thread and runtime, as tracing is
+    /// set up before the server's runtimes exist.
+    pub fn new(endpoint: &str, service_name: &str) -> Result<Self, String> {
+        let exporter = Exporter::new(endpoint, service_name)?;
+        let (sender, receiver) = channel(QUEUE_SIZE);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| err.to_string())?;
+        std::thread::Builder::new()
+            .name("otlp-exporter".to_string())
+            .spawn(move || runtime.block_on(exporter.run(receiver)))
+            .map_err(|err| err.to_string())?;
+        Ok(Self { sender })
+    }
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().target() != TRACE_TARGET {
+            return;
+        }
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut data = SpanData {
+            name: attrs.metadata().name(),
+            start: unix_nanos(),
+            ..Default::default()
+        };
+        attrs.record(&mut data);
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+                values.record(data);
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(mut data) = ctx
+            .span(&id)
+            .and_then(|span| span.extensions_mut().remove::<SpanData>())
+        else {
+            return;
+        };
+        data.end = unix_nanos();
+        // A full queue means the collector is behind: drop the span rather than block requests.
+        let _ = self.sender.try_send(data);
+    }
+}
+
+/// The traces URL of the collector at `endpoint`: a bare `http(s)://host:port` posts to
+/// `/v1/traces`, a URL with a path is used as is.
+pub fn traces_url(endpoint: &str) -> Result<Url, String> {
+    let mut url =
+        Url::parse(endpoint).map_err(|err| format!("Invalid OTLP endpoint '{endpoint}': {err}"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!(
+            "OTLP endpoint '{endpoint}' must be an http:// or https:// URL"
+        ));
+    }
+    if url.host_str().is_none() {
+        return Err(format!("OTLP endpoint '{endpoint}' has no host"));
+    }
+    if url.path() == "/" {
+        url.set_path(DEFAULT_TRACES_PATH);
+    }
+    Ok(url)
+}
+
+/// Posts batches of spans to the collector.
+struct Exporter {
+    client: Client,
+    url: Url,
+    resource: Value,
+}
+
+impl Exporter {
+    fn new(endpoint: &str, service_name: &str) -> Result<Self, String> {
+        let client = Client::builder()
+            .timeout(EXPORT_TIMEOUT)
+            .build()
+            .map_err(|err| err.to_string())?;
+        Ok(Self {
+            client,
+            url: traces_url(endpoint)?,
+            resource: json!({
+                "attributes": [
+                    { "key": "service.name", "value": { "stringValue": service_name } }
+                ]
+            }),
+        })
+    }
+
+    async fn run(self, mut receiver: Receiver<SpanData>) {
+        let mut batch = Vec::with_capacity(MAX_BATCH_SIZE);
+        loop {
+            let deadline = Instant::now() + BATCH_TIMEOUT;
+            while batch.len() < MAX_BATCH_SIZE {
+                match timeout_at(deadline, receiver.recv()).await {
+                    Ok(Some(span)) => batch.push(span.to_json()),
+                    Ok(None) => return,
+                    Err(_) => break,
+                }
+            }
+            if batch.is_empty() {
+                continue;
+            }
+            if let Err(err) = self.export(&batch).await {
+                tracing::warn!("Failed to export {} span(s) over OTLP: {err}", batch.len());
+            }
+            batch.clear();
+        }
+    }
+
+    async fn export(&self, spans: &[Value]) -> reqwest::Result<()> {
+        let body = json!({
+            "resourceSpans": [{
+                "resource": self.resource,
+                "scopeSpans": [{ "scope": { "name": "routini" }, "spans": spans }]
+            }]
+        });
+        self.client
+            .post(self.url.clone())
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()
+            .map(drop)
+    }
+}
+
+fn unix_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::trace_context::TraceContext;
+
+    #[test]
+    fn test_traces_url() {
+        let url = |endpoint| traces_url(endpoint).map(String::from);
+        assert_eq!(
+            url("http://collector:4318").unwrap(),
+            "http://collector:4318/v1/traces"
+        );
+        assert_eq!(
+            url("https://collector/otlp/traces").unwrap(),
+            "https://collector/otlp/traces"
+        );
+        assert!(url("grpc://collector:4317").is_err());
+        assert!(url("collector:4318").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_export_posts_batches() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // The body of an HTTP request, once its headers are in.
+        fn body(request: &[u8]) -> &[u8] {
+            match request.windows(4).position(|w| w == b"\r\n\r\n") {
+                Some(end) => &request[end + 4..],
+                None => &[],
+            }
+        }
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let collector = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            // the exporter sends a Content-Length, so the request ends with the JSON body
+            while serde_json::from_slice::<Value>(body(&request)).is_err() {
+                let read = stream.read(&mut buf).await.unwrap();
+                assert!(read > 0, "the exporter closed the connection");
+                request.extend_from_slice(&buf[..read]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let exporter = Exporter::new(&endpoint, "edge").unwrap();
+        let span = json!({ "name": "request" });
+        exporter.export(std::slice::from_ref(&span)).await.unwrap();
+
+        let request = collector.await.unwrap();
+        assert!(request.starts_with("POST /v1/traces HTTP/1.1"));
+        let body: Value = serde_json::from_slice(body(request.as_bytes())).unwrap();
+        let resource_spans = &body["resourceSpans"][0];
+        assert_eq!(
+            resource_spans["resource"]["attributes"][0]["value"]["stringValue"],
+            "edge"
+        );
+        assert_eq!(resource_spans["scopeSpans"][0]["spans"][0], span);
+    }
+
+    #[test]
+    fn test_layer_records_spans() {
+        let (sender, mut receiver) = channel(8);
+        let subscriber = tracing_subscriber::registry().with(OtlpLayer { sender });
+        let ctx = TraceContext::root().child();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = ctx.upstream_span("/api/*", "127.0.0.1:8080", "RoundRobin", 1);
+            span.record("status", 502);
+            span.record("error", "bad gateway");
+        });
+
+        let span = receiver.try_recv().unwrap().to_json();
+        assert_eq!(span["traceId"], ctx.trace_id_hex());
+        assert_eq!(span["kind"], SPAN_KIND_CLIENT);
+        assert!(span["parentSpanId"].is_string());
+        assert_eq!(span["status"]["code"], STATUS_CODE_ERROR);
+        let attributes = span["attributes"].as_array().unwrap();
+        assert!(attributes.contains(&json!({ "key": "retry", "value": { "intValue": "1" } })));
+        assert!(
+            attributes.contains(
+                &json!({ "key": "backend", "value": { "stringValue": "127.0.0.1:8080" } })
+            )
+        );
+    }
+}
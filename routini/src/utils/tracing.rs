@@ -1,11 +1,19 @@
 use std::fs;
 use std::path::PathBuf;
 use std::time::{Duration, SystemTime};
+use tracing::Level;
 use tracing_error::ErrorLayer;
-use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{
+    EnvFilter, Layer, filter::Targets, fmt, layer::SubscriberExt, util::SubscriberInitExt,
+};
 
-use crate::utils::constants::{
-    DEFAULT_LOG_JSON, DEFAULT_LOG_LEVEL_FILTER, DEFAULT_MAX_LOG_AGE_DAYS,
+use crate::trace_context::TRACE_TARGET;
+use crate::utils::{
+    constants::{
+        DEFAULT_LOG_JSON, DEFAULT_LOG_LEVEL_FILTER, DEFAULT_MAX_LOG_AGE_DAYS,
+        DEFAULT_OTEL_SERVICE_NAME,
+    },
+    otlp::OtlpLayer,
 };
 
 pub struct LogConfig {
@@ -21,6 +29,10 @@ pub struct LogConfig {
     pub ansi: bool,
     /// Maximum age of log files in days (0 = keep forever)
     pub max_log_age_days: u64,
+    /// OTLP/HTTP collector to export request spans to (None = no export)
+    pub otlp_endpoint: Option<String>,
+    /// `service.name` of the exported spans
+    pub service_name: String,
 }
 
 impl Default for LogConfig {
@@ -36,6 +48,9 @@ impl Default for LogConfig {
                 .ok()
                 .and_then(|v| v.parse().ok())
                 .unwrap_or(DEFAULT_MAX_LOG_AGE_DAYS),
+            otlp_endpoint: std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok(),
+            service_name: std::env::var("OTEL_SERVICE_NAME")
+                .unwrap_or_else(|_| DEFAULT_OTEL_SERVICE_NAME.to_string()),
         }
     }
 }
@@ -149,11 +164,19 @@ fn spawn_log_cleanup_task(log_dir: String, file_prefix: String, max_age_days: u6
 }
 
 pub fn init_tracing_with_config(config: LogConfig) -> Result<(), Box<dyn std::error::Error>> {
+    // The filter applies to the logs only, so request spans are exported whatever RUST_LOG says
     let filter_layer = EnvFilter::try_new(&config.filter)?;
 
-    let registry = tracing_subscriber::registry()
-        .with(filter_layer)
-        .with(ErrorLayer::default());
+    // Request spans go to the OTLP collector, when one is configured
+    let otlp_layer = match &config.otlp_endpoint {
+        Some(endpoint) => Some(
+            OtlpLayer::new(endpoint, &config.service_name)?
+                .with_filter(Targets::new().with_target(TRACE_TARGET, Level::INFO)),
+        ),
+        None => None,
+    };
+
+    let registry = tracing_subscriber::registry().with(otlp_layer);
 
     match config.log_dir {
         Some(log_dir) => {
@@ -186,7 +209,11 @@ pub fn init_tracing_with_config(config: LogConfig) -> Result<(), Box<dyn std::er
                     .with_ansi(config.ansi)
                     .with_writer(std::io::stdout);
 
-                registry.with(file_layer).with(stdout_layer).init();
+                let log_layer = ErrorLayer::default()
+                    .and_then(file_layer)
+                    .and_then(stdout_layer)
+                    .with_filter(filter_layer);
+                registry.with(log_layer).init();
             } else {
                 // Plain format to both file and stdout
                 let file_layer = fmt::layer()
@@ -200,7 +227,11 @@ pub fn init_tracing_with_config(config: LogConfig) -> Result<(), Box<dyn std::er
                     .with_ansi(config.ansi)
                     .with_writer(std::io::stdout);
 
-                registry.with(file_layer).with(stdout_layer).init();
+                let log_layer = ErrorLayer::default()
+                    .and_then(file_layer)
+                    .and_then(stdout_layer)
+                    .with_filter(filter_layer);
+                registry.with(log_layer).init();
             }
 
             // Guard needs to live for the entire program
@@ -215,11 +246,17 @@ pub fn init_tracing_with_config(config: LogConfig) -> Result<(), Box<dyn std::er
                     .with_thread_ids(true)
                     .with_thread_names(true);
 
-                registry.with(fmt_layer).init();
+                let log_layer = ErrorLayer::default()
+                    .and_then(fmt_layer)
+                    .with_filter(filter_layer);
+                registry.with(log_layer).init();
             } else {
                 let fmt_layer = fmt::layer().compact().with_ansi(config.ansi);
 
-                registry.with(fmt_layer).init();
+                let log_layer = ErrorLayer::default()
+                    .and_then(fmt_layer)
+                    .with_filter(filter_layer);
+                registry.with(log_layer).init();
             }
         }
     }